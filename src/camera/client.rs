@@ -1,85 +1,232 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use reqwest::Client;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
 use crate::onvif::auth::WsSecurityAuth;
+use crate::onvif::soap;
 use crate::camera::config::CameraConfig;
+use crate::camera::digest::DigestChallenge;
+use crate::config::{AuthMode, CredentialMode};
+use crate::onvif::soap::WsSecurity;
+use crate::onvif::types::Profile;
+use crate::camera::error_log::ErrorLog;
+use crate::camera::health::CameraHealth;
+use crate::camera::learned::LearnedState;
+use crate::camera::queue::{Priority, QueuePermit, QueueStats, RequestQueue};
+use crate::camera::reolink_cgi::{self, CgiToken};
+use crate::camera::simulated::SimulatedCamera;
+use crate::translator::rules::RuleEngine;
+use crate::translator::tokens::TokenMap;
+use crate::util::redact_secrets;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+/// Wait before the first retry of a failed request, doubled for each further one
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
 
 #[derive(Clone)]
 pub struct CameraClient {
     config: CameraConfig,
     http_client: Client,
     auth: WsSecurityAuth,
+    health: Arc<RwLock<CameraHealth>>,  // Shared by all clones of this client
+    motion_triggered_until: Arc<RwLock<Option<DateTime<Utc>>>>,  // Simulated cameras only
+    queue: Option<RequestQueue>,  // Only when max_concurrent_requests is set
+    learned: Arc<RwLock<LearnedState>>,
+    error_log: Arc<std::sync::Mutex<ErrorLog>>,
+    client_tokens: Arc<std::sync::Mutex<TokenMap>>,  // client_profile: milestone only
+    cgi_token: Arc<RwLock<Option<CgiToken>>>,  // Reolink CGI login, for motion polling
+    cgi_client: Client,  // Reolink CGI, which uses self-signed certs
+    profiles: Arc<RwLock<Option<Arc<Vec<Profile>>>>>,  // Last GetProfiles, see MediaService::find_profile
+    caller_security: Option<Arc<str>>,  // credential_mode: passthrough, per request
+    rules: Arc<RuleEngine>,
 }
 
 impl CameraClient {
     pub fn new(config: CameraConfig) -> Self {
-        let auth = WsSecurityAuth::new(config.username.clone(), config.password.clone());
+        let auth = match config.password_sha1_bytes() {
+            // A hash can't stand in for the cleartext of PasswordText
+            Some(password_sha1) if config.auth_mode == AuthMode::Digest => {
+                WsSecurityAuth::with_password_sha1(config.username.clone(), password_sha1)
+            }
+            _ => {
+                if config.password_sha1.is_some() && config.password_sha1_bytes().is_none() {
+                    tracing::error!("Camera {}: password_sha1 is not a base64 SHA-1 digest, using password", config.id);
+                }
+                WsSecurityAuth::new(config.username.clone(), config.password.clone())
+            }
+        }
+        .with_mode(config.auth_mode);
         let http_client = Client::builder()
-            .timeout(std::time::Duration::from_secs(10))
+            .timeout(Duration::from_secs(config.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS)))
             .build()
             .expect("Failed to create HTTP client");
 
+        let queue = config.max_concurrent_requests.map(RequestQueue::new);
+        let rules = RuleEngine::from_rules(&config.rules).unwrap_or_else(|e| {
+            tracing::error!("Camera {}: {:#}, translation rules disabled", config.id, e);
+            RuleEngine::new()
+        });
+
         Self {
             config,
             http_client,
             auth,
+            health: Arc::new(RwLock::new(CameraHealth::default())),
+            motion_triggered_until: Arc::new(RwLock::new(None)),
+            queue,
+            learned: Arc::new(RwLock::new(LearnedState::default())),
+            error_log: Arc::new(std::sync::Mutex::new(ErrorLog::default())),
+            client_tokens: Arc::new(std::sync::Mutex::new(TokenMap::default())),
+            cgi_token: Arc::new(RwLock::new(None)),
+            cgi_client: reolink_cgi::http_client().expect("Failed to create CGI client"),
+            profiles: Arc::new(RwLock::new(None)),
+            caller_security: None,
+            rules: Arc::new(rules),
         }
     }
 
+    /// This client for one request: with `credential_mode: passthrough`, requests it sends carry
+    /// the caller's `token` (when there is one) instead of a WS-Security header from the
+    /// configured credentials. Everything else (health, learned state, queue) is shared
+    pub fn with_caller_token(&self, token: Option<&WsSecurity>) -> Self {
+        let mut client = self.clone();
+        if self.config.credential_mode == CredentialMode::Passthrough {
+            client.caller_security = token.map(|token| Arc::from(token.xml.as_str()));
+        }
+        client
+    }
+
+    /// `send_soap_request` with the caller's token in place of the proxy's, when the camera's
+    /// `credential_mode` allows it
+    pub async fn send_soap_request_with_header(&self, service_path: &str, soap_body: &str, token: Option<&WsSecurity>) -> Result<String> {
+        self.with_caller_token(token).send_soap_request(service_path, soap_body).await
+    }
+
     pub async fn send_soap_request(&self, service_path: &str, soap_body: &str) -> Result<String> {
-        self.send_soap_request_with_auth(service_path, soap_body, true).await
+        let response = self.send_soap_request_with_auth(service_path, soap_body, true, "").await?;
+        // Strip a BOM / add a missing XML declaration before anything string-matches on it
+        Ok(soap::normalize_prolog(&response))
     }
 
     pub async fn send_soap_request_no_auth(&self, service_path: &str, soap_body: &str) -> Result<String> {
-        self.send_soap_request_with_auth(service_path, soap_body, false).await
+        let response = self.send_soap_request_with_auth(service_path, soap_body, false, "").await?;
+        Ok(soap::normalize_prolog(&response))
     }
 
-    async fn send_soap_request_with_auth(&self, service_path: &str, soap_body: &str, use_auth: bool) -> Result<String> {
-        let url = format!("{}{}", self.config.base_url(), service_path);
+    /// Send a request and return the camera's response body exactly as received; `headers` are
+    /// extra header blocks (the client's WS-Addressing) next to the WS-Security header
+    pub async fn send_soap_request_verbatim(&self, service_path: &str, soap_body: &str, headers: &str) -> Result<String> {
+        self.send_soap_request_with_auth(service_path, soap_body, true, headers).await
+    }
 
+    /// The time to put in WS-Security tokens: the proxy clock, moved by the learned clock skew
+    /// with `correct_clock_skew`
+    async fn camera_now(&self) -> DateTime<Utc> {
+        match self.config.correct_clock_skew {
+            true => self.camera_clock().await,
+            false => Utc::now(),
+        }
+    }
+
+    /// The camera's clock as far as the proxy knows: the proxy clock moved by the learned clock
+    /// skew. ONVIF clients set theirs from the camera's GetSystemDateAndTime
+    pub async fn camera_clock(&self) -> DateTime<Utc> {
+        let skew = self.learned.read().await.clock_skew_secs.unwrap_or(0);
+        Utc::now() + chrono::Duration::seconds(skew)
+    }
+
+    /// The camera's URL for `service_path`
+    pub fn service_url(&self, service_path: &str) -> String {
+        format!("{}{}", self.config.base_url(), service_path)
+    }
+
+    async fn send_soap_request_with_auth(&self, service_path: &str, soap_body: &str, use_auth: bool, extra_headers: &str) -> Result<String> {
+        if self.config.is_simulated() {
+            self.health.write().await.record_success();
+            return Ok(SimulatedCamera::respond(&self.config, soap_body));
+        }
+
+        // Held until the response has been read
+        let action = soap::body_action(soap_body);
+        let _permit = self.acquire_slot(Priority::for_action(action)).await;
+
+        let url = self.service_url(service_path);
+
+        // Configured soap_headers go in every request, authenticated or not
+        let custom_headers = self.config.soap_headers.concat();
         let soap_request = if use_auth {
             // Create SOAP envelope with WS-Security header
-            let security_header = self.auth.generate_header();
+            let security_header = if self.config.omit_security_header {
+                String::new()
+            } else if let Some(caller_security) = &self.caller_security {
+                caller_security.to_string()
+            } else {
+                self.auth.generate_header_at(self.camera_now().await)
+            };
             format!(
                 r#"<?xml version="1.0" encoding="UTF-8"?>
 <SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope" xmlns:tds="http://www.onvif.org/ver10/device/wsdl" xmlns:trt="http://www.onvif.org/ver10/media/wsdl" xmlns:tev="http://www.onvif.org/ver10/events/wsdl" xmlns:tt="http://www.onvif.org/ver10/schema" xmlns:wsse="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd" xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd">
 <SOAP-ENV:Header>
-{}
+{}{}{}
 </SOAP-ENV:Header>
 <SOAP-ENV:Body>
 {}
 </SOAP-ENV:Body>
 </SOAP-ENV:Envelope>"#,
-                security_header, soap_body
+                security_header, custom_headers, extra_headers, soap_body
             )
         } else {
             // Create SOAP envelope without WS-Security header (for subscription endpoints)
             format!(
                 r#"<?xml version="1.0" encoding="UTF-8"?>
 <SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope" xmlns:tds="http://www.onvif.org/ver10/device/wsdl" xmlns:trt="http://www.onvif.org/ver10/media/wsdl" xmlns:tev="http://www.onvif.org/ver10/events/wsdl" xmlns:tt="http://www.onvif.org/ver10/schema">
-<SOAP-ENV:Body>
+{}<SOAP-ENV:Body>
 {}
 </SOAP-ENV:Body>
 </SOAP-ENV:Envelope>"#,
+                if custom_headers.is_empty() { String::new() } else { format!("<SOAP-ENV:Header>\n{}\n</SOAP-ENV:Header>\n", custom_headers) },
                 soap_body
             )
         };
 
-        tracing::trace!("Sending SOAP request to {} (auth={}): {}", url, use_auth, soap_request);
+        tracing::trace!("Sending SOAP request to {} (auth={}): {}", url, use_auth, redact_secrets(&soap_request));
 
-        let response = self
-            .http_client
-            .post(&url)
-            .header("Content-Type", "application/soap+xml; charset=utf-8")
-            .body(soap_request)
-            .send()
-            .await
-            .context("Failed to send SOAP request to camera")?;
-
-        let status = response.status();
-        let response_text = response
-            .text()
-            .await
-            .context("Failed to read response from camera")?;
+        // A request that never connected can always be sent again. One that timed out or got a
+        // 5xx may still have been carried out, so only queries are repeated then
+        let idempotent = is_idempotent(action);
+        let mut attempt = 0;
+        let (status, response_text) = loop {
+            let retry = attempt < self.config.max_retries;
+            let response = match self.exchange(&url, &soap_request).await {
+                Ok(response) => response,
+                Err(e) if retry && (e.is_connect() || (idempotent && e.is_timeout())) => {
+                    tracing::debug!("Request to camera {} failed ({}), retrying", self.config.id, e.without_url());
+                    self.retry_backoff(&mut attempt).await;
+                    continue;
+                }
+                Err(e) => return Err(self.send_failed(e).await),
+            };
+
+            // Any HTTP answer (even an error status) means the camera is reachable
+            self.health.write().await.record_success();
+            self.report_success();
+
+            let status = response.status();
+            let response_text = response
+                .text()
+                .await
+                .context("Failed to read response from camera")?;
+
+            // A fault is the camera's answer, not a transient failure
+            if retry && idempotent && status.is_server_error() && !soap::is_fault(&response_text) {
+                tracing::debug!("Camera {} returned {}, retrying", self.config.id, status);
+                self.retry_backoff(&mut attempt).await;
+                continue;
+            }
+            break (status, response_text);
+        };
 
         if !status.is_success() {
             tracing::warn!("Camera returned error status {}: {}", status, response_text);
@@ -90,6 +237,138 @@ impl CameraClient {
         Ok(response_text)
     }
 
+    /// POST a SOAP request, answering a Digest challenge once: firmware that wants HTTP Digest on
+    /// a service answers 401 with one
+    async fn exchange(&self, url: &str, soap_request: &str) -> reqwest::Result<reqwest::Response> {
+        let response = self.post_soap(url, soap_request.to_string(), None).await?;
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let challenge = response
+            .headers()
+            .get_all(reqwest::header::WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(DigestChallenge::parse);
+        let Some(challenge) = challenge else {
+            return Ok(response);
+        };
+        let uri = response.url().path().to_string();
+        let cnonce = uuid::Uuid::new_v4().simple().to_string();
+        let authorization = challenge.authorization(&self.config.username, &self.config.password, "POST", &uri, &cnonce[..16]);
+        tracing::trace!("Camera {} sent a Digest challenge for {}, retrying with HTTP Digest", self.config.id, uri);
+        self.post_soap(url, soap_request.to_string(), Some(authorization)).await
+    }
+
+    /// Sleep before retry number `attempt + 1`, counting it
+    async fn retry_backoff(&self, attempt: &mut u32) {
+        tokio::time::sleep(RETRY_BACKOFF * 2u32.saturating_pow(*attempt)).await;
+        *attempt += 1;
+    }
+
+    /// GET `url` (e.g. the camera's snapshot URL) with the camera's credentials: HTTP Basic,
+    /// or HTTP Digest when the camera answers with a Digest challenge
+    pub async fn get_with_credentials(&self, url: &str) -> Result<reqwest::Response> {
+        let basic = || self.http_client.get(url).basic_auth(&self.config.username, Some(&self.config.password));
+        let response = match basic().send().await {
+            Ok(response) => response,
+            Err(e) => return Err(self.send_failed(e).await),
+        };
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let challenge = response
+            .headers()
+            .get_all(reqwest::header::WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(DigestChallenge::parse);
+        let Some(challenge) = challenge else {
+            return Ok(response);
+        };
+        let uri = match response.url().query() {
+            Some(query) => format!("{}?{}", response.url().path(), query),
+            None => response.url().path().to_string(),
+        };
+        let cnonce = uuid::Uuid::new_v4().simple().to_string();
+        let authorization = challenge.authorization(&self.config.username, &self.config.password, "GET", &uri, &cnonce[..16]);
+        match self.http_client.get(url).header(reqwest::header::AUTHORIZATION, authorization).send().await {
+            Ok(response) => Ok(response),
+            Err(e) => Err(self.send_failed(e).await),
+        }
+    }
+
+    async fn post_soap(&self, url: &str, soap_request: String, authorization: Option<String>) -> reqwest::Result<reqwest::Response> {
+        let mut request = self
+            .http_client
+            .post(url)
+            .header("Content-Type", "application/soap+xml; charset=utf-8");
+        if let Some(authorization) = authorization {
+            request = request.header(reqwest::header::AUTHORIZATION, authorization);
+        }
+        request.body(soap_request).send().await
+    }
+
+    /// Record a request that got no answer, and the error to return for it
+    async fn send_failed(&self, e: reqwest::Error) -> anyhow::Error {
+        self.health.write().await.record_failure(e.to_string());
+        // Without the URL, so the same failure on different services is collapsed
+        let url = e.url().cloned();
+        let e = anyhow::Error::from(e.without_url());
+        self.report_error(&format!("{:#}", e));
+        let e = match url {
+            Some(url) => e.context(format!("POST {}", url)),
+            None => e,
+        };
+        e.context("Failed to send SOAP request to camera")
+    }
+
+    /// Log a failure talking to the camera, collapsing repeats while it stays down
+    pub fn report_error(&self, error: &str) {
+        let line = self.error_log.lock().unwrap().record_error(&self.config.id, error, Instant::now());
+        if let Some(line) = line {
+            tracing::warn!("{}", line);
+        }
+    }
+
+    /// Note a successful exchange; logs once if the camera had been failing
+    pub fn report_success(&self) {
+        let line = self.error_log.lock().unwrap().record_success(&self.config.id, Instant::now());
+        if let Some(line) = line {
+            tracing::info!("{}", line);
+        }
+    }
+
+    pub async fn record_deadline_exceeded(&self) {
+        self.health.write().await.deadline_exceeded += 1;
+    }
+
+    pub async fn record_motion(&self) {
+        self.health.write().await.last_motion = Some(chrono::Utc::now());
+    }
+
+    /// The camera's cached CGI login token, see `reolink_cgi::send_with_token`
+    pub fn cgi_token(&self) -> &RwLock<Option<CgiToken>> {
+        &self.cgi_token
+    }
+
+    /// The camera's profiles from its last GetProfiles, see `MediaService::find_profile`
+    pub fn profiles(&self) -> &RwLock<Option<Arc<Vec<Profile>>>> {
+        &self.profiles
+    }
+
+    /// The camera's client for the Reolink CGI API, shared by every CGI call
+    pub fn cgi_client(&self) -> &Client {
+        &self.cgi_client
+    }
+
+    /// Tokens rewritten for the camera's `client_profile`, see `TokenMap`
+    pub fn client_tokens(&self) -> std::sync::MutexGuard<'_, TokenMap> {
+        self.client_tokens.lock().unwrap()
+    }
+
     pub fn camera_id(&self) -> &str {
         &self.config.id
     }
@@ -97,4 +376,296 @@ impl CameraClient {
     pub fn config(&self) -> &CameraConfig {
         &self.config
     }
+
+    /// The camera's translation `rules`
+    pub fn rules(&self) -> &RuleEngine {
+        &self.rules
+    }
+
+    pub async fn health(&self) -> CameraHealth {
+        self.health.read().await.clone()
+    }
+
+    /// Wait for a request slot when `max_concurrent_requests` is set; for traffic to the
+    /// camera that doesn't go through `send_soap_request` (e.g. CGI polling)
+    pub async fn acquire_slot(&self, priority: Priority) -> Option<QueuePermit> {
+        match &self.queue {
+            Some(queue) => Some(queue.acquire(priority).await),
+            None => None,
+        }
+    }
+
+    pub fn queue_stats(&self) -> Option<QueueStats> {
+        self.queue.as_ref().map(RequestQueue::stats)
+    }
+
+    /// Re-resolve a hostname address and record the result, logging when the camera moved.
+    /// Requests resolve on each new connection anyway; this makes address changes visible.
+    pub async fn refresh_resolved_addrs(&self) {
+        let host = self.config.lookup_host();
+        if host.parse::<std::net::IpAddr>().is_ok() || self.config.is_simulated() {
+            return;
+        }
+
+        let port = self.config.port().unwrap_or(80);
+        let mut addrs: Vec<String> = match tokio::net::lookup_host((host, port)).await {
+            Ok(addrs) => addrs.map(|addr| addr.ip().to_string()).collect(),
+            Err(e) => {
+                tracing::debug!("DNS lookup for camera {} ({}) failed: {}", self.config.id, host, e);
+                return;
+            }
+        };
+        addrs.sort();
+        addrs.dedup();
+
+        let mut health = self.health.write().await;
+        if !health.resolved_addrs.is_empty() && health.resolved_addrs != addrs {
+            tracing::info!(
+                "Camera {} ({}) now resolves to {:?} (was {:?})",
+                self.config.id,
+                host,
+                addrs,
+                health.resolved_addrs
+            );
+        }
+        health.resolved_addrs = addrs;
+    }
+
+    pub async fn learned_state(&self) -> LearnedState {
+        self.learned.read().await.clone()
+    }
+
+    pub async fn set_learned_state(&self, state: LearnedState) {
+        *self.learned.write().await = state;
+    }
+
+    pub async fn update_learned_state(&self, update: impl FnOnce(&mut LearnedState)) {
+        update(&mut *self.learned.write().await);
+    }
+
+    /// Stable UUID for this camera, generated on first use and persisted with the learned state
+    pub async fn device_uuid(&self) -> String {
+        let mut learned = self.learned.write().await;
+        learned
+            .device_uuid
+            .get_or_insert_with(|| uuid::Uuid::new_v4().to_string())
+            .clone()
+    }
+
+    /// Force motion on a simulated camera for the given duration
+    pub async fn trigger_simulated_motion(&self, duration: chrono::Duration) {
+        *self.motion_triggered_until.write().await = Some(Utc::now() + duration);
+    }
+
+    pub async fn simulated_motion_active(&self) -> bool {
+        SimulatedCamera::motion_active(Utc::now(), *self.motion_triggered_until.read().await)
+    }
+}
+
+/// Whether sending `action` twice does no more than sending it once: the `Get*` queries, unlike
+/// reboots, clock changes, PTZ moves or `Create*`/`Delete*`
+fn is_idempotent(action: &str) -> bool {
+    action.starts_with("Get")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Router};
+
+    #[tokio::test]
+    async fn test_digest_fallback() {
+        use axum::http::{header, HeaderMap, StatusCode};
+        use axum::response::IntoResponse;
+
+        // Mock camera demanding HTTP Digest, recording each Authorization it gets
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        let mock = Router::new().route(
+            "/onvif/device_service",
+            post(move |headers: HeaderMap| {
+                let recorded = recorded.clone();
+                async move {
+                    let authorization = headers.get(header::AUTHORIZATION).map(|v| v.to_str().unwrap().to_string());
+                    recorded.lock().unwrap().push(authorization.clone());
+                    match authorization {
+                        Some(authorization) if authorization.starts_with("Digest ") => "<tds:GetDeviceInformationResponse/>".into_response(),
+                        _ => (
+                            StatusCode::UNAUTHORIZED,
+                            [(header::WWW_AUTHENTICATE, r#"Digest realm="IPC", qop="auth", nonce="abc123", opaque="xyz""#)],
+                            "",
+                        )
+                            .into_response(),
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await.unwrap() });
+
+        let camera = CameraClient::new(CameraConfig {
+            id: "cam1".to_string(),
+            address: address.to_string(),
+            username: "admin".to_string(),
+            password: "secret".to_string(),
+            ..Default::default()
+        });
+        let response = camera.send_soap_request("/onvif/device_service", "<tds:GetDeviceInformation/>").await.unwrap();
+        assert!(response.contains("GetDeviceInformationResponse"));
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0], None);
+        let authorization = seen[1].as_deref().unwrap();
+        for part in [r#"username="admin""#, r#"realm="IPC""#, r#"nonce="abc123""#, r#"uri="/onvif/device_service""#, "qop=auth", r#"opaque="xyz""#] {
+            assert!(authorization.contains(part), "{}", authorization);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        use axum::http::StatusCode;
+        use axum::response::IntoResponse;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Mock camera answering 503 to the first two requests, and a fault on the fault service
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+        let faults = Arc::new(AtomicUsize::new(0));
+        let counted_faults = faults.clone();
+        let mock = Router::new()
+            .route(
+                "/onvif/device_service",
+                post(move || {
+                    let attempt = counted.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        match attempt {
+                            0 | 1 => (StatusCode::SERVICE_UNAVAILABLE, "busy").into_response(),
+                            _ => "<tds:GetDeviceInformationResponse/>".into_response(),
+                        }
+                    }
+                }),
+            )
+            .route(
+                "/onvif/fault_service",
+                post(move || {
+                    counted_faults.fetch_add(1, Ordering::SeqCst);
+                    async { (StatusCode::INTERNAL_SERVER_ERROR, soap::build_fault(soap::FaultCode::Receiver, "ActionFailed", "no")).into_response() }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await.unwrap() });
+
+        let camera = CameraClient::new(CameraConfig {
+            id: "cam1".to_string(),
+            address: address.to_string(),
+            max_retries: 3,
+            ..Default::default()
+        });
+        let response = camera.send_soap_request("/onvif/device_service", "<tds:GetDeviceInformation/>").await.unwrap();
+        assert!(response.contains("GetDeviceInformationResponse"));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        camera.send_soap_request("/onvif/fault_service", "<tds:GetDeviceInformation/>").await.unwrap();
+        assert_eq!(faults.load(Ordering::SeqCst), 1);
+
+        // A 503 may come after the camera acted, so only queries are repeated
+        requests.store(0, Ordering::SeqCst);
+        let response = camera.send_soap_request("/onvif/device_service", "<tds:SystemReboot/>").await.unwrap();
+        assert!(response.contains("busy"));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Without retries the first 503 is the answer
+        requests.store(0, Ordering::SeqCst);
+        let camera = CameraClient::new(CameraConfig {
+            id: "cam1".to_string(),
+            address: address.to_string(),
+            ..Default::default()
+        });
+        let response = camera.send_soap_request("/onvif/device_service", "<tds:GetDeviceInformation/>").await.unwrap();
+        assert!(response.contains("busy"));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_credential_passthrough() {
+        use crate::onvif::auth::WsSecurityAuth;
+        use crate::onvif::soap::SoapEnvelope;
+
+        // Mock camera echoing each request envelope back
+        let mock = Router::new().route("/onvif/device_service", post(|body: String| async move { body }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await.unwrap() });
+
+        let caller_token = WsSecurityAuth::new("nvr-user".to_string(), "camera-password".to_string()).generate_header();
+        let inbound = format!(
+            r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:wsse="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd" xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd"><s:Header>{}</s:Header><s:Body><tds:GetDeviceInformation xmlns:tds="http://www.onvif.org/ver10/device/wsdl"/></s:Body></s:Envelope>"#,
+            caller_token
+        );
+        let envelope = SoapEnvelope::parse(&inbound).unwrap();
+        let token = envelope.security().unwrap();
+
+        let camera = |credential_mode: CredentialMode| {
+            CameraClient::new(CameraConfig {
+                id: "cam1".to_string(),
+                address: address.to_string(),
+                username: "proxy-user".to_string(),
+                password: "secret".to_string(),
+                credential_mode,
+                ..Default::default()
+            })
+        };
+
+        let passthrough = camera(CredentialMode::Passthrough);
+        let sent = passthrough.send_soap_request_with_header("/onvif/device_service", "<tds:GetDeviceInformation/>", Some(token)).await.unwrap();
+        assert!(sent.contains(&token.xml), "{}", sent);
+        assert!(sent.contains(caller_token.trim_end_matches("</wsse:Security>").split_once('>').unwrap().1));
+        assert!(!sent.contains("proxy-user"));
+        // Without a caller token the proxy's own is sent
+        let sent = passthrough.send_soap_request_with_header("/onvif/device_service", "<tds:GetDeviceInformation/>", None).await.unwrap();
+        assert!(sent.contains("<wsse:Username>proxy-user</wsse:Username>"));
+
+        let sent = camera(CredentialMode::Proxy).send_soap_request_with_header("/onvif/device_service", "<tds:GetDeviceInformation/>", Some(token)).await.unwrap();
+        assert!(sent.contains("<wsse:Username>proxy-user</wsse:Username>"));
+        assert!(!sent.contains("nvr-user"));
+    }
+
+    #[tokio::test]
+    async fn test_custom_soap_headers() {
+        // Mock camera echoing each request envelope back
+        let mock = Router::new().route("/onvif/device_service", post(|body: String| async move { body }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await.unwrap() });
+
+        let camera = |omit_security_header: bool| {
+            CameraClient::new(CameraConfig {
+                id: "cam1".to_string(),
+                address: address.to_string(),
+                username: "admin".to_string(),
+                password: "secret".to_string(),
+                soap_headers: vec![r#"<v:Token xmlns:v="urn:vendor">abc</v:Token>"#.to_string()],
+                omit_security_header,
+                ..Default::default()
+            })
+        };
+        let body = "<tds:GetDeviceInformation/>";
+        let header = |request: &str| {
+            let envelope = soap::SoapEnvelope::parse(request).unwrap();
+            envelope._header.unwrap().blocks.iter().map(|block| block.name.clone()).collect::<Vec<_>>()
+        };
+
+        let sent = camera(false).send_soap_request("/onvif/device_service", body).await.unwrap();
+        assert_eq!(header(&sent), ["Security", "Token"]);
+
+        let sent = camera(true).send_soap_request("/onvif/device_service", body).await.unwrap();
+        assert_eq!(header(&sent), ["Token"]);
+
+        // Requests without WS-Security still carry them
+        let sent = camera(false).send_soap_request_no_auth("/onvif/device_service", body).await.unwrap();
+        assert_eq!(header(&sent), ["Token"]);
+    }
 }
@@ -0,0 +1,135 @@
+use md5::{Digest, Md5};
+
+/// A `WWW-Authenticate: Digest` challenge (RFC 2617), which some firmware sends on the device
+/// service instead of honoring WS-Security
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestChallenge {
+    pub realm: String,
+    pub nonce: String,
+    /// Set when the camera offers `qop="auth"`; `auth-int` alone is not supported
+    pub qop: Option<String>,
+    pub opaque: Option<String>,
+    pub algorithm: Option<String>,
+}
+
+impl DigestChallenge {
+    /// Parse a `WWW-Authenticate` value; None for other schemes or a challenge without a nonce
+    pub fn parse(header: &str) -> Option<Self> {
+        let (scheme, params) = header.trim().split_once(char::is_whitespace)?;
+        if !scheme.eq_ignore_ascii_case("digest") {
+            return None;
+        }
+
+        let mut challenge = DigestChallenge {
+            realm: String::new(),
+            nonce: String::new(),
+            qop: None,
+            opaque: None,
+            algorithm: None,
+        };
+        for (name, value) in split_params(params) {
+            match name.to_ascii_lowercase().as_str() {
+                "realm" => challenge.realm = value,
+                "nonce" => challenge.nonce = value,
+                "qop" => {
+                    challenge.qop = value
+                        .split(',')
+                        .map(str::trim)
+                        .find(|qop| qop.eq_ignore_ascii_case("auth"))
+                        .map(str::to_string)
+                }
+                "opaque" => challenge.opaque = Some(value),
+                "algorithm" => challenge.algorithm = Some(value),
+                _ => {}
+            }
+        }
+
+        // Only MD5 is implemented
+        let md5 = challenge.algorithm.as_deref().is_none_or(|algorithm| algorithm.eq_ignore_ascii_case("md5"));
+        (!challenge.nonce.is_empty() && md5).then_some(challenge)
+    }
+
+    /// The `Authorization` header answering this challenge for one request
+    pub fn authorization(&self, username: &str, password: &str, method: &str, uri: &str, cnonce: &str) -> String {
+        let ha1 = md5_hex(&format!("{}:{}:{}", username, self.realm, password));
+        let ha2 = md5_hex(&format!("{}:{}", method, uri));
+        // Each challenge answers a single request, so the nonce count is always 1
+        let nc = "00000001";
+
+        let mut header = match &self.qop {
+            Some(qop) => {
+                let response = md5_hex(&format!("{}:{}:{}:{}:{}:{}", ha1, self.nonce, nc, cnonce, qop, ha2));
+                format!(
+                    r#"Digest username="{}", realm="{}", nonce="{}", uri="{}", qop={}, nc={}, cnonce="{}", response="{}""#,
+                    username, self.realm, self.nonce, uri, qop, nc, cnonce, response
+                )
+            }
+            None => {
+                let response = md5_hex(&format!("{}:{}:{}", ha1, self.nonce, ha2));
+                format!(
+                    r#"Digest username="{}", realm="{}", nonce="{}", uri="{}", response="{}""#,
+                    username, self.realm, self.nonce, uri, response
+                )
+            }
+        };
+        if let Some(opaque) = &self.opaque {
+            header.push_str(&format!(r#", opaque="{}""#, opaque));
+        }
+        if let Some(algorithm) = &self.algorithm {
+            header.push_str(&format!(", algorithm={}", algorithm));
+        }
+        header
+    }
+}
+
+/// `name=value` pairs of a challenge, values optionally quoted (quoted values may hold commas)
+fn split_params(params: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut rest = params.trim();
+
+    while let Some((name, after)) = rest.split_once('=') {
+        let name = name.trim().trim_start_matches(',').trim().to_string();
+        let after = after.trim_start();
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (quoted[..end].to_string(), quoted.get(end + 1..).unwrap_or(""))
+            }
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            }
+        };
+        pairs.push((name, value));
+        rest = remaining.trim_start().trim_start_matches(',');
+    }
+    pairs
+}
+
+fn md5_hex(value: &str) -> String {
+    Md5::digest(value.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc2617_example() {
+        let challenge = DigestChallenge::parse(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        )
+        .unwrap();
+        assert_eq!(challenge.realm, "testrealm@host.com");
+        assert_eq!(challenge.qop.as_deref(), Some("auth"));
+
+        let authorization = challenge.authorization("Mufasa", "Circle Of Life", "GET", "/dir/index.html", "0a4f113b");
+        assert_eq!(
+            authorization,
+            r#"Digest username="Mufasa", realm="testrealm@host.com", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", uri="/dir/index.html", qop=auth, nc=00000001, cnonce="0a4f113b", response="6629fae49393a05397450978507c4ef1", opaque="5ccc069c403ebaf9f0171e9517f40e41""#
+        );
+
+        assert_eq!(DigestChallenge::parse(r#"Basic realm="camera""#), None);
+        assert_eq!(DigestChallenge::parse(r#"Digest realm="camera", nonce="abc", algorithm=SHA-256"#), None);
+    }
+}
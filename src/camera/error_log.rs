@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How often a summary of suppressed repeats is logged during an outage
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Repeats {
+    /// Occurrences since the last line was logged for this error
+    suppressed: u64,
+    last_logged: Instant,
+}
+
+struct Outage {
+    started: Instant,
+    attempts: u64,
+    /// Keyed by error text, so errors from different pollers don't reset each other
+    errors: HashMap<String, Repeats>,
+}
+
+/// Collapses repeated identical errors from one camera, so a camera that is down doesn't
+/// log every failed poll. Each distinct error is logged once, then summarized once a minute,
+/// and a single line is logged when the camera recovers.
+#[derive(Default)]
+pub struct ErrorLog {
+    outage: Option<Outage>,
+}
+
+impl ErrorLog {
+    /// Record a failure; returns the line to log, if any
+    pub fn record_error(&mut self, camera_id: &str, error: &str, now: Instant) -> Option<String> {
+        let outage = self.outage.get_or_insert_with(|| Outage {
+            started: now,
+            attempts: 0,
+            errors: HashMap::new(),
+        });
+        outage.attempts += 1;
+
+        let Some(repeats) = outage.errors.get_mut(error) else {
+            outage.errors.insert(error.to_string(), Repeats { suppressed: 0, last_logged: now });
+            return Some(format!("Camera {} unreachable: {}", camera_id, error));
+        };
+
+        repeats.suppressed += 1;
+        let elapsed = now.duration_since(repeats.last_logged);
+        if elapsed < SUMMARY_INTERVAL {
+            return None;
+        }
+
+        let line = format!(
+            "Camera {} unreachable: {} ({} occurrences in last {}s)",
+            camera_id,
+            error,
+            repeats.suppressed,
+            elapsed.as_secs()
+        );
+        repeats.suppressed = 0;
+        repeats.last_logged = now;
+        Some(line)
+    }
+
+    /// Record a success; returns a recovery line if the camera was failing
+    pub fn record_success(&mut self, camera_id: &str, now: Instant) -> Option<String> {
+        let outage = self.outage.take()?;
+        Some(format!(
+            "Camera {} recovered after {} failed attempts over {}s",
+            camera_id,
+            outage.attempts,
+            now.duration_since(outage.started).as_secs()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeats_collapsed_until_recovery() {
+        let mut log = ErrorLog::default();
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        assert_eq!(log.record_success("cam1", at(0)), None);
+        assert_eq!(log.record_error("cam1", "connection refused", at(0)).as_deref(), Some("Camera cam1 unreachable: connection refused"));
+        for i in 1..60 {
+            assert_eq!(log.record_error("cam1", "connection refused", at(i)), None);
+        }
+        assert_eq!(
+            log.record_error("cam1", "connection refused", at(60)).as_deref(),
+            Some("Camera cam1 unreachable: connection refused (60 occurrences in last 60s)")
+        );
+
+        // A different error is reported straight away, without resetting the first one
+        assert!(log.record_error("cam1", "timed out", at(61)).is_some());
+        assert_eq!(log.record_error("cam1", "timed out", at(62)), None);
+        assert_eq!(log.record_error("cam1", "connection refused", at(62)), None);
+
+        assert_eq!(
+            log.record_success("cam1", at(90)).as_deref(),
+            Some("Camera cam1 recovered after 64 failed attempts over 90s")
+        );
+        assert_eq!(log.record_success("cam1", at(91)), None);
+    }
+}
@@ -0,0 +1,55 @@
+use chrono::{DateTime, Utc};
+
+/// Reachability of a camera, updated by every request and by the optional keepalive ping
+#[derive(Debug, Clone, Default)]
+pub struct CameraHealth {
+    /// `None` until the first request or keepalive completes
+    pub online: Option<bool>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    /// Requests answered with a timeout fault because of `proxy.request_deadline_secs`
+    pub deadline_exceeded: u64,
+    /// Last time motion started, as seen by the motion-state poller
+    pub last_motion: Option<DateTime<Utc>>,
+    /// Addresses the camera's host resolved to at the last keepalive (hostname addresses only)
+    pub resolved_addrs: Vec<String>,
+}
+
+impl CameraHealth {
+    pub fn record_success(&mut self) {
+        self.online = Some(true);
+        self.last_success = Some(Utc::now());
+        self.consecutive_failures = 0;
+    }
+
+    pub fn record_failure(&mut self, error: String) {
+        self.online = Some(false);
+        self.last_failure = Some(Utc::now());
+        self.last_error = Some(error);
+        self.consecutive_failures += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failures_reset_on_success() {
+        let mut health = CameraHealth::default();
+        assert_eq!(health.online, None);
+
+        health.record_failure("connection refused".to_string());
+        health.record_failure("connection refused".to_string());
+        assert_eq!(health.online, Some(false));
+        assert_eq!(health.consecutive_failures, 2);
+
+        health.record_success();
+        assert_eq!(health.online, Some(true));
+        assert_eq!(health.consecutive_failures, 0);
+        // The last error is kept for diagnostics
+        assert_eq!(health.last_error.as_deref(), Some("connection refused"));
+    }
+}
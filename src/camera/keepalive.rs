@@ -0,0 +1,50 @@
+use std::time::Duration;
+use uuid::Uuid;
+use crate::camera::CameraClient;
+use crate::onvif::device::DeviceService;
+
+/// Ping the camera with GetSystemDateAndTime every `interval_secs` so its health state
+/// reflects current reachability even when no client is connected
+pub async fn run_keepalive(camera: CameraClient, interval_secs: u64) {
+    let interval = Duration::from_secs(interval_secs.max(1));
+
+    // Spread cameras out so they aren't all pinged at the same instant
+    tokio::time::sleep(jitter(interval)).await;
+
+    loop {
+        camera.refresh_resolved_addrs().await;
+
+        // Success/failure is recorded on the client's health state by the request itself
+        match DeviceService::get_system_date_and_time(&camera).await {
+            Ok(_) => tracing::trace!("Keepalive OK for camera {}", camera.camera_id()),
+            Err(e) => tracing::debug!("Keepalive failed for camera {}: {}", camera.camera_id(), e),
+        }
+
+        // Sleep interval +/- 10%
+        tokio::time::sleep(interval - interval / 10 + jitter(interval / 5)).await;
+    }
+}
+
+fn jitter(max: Duration) -> Duration {
+    let max_ms = max.as_millis() as u64;
+    if max_ms == 0 {
+        return Duration::ZERO;
+    }
+
+    let random = Uuid::new_v4().as_u128() as u64;
+    Duration::from_millis(random % max_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jitter_bounds() {
+        assert_eq!(jitter(Duration::ZERO), Duration::ZERO);
+
+        for _ in 0..100 {
+            assert!(jitter(Duration::from_secs(2)) < Duration::from_secs(2));
+        }
+    }
+}
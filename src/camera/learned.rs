@@ -0,0 +1,106 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::camera::CameraManager;
+
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Facts about a camera discovered at runtime, kept across restarts via `proxy.state_path`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LearnedState {
+    /// Camera clock minus proxy clock, from the last GetSystemDateAndTime
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_skew_secs: Option<i64>,
+    /// Stable identity presented for this camera, generated once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_uuid: Option<String>,
+    /// Service namespaces from the last GetServices
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<String>,
+}
+
+/// On-disk format: learned state keyed by camera id
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StateFile {
+    cameras: BTreeMap<String, LearnedState>,
+}
+
+pub fn load(path: &Path) -> Result<BTreeMap<String, LearnedState>> {
+    let contents = std::fs::read_to_string(path).context("Failed to read state file")?;
+    let file: StateFile = serde_json::from_str(&contents).context("Failed to parse state file")?;
+    Ok(file.cameras)
+}
+
+pub fn save(path: &Path, cameras: BTreeMap<String, LearnedState>) -> Result<()> {
+    let json = serde_json::to_string_pretty(&StateFile { cameras })?;
+
+    // Write-then-rename so a crash mid-write never leaves a truncated file
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, json).context("Failed to write state file")?;
+    std::fs::rename(&tmp, path).context("Failed to replace state file")?;
+    Ok(())
+}
+
+/// Restore learned state for configured cameras from `path`, if it exists
+pub async fn restore(manager: &CameraManager, path: &Path) {
+    if !path.exists() {
+        return;
+    }
+
+    match load(path) {
+        Ok(cameras) => {
+            let restored = manager.import_learned_state(cameras).await;
+            tracing::info!("Restored learned state for {} cameras from {}", restored, path.display());
+        }
+        Err(e) => tracing::warn!("Ignoring state file {}: {:#}", path.display(), e),
+    }
+}
+
+/// Periodically write learned state to `path` whenever it has changed
+pub async fn run_persistence(manager: CameraManager, path: PathBuf) {
+    let mut last_saved = None;
+
+    loop {
+        tokio::time::sleep(SAVE_INTERVAL).await;
+
+        let state = manager.export_learned_state().await;
+        if last_saved.as_ref() == Some(&state) {
+            continue;
+        }
+
+        match save(&path, state.clone()) {
+            Ok(()) => {
+                tracing::debug!("Saved learned state to {}", path.display());
+                last_saved = Some(state);
+            }
+            Err(e) => tracing::warn!("Failed to save learned state to {}: {:#}", path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("onvif-proxy-state-{}.json", uuid::Uuid::new_v4()));
+        let mut cameras = BTreeMap::new();
+        cameras.insert(
+            "cam1".to_string(),
+            LearnedState {
+                clock_skew_secs: Some(-42),
+                device_uuid: Some("5f0c1a2b-0000-4000-8000-000000000001".to_string()),
+                services: vec!["http://www.onvif.org/ver10/media/wsdl".to_string()],
+            },
+        );
+
+        save(&path, cameras.clone()).unwrap();
+        let loaded = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, cameras);
+    }
+}
@@ -1,39 +1,126 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use crate::camera::{CameraClient, CameraConfig};
+use tokio::task::JoinHandle;
+use crate::camera::{keepalive, CameraClient, CameraConfig};
+use crate::camera::learned::LearnedState;
 
 pub struct CameraManager {
     cameras: Arc<RwLock<HashMap<String, CameraClient>>>,
+    keepalive_tasks: Arc<RwLock<HashMap<String, JoinHandle<()>>>>,
 }
 
 impl CameraManager {
     pub fn new() -> Self {
         Self {
             cameras: Arc::new(RwLock::new(HashMap::new())),
+            keepalive_tasks: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
     pub async fn add_camera(&self, config: CameraConfig) {
+        let mut cameras = self.cameras.write().await;
+        self.insert_camera(&mut cameras, config).await;
+    }
+
+    /// Add a camera unless its id is taken; false, with nothing changed, when it is. The check
+    /// and the insert happen under one lock, so of two concurrent adds only one succeeds
+    pub async fn add_camera_if_absent(&self, config: CameraConfig) -> bool {
+        let mut cameras = self.cameras.write().await;
+        if cameras.contains_key(&config.id) {
+            return false;
+        }
+        self.insert_camera(&mut cameras, config).await;
+        true
+    }
+
+    async fn insert_camera(&self, cameras: &mut HashMap<String, CameraClient>, config: CameraConfig) {
         let camera_id = config.id.clone();
+        let keepalive_secs = config.keepalive_secs;
         let client = CameraClient::new(config);
 
-        let mut cameras = self.cameras.write().await;
-        cameras.insert(camera_id.clone(), client);
+        cameras.insert(camera_id.clone(), client.clone());
+
+        // Replace any keepalive task left over from a previous client with this id
+        let mut tasks = self.keepalive_tasks.write().await;
+        if let Some(task) = tasks.remove(&camera_id) {
+            task.abort();
+        }
+        if let Some(secs) = keepalive_secs {
+            tracing::debug!("Starting keepalive for camera {} every {}s", camera_id, secs);
+            tasks.insert(camera_id.clone(), tokio::spawn(keepalive::run_keepalive(client, secs)));
+        }
 
         tracing::info!("Added camera: {}", camera_id);
     }
 
+    /// Remove a camera and stop its keepalive; false when there was no such camera
+    pub async fn remove_camera(&self, camera_id: &str) -> bool {
+        let removed = self.cameras.write().await.remove(camera_id).is_some();
+        if let Some(task) = self.keepalive_tasks.write().await.remove(camera_id) {
+            task.abort();
+        }
+        if removed {
+            tracing::info!("Removed camera: {}", camera_id);
+        }
+        removed
+    }
+
     pub async fn get_camera(&self, camera_id: &str) -> Option<CameraClient> {
         let cameras = self.cameras.read().await;
         cameras.get(camera_id).cloned()
     }
+
+    /// Replace a camera's client with a fresh one built from the same config: health, learned
+    /// clock skew and services, and any connection state start over. The device UUID is kept
+    /// so clients still see the same device. Returns the new client.
+    pub async fn refresh_camera(&self, camera_id: &str) -> Option<CameraClient> {
+        let old = self.get_camera(camera_id).await?;
+        let device_uuid = old.learned_state().await.device_uuid;
+
+        self.add_camera(old.config().clone()).await;
+        let client = self.get_camera(camera_id).await?;
+        client.update_learned_state(|state| state.device_uuid = device_uuid).await;
+
+        tracing::info!("Refreshed camera: {}", camera_id);
+        Some(client)
+    }
+
+    /// All cameras, sorted by id
+    pub async fn cameras(&self) -> Vec<CameraClient> {
+        let cameras = self.cameras.read().await;
+        let mut list: Vec<CameraClient> = cameras.values().cloned().collect();
+        list.sort_by(|a, b| a.camera_id().cmp(b.camera_id()));
+        list
+    }
+
+    pub async fn export_learned_state(&self) -> BTreeMap<String, LearnedState> {
+        let mut state = BTreeMap::new();
+        for camera in self.cameras().await {
+            state.insert(camera.camera_id().to_string(), camera.learned_state().await);
+        }
+        state
+    }
+
+    /// Apply previously learned state to configured cameras; returns how many matched
+    pub async fn import_learned_state(&self, state: BTreeMap<String, LearnedState>) -> usize {
+        let cameras = self.cameras.read().await;
+        let mut restored = 0;
+        for (camera_id, learned) in state {
+            if let Some(camera) = cameras.get(&camera_id) {
+                camera.set_learned_state(learned).await;
+                restored += 1;
+            }
+        }
+        restored
+    }
 }
 
 impl Clone for CameraManager {
     fn clone(&self) -> Self {
         Self {
             cameras: Arc::clone(&self.cameras),
+            keepalive_tasks: Arc::clone(&self.keepalive_tasks),
         }
     }
 }
@@ -43,3 +130,62 @@ impl Default for CameraManager {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_refresh_keeps_identity_only() {
+        let manager = CameraManager::new();
+        manager
+            .add_camera(CameraConfig {
+                id: "sim".to_string(),
+                model: "simulated".to_string(),
+                ..Default::default()
+            })
+            .await;
+
+        let old = manager.get_camera("sim").await.unwrap();
+        let uuid = old.device_uuid().await;
+        old.update_learned_state(|state| state.clock_skew_secs = Some(42)).await;
+
+        let refreshed = manager.refresh_camera("sim").await.unwrap();
+        let learned = refreshed.learned_state().await;
+        assert_eq!(learned.device_uuid.as_deref(), Some(uuid.as_str()));
+        assert_eq!(learned.clock_skew_secs, None);
+        assert!(manager.refresh_camera("missing").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_remove_camera() {
+        let manager = CameraManager::new();
+        manager
+            .add_camera(CameraConfig {
+                id: "sim".to_string(),
+                model: "simulated".to_string(),
+                keepalive_secs: Some(60),
+                ..Default::default()
+            })
+            .await;
+
+        assert!(manager.remove_camera("sim").await);
+        assert!(manager.get_camera("sim").await.is_none());
+        assert!(manager.keepalive_tasks.read().await.is_empty());
+        assert!(!manager.remove_camera("sim").await);
+    }
+
+    #[tokio::test]
+    async fn test_add_camera_if_absent() {
+        let manager = CameraManager::new();
+        let config = CameraConfig {
+            id: "sim".to_string(),
+            model: "simulated".to_string(),
+            ..Default::default()
+        };
+
+        assert!(manager.add_camera_if_absent(config.clone()).await);
+        assert!(!manager.add_camera_if_absent(CameraConfig { name: "Other".to_string(), ..config }).await);
+        assert_eq!(manager.get_camera("sim").await.unwrap().config().name, "");
+    }
+}
@@ -1,7 +1,16 @@
 pub mod manager;
 pub mod client;
 pub mod config;
+pub mod digest;
+pub mod error_log;
+pub mod health;
+pub mod keepalive;
+pub mod learned;
+pub mod queue;
+pub mod reolink_cgi;
+pub mod simulated;
 
 pub use manager::CameraManager;
 pub use client::CameraClient;
 pub use config::CameraConfig;
+pub use health::CameraHealth;
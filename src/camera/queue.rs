@@ -0,0 +1,191 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// Actions a user is waiting on (opening a stream in the VMS); these jump the queue
+const INTERACTIVE_ACTIONS: &[&str] = &[
+    "GetStreamUri",
+    "GetSnapshotUri",
+    "GetProfiles",
+    "GetProfile",
+    "GetVideoSources",
+    "GetVideoEncoderConfiguration",
+    "GetVideoEncoderConfigurations",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Interactive,
+    Background,
+}
+
+impl Priority {
+    pub fn for_action(action: &str) -> Self {
+        if INTERACTIVE_ACTIONS.contains(&action) {
+            Priority::Interactive
+        } else {
+            Priority::Background
+        }
+    }
+}
+
+/// How long requests spent waiting for a slot
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueueStats {
+    pub queued_requests: u64,
+    pub total_wait_ms: u64,
+    pub max_wait_ms: u64,
+    pub last_wait_ms: u64,
+}
+
+impl QueueStats {
+    fn record(&mut self, wait: Duration) {
+        let ms = wait.as_millis() as u64;
+        self.queued_requests += 1;
+        self.total_wait_ms += ms;
+        self.max_wait_ms = self.max_wait_ms.max(ms);
+        self.last_wait_ms = ms;
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    in_flight: usize,
+    interactive: VecDeque<oneshot::Sender<QueuePermit>>,
+    background: VecDeque<oneshot::Sender<QueuePermit>>,
+    stats: QueueStats,
+}
+
+/// Per-camera concurrency limit; when all slots are busy, interactive requests
+/// are handed the next free slot before any queued background request
+#[derive(Clone)]
+pub struct RequestQueue {
+    max_concurrent: usize,
+    state: Arc<Mutex<QueueState>>,
+}
+
+/// A request slot, released (or handed to the next waiter) on drop
+pub struct QueuePermit {
+    /// `None` once the slot has been released or passed on
+    state: Option<Arc<Mutex<QueueState>>>,
+}
+
+impl RequestQueue {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            max_concurrent: max_concurrent.max(1),
+            state: Arc::new(Mutex::new(QueueState::default())),
+        }
+    }
+
+    pub async fn acquire(&self, priority: Priority) -> QueuePermit {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if state.in_flight < self.max_concurrent {
+                state.in_flight += 1;
+                return QueuePermit { state: Some(self.state.clone()) };
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            match priority {
+                Priority::Interactive => state.interactive.push_back(sender),
+                Priority::Background => state.background.push_back(sender),
+            }
+            receiver
+        };
+
+        let started = Instant::now();
+        // The sender is only dropped together with the queue state, which we hold a reference to
+        let permit = receiver.await.expect("request queue dropped while waiting");
+        self.state.lock().unwrap().stats.record(started.elapsed());
+        permit
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.state.lock().unwrap().stats.clone()
+    }
+}
+
+impl Drop for QueuePermit {
+    fn drop(&mut self) {
+        let Some(shared) = self.state.take() else {
+            return;
+        };
+        let mut state = shared.lock().unwrap();
+
+        loop {
+            let next = state
+                .interactive
+                .pop_front()
+                .or_else(|| state.background.pop_front());
+
+            let Some(sender) = next else {
+                state.in_flight -= 1;
+                return;
+            };
+
+            // Hand the slot over without touching in_flight; a waiter that gave up
+            // returns the permit here and the next one is tried
+            let permit = QueuePermit { state: Some(shared.clone()) };
+            match sender.send(permit) {
+                Ok(()) => return,
+                Err(mut returned) => {
+                    returned.state = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_interactive_goes_first() {
+        let queue = RequestQueue::new(1);
+        let held = queue.acquire(Priority::Background).await;
+
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        for (name, priority) in [("poll", Priority::Background), ("stream", Priority::Interactive)] {
+            let queue = queue.clone();
+            let order_tx = order_tx.clone();
+            tokio::spawn(async move {
+                let _permit = queue.acquire(priority).await;
+                order_tx.send(name).unwrap();
+            });
+            // Make sure the waiters queue up in this order
+            tokio::task::yield_now().await;
+        }
+
+        drop(held);
+        assert_eq!(order_rx.recv().await, Some("stream"));
+        assert_eq!(order_rx.recv().await, Some("poll"));
+        assert_eq!(queue.stats().queued_requests, 2);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_waiter_releases_slot() {
+        let queue = RequestQueue::new(1);
+        let held = queue.acquire(Priority::Background).await;
+
+        // A waiter that is cancelled before it gets the slot
+        let waiter = tokio::spawn({
+            let queue = queue.clone();
+            async move {
+                let _permit = queue.acquire(Priority::Interactive).await;
+            }
+        });
+        tokio::task::yield_now().await;
+        waiter.abort();
+        let _ = waiter.await;
+
+        drop(held);
+        // The slot must be free again
+        let _permit = tokio::time::timeout(Duration::from_secs(1), queue.acquire(Priority::Background))
+            .await
+            .expect("slot leaked");
+    }
+}
@@ -0,0 +1,417 @@
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::camera::{CameraClient, CameraConfig};
+
+/// A login token is renewed this long before its lease runs out
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+/// `rspCode` of a reply to an expired or unknown token ("please login first")
+const RSP_LOGIN_REQUIRED: i64 = -6;
+
+/// One entry of a Reolink CGI reply, which is always a JSON array of these:
+/// `[{"cmd":"GetMdState","code":0,"value":{"state":1}}]`, or on failure
+/// `[{"cmd":"GetMdState","code":1,"error":{"rspCode":-6,"detail":"please login first"}}]`
+#[derive(Debug, Deserialize)]
+struct CgiResponse<T> {
+    cmd: String,
+    code: i64,
+    value: Option<T>,
+    error: Option<CgiError>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CgiError {
+    rsp_code: i64,
+    #[serde(default)]
+    detail: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MdState {
+    state: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginValue {
+    #[serde(rename = "Token")]
+    token: LoginToken,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoginToken {
+    name: String,
+    lease_time: u64,
+}
+
+/// A `cmd=Login` token, used instead of the password on frequent polls
+#[derive(Debug, Clone)]
+pub struct CgiToken {
+    pub name: String,
+    pub expires_at: Instant,
+}
+
+impl CgiToken {
+    fn is_fresh(&self, now: Instant) -> bool {
+        now + TOKEN_MARGIN < self.expires_at
+    }
+}
+
+/// Image adjustment (`GetImage`/`SetImage`), each setting 0..=255
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Image {
+    #[serde(default)]
+    pub channel: u8,
+    pub bright: u8,
+    pub contrast: u8,
+    pub saturation: u8,
+    pub hue: u8,
+    pub sharpen: u8,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImageValue {
+    #[serde(rename = "Image")]
+    image: Image,
+}
+
+/// The camera's CGI endpoint: `cgi_base_url`, else HTTPS on the camera's host
+pub fn api_url(config: &CameraConfig) -> Result<reqwest::Url> {
+    let base = match &config.cgi_base_url {
+        Some(base) => base.trim_end_matches('/').to_string(),
+        None => format!("https://{}", config.url_host()),
+    };
+    reqwest::Url::parse(&format!("{}/cgi-bin/api.cgi", base)).context("Invalid CGI URL")
+}
+
+/// Send one CGI command to the camera (credentials in the query string as the API expects)
+/// and return the reply text
+pub async fn send_command(config: &CameraConfig, cmd: &str, param: serde_json::Value) -> Result<String> {
+    let mut url = api_url(config)?;
+    url.query_pairs_mut()
+        .append_pair("cmd", cmd)
+        .append_pair("user", &config.username)
+        .append_pair("password", &config.password);
+    post(&http_client()?, url, cmd, param).await
+}
+
+/// Send one CGI command authenticated by the camera's login token, logging in when there is no
+/// fresh token and once more when the camera refuses it
+pub async fn send_with_token(camera: &CameraClient, cmd: &str, param: serde_json::Value) -> Result<String> {
+    let mut refused = false;
+    loop {
+        let token = token(camera, refused).await?.name;
+        let mut url = api_url(camera.config())?;
+        url.query_pairs_mut().append_pair("cmd", cmd).append_pair("token", &token);
+
+        let text = post(camera.cgi_client(), url, cmd, param.clone()).await?;
+        if refused || !is_login_required(&text) {
+            return Ok(text);
+        }
+        tracing::debug!("Camera {} refused its CGI token, logging in again", camera.camera_id());
+        refused = true;
+    }
+}
+
+/// The camera's reply to `cmd=Snap` on its channel, optionally of another stream (`snapType`),
+/// authenticated by the login token, with the image not yet read. The camera answers with JSON
+/// instead of an image on errors, which is returned as an error
+pub async fn snapshot(camera: &CameraClient, snap_type: Option<&str>) -> Result<reqwest::Response> {
+    let mut refused = false;
+    loop {
+        let token = token(camera, refused).await?.name;
+        let mut url = api_url(camera.config())?;
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("cmd", "Snap")
+                .append_pair("channel", &camera.config().channel.to_string())
+                .append_pair("rs", &uuid::Uuid::new_v4().simple().to_string())
+                .append_pair("token", &token);
+            if let Some(snap_type) = snap_type {
+                query.append_pair("snapType", snap_type);
+            }
+        }
+
+        let response = camera.cgi_client().get(url).send().await.map_err(|e| e.without_url())?;
+        let is_image = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("image/"));
+        if is_image || !response.status().is_success() {
+            return Ok(response);
+        }
+
+        let text = response.text().await.map_err(|e| e.without_url())?;
+        if refused || !is_login_required(&text) {
+            bail!("Camera returned no snapshot: {}", text.chars().take(200).collect::<String>());
+        }
+        tracing::debug!("Camera {} refused its CGI token, logging in again", camera.camera_id());
+        refused = true;
+    }
+}
+
+/// The camera's login token, logging in when there is no fresh one; for URLs handed to clients
+/// that must not carry the password
+pub async fn login_token(camera: &CameraClient) -> Result<CgiToken> {
+    token(camera, false).await
+}
+
+/// The cached token, or a new one from `cmd=Login` when it is stale or `refresh` is set
+async fn token(camera: &CameraClient, refresh: bool) -> Result<CgiToken> {
+    let cache = camera.cgi_token();
+    if !refresh {
+        if let Some(token) = cache.read().await.as_ref().filter(|token| token.is_fresh(Instant::now())) {
+            return Ok(token.clone());
+        }
+    }
+
+    // Held while logging in, so concurrent pollers share one login
+    let mut cached = cache.write().await;
+    if let Some(token) = cached.as_ref().filter(|token| !refresh && token.is_fresh(Instant::now())) {
+        return Ok(token.clone());
+    }
+    let token = login(camera).await?;
+    tracing::debug!("Logged in to the CGI API of camera {}", camera.camera_id());
+    *cached = Some(token.clone());
+    Ok(token)
+}
+
+async fn login(camera: &CameraClient) -> Result<CgiToken> {
+    let config = camera.config();
+    let mut url = api_url(config)?;
+    url.query_pairs_mut().append_pair("cmd", "Login");
+    let param = serde_json::json!({ "User": { "Version": "0", "userName": config.username, "password": config.password } });
+    parse_login(&post(camera.cgi_client(), url, "Login", param).await?, Instant::now())
+}
+
+async fn post(client: &reqwest::Client, url: reqwest::Url, cmd: &str, param: serde_json::Value) -> Result<String> {
+    let body = serde_json::json!([{ "cmd": cmd, "action": 0, "param": param }]);
+
+    // Keep the credentials in the query string out of error messages
+    let text = client
+        .post(url)
+        .body(body.to_string())
+        .send()
+        .await
+        .map_err(|e| e.without_url())?
+        .text()
+        .await
+        .map_err(|e| e.without_url())?;
+    Ok(text)
+}
+
+/// A client for the CGI API, as each camera keeps one (see `CameraClient::cgi_client`)
+pub fn http_client() -> Result<reqwest::Client> {
+    // Cameras use self-signed certs
+    Ok(reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .timeout(std::time::Duration::from_secs(5))
+        .build()?)
+}
+
+/// The token in a Login reply, expiring after its `leaseTime`
+fn parse_login(text: &str, now: Instant) -> Result<CgiToken> {
+    let token = parse_value::<LoginValue>(text, "Login")?.token;
+    Ok(CgiToken {
+        name: token.name,
+        expires_at: now + Duration::from_secs(token.lease_time),
+    })
+}
+
+/// Whether a reply says the token is expired or unknown
+fn is_login_required(text: &str) -> bool {
+    serde_json::from_str::<Vec<CgiResponse<serde_json::Value>>>(text)
+        .map(|responses| {
+            responses
+                .iter()
+                .any(|response| response.error.as_ref().is_some_and(|error| error.rsp_code == RSP_LOGIN_REQUIRED))
+        })
+        .unwrap_or(false)
+}
+
+/// The `value` of the reply to `cmd`; a non-zero `code` is returned as an error
+fn parse_value<T: DeserializeOwned>(text: &str, cmd: &str) -> Result<T> {
+    let responses: Vec<CgiResponse<T>> =
+        serde_json::from_str(text).with_context(|| format!("Invalid {} response from camera", cmd))?;
+    let response = responses
+        .into_iter()
+        .find(|response| response.cmd == cmd)
+        .with_context(|| format!("Camera reply has no {} entry", cmd))?;
+
+    if response.code != 0 {
+        match response.error {
+            Some(error) => bail!("{} failed: {} (rspCode {})", cmd, error.detail, error.rsp_code),
+            None => bail!("{} failed with code {}", cmd, response.code),
+        }
+    }
+    response.value.with_context(|| format!("{} reply has no value", cmd))
+}
+
+/// Whether a GetMdState reply reports motion
+pub fn parse_md_state(text: &str) -> Result<bool> {
+    Ok(parse_value::<MdState>(text, "GetMdState")?.state != 0)
+}
+
+/// Alarm state per AI class (`people`, `vehicle`, `dog_cat`, ...) in a GetAiState reply, for the
+/// classes the camera supports. Newer firmware sends `{"alarm_state":1,"support":1}` per class,
+/// older firmware a bare `0`/`1`
+pub fn parse_ai_state(text: &str) -> Result<HashMap<String, bool>> {
+    let value = parse_value::<serde_json::Map<String, serde_json::Value>>(text, "GetAiState")?;
+    let states = value
+        .into_iter()
+        .filter(|(class, _)| class != "channel")
+        .filter_map(|(class, state)| {
+            let active = match &state {
+                serde_json::Value::Number(n) => n.as_i64()? != 0,
+                serde_json::Value::Object(fields) => {
+                    if fields.get("support").and_then(|s| s.as_i64()) == Some(0) {
+                        return None;
+                    }
+                    fields.get("alarm_state")?.as_i64()? != 0
+                }
+                _ => return None,
+            };
+            Some((class, active))
+        })
+        .collect();
+    Ok(states)
+}
+
+/// The settings in a GetImage reply
+pub fn parse_image(text: &str) -> Result<Image> {
+    Ok(parse_value::<ImageValue>(text, "GetImage")?.image)
+}
+
+/// Check the reply to a setter such as SetImage
+pub fn parse_set(text: &str, cmd: &str) -> Result<()> {
+    parse_value::<serde_json::Value>(text, cmd).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Query, routing::post, Router};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_token_reused_across_polls() {
+        // Mock CGI issuing numbered tokens; only the latest is accepted
+        let logins = Arc::new(AtomicUsize::new(0));
+        let issued = logins.clone();
+        let mock = Router::new().route(
+            "/cgi-bin/api.cgi",
+            post(move |Query(query): Query<HashMap<String, String>>, body: String| {
+                let issued = issued.clone();
+                async move {
+                    match query["cmd"].as_str() {
+                        "Login" => {
+                            assert!(body.contains(r#""password":"secret""#));
+                            let n = issued.fetch_add(1, Ordering::SeqCst) + 1;
+                            format!(r#"[{{"cmd":"Login","code":0,"value":{{"Token":{{"leaseTime":3600,"name":"tok{}"}}}}}}]"#, n)
+                        }
+                        _ if query.get("token") == Some(&format!("tok{}", issued.load(Ordering::SeqCst))) => {
+                            assert!(!query.contains_key("password"));
+                            r#"[{"cmd":"GetMdState","code":0,"value":{"state":1}}]"#.to_string()
+                        }
+                        _ => r#"[{"cmd":"GetMdState","code":1,"error":{"detail":"please login first","rspCode":-6}}]"#.to_string(),
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await.unwrap() });
+
+        let camera = CameraClient::new(CameraConfig {
+            id: "cam1".to_string(),
+            address: address.to_string(),
+            username: "admin".to_string(),
+            password: "secret".to_string(),
+            cgi_base_url: Some(format!("http://{}", address)),
+            ..Default::default()
+        });
+        let poll = || async { parse_md_state(&send_with_token(&camera, "GetMdState", serde_json::json!({ "channel": 0 })).await.unwrap()) };
+
+        for _ in 0..3 {
+            assert!(poll().await.unwrap());
+        }
+        assert_eq!(logins.load(Ordering::SeqCst), 1);
+
+        // A token the camera no longer accepts (e.g. after a reboot) is replaced once
+        *camera.cgi_token().write().await = Some(CgiToken { name: "stale".to_string(), expires_at: Instant::now() + Duration::from_secs(3600) });
+        assert!(poll().await.unwrap());
+        assert_eq!(logins.load(Ordering::SeqCst), 2);
+
+        // So is one about to expire
+        camera.cgi_token().write().await.as_mut().unwrap().expires_at = Instant::now() + Duration::from_secs(30);
+        assert!(poll().await.unwrap());
+        assert_eq!(logins.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_parse_login() {
+        let now = Instant::now();
+        let token = parse_login(r#"[{"cmd":"Login","code":0,"value":{"Token":{"leaseTime":3600,"name":"6a4b2c"}}}]"#, now).unwrap();
+        assert_eq!(token.name, "6a4b2c");
+        assert_eq!(token.expires_at, now + Duration::from_secs(3600));
+        assert!(parse_login(r#"[{"cmd":"Login","code":1,"error":{"detail":"login failed","rspCode":-7}}]"#, now).is_err());
+
+        assert!(is_login_required(r#"[{"cmd":"GetMdState","code":1,"error":{"detail":"please login first","rspCode":-6}}]"#));
+        assert!(!is_login_required(r#"[{"cmd":"GetMdState","code":0,"value":{"state":0}}]"#));
+    }
+
+    #[test]
+    fn test_parse_image() {
+        let reply = r#"[{"cmd":"GetImage","code":0,"value":{"Image":{"bright":128,"channel":0,"contrast":120,"hue":128,"saturation":140,"sharpen":128}}}]"#;
+        let image = parse_image(reply).unwrap();
+        assert_eq!((image.bright, image.contrast, image.saturation, image.sharpen), (128, 120, 140, 128));
+
+        assert!(parse_set(r#"[{"cmd":"SetImage","code":0,"value":{"rspCode":200}}]"#, "SetImage").is_ok());
+        assert!(parse_set(r#"[{"cmd":"SetImage","code":1,"error":{"detail":"param error","rspCode":-4}}]"#, "SetImage").is_err());
+    }
+
+    #[test]
+    fn test_parse_ai_state() {
+        let current = r#"[{"cmd":"GetAiState","code":0,"value":{"channel":0,"dog_cat":{"alarm_state":0,"support":1},"face":{"alarm_state":0,"support":0},"people":{"alarm_state":1,"support":1},"vehicle":{"alarm_state":0,"support":1}}}]"#;
+        let states = parse_ai_state(current).unwrap();
+        assert_eq!(states.len(), 3);
+        assert_eq!((states["people"], states["vehicle"], states["dog_cat"]), (true, false, false));
+
+        // Older firmware: a bare state per class
+        let legacy = r#"[{"cmd":"GetAiState","code":0,"value":{"channel":0,"dog_cat":1,"people":0,"vehicle":0}}]"#;
+        let states = parse_ai_state(legacy).unwrap();
+        assert_eq!((states["people"], states["vehicle"], states["dog_cat"]), (false, false, true));
+
+        // Cameras without AI answer with an error
+        let unsupported = r#"[{"cmd":"GetAiState","code":1,"error":{"detail":"not support","rspCode":-9}}]"#;
+        assert!(parse_ai_state(unsupported).is_err());
+    }
+
+    #[test]
+    fn test_parse_md_state() {
+        assert!(parse_md_state(r#"[{"cmd":"GetMdState","code":0,"value":{"state":1}}]"#).unwrap());
+        assert!(!parse_md_state(r#"[{"cmd":"GetMdState","code":0,"value":{"state":0}}]"#).unwrap());
+
+        // Pretty-printed, as newer firmware sends it
+        let spaced = "[\n   {\n      \"cmd\" : \"GetMdState\",\n      \"code\" : 0,\n      \"value\" : {\n         \"state\" : 1\n      }\n   }\n]\n";
+        assert!(parse_md_state(spaced).unwrap());
+
+        let login = r#"[{"cmd":"GetMdState","code":1,"error":{"detail":"please login first","rspCode":-6}}]"#;
+        let error = parse_md_state(login).unwrap_err().to_string();
+        assert_eq!(error, "GetMdState failed: please login first (rspCode -6)");
+
+        // "state" elsewhere in the reply must not count as motion
+        assert!(parse_md_state(r#"[{"cmd":"GetMdState","code":1,"error":{"detail":"\"state\":1","rspCode":-1}}]"#).is_err());
+        assert!(parse_md_state("<html>502 Bad Gateway</html>").is_err());
+        assert!(parse_md_state("[]").is_err());
+    }
+}
@@ -0,0 +1,280 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use crate::camera::CameraConfig;
+use crate::onvif::soap::{self, FaultCode};
+
+/// Motion is simulated for this many seconds at the start of every cycle
+const MOTION_ON_SECS: u32 = 10;
+const MOTION_CYCLE_SECS: u32 = 60;
+
+const NS_TDS: (&str, &str) = ("tds", "http://www.onvif.org/ver10/device/wsdl");
+const NS_TRT: (&str, &str) = ("trt", "http://www.onvif.org/ver10/media/wsdl");
+const NS_TEV: (&str, &str) = ("tev", "http://www.onvif.org/ver10/events/wsdl");
+const NS_TT: (&str, &str) = ("tt", "http://www.onvif.org/ver10/schema");
+const NS_TNS1: (&str, &str) = ("tns1", "http://www.onvif.org/ver10/topics");
+const NS_WSNT: (&str, &str) = ("wsnt", "http://docs.oasis-open.org/wsn/b-2");
+const NS_WSA: (&str, &str) = ("wsa", "http://www.w3.org/2005/08/addressing");
+const NS_WSTOP: (&str, &str) = ("wstop", "http://docs.oasis-open.org/wsn/t-1");
+
+/// Canned, spec-valid answers for `model: simulated` cameras, so the proxy can be
+/// exercised end-to-end without hardware
+pub struct SimulatedCamera;
+
+impl SimulatedCamera {
+    pub fn respond(config: &CameraConfig, soap_body: &str) -> String {
+        let action = soap::body_action(soap_body);
+        let host = config.address.as_str();
+
+        match action {
+            "GetSystemDateAndTime" => Self::system_date_and_time(Utc::now()),
+            "GetDeviceInformation" => soap::build_envelope(
+                &[NS_TDS],
+                &format!(
+                    r#"<tds:GetDeviceInformationResponse>
+  <tds:Manufacturer>Simulated</tds:Manufacturer>
+  <tds:Model>Simulated Camera</tds:Model>
+  <tds:FirmwareVersion>1.0.0</tds:FirmwareVersion>
+  <tds:SerialNumber>{}</tds:SerialNumber>
+  <tds:HardwareId>SIM-1</tds:HardwareId>
+</tds:GetDeviceInformationResponse>"#,
+                    quick_xml::escape::escape(config.id.as_str())
+                ),
+            ),
+            "GetCapabilities" => soap::build_envelope(
+                &[NS_TDS, NS_TT],
+                &format!(
+                    r#"<tds:GetCapabilitiesResponse>
+  <tds:Capabilities>
+    <tt:Device><tt:XAddr>http://{host}/onvif/device_service</tt:XAddr></tt:Device>
+    <tt:Events>
+      <tt:XAddr>http://{host}/onvif/event_service</tt:XAddr>
+      <tt:WSSubscriptionPolicySupport>false</tt:WSSubscriptionPolicySupport>
+      <tt:WSPullPointSupport>true</tt:WSPullPointSupport>
+      <tt:WSPausableSubscriptionManagerInterfaceSupport>false</tt:WSPausableSubscriptionManagerInterfaceSupport>
+    </tt:Events>
+    <tt:Media>
+      <tt:XAddr>http://{host}/onvif/media_service</tt:XAddr>
+      <tt:StreamingCapabilities>
+        <tt:RTPMulticast>false</tt:RTPMulticast>
+        <tt:RTP_TCP>true</tt:RTP_TCP>
+        <tt:RTP_RTSP_TCP>true</tt:RTP_RTSP_TCP>
+      </tt:StreamingCapabilities>
+    </tt:Media>
+  </tds:Capabilities>
+</tds:GetCapabilitiesResponse>"#
+                ),
+            ),
+            "GetServices" => soap::build_envelope(
+                &[NS_TDS, NS_TT],
+                &format!(
+                    r#"<tds:GetServicesResponse>
+  <tds:Service><tds:Namespace>http://www.onvif.org/ver10/device/wsdl</tds:Namespace><tds:XAddr>http://{host}/onvif/device_service</tds:XAddr><tds:Version><tt:Major>2</tt:Major><tt:Minor>60</tt:Minor></tds:Version></tds:Service>
+  <tds:Service><tds:Namespace>http://www.onvif.org/ver10/media/wsdl</tds:Namespace><tds:XAddr>http://{host}/onvif/media_service</tds:XAddr><tds:Version><tt:Major>2</tt:Major><tt:Minor>60</tt:Minor></tds:Version></tds:Service>
+  <tds:Service><tds:Namespace>http://www.onvif.org/ver10/events/wsdl</tds:Namespace><tds:XAddr>http://{host}/onvif/event_service</tds:XAddr><tds:Version><tt:Major>2</tt:Major><tt:Minor>60</tt:Minor></tds:Version></tds:Service>
+</tds:GetServicesResponse>"#
+                ),
+            ),
+            "GetProfiles" => soap::build_envelope(
+                &[NS_TRT, NS_TT],
+                &format!("<trt:GetProfilesResponse>\n{}\n</trt:GetProfilesResponse>", Self::profile_xml("Profiles")),
+            ),
+            "GetProfile" => soap::build_envelope(
+                &[NS_TRT, NS_TT],
+                &format!("<trt:GetProfileResponse>\n{}\n</trt:GetProfileResponse>", Self::profile_xml("Profile")),
+            ),
+            "GetStreamUri" => soap::build_envelope(
+                &[NS_TRT, NS_TT],
+                &format!(
+                    r#"<trt:GetStreamUriResponse>
+  <trt:MediaUri>
+    <tt:Uri>rtsp://{}:554/simulated/main</tt:Uri>
+    <tt:InvalidAfterConnect>false</tt:InvalidAfterConnect>
+    <tt:InvalidAfterReboot>false</tt:InvalidAfterReboot>
+    <tt:Timeout>PT0S</tt:Timeout>
+  </trt:MediaUri>
+</trt:GetStreamUriResponse>"#,
+                    config.url_host()
+                ),
+            ),
+            "GetSnapshotUri" => soap::build_envelope(
+                &[NS_TRT, NS_TT],
+                &format!(
+                    r#"<trt:GetSnapshotUriResponse>
+  <trt:MediaUri>
+    <tt:Uri>http://{host}/snapshot.jpg</tt:Uri>
+    <tt:InvalidAfterConnect>false</tt:InvalidAfterConnect>
+    <tt:InvalidAfterReboot>false</tt:InvalidAfterReboot>
+    <tt:Timeout>PT0S</tt:Timeout>
+  </trt:MediaUri>
+</trt:GetSnapshotUriResponse>"#
+                ),
+            ),
+            "GetEventProperties" => soap::build_envelope(
+                &[NS_TEV, NS_TT, NS_TNS1, NS_WSNT, NS_WSTOP],
+                r#"<tev:GetEventPropertiesResponse>
+  <tev:TopicNamespaceLocation>http://www.onvif.org/onvif/ver10/topics/topicns.xml</tev:TopicNamespaceLocation>
+  <wsnt:FixedTopicSet>true</wsnt:FixedTopicSet>
+  <wstop:TopicSet>
+    <tns1:RuleEngine><CellMotionDetector><Motion wstop:topic="true">
+      <tt:MessageDescription IsProperty="true">
+        <tt:Data><tt:SimpleItemDescription Name="IsMotion" Type="xs:boolean"/></tt:Data>
+      </tt:MessageDescription>
+    </Motion></CellMotionDetector></tns1:RuleEngine>
+  </wstop:TopicSet>
+  <wsnt:TopicExpressionDialect>http://www.onvif.org/ver10/tev/topicExpression/ConcreteSet</wsnt:TopicExpressionDialect>
+</tev:GetEventPropertiesResponse>"#,
+            ),
+            "CreatePullPointSubscription" => {
+                let now = Utc::now();
+                soap::build_envelope(
+                    &[NS_TEV, NS_WSA, NS_WSNT],
+                    &format!(
+                        r#"<tev:CreatePullPointSubscriptionResponse>
+  <tev:SubscriptionReference>
+    <wsa:Address>http://{}/onvif/subscription/simulated</wsa:Address>
+  </tev:SubscriptionReference>
+  <wsnt:CurrentTime>{}</wsnt:CurrentTime>
+  <wsnt:TerminationTime>{}</wsnt:TerminationTime>
+</tev:CreatePullPointSubscriptionResponse>"#,
+                        host,
+                        now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                        (now + chrono::Duration::seconds(600)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                    ),
+                )
+            }
+            "Renew" => {
+                let now = Utc::now();
+                soap::build_envelope(
+                    &[NS_WSNT],
+                    &format!(
+                        "<wsnt:RenewResponse><wsnt:TerminationTime>{}</wsnt:TerminationTime><wsnt:CurrentTime>{}</wsnt:CurrentTime></wsnt:RenewResponse>",
+                        (now + chrono::Duration::seconds(600)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                        now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                    ),
+                )
+            }
+            // Motion is reported through the CGI path; a pull just comes back empty
+            "PullMessages" => {
+                let now = Utc::now();
+                soap::build_envelope(
+                    &[NS_TEV],
+                    &format!(
+                        "<tev:PullMessagesResponse><tev:CurrentTime>{}</tev:CurrentTime><tev:TerminationTime>{}</tev:TerminationTime></tev:PullMessagesResponse>",
+                        now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                        (now + chrono::Duration::seconds(600)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                    ),
+                )
+            }
+            "Unsubscribe" => soap::build_envelope(&[NS_WSNT], "<wsnt:UnsubscribeResponse/>"),
+            _ => soap::build_fault(
+                FaultCode::Sender,
+                "ActionNotSupported",
+                &format!("Simulated camera does not implement {}", action),
+            ),
+        }
+    }
+
+    /// Motion is on for the first few seconds of every minute, or while an admin trigger is active
+    pub fn motion_active(now: DateTime<Utc>, triggered_until: Option<DateTime<Utc>>) -> bool {
+        if triggered_until.is_some_and(|until| now < until) {
+            return true;
+        }
+        now.second() % MOTION_CYCLE_SECS < MOTION_ON_SECS
+    }
+
+    fn system_date_and_time(now: DateTime<Utc>) -> String {
+        soap::build_envelope(
+            &[NS_TDS, NS_TT],
+            &format!(
+                r#"<tds:GetSystemDateAndTimeResponse>
+  <tds:SystemDateAndTime>
+    <tt:DateTimeType>Manual</tt:DateTimeType>
+    <tt:DaylightSavings>false</tt:DaylightSavings>
+    <tt:TimeZone><tt:TZ>UTC0</tt:TZ></tt:TimeZone>
+    <tt:UTCDateTime>
+      <tt:Time><tt:Hour>{}</tt:Hour><tt:Minute>{}</tt:Minute><tt:Second>{}</tt:Second></tt:Time>
+      <tt:Date><tt:Year>{}</tt:Year><tt:Month>{}</tt:Month><tt:Day>{}</tt:Day></tt:Date>
+    </tt:UTCDateTime>
+  </tds:SystemDateAndTime>
+</tds:GetSystemDateAndTimeResponse>"#,
+                now.hour(),
+                now.minute(),
+                now.second(),
+                now.year(),
+                now.month(),
+                now.day()
+            ),
+        )
+    }
+
+    fn profile_xml(element: &str) -> String {
+        format!(
+            r#"<trt:{element} token="000" fixed="true">
+  <tt:Name>mainStream</tt:Name>
+  <tt:VideoSourceConfiguration token="000">
+    <tt:Name>VideoSource</tt:Name>
+    <tt:UseCount>1</tt:UseCount>
+    <tt:SourceToken>000</tt:SourceToken>
+    <tt:Bounds x="0" y="0" width="1920" height="1080"/>
+  </tt:VideoSourceConfiguration>
+  <tt:VideoEncoderConfiguration token="000">
+    <tt:Name>MainStream</tt:Name>
+    <tt:UseCount>1</tt:UseCount>
+    <tt:Encoding>H264</tt:Encoding>
+    <tt:Resolution><tt:Width>1920</tt:Width><tt:Height>1080</tt:Height></tt:Resolution>
+    <tt:Quality>4</tt:Quality>
+    <tt:RateControl><tt:FrameRateLimit>25</tt:FrameRateLimit><tt:EncodingInterval>1</tt:EncodingInterval><tt:BitrateLimit>4096</tt:BitrateLimit></tt:RateControl>
+    <tt:H264><tt:GovLength>50</tt:GovLength><tt:H264Profile>High</tt:H264Profile></tt:H264>
+    <tt:Multicast><tt:Address><tt:Type>IPv4</tt:Type><tt:IPv4Address>0.0.0.0</tt:IPv4Address></tt:Address><tt:Port>0</tt:Port><tt:TTL>0</tt:TTL><tt:AutoStart>false</tt:AutoStart></tt:Multicast>
+    <tt:SessionTimeout>PT60S</tt:SessionTimeout>
+  </tt:VideoEncoderConfiguration>
+</trt:{element}>"#
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::onvif::soap::SoapEnvelope;
+
+    fn config() -> CameraConfig {
+        CameraConfig {
+            id: "sim1".to_string(),
+            address: "sim.local:8000".to_string(),
+            model: "simulated".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_responses_are_valid_soap() {
+        for body in [
+            r#"<tds:GetDeviceInformation xmlns:tds="http://www.onvif.org/ver10/device/wsdl"/>"#,
+            r#"<tds:GetCapabilities xmlns:tds="http://www.onvif.org/ver10/device/wsdl"><tds:Category>All</tds:Category></tds:GetCapabilities>"#,
+            r#"<trt:GetProfiles xmlns:trt="http://www.onvif.org/ver10/media/wsdl"/>"#,
+            r#"<tev:CreatePullPointSubscription xmlns:tev="http://www.onvif.org/ver10/events/wsdl"/>"#,
+        ] {
+            let response = SimulatedCamera::respond(&config(), body);
+            let envelope = SoapEnvelope::parse(&response).unwrap();
+            assert!(envelope.body.action.ends_with("Response"), "{}", envelope.body.action);
+        }
+
+        let info = SimulatedCamera::respond(&config(), "<tds:GetDeviceInformation/>");
+        assert!(info.contains("<tds:SerialNumber>sim1</tds:SerialNumber>"));
+    }
+
+    #[test]
+    fn test_unknown_action_faults() {
+        let response = SimulatedCamera::respond(&config(), "<tds:SystemReboot/>");
+        assert!(response.contains("ter:ActionNotSupported"));
+    }
+
+    #[test]
+    fn test_motion_trigger() {
+        let quiet = Utc::now().with_second(30).unwrap();
+        assert!(!SimulatedCamera::motion_active(quiet, None));
+        assert!(SimulatedCamera::motion_active(quiet, Some(quiet + chrono::Duration::seconds(5))));
+        assert!(!SimulatedCamera::motion_active(quiet, Some(quiet - chrono::Duration::seconds(5))));
+        assert!(SimulatedCamera::motion_active(quiet.with_second(3).unwrap(), None));
+    }
+}
@@ -0,0 +1,411 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::AppConfig;
+use crate::onvif::discovery::{self, DiscoveredDevice, Subnet};
+
+const USAGE: &str = "Usage:
+  onvif-proxy                 Run the proxy (config from CONFIG_PATH, default config/cameras.yaml)
+  onvif-proxy scan [options]  Find ONVIF devices with WS-Discovery
+  onvif-proxy validate-config [PATH]
+                              Check a config file (default CONFIG_PATH) and exit
+  onvif-proxy dump --camera <ID> [--simulated]
+                              Print the device responses a client would get for a camera
+
+Scan options:
+  --subnet <CIDR>    Only list devices in this subnet, and probe each of its hosts directly
+  --timeout <SECS>   How long to wait for answers (default 3)
+  --output <FILE>    Write a starter cameras.yaml for the devices found
+
+Dump options:
+  --camera <ID>      Camera to query (from CONFIG_PATH)
+  --simulated        Answer with simulated camera data instead of contacting the camera";
+
+const DEFAULT_SCAN_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Serve,
+    Scan(ScanOptions),
+    ValidateConfig(PathBuf),
+    Dump(DumpOptions),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ScanOptions {
+    pub subnet: Option<Subnet>,
+    pub timeout: Duration,
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DumpOptions {
+    pub camera_id: String,
+    pub simulated: bool,
+}
+
+/// Device service actions printed by `dump`, in onboarding order
+const DUMP_ACTIONS: &[(&str, &str)] = &[
+    ("GetDeviceInformation", "<tds:GetDeviceInformation/>"),
+    ("GetCapabilities", "<tds:GetCapabilities><tds:Category>All</tds:Category></tds:GetCapabilities>"),
+    ("GetServices", "<tds:GetServices><tds:IncludeCapability>true</tds:IncludeCapability></tds:GetServices>"),
+];
+
+/// Parse command-line arguments (without the program name)
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command> {
+    let mut args = args.into_iter();
+
+    match args.next().as_deref() {
+        None => Ok(Command::Serve),
+        Some("scan") => {
+            let mut options = ScanOptions {
+                subnet: None,
+                timeout: DEFAULT_SCAN_TIMEOUT,
+                output: None,
+            };
+
+            while let Some(flag) = args.next() {
+                let mut value = || args.next().with_context(|| format!("{} needs a value\n\n{}", flag, USAGE));
+                match flag.as_str() {
+                    "--subnet" => options.subnet = Some(value()?.parse()?),
+                    "--timeout" => {
+                        let secs: f64 = value()?.parse().context("--timeout must be a number of seconds")?;
+                        options.timeout = Duration::from_secs_f64(secs.max(0.0));
+                    }
+                    "--output" => options.output = Some(PathBuf::from(value()?)),
+                    other => bail!("Unknown option: {}\n\n{}", other, USAGE),
+                }
+            }
+
+            Ok(Command::Scan(options))
+        }
+        Some("validate-config") => {
+            let path = args.next().map(PathBuf::from).unwrap_or_else(config_path);
+            if let Some(extra) = args.next() {
+                bail!("Unexpected argument: {}\n\n{}", extra, USAGE);
+            }
+            Ok(Command::ValidateConfig(path))
+        }
+        Some("dump") => {
+            let mut camera_id = None;
+            let mut simulated = false;
+
+            while let Some(flag) = args.next() {
+                match flag.as_str() {
+                    "--camera" => camera_id = Some(args.next().with_context(|| format!("--camera needs a value\n\n{}", USAGE))?),
+                    "--simulated" => simulated = true,
+                    other => bail!("Unknown option: {}\n\n{}", other, USAGE),
+                }
+            }
+
+            let camera_id = camera_id.with_context(|| format!("dump needs --camera\n\n{}", USAGE))?;
+            Ok(Command::Dump(DumpOptions { camera_id, simulated }))
+        }
+        Some("-h" | "--help" | "help") => {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        Some(other) => bail!("Unknown command: {}\n\n{}", other, USAGE),
+    }
+}
+
+/// Config file location: `CONFIG_PATH`, or `config/cameras.yaml`
+pub fn config_path() -> PathBuf {
+    std::env::var("CONFIG_PATH")
+        .unwrap_or_else(|_| "config/cameras.yaml".to_string())
+        .into()
+}
+
+/// Load and validate a config file, printing what was found; fails if the config has errors
+pub fn run_validate_config(path: &Path) -> Result<()> {
+    let config = AppConfig::load_from_file(path).with_context(|| format!("{} is not a valid config", path.display()))?;
+    let report = config.validate();
+
+    for warning in &report.warnings {
+        println!("warning: {}", warning);
+    }
+    for error in &report.errors {
+        println!("error: {}", error);
+    }
+
+    if !report.is_ok() {
+        bail!("{}: {} errors, {} warnings", path.display(), report.errors.len(), report.warnings.len());
+    }
+    println!(
+        "{}: OK ({} cameras, {} warnings)",
+        path.display(),
+        config.cameras.len(),
+        report.warnings.len()
+    );
+    Ok(())
+}
+
+/// Print the translated device responses a client would receive for one camera
+pub async fn run_dump(options: DumpOptions) -> Result<()> {
+    let path = config_path();
+    let config = AppConfig::load_from_file(&path).with_context(|| format!("{} is not a valid config", path.display()))?;
+
+    for (action, status, xml) in dump_responses(config, &options.camera_id, options.simulated).await? {
+        println!("==> {} ({})", action, status);
+        println!("{}\n", xml);
+    }
+    Ok(())
+}
+
+/// Send the `DUMP_ACTIONS` through the proxy's own router, so the responses have every
+/// translation, quirk and XAddr rewrite a client would see
+async fn dump_responses(config: AppConfig, camera_id: &str, simulated: bool) -> Result<Vec<(&'static str, u16, String)>> {
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    use crate::camera::CameraManager;
+    use crate::onvif::events::EventsService;
+    use crate::onvif::soap;
+    use crate::server::cache::ResponseCache;
+    use crate::server::routes::{create_router, AppState};
+
+    let mut camera = config
+        .cameras
+        .into_iter()
+        .find(|camera| camera.id == camera_id)
+        .with_context(|| format!("No camera {:?} in the config", camera_id))?;
+    if simulated {
+        camera.model = "simulated".to_string();
+    }
+
+    soap::set_soap_prefix(&config.proxy.soap_prefix).context("Invalid proxy.soap_prefix")?;
+    let camera_manager = CameraManager::new();
+    camera_manager.add_camera(camera).await;
+    let app = create_router(AppState {
+        camera_manager,
+        events_service: EventsService::with_config(config.proxy.events.clone()),
+        base_url: config.proxy.resolve_base_url(),
+        response_cache: ResponseCache::new(config.proxy.cache.clone()),
+        startup_complete: Default::default(),
+        config_path: None,
+        proxy_config: Arc::new(config.proxy),
+    });
+
+    let mut responses = Vec::new();
+    for (action, body) in DUMP_ACTIONS {
+        let envelope = soap::build_envelope(&[("tds", "http://www.onvif.org/ver10/device/wsdl")], body);
+        let request = Request::post(format!("/onvif/{}/device_service", camera_id))
+            .header("Content-Type", "application/soap+xml; charset=utf-8")
+            .body(Body::from(envelope))?;
+        let response = app.clone().oneshot(request).await?;
+        let status = response.status().as_u16();
+        let xml = to_bytes(response.into_body(), usize::MAX).await?;
+        responses.push((*action, status, String::from_utf8_lossy(&xml).into_owned()));
+    }
+    Ok(responses)
+}
+
+pub async fn run_scan(options: ScanOptions) -> Result<()> {
+    match options.subnet {
+        Some(subnet) => println!("Scanning {} for {:.1}s...", subnet, options.timeout.as_secs_f64()),
+        None => println!("Scanning the local network for {:.1}s...", options.timeout.as_secs_f64()),
+    }
+
+    let devices = discovery::scan(options.subnet, options.timeout).await?;
+    if devices.is_empty() {
+        println!("No ONVIF devices found");
+        return Ok(());
+    }
+
+    print!("{}", format_table(&devices));
+
+    if let Some(path) = options.output {
+        std::fs::write(&path, starter_config(&devices)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        println!("\nWrote {} cameras to {} (fill in username/password)", devices.len(), path.display());
+    }
+
+    Ok(())
+}
+
+pub fn format_table(devices: &[DiscoveredDevice]) -> String {
+    let rows: Vec<[String; 4]> = devices
+        .iter()
+        .map(|d| {
+            [
+                d.address(),
+                d.manufacturer().unwrap_or_else(|| "-".to_string()),
+                d.hardware().unwrap_or_else(|| "-".to_string()),
+                d.name().unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+
+    let header = ["ADDRESS", "MANUFACTURER", "HARDWARE", "NAME"].map(str::to_string);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[derive(Serialize)]
+struct StarterConfig {
+    proxy: StarterProxy,
+    cameras: Vec<StarterCamera>,
+}
+
+#[derive(Serialize)]
+struct StarterProxy {
+    listen_address: &'static str,
+    base_path: &'static str,
+    log_level: &'static str,
+}
+
+#[derive(Serialize)]
+struct StarterCamera {
+    id: String,
+    name: String,
+    address: String,
+    username: String,
+    password: String,
+    model: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    quirks: Vec<&'static str>,
+}
+
+/// A cameras.yaml listing the discovered devices, credentials left blank
+pub fn starter_config(devices: &[DiscoveredDevice]) -> Result<String> {
+    let cameras = devices
+        .iter()
+        .enumerate()
+        .map(|(i, device)| {
+            let id = format!("camera-{:02}", i + 1);
+            let (model, quirks) = if device.is_reolink() {
+                ("reolink", vec!["fix_device_info_namespace", "normalize_media_profiles", "translate_smart_events", "fix_analytics_xaddr"])
+            } else {
+                ("generic", Vec::new())
+            };
+            StarterCamera {
+                name: device.name().unwrap_or_else(|| id.clone()),
+                id,
+                address: device.address(),
+                username: String::new(),
+                password: String::new(),
+                model,
+                quirks,
+            }
+        })
+        .collect();
+
+    let config = StarterConfig {
+        proxy: StarterProxy {
+            listen_address: "0.0.0.0:8000",
+            base_path: "/onvif",
+            log_level: "info",
+        },
+        cameras,
+    };
+
+    let yaml = serde_yaml::to_string(&config)?;
+    Ok(format!("# Generated by `onvif-proxy scan`; fill in each camera's username and password\n{}", yaml))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_args(args(&[])).unwrap(), Command::Serve);
+        assert_eq!(
+            parse_args(args(&["scan", "--subnet", "192.168.1.0/24", "--timeout", "5", "--output", "cams.yaml"])).unwrap(),
+            Command::Scan(ScanOptions {
+                subnet: Some("192.168.1.0/24".parse().unwrap()),
+                timeout: Duration::from_secs(5),
+                output: Some(PathBuf::from("cams.yaml")),
+            })
+        );
+        assert!(parse_args(args(&["scan", "--subnet"])).is_err());
+        assert!(parse_args(args(&["scan", "--verbose"])).is_err());
+        assert!(parse_args(args(&["serve"])).is_err());
+        assert_eq!(
+            parse_args(args(&["validate-config", "/etc/onvif-proxy.yaml"])).unwrap(),
+            Command::ValidateConfig(PathBuf::from("/etc/onvif-proxy.yaml"))
+        );
+        assert!(parse_args(args(&["validate-config", "a.yaml", "b.yaml"])).is_err());
+        assert_eq!(
+            parse_args(args(&["dump", "--camera", "camera-01", "--simulated"])).unwrap(),
+            Command::Dump(DumpOptions { camera_id: "camera-01".to_string(), simulated: true })
+        );
+        assert!(parse_args(args(&["dump"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dump_responses() {
+        let yaml = r#"
+proxy:
+  listen_address: "0.0.0.0:8000"
+  base_path: /onvif
+  base_url: "http://proxy.example:8000"
+cameras:
+  - id: "camera-01"
+    name: "Front Door"
+    address: "192.168.1.100:80"
+    username: "admin"
+    password: "secret"
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        assert!(dump_responses(config.clone(), "camera-02", true).await.is_err());
+
+        let responses = dump_responses(config, "camera-01", true).await.unwrap();
+        let actions: Vec<_> = responses.iter().map(|(action, status, _)| (*action, *status)).collect();
+        assert_eq!(actions, [("GetDeviceInformation", 200), ("GetCapabilities", 200), ("GetServices", 200)]);
+        assert!(responses[1].2.contains("http://proxy.example:8000/onvif/camera-01/"));
+    }
+
+    #[test]
+    fn test_starter_config_loads() {
+        let device = |ip: &str, scopes: &[&str]| DiscoveredDevice {
+            source: ip.parse().unwrap(),
+            endpoint: format!("urn:uuid:{}", ip),
+            xaddrs: vec![format!("http://{}/onvif/device_service", ip)],
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+        };
+        let devices = vec![
+            device("192.168.1.100", &["onvif://www.onvif.org/name/Front%20Door", "onvif://www.onvif.org/MfrName/Reolink"]),
+            device("192.168.1.101", &["onvif://www.onvif.org/hardware/DS-2CD2143"]),
+        ];
+
+        let yaml = starter_config(&devices).unwrap();
+        let config: AppConfig = serde_yaml::from_str(&yaml).unwrap();
+
+        assert_eq!(config.cameras.len(), 2);
+        assert_eq!(config.cameras[0].name, "Front Door");
+        assert_eq!(config.cameras[0].address, "192.168.1.100:80");
+        assert_eq!(config.cameras[0].model, "reolink");
+        assert_eq!(config.cameras[1].id, "camera-02");
+        assert_eq!(config.cameras[1].model, "generic");
+        assert!(config.cameras[1].username.is_empty());
+
+        let table = format_table(&devices);
+        assert!(table.starts_with("ADDRESS"));
+        assert!(table.contains("DS-2CD2143"));
+    }
+}
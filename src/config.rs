@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
 use anyhow::{Context, Result};
 
@@ -11,11 +12,154 @@ pub struct AppConfig {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ProxyConfig {
     pub listen_address: String,
+    /// Also serve on this Unix domain socket, e.g. `/run/onvif-proxy.sock`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listen_socket: Option<String>,
     pub base_path: String,
     #[serde(default = "default_log_level")]
     pub log_level: String,
+    /// `text` for interactive use, `json` for log aggregation (ELK, Loki, ...)
     #[serde(default)]
+    pub log_format: LogFormat,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub base_url: Option<String>,
+    /// Per-service base URL overrides keyed by service name (e.g. `media_service`),
+    /// used instead of `base_url` when rewriting that service's XAddr
+    #[serde(default)]
+    pub service_base_urls: HashMap<String, String>,
+    /// Bearer token for the `/admin` endpoints; the admin API is disabled when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<String>,
+    /// Credentials clients must present on the ONVIF endpoints; anyone is served when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<ClientAuthConfig>,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+    /// Serve an HTML status page at `/status`
+    #[serde(default)]
+    pub enable_dashboard: bool,
+    /// Answer with a `ter:Timeout` fault when a request takes longer than this (off when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_deadline_secs: Option<u64>,
+    /// Answer WS-Discovery probes (UDP 3702) for the proxied cameras, so clients find them in scans
+    #[serde(default = "default_ws_discovery")]
+    pub ws_discovery: bool,
+    /// Fault requests carrying a mustUnderstand header the proxy doesn't process, as SOAP 1.2
+    /// requires; by default such headers are ignored
+    #[serde(default)]
+    pub strict_soap: bool,
+    /// Envelope prefix in responses the proxy generates itself (e.g. `s`, `soapenv`)
+    #[serde(default = "default_soap_prefix")]
+    pub soap_prefix: String,
+    /// File where learned per-camera state (clock skew, device UUID, ...) is kept across restarts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_path: Option<String>,
+}
+
+impl ProxyConfig {
+    /// The proxy's URL as clients see it: `base_url`, else `BASE_URL`, else the local IP
+    /// with the listen port
+    pub fn resolve_base_url(&self) -> String {
+        self.base_url
+            .clone()
+            .filter(|s| !s.trim().is_empty()) // Treat empty strings as None
+            .or_else(|| std::env::var("BASE_URL").ok())
+            .unwrap_or_else(|| {
+                // Auto-detect: extract port from listen_address
+                let port = self.listen_port().unwrap_or("8000");
+
+                // Try to get local IP, fallback to localhost; IPv6 is bracketed in URLs
+                let ip = match local_ip_address::local_ip() {
+                    Ok(std::net::IpAddr::V6(ip)) => format!("[{}]", ip),
+                    Ok(ip) => ip.to_string(),
+                    Err(_) => "127.0.0.1".to_string(),
+                };
+
+                let url = format!("http://{}:{}", ip, port);
+                tracing::info!("Auto-detected base URL: {}", url);
+                url
+            })
+    }
+
+    /// Port of `listen_address`, which is `host:port` or `[v6]:port`
+    fn listen_port(&self) -> Option<&str> {
+        let (host, port) = self.listen_address.rsplit_once(':')?;
+        // A bare IPv6 address has no port
+        (!host.contains(':') || host.ends_with(']')).then_some(port)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, with request fields as separate keys
+    Json,
+}
+
+/// Credentials required of clients, as a WS-Security UsernameToken or HTTP Basic
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ClientAuthConfig {
+    pub username: String,
+    pub password: String,
+    /// Credentials for single cameras, by camera id, instead of the ones above
+    #[serde(default)]
+    pub cameras: HashMap<String, ClientCredentials>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ClientCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl ClientAuthConfig {
+    /// Username and password clients of `camera_id` must present
+    pub fn credentials_for(&self, camera_id: &str) -> (&str, &str) {
+        match self.cameras.get(camera_id) {
+            Some(credentials) => (&credentials.username, &credentials.password),
+            None => (&self.username, &self.password),
+        }
+    }
+}
+
+/// Response cache for read-only actions (GetCapabilities, GetProfiles, ...)
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CacheConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_cache_ttl_secs")]
+    pub default_ttl_secs: u64,
+    /// Per-action TTL overrides; 0 disables caching for that action
+    #[serde(default)]
+    pub ttl_secs: HashMap<String, u64>,
+}
+
+/// Lease lengths of PullPoint subscriptions
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EventsConfig {
+    /// Lease granted when the client doesn't ask for one
+    #[serde(default = "default_termination_secs")]
+    pub default_termination_secs: u64,
+    /// Longer requested leases are cut to this
+    #[serde(default = "default_max_termination_secs")]
+    pub max_termination_secs: u64,
+    /// Shorter requested leases are raised to this
+    #[serde(default = "default_min_termination_secs")]
+    pub min_termination_secs: u64,
+    /// Longer PullMessages `Timeout`s are cut to this
+    #[serde(default = "default_max_pull_timeout_secs")]
+    pub max_pull_timeout_secs: u64,
+    /// Start new subscriptions with the last state seen on the camera (`Initialized` events)
+    #[serde(default)]
+    pub replay_last_state: bool,
+    /// Create subscriptions without contacting the camera, for cameras whose PullPoint is
+    /// broken; only when events come from the CGI alone (`event_method: cgi`)
+    #[serde(default)]
+    pub local_subscription: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -24,23 +168,261 @@ pub struct CameraConfig {
     pub name: String,
     pub address: String,
     pub username: String,
+    #[serde(default)]
     pub password: String,
+    /// Base64 SHA-1 of the password, for WS-Security with devices that accept a digest keyed
+    /// on the hashed password; `password` can then be left out (see README)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_sha1: Option<String>,
+    /// How the WS-Security token carries the password: a digest, or the cleartext for firmware
+    /// that rejects digests on some endpoints
+    #[serde(default)]
+    pub auth_mode: AuthMode,
+    /// Send the client's WS-Security token to the camera instead of the proxy's, for clients
+    /// that know the camera's password
+    #[serde(default)]
+    pub credential_mode: CredentialMode,
     #[serde(default = "default_model")]
     pub model: String,
+    /// Poll GetAiState with the motion state and raise person/vehicle/pet events on their own topics
     #[serde(default)]
     pub enable_smart_detection: bool,
     #[serde(default)]
     pub quirks: Vec<String>,
+    /// Rewrites applied to every response after the model's quirks, in order
+    #[serde(default)]
+    pub rules: Vec<crate::translator::rules::TranslationRule>,
+    /// Allow CreateRules/ModifyRules/DeleteRules to reach the camera's analytics service
+    #[serde(default)]
+    pub enable_analytics_writes: bool,
+    /// Manufacturer reported in GetDeviceInformation instead of the camera's own value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report_manufacturer: Option<String>,
+    /// Model reported in GetDeviceInformation instead of the camera's own value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report_model: Option<String>,
+    /// Ping the camera every N seconds to keep its health state current (off when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalive_secs: Option<u64>,
+    /// Actions whose camera response is returned untouched (no namespace/URL fixing or quirks)
+    #[serde(default)]
+    pub raw_actions: Vec<String>,
+    /// Limit on concurrent requests to the camera; interactive media actions are served first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_requests: Option<usize>,
+    /// Timeout of each HTTP request to the camera (10 seconds when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    /// Times a SOAP request is retried, with exponential backoff, after a connection failure;
+    /// `Get*` queries also after a timeout or a 5xx answer that isn't a SOAP fault
+    #[serde(default)]
+    pub max_retries: u32,
+    /// Add the proxy's own features (analytics service, synthetic motion events) to GetCapabilities
+    #[serde(default)]
+    pub advertise_proxy_capabilities: bool,
+    /// Where PullPoint events come from: the CGI motion state, the camera's own PullMessages, or both
+    #[serde(default, alias = "event_source")]
+    pub event_method: EventMethod,
+    /// Raw XML fragments added to the SOAP Header of every request sent to the camera, for
+    /// firmware or middleboxes that expect a vendor token or a particular header
+    #[serde(default)]
+    pub soap_headers: Vec<String>,
+    /// Leave the WS-Security token out of camera requests, e.g. when `soap_headers` carries
+    /// its own credentials
+    #[serde(default)]
+    pub omit_security_header: bool,
+    /// Stamp WS-Security `Created` with the camera's clock, as learned from GetSystemDateAndTime,
+    /// for cameras whose clock is too far off to accept the proxy's
+    #[serde(default)]
+    pub correct_clock_skew: bool,
+    /// Base URL of the Reolink CGI API (`http://192.168.1.10:8080`) instead of HTTPS on the
+    /// camera's host, for cameras with HTTPS turned off or moved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cgi_base_url: Option<String>,
+    /// Channel of the camera on a Reolink NVR, used for the motion and imaging CGI; a camera
+    /// entry per channel, all with the NVR's address, gives each channel its own device
+    #[serde(default)]
+    pub channel: u8,
+    /// What answers imaging requests: the camera's ONVIF imaging service, Reolink's
+    /// GetImage/SetImage CGI, or the CGI when ONVIF imaging fails
+    #[serde(default)]
+    pub imaging_backend: ImagingBackend,
+    /// Always negotiate RTP interleaved over the RTSP (TCP) connection in GetStreamUri
+    #[serde(default)]
+    pub force_rtsp_tcp: bool,
+    /// Profiles (by token or name, e.g. `002` or `extStream`) left out of GetProfiles and refused for streaming
+    #[serde(default)]
+    pub hidden_profiles: Vec<String>,
+    /// Order of the profiles in GetProfiles, for VMSes that take the first one
+    #[serde(default)]
+    pub profile_order: ProfileOrder,
+    /// Name profiles `MainStream`, `SubStream` and `ExtStream` by resolution in GetProfiles
+    #[serde(default)]
+    pub rename_profiles: bool,
+    /// Reolink stream (`main`, `sub`, `ext`) each profile token's snapshot is taken from
+    #[serde(default)]
+    pub snapshot_streams: HashMap<String, String>,
+    /// Hand out the proxy's own `/onvif/{id}/snapshot` URL from GetSnapshotUri, which fetches
+    /// the image from the camera with its credentials; off hands out the camera's own URL
+    #[serde(default = "default_proxy_snapshots")]
+    pub proxy_snapshots: bool,
+    /// Answer every GetStreamUri with the Reolink HTTP-FLV URL, not only `HTTP` requests
+    #[serde(default)]
+    pub prefer_flv: bool,
+    /// `Rule` value in synthetic motion events (default `MotionDetectorRule`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_rule_name: Option<String>,
+    /// Name of the video source item in synthetic event Source (default `VideoSourceConfigurationToken`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_source_item_name: Option<String>,
+    /// Name of the analytics item in synthetic event Source (default `VideoAnalyticsConfigurationToken`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_analytics_item_name: Option<String>,
+    /// Video source token in synthetic events (default: the camera id)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_source_token: Option<String>,
+    /// Analytics configuration token in synthetic events (default: the camera id)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_analytics_token: Option<String>,
+    /// Adapt responses to a VMS with stricter requirements (`milestone`: short alphanumeric tokens)
+    #[serde(default)]
+    pub client_profile: ClientProfile,
+    /// How actions the proxy does not handle are answered
+    #[serde(default)]
+    pub unknown_action_behavior: UnknownActionBehavior,
+    /// If non-empty, only these actions are passed on; anything else gets `ter:OperationProhibited`
+    #[serde(default)]
+    pub allowed_actions: Vec<String>,
+    /// Actions always refused with `ter:OperationProhibited`, even if listed in `allowed_actions`
+    #[serde(default)]
+    pub denied_actions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// `PasswordDigest`, with a nonce and creation time
+    #[default]
+    Digest,
+    /// `PasswordText`, the password itself
+    Text,
+}
+
+/// Whose WS-Security token goes to the camera
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialMode {
+    /// A token the proxy generates from the configured credentials
+    #[default]
+    Proxy,
+    /// The client's own token, as it sent it; the proxy's when the client sent none
+    Passthrough,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientProfile {
+    #[default]
+    Standard,
+    /// Milestone XProtect: tokens rewritten to at most 32 ASCII letters and digits
+    Milestone,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownActionBehavior {
+    /// SOAP fault with the `ter:ActionNotSupported` subcode
+    #[default]
+    Fault,
+    /// Plain-text HTTP 501
+    NotImplemented,
+    /// HTTP 200 with an empty SOAP body
+    EmptyOk,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileOrder {
+    /// The camera's own order
+    #[default]
+    AsIs,
+    /// Highest resolution first
+    ResolutionDesc,
+    ResolutionAsc,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImagingBackend {
+    #[default]
+    Onvif,
+    Cgi,
+    /// ONVIF, falling back to the CGI when the camera faults or fails
+    Auto,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EventMethod {
+    /// Poll Reolink's GetMdState CGI (works around broken PullPoint firmware)
+    #[default]
+    Cgi,
+    /// Relay the camera's own ONVIF PullMessages, translated
+    #[serde(alias = "pullpoint")]
+    Onvif,
+    Both,
+}
+
+impl EventMethod {
+    pub fn uses_cgi(&self) -> bool {
+        matches!(self, EventMethod::Cgi | EventMethod::Both)
+    }
+
+    pub fn uses_onvif(&self) -> bool {
+        matches!(self, EventMethod::Onvif | EventMethod::Both)
+    }
 }
 
 fn default_log_level() -> String {
     "info".to_string()
 }
 
+fn default_cache_ttl_secs() -> u64 {
+    30
+}
+
+fn default_termination_secs() -> u64 {
+    600
+}
+
+fn default_max_termination_secs() -> u64 {
+    3600
+}
+
+fn default_min_termination_secs() -> u64 {
+    60
+}
+
+fn default_max_pull_timeout_secs() -> u64 {
+    60
+}
+
+fn default_ws_discovery() -> bool {
+    true
+}
+
+fn default_soap_prefix() -> String {
+    crate::onvif::soap::DEFAULT_SOAP_PREFIX.to_string()
+}
+
 fn default_model() -> String {
     "reolink".to_string()
 }
 
+fn default_proxy_snapshots() -> bool {
+    true
+}
+
 impl AppConfig {
     pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let contents = std::fs::read_to_string(path.as_ref())
@@ -51,10 +433,497 @@ impl AppConfig {
 
         Ok(config)
     }
+
+    /// Write the configuration as YAML, replacing `path` atomically: the file is written next
+    /// to it, synced and renamed over it, so a crash never leaves a half-written config. The
+    /// old file's permissions are kept (it holds camera passwords); its comments and layout are not
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        use std::io::Write;
+
+        let path = path.as_ref();
+        let contents = serde_yaml::to_string(self).context("Failed to serialize configuration")?;
+
+        let file_name = path.file_name().context("Configuration path has no file name")?;
+        let temp_path = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+        let write = || -> std::io::Result<()> {
+            let mut file = std::fs::File::create(&temp_path)?;
+            if let Ok(metadata) = std::fs::metadata(path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        };
+        write().with_context(|| format!("Failed to write {}", temp_path.display()))?;
+        std::fs::rename(&temp_path, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Check the configuration without starting anything. Errors would stop the proxy from
+    /// starting or serving a camera; warnings are settings that are probably mistakes.
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let proxy = &self.proxy;
+
+        if proxy.listen_address.parse::<std::net::SocketAddr>().is_err() {
+            report.error(format!("proxy.listen_address {:?} is not an IP:port address", proxy.listen_address));
+        }
+        if !proxy.base_path.starts_with('/') {
+            report.error(format!("proxy.base_path {:?} must start with '/'", proxy.base_path));
+        }
+        if !crate::onvif::soap::is_valid_prefix(&proxy.soap_prefix) {
+            report.error(format!("proxy.soap_prefix {:?} is not a valid XML prefix", proxy.soap_prefix));
+        }
+
+        if proxy.events.min_termination_secs > proxy.events.max_termination_secs {
+            report.error(format!(
+                "proxy.events.min_termination_secs ({}) is greater than max_termination_secs ({})",
+                proxy.events.min_termination_secs, proxy.events.max_termination_secs
+            ));
+        }
+
+        let base_urls = proxy
+            .base_url
+            .iter()
+            .filter(|url| !url.trim().is_empty())
+            .map(|url| ("proxy.base_url".to_string(), url))
+            .chain(
+                proxy
+                    .service_base_urls
+                    .iter()
+                    .map(|(service, url)| (format!("proxy.service_base_urls.{}", service), url)),
+            );
+        for (key, url) in base_urls {
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {
+                    if is_loopback_host(parsed.host_str().unwrap_or_default()) {
+                        report.warning(format!("{} {:?} is a loopback address; clients on other hosts can't reach it", key, url));
+                    }
+                }
+                _ => report.error(format!("{} {:?} is not an http(s) URL", key, url)),
+            }
+        }
+
+        if self.cameras.is_empty() {
+            report.warning("No cameras are configured".to_string());
+        }
+
+        let mut seen_ids = std::collections::HashSet::new();
+        for (i, camera) in self.cameras.iter().enumerate() {
+            let name = if camera.id.is_empty() { format!("cameras[{}]", i) } else { format!("camera {}", camera.id) };
+            if !camera.id.is_empty() && !seen_ids.insert(camera.id.as_str()) {
+                report.error(format!("{}: duplicate id", name));
+            }
+            camera.check(&name, &mut report);
+        }
+
+        report
+    }
+}
+
+/// Whether `xml` is one or more complete elements
+fn is_xml_fragment(xml: &str) -> bool {
+    use quick_xml::events::Event;
+
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut depth = 0usize;
+    let mut elements = 0;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(_)) => {
+                depth += 1;
+                elements += 1;
+            }
+            Ok(Event::End(_)) => depth -= 1,
+            Ok(Event::Empty(_)) => elements += 1,
+            Ok(Event::Text(text)) if depth == 0 && !text.iter().all(u8::is_ascii_whitespace) => return false,
+            Ok(Event::Eof) => return depth == 0 && elements > 0,
+            Ok(_) => {}
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Problems found by `AppConfig::validate`
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    fn error(&mut self, message: String) {
+        self.errors.push(message);
+    }
+
+    fn warning(&mut self, message: String) {
+        self.warnings.push(message);
+    }
+}
+
+fn is_loopback_host(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            default_ttl_secs: default_cache_ttl_secs(),
+            ttl_secs: HashMap::new(),
+        }
+    }
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            default_termination_secs: default_termination_secs(),
+            max_termination_secs: default_max_termination_secs(),
+            min_termination_secs: default_min_termination_secs(),
+            max_pull_timeout_secs: default_max_pull_timeout_secs(),
+            replay_last_state: false,
+            local_subscription: false,
+        }
+    }
+}
+
+impl EventsConfig {
+    /// Lease to grant for a requested length in seconds (the default when not requested),
+    /// clamped into `min_termination_secs..=max_termination_secs`
+    pub fn grant(&self, requested_secs: Option<i64>) -> chrono::Duration {
+        let requested = requested_secs.map_or(self.default_termination_secs, |secs| secs.max(0) as u64);
+        let granted = requested.min(self.max_termination_secs).max(self.min_termination_secs);
+        chrono::Duration::seconds(granted as i64)
+    }
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            name: String::new(),
+            address: String::new(),
+            username: String::new(),
+            password: String::new(),
+            password_sha1: None,
+            auth_mode: AuthMode::default(),
+            credential_mode: CredentialMode::default(),
+            model: default_model(),
+            enable_smart_detection: false,
+            quirks: Vec::new(),
+            rules: Vec::new(),
+            enable_analytics_writes: false,
+            report_manufacturer: None,
+            report_model: None,
+            keepalive_secs: None,
+            raw_actions: Vec::new(),
+            max_concurrent_requests: None,
+            timeout_secs: None,
+            max_retries: 0,
+            advertise_proxy_capabilities: false,
+            event_method: EventMethod::default(),
+            soap_headers: Vec::new(),
+            omit_security_header: false,
+            correct_clock_skew: false,
+            cgi_base_url: None,
+            channel: 0,
+            imaging_backend: ImagingBackend::default(),
+            profile_order: ProfileOrder::default(),
+            rename_profiles: false,
+            snapshot_streams: HashMap::new(),
+            proxy_snapshots: default_proxy_snapshots(),
+            force_rtsp_tcp: false,
+            prefer_flv: false,
+            hidden_profiles: Vec::new(),
+            event_rule_name: None,
+            event_source_item_name: None,
+            event_analytics_item_name: None,
+            event_source_token: None,
+            event_analytics_token: None,
+            client_profile: ClientProfile::default(),
+            unknown_action_behavior: UnknownActionBehavior::default(),
+            allowed_actions: Vec::new(),
+            denied_actions: Vec::new(),
+        }
+    }
 }
 
 impl CameraConfig {
+    /// This config with `password`, `password_sha1` and credentials in `soap_headers` blanked
+    /// out, for the management API
+    pub fn redacted(&self) -> Self {
+        let mask = |secret: &str| if secret.is_empty() { String::new() } else { "***".to_string() };
+        Self {
+            password: mask(&self.password),
+            password_sha1: self.password_sha1.as_deref().map(mask),
+            soap_headers: self.soap_headers.iter().map(|header| crate::util::redact_secrets(header)).collect(),
+            ..self.clone()
+        }
+    }
+
     pub fn base_url(&self) -> String {
         format!("http://{}", self.address)
     }
+
+    /// Host part of `address` as it belongs in a URL: a hostname, an IPv4 address or a
+    /// bracketed IPv6 address, without the port
+    pub fn url_host(&self) -> &str {
+        let address = self.address.as_str();
+        if address.starts_with('[') {
+            // [fe80::1]:80
+            return address.find(']').map(|end| &address[..=end]).unwrap_or(address);
+        }
+        match address.rsplit_once(':') {
+            // Bare IPv6 without port or brackets can't be split on ':'
+            Some((host, _)) if !host.contains(':') => host,
+            _ => address,
+        }
+    }
+
+    /// Host part of `address` for DNS lookups (no brackets)
+    pub fn lookup_host(&self) -> &str {
+        self.url_host().trim_start_matches('[').trim_end_matches(']')
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        let address = self.address.as_str();
+        let rest = match address.find(']') {
+            Some(end) => &address[end + 1..],
+            None if address.matches(':').count() == 1 => &address[address.find(':').unwrap()..],
+            None => return None,
+        };
+        rest.strip_prefix(':').and_then(|port| port.parse().ok())
+    }
+
+    /// Decoded `password_sha1`, if set and a valid 20-byte digest
+    pub fn password_sha1_bytes(&self) -> Option<Vec<u8>> {
+        use base64::Engine;
+        let hash = self.password_sha1.as_deref()?.trim();
+        base64::engine::general_purpose::STANDARD
+            .decode(hash)
+            .ok()
+            .filter(|bytes| bytes.len() == 20)
+    }
+
+    pub fn is_raw_action(&self, action: &str) -> bool {
+        self.raw_actions.iter().any(|a| a == action)
+    }
+
+    pub fn is_action_allowed(&self, action: &str) -> bool {
+        if self.denied_actions.iter().any(|a| a == action) {
+            return false;
+        }
+        self.allowed_actions.is_empty() || self.allowed_actions.iter().any(|a| a == action)
+    }
+
+    /// Check this camera on its own, as `AppConfig::validate` does for each configured one; for
+    /// cameras added at runtime. Ids taken by other cameras aren't checked here
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let name = if self.id.is_empty() { "camera".to_string() } else { format!("camera {}", self.id) };
+        self.check(&name, &mut report);
+        report
+    }
+
+    fn check(&self, name: &str, report: &mut ValidationReport) {
+        if self.id.is_empty() {
+            report.error(format!("{}: id is empty", name));
+        } else if self.id.contains('/') {
+            report.error(format!("{}: id must not contain '/'", name));
+        }
+
+        if !crate::translator::response::ResponseTranslator::is_known_model(&self.model) {
+            report.warning(format!("{}: unknown model {:?}, responses are passed through untranslated", name, self.model));
+        }
+        for quirk in &self.quirks {
+            if !crate::translator::reolink::ReolinkEventTranslator::QUIRKS.contains(&quirk.as_str()) {
+                report.warning(format!("{}: unknown quirk {:?}", name, quirk));
+            } else if self.model == "generic" && !crate::translator::generic::GenericTranslator::SAFE_QUIRKS.contains(&quirk.as_str()) {
+                report.warning(format!("{}: quirk {:?} is Reolink-specific and ignored for generic cameras", name, quirk));
+            }
+        }
+        if let Err(e) = crate::translator::rules::RuleEngine::from_rules(&self.rules) {
+            report.error(format!("{}: {:#}", name, e));
+        }
+
+        // Simulated cameras are never contacted
+        if self.is_simulated() {
+            return;
+        }
+        if self.address.trim().is_empty() {
+            report.error(format!("{}: address is empty", name));
+        } else if self.address.contains("://") {
+            report.error(format!("{}: address {:?} should be host[:port], without a scheme", name, self.address));
+        }
+        if self.username.is_empty() || (self.password.is_empty() && self.password_sha1.is_none()) {
+            report.warning(format!("{}: username or password is empty", name));
+        }
+        if let Some(hash) = &self.password_sha1 {
+            if self.password_sha1_bytes().is_none() {
+                report.error(format!("{}: password_sha1 {:?} is not a base64 SHA-1 digest", name, hash));
+            }
+            // The CGI API takes the cleartext password
+            if self.password.is_empty() && (self.event_method.uses_cgi() || self.prefer_flv) {
+                report.warning(format!("{}: CGI motion events and HTTP-FLV need the cleartext password", name));
+            }
+            if self.password.is_empty() && self.auth_mode == AuthMode::Text {
+                report.error(format!("{}: auth_mode text needs the cleartext password", name));
+            }
+        }
+        if let Some(url) = &self.cgi_base_url {
+            if !reqwest::Url::parse(url).is_ok_and(|parsed| matches!(parsed.scheme(), "http" | "https")) {
+                report.error(format!("{}: cgi_base_url {:?} is not an http(s) URL", name, url));
+            }
+        }
+        for header in &self.soap_headers {
+            if !is_xml_fragment(header) {
+                report.error(format!("{}: soap_headers entry {:?} is not well-formed XML", name, header));
+            }
+        }
+    }
+
+    /// `model: simulated` cameras are answered in-process and never contacted over the network
+    pub fn is_simulated(&self) -> bool {
+        self.model == "simulated"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_address(address: &str) -> CameraConfig {
+        CameraConfig {
+            address: address.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_address_parts() {
+        let cases = [
+            ("192.168.1.100:80", "192.168.1.100", "192.168.1.100", Some(80)),
+            ("192.168.1.100", "192.168.1.100", "192.168.1.100", None),
+            ("cam-front.lan:8000", "cam-front.lan", "cam-front.lan", Some(8000)),
+            ("cam-front.lan", "cam-front.lan", "cam-front.lan", None),
+            ("[fe80::1]:80", "[fe80::1]", "fe80::1", Some(80)),
+            ("fe80::1", "fe80::1", "fe80::1", None),
+            ("192.168.1.10:8000", "192.168.1.10", "192.168.1.10", Some(8000)),
+            ("[fe80::1]:8000", "[fe80::1]", "fe80::1", Some(8000)),
+            ("[2001:db8::1]:8000", "[2001:db8::1]", "2001:db8::1", Some(8000)),
+            ("frontdoor", "frontdoor", "frontdoor", None),
+        ];
+
+        for (address, url_host, lookup_host, port) in cases {
+            let config = with_address(address);
+            assert_eq!(config.url_host(), url_host, "{}", address);
+            assert_eq!(config.lookup_host(), lookup_host, "{}", address);
+            assert_eq!(config.port(), port, "{}", address);
+        }
+
+        let listen = |listen_address: &str| {
+            let proxy: ProxyConfig = serde_yaml::from_str(&format!("listen_address: '{}'\nbase_path: /onvif", listen_address)).unwrap();
+            proxy.listen_port().map(str::to_string)
+        };
+        assert_eq!(listen("0.0.0.0:8000").as_deref(), Some("8000"));
+        assert_eq!(listen("[::]:8080").as_deref(), Some("8080"));
+        assert_eq!(listen("::1"), None);
+    }
+
+    #[test]
+    fn test_example_config_is_valid() {
+        let config = AppConfig::load_from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/config/cameras.yaml.example")).unwrap();
+        let report = config.validate();
+        assert!(report.is_ok(), "{:?}", report.errors);
+    }
+
+    #[test]
+    fn test_validate() {
+        let yaml = r#"
+proxy:
+  listen_address: "0.0.0.0"
+  base_path: "/onvif"
+  base_url: "http://127.0.0.1:8000"
+cameras:
+  - id: "cam1"
+    name: "One"
+    address: "192.168.1.100:80"
+    username: "admin"
+    password: ""
+    quirks: [fix_device_info_namespace, fix_everything]
+  - id: "cam1"
+    name: "Duplicate"
+    address: "http://192.168.1.101"
+    username: "admin"
+    password: "secret"
+  - id: "sim"
+    name: "Simulated"
+    address: ""
+    username: ""
+    password: ""
+    model: simulated
+"#;
+        let config: AppConfig = serde_yaml::from_str(yaml).unwrap();
+        let report = config.validate();
+
+        assert_eq!(report.errors.len(), 3, "{:?}", report.errors);
+        assert!(report.errors[0].starts_with("proxy.listen_address"));
+        assert_eq!(report.errors[1], "camera cam1: duplicate id");
+        assert!(report.errors[2].contains("without a scheme"));
+
+        assert_eq!(report.warnings.len(), 3, "{:?}", report.warnings);
+        assert!(report.warnings[0].contains("loopback"));
+        assert_eq!(report.warnings[1], "camera cam1: unknown quirk \"fix_everything\"");
+        assert_eq!(report.warnings[2], "camera cam1: username or password is empty");
+    }
+
+    #[test]
+    fn test_soap_headers_validated() {
+        assert!(is_xml_fragment(r#"<v:Token xmlns:v="urn:vendor">abc</v:Token>"#));
+        assert!(is_xml_fragment("<a/> <b>1</b>"));
+        assert!(!is_xml_fragment("<a>"));
+        assert!(!is_xml_fragment("<a></b>"));
+        assert!(!is_xml_fragment("token=abc"));
+        assert!(!is_xml_fragment(""));
+    }
+
+    #[test]
+    fn test_password_sha1() {
+        let camera = |hash: &str| CameraConfig {
+            id: "cam1".to_string(),
+            address: "192.168.1.100".to_string(),
+            username: "admin".to_string(),
+            password_sha1: Some(hash.to_string()),
+            event_method: EventMethod::Onvif,
+            ..Default::default()
+        };
+
+        assert_eq!(camera("W6ph5Mm5Pz8GgiULbPgzG37mj9g=").password_sha1_bytes().map(|b| b.len()), Some(20));
+        assert_eq!(camera("cGFzc3dvcmQ=").password_sha1_bytes(), None);
+
+        let config = |camera| AppConfig {
+            proxy: serde_yaml::from_str("listen_address: 0.0.0.0:8000\nbase_path: /onvif").unwrap(),
+            cameras: vec![camera],
+        };
+        let valid = config(camera("W6ph5Mm5Pz8GgiULbPgzG37mj9g=")).validate();
+        assert!(valid.is_ok() && valid.warnings.is_empty(), "{:?}", valid);
+        assert_eq!(config(camera("not base64")).validate().errors.len(), 1);
+    }
+
+    #[test]
+    fn test_termination_grant() {
+        let events = EventsConfig::default();
+        assert_eq!(events.grant(None), chrono::Duration::seconds(600));
+        assert_eq!(events.grant(Some(120)), chrono::Duration::seconds(120));
+        assert_eq!(events.grant(Some(86400)), chrono::Duration::seconds(3600));
+        assert_eq!(events.grant(Some(5)), chrono::Duration::seconds(60));
+        assert_eq!(events.grant(Some(-30)), chrono::Duration::seconds(60));
+    }
 }
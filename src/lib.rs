@@ -1,5 +1,7 @@
 pub mod camera;
+pub mod cli;
 pub mod config;
 pub mod onvif;
 pub mod server;
 pub mod translator;
+pub mod util;
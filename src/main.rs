@@ -1,31 +1,37 @@
-mod camera;
-mod config;
-mod onvif;
-mod server;
-mod translator;
+use onvif_proxy::{camera, cli, config, server};
 
 use anyhow::{Context, Result};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "onvif_proxy=info,tower_http=warn,reqwest=warn,hyper=warn".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
-    tracing::info!("Starting ONVIF Proxy for Reolink Cameras");
-
-    // Load configuration
-    let config_path = std::env::var("CONFIG_PATH")
-        .unwrap_or_else(|_| "config/cameras.yaml".to_string());
+    let config_path = match cli::parse_args(std::env::args().skip(1))? {
+        cli::Command::Serve => cli::config_path(),
+        cli::Command::Scan(options) => {
+            init_tracing(config::LogFormat::Text);
+            return cli::run_scan(options).await;
+        }
+        cli::Command::ValidateConfig(path) => return cli::run_validate_config(&path),
+        cli::Command::Dump(options) => {
+            init_tracing(config::LogFormat::Text);
+            return cli::run_dump(options).await;
+        }
+    };
 
+    // Load configuration first, it picks the log format
     let config = config::AppConfig::load_from_file(&config_path)
         .context("Failed to load configuration")?;
+    init_tracing(config.proxy.log_format);
+
+    tracing::info!("Starting ONVIF Proxy for Reolink Cameras");
+
+    let report = config.validate();
+    for warning in &report.warnings {
+        tracing::warn!("Config: {}", warning);
+    }
+    if !report.is_ok() {
+        anyhow::bail!("Invalid configuration:\n  {}", report.errors.join("\n  "));
+    }
 
     tracing::info!("Loaded configuration with {} cameras", config.cameras.len());
 
@@ -38,42 +44,33 @@ async fn main() -> Result<()> {
         camera_manager.add_camera(camera_config).await;
     }
 
+    // Reload what was learned about the cameras before the last restart
+    if let Some(state_path) = &config.proxy.state_path {
+        let state_path = std::path::PathBuf::from(state_path);
+        camera::learned::restore(&camera_manager, &state_path).await;
+        tokio::spawn(camera::learned::run_persistence(camera_manager.clone(), state_path));
+    }
+
     // Determine base URL for the proxy
     // Priority: config file > environment variable > auto-detect
-    let base_url = config
-        .proxy
-        .base_url
-        .clone()
-        .filter(|s| !s.trim().is_empty()) // Treat empty strings as None
-        .or_else(|| std::env::var("BASE_URL").ok())
-        .unwrap_or_else(|| {
-            // Auto-detect: extract port from listen_address
-            let port = config
-                .proxy
-                .listen_address
-                .split(':')
-                .nth(1)
-                .unwrap_or("8000");
-
-            // Try to get local IP, fallback to localhost
-            let ip = local_ip_address::local_ip()
-                .map(|ip| ip.to_string())
-                .unwrap_or_else(|_| "127.0.0.1".to_string());
-
-            let url = format!("http://{}:{}", ip, port);
-            tracing::info!("Auto-detected base URL: {}", url);
-            url
-        });
+    let base_url = config.proxy.resolve_base_url();
 
     tracing::info!("Proxy base URL: {}", base_url);
 
     // Start the server
-    server::start_server(
-        config.proxy.listen_address.clone(),
-        base_url,
-        camera_manager,
-    )
-    .await?;
+    server::start_server(config.proxy.clone(), base_url, camera_manager, Some(config_path)).await?;
 
     Ok(())
 }
+
+fn init_tracing(format: config::LogFormat) {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| "onvif_proxy=info,tower_http=warn,reqwest=warn,hyper=warn".into());
+    let json = format == config::LogFormat::Json;
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with((!json).then(tracing_subscriber::fmt::layer))
+        .with(json.then(|| tracing_subscriber::fmt::layer().json().flatten_event(true)))
+        .init();
+}
@@ -0,0 +1,88 @@
+use crate::camera::CameraClient;
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+/// Rule types Reolink firmware can represent (motion, intrusion zones, line crossing).
+/// Anything else is rejected before it reaches a Reolink camera.
+const SUPPORTED_RULE_TYPES: &[&str] = &["CellMotionDetector", "FieldDetector", "LineDetector"];
+
+pub struct AnalyticsService;
+
+impl AnalyticsService {
+    pub fn is_read_action(action: &str) -> bool {
+        matches!(
+            action,
+            "GetAnalyticsConfigurations" | "GetSupportedRules" | "GetRules" | "GetAnalyticsModules"
+        )
+    }
+
+    pub fn is_write_action(action: &str) -> bool {
+        matches!(action, "CreateRules" | "ModifyRules" | "DeleteRules")
+    }
+
+    /// Forward an analytics request to the camera's ONVIF analytics service as it is; rules are
+    /// not translated to Reolink's CGI AI config. For rule writes, callers must check
+    /// `enable_analytics_writes` and, on Reolink cameras, `unsupported_rule_type` first.
+    pub async fn forward(camera: &CameraClient, soap_body: &str) -> Result<String> {
+        let response = camera
+            .send_soap_request("/onvif/analytics_service", soap_body)
+            .await?;
+
+        Ok(response)
+    }
+
+    /// Returns the first rule type in the request that Reolink can't represent, if any
+    pub fn unsupported_rule_type(soap_body: &str) -> Option<String> {
+        Self::rule_types(soap_body)
+            .into_iter()
+            .find(|rule_type| !SUPPORTED_RULE_TYPES.contains(&rule_type.as_str()))
+    }
+
+    fn rule_types(xml: &str) -> Vec<String> {
+        // Rule elements carry their type as a QName attribute, e.g. <tt:Rule Type="tt:LineDetector">
+        let mut reader = Reader::from_str(xml);
+        let mut types = Vec::new();
+        let mut buf = Vec::new();
+
+        loop {
+            match reader.read_event_into(&mut buf) {
+                Ok(Event::Start(e)) | Ok(Event::Empty(e)) if e.local_name().as_ref() == b"Rule" => {
+                    for attr in e.attributes().flatten() {
+                        if attr.key.local_name().as_ref() == b"Type" {
+                            let value = String::from_utf8_lossy(&attr.value).to_string();
+                            // Strip the namespace prefix of the QName
+                            let local = value.rsplit(':').next().unwrap_or(&value).to_string();
+                            types.push(local);
+                        }
+                    }
+                }
+                Ok(Event::Eof) | Err(_) => break,
+                _ => {}
+            }
+            buf.clear();
+        }
+
+        types
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsupported_rule_type() {
+        let supported = r#"<CreateRules><Rule Name="Zone1" Type="tt:FieldDetector"/></CreateRules>"#;
+        assert_eq!(AnalyticsService::unsupported_rule_type(supported), None);
+
+        let unsupported = r#"<CreateRules>
+  <Rule Name="Zone1" Type="tt:CellMotionDetector"></Rule>
+  <Rule Name="Loiter" Type="tt:LoiteringDetector"></Rule>
+</CreateRules>"#;
+        assert_eq!(
+            AnalyticsService::unsupported_rule_type(unsupported),
+            Some("LoiteringDetector".to_string())
+        );
+    }
+}
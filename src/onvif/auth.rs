@@ -1,33 +1,70 @@
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD as BASE64;
 use sha1::{Sha1, Digest};
+use std::collections::HashMap;
+use std::sync::Mutex;
 use uuid::Uuid;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+
+use crate::config::AuthMode;
+use crate::onvif::soap::WsSecurity;
+use quick_xml::escape::escape;
 
 #[derive(Debug, Clone)]
 pub struct WsSecurityAuth {
     pub username: String,
     pub password: String,
+    /// SHA-1 of the password, used as the digest secret instead of `password` when set
+    pub password_sha1: Option<Vec<u8>>,
+    pub mode: AuthMode,
 }
 
 impl WsSecurityAuth {
     pub fn new(username: String, password: String) -> Self {
-        Self { username, password }
+        Self { username, password, password_sha1: None, mode: AuthMode::Digest }
+    }
+
+    /// Authenticate with a stored SHA-1 password hash instead of the password. The digest is
+    /// `Base64(SHA1(nonce + created + SHA1(password)))`, which only devices that themselves key
+    /// the digest on the hashed password accept; standard ONVIF devices need the cleartext.
+    pub fn with_password_sha1(username: String, password_sha1: Vec<u8>) -> Self {
+        Self {
+            username,
+            password: String::new(),
+            password_sha1: Some(password_sha1),
+            mode: AuthMode::Digest,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: AuthMode) -> Self {
+        self.mode = mode;
+        self
     }
 
     pub fn generate_header(&self) -> String {
+        self.generate_header_at(Utc::now())
+    }
+
+    /// The header with `now` as the `Created` time, e.g. the proxy clock moved to the camera's
+    pub fn generate_header_at(&self, now: DateTime<Utc>) -> String {
+        if self.mode == AuthMode::Text {
+            return format!(
+                r#"<wsse:Security>
+  <wsse:UsernameToken>
+    <wsse:Username>{}</wsse:Username>
+    <wsse:Password Type="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-username-token-profile-1.0#PasswordText">{}</wsse:Password>
+  </wsse:UsernameToken>
+</wsse:Security>"#,
+                escape(&self.username),
+                escape(&self.password)
+            );
+        }
+
         let nonce_bytes = Uuid::new_v4().as_bytes().to_vec();
         let nonce_base64 = BASE64.encode(&nonce_bytes);
 
-        let created = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-
-        // Calculate password digest: Base64(SHA1(nonce + created + password))
-        let mut hasher = Sha1::new();
-        hasher.update(&nonce_bytes);
-        hasher.update(created.as_bytes());
-        hasher.update(self.password.as_bytes());
-        let digest = hasher.finalize();
-        let password_digest = BASE64.encode(digest);
+        let created = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        let password_digest = self.password_digest(&nonce_bytes, &created);
 
         format!(
             r#"<wsse:Security>
@@ -38,9 +75,71 @@ impl WsSecurityAuth {
     <wsu:Created>{}</wsu:Created>
   </wsse:UsernameToken>
 </wsse:Security>"#,
-            self.username, password_digest, nonce_base64, created
+            escape(&self.username), password_digest, nonce_base64, created
         )
     }
+
+    /// Base64(SHA1(nonce + created + secret)), the secret being the password or its SHA-1
+    fn password_digest(&self, nonce: &[u8], created: &str) -> String {
+        let secret = self.password_sha1.as_deref().unwrap_or(self.password.as_bytes());
+
+        let mut hasher = Sha1::new();
+        hasher.update(nonce);
+        hasher.update(created.as_bytes());
+        hasher.update(secret);
+        BASE64.encode(hasher.finalize())
+    }
+}
+
+/// How far a PasswordDigest token's `Created` may be from the verifier's clock, either way
+pub const TOKEN_FRESHNESS_SECS: i64 = 300;
+
+/// Nonces of the PasswordDigest tokens accepted within the freshness window, so a captured
+/// token can't be replayed; older ones are rejected for their `Created` anyway
+#[derive(Default)]
+pub struct NonceCache {
+    seen: Mutex<HashMap<(String, String), DateTime<Utc>>>,
+}
+
+impl NonceCache {
+    /// Record the nonce of `username`'s token created at `created`; false if it was seen before
+    fn insert(&self, username: &str, nonce: &str, created: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, created| (now - *created).num_seconds() <= TOKEN_FRESHNESS_SECS);
+        seen.insert((username.to_string(), nonce.to_string()), created).is_none()
+    }
+}
+
+/// Whether a client's UsernameToken proves `username` and `password`: a PasswordDigest
+/// (`Base64(SHA1(nonce + created + password))`) created within `TOKEN_FRESHNESS_SECS` of `now`
+/// whose nonce `nonces` hasn't seen, or the PasswordText itself. `now` should be the camera's
+/// clock, which clients set from its GetSystemDateAndTime
+pub fn verify_username_token(token: &WsSecurity, username: &str, password: &str, now: DateTime<Utc>, nonces: &NonceCache) -> bool {
+    if token.username != username {
+        return false;
+    }
+    if !token.is_digest {
+        return constant_time_eq(token.password.as_bytes(), password.as_bytes());
+    }
+
+    let Ok(created) = DateTime::parse_from_rfc3339(token.created.trim()) else {
+        return false;
+    };
+    let created = created.with_timezone(&Utc);
+    if (now - created).num_seconds().abs() > TOKEN_FRESHNESS_SECS {
+        return false;
+    }
+    let Ok(nonce) = BASE64.decode(token.nonce.trim()) else {
+        return false;
+    };
+    let expected = WsSecurityAuth::new(username.to_string(), password.to_string()).password_digest(&nonce, &token.created);
+    constant_time_eq(token.password.trim().as_bytes(), expected.as_bytes())
+        && nonces.insert(username, token.nonce.trim(), created, now)
+}
+
+/// Compare secrets in time independent of where they differ
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
 }
 
 #[cfg(test)]
@@ -57,4 +156,64 @@ mod tests {
         assert!(header.contains("<wsse:Nonce"));
         assert!(header.contains("<wsu:Created"));
     }
+
+    #[test]
+    fn test_auth_modes() {
+        let digest = WsSecurityAuth::new("admin".to_string(), "pa<ss".to_string()).generate_header();
+        assert!(digest.contains(r#"#PasswordDigest">"#));
+        assert!(!digest.contains("pa<ss") && !digest.contains("pa&lt;ss"));
+        assert!(digest.contains("<wsse:Nonce"));
+        let digest = WsSecurityAuth::new("R&D<ops>".to_string(), "password".to_string()).generate_header();
+        assert!(digest.contains("<wsse:Username>R&amp;D&lt;ops&gt;</wsse:Username>"));
+
+        let text = WsSecurityAuth::new("admin".to_string(), "pa<ss".to_string()).with_mode(AuthMode::Text).generate_header();
+        assert!(text.contains(r#"#PasswordText">pa&lt;ss</wsse:Password>"#));
+        assert!(!text.contains("<wsse:Nonce") && !text.contains("<wsu:Created"));
+    }
+
+    #[test]
+    fn test_password_digest() {
+        let nonce = b"0123456789abcdef";
+        let created = "2026-01-01T00:00:00.000Z";
+
+        let plain = WsSecurityAuth::new("admin".to_string(), "password".to_string());
+        assert_eq!(plain.password_digest(nonce, created), "yEGjglrpcgLYIOUsQnUl1Jx4yE4=");
+
+        let sha1 = BASE64.decode("W6ph5Mm5Pz8GgiULbPgzG37mj9g=").unwrap();
+        let hashed = WsSecurityAuth::with_password_sha1("admin".to_string(), sha1);
+        assert_eq!(hashed.password_digest(nonce, created), "VOUFmjSFzR5eQYwc3QRDpjD2Rd4=");
+    }
+
+    #[test]
+    fn test_verify_username_token() {
+        let token = WsSecurity {
+            username: "admin".to_string(),
+            password: "yEGjglrpcgLYIOUsQnUl1Jx4yE4=".to_string(),
+            is_digest: true,
+            nonce: BASE64.encode(b"0123456789abcdef"),
+            created: "2026-01-01T00:00:00.000Z".to_string(),
+            xml: String::new(),
+        };
+        let now = DateTime::parse_from_rfc3339("2026-01-01T00:01:00Z").unwrap().with_timezone(&Utc);
+        let verify = |token: &WsSecurity, username: &str, password: &str| {
+            verify_username_token(token, username, password, now, &NonceCache::default())
+        };
+        assert!(verify(&token, "admin", "password"));
+        assert!(!verify(&token, "admin", "wrong"));
+        assert!(!verify(&token, "operator", "password"));
+        assert!(!verify(&WsSecurity { nonce: "not base64!".to_string(), ..token.clone() }, "admin", "password"));
+
+        let text = WsSecurity { password: "password".to_string(), is_digest: false, ..token.clone() };
+        assert!(verify(&text, "admin", "password"));
+        assert!(!verify(&text, "admin", "passwor"));
+
+        // Stale (or far future) tokens, and a nonce used before, are refused
+        let later = now + chrono::Duration::seconds(TOKEN_FRESHNESS_SECS);
+        assert!(!verify_username_token(&token, "admin", "password", later, &NonceCache::default()));
+        let earlier = now - chrono::Duration::seconds(TOKEN_FRESHNESS_SECS + 120);
+        assert!(!verify_username_token(&token, "admin", "password", earlier, &NonceCache::default()));
+        let nonces = NonceCache::default();
+        assert!(verify_username_token(&token, "admin", "password", now, &nonces));
+        assert!(!verify_username_token(&token, "admin", "password", now, &nonces));
+    }
 }
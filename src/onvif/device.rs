@@ -1,19 +1,191 @@
 use crate::camera::CameraClient;
+use crate::onvif::{discovery, soap, xmlns};
+use crate::onvif::types::EventsCapabilities;
 use anyhow::Result;
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Timelike, Utc};
+use std::collections::HashMap;
+
+/// Namespace of the vendor element listing proxy-provided features in GetCapabilities
+const PROXY_CAPABILITIES_NS: &str = "urn:onvif-proxy:capabilities";
+const PROXY_CAPABILITIES_PREFIX: &str = "opx";
 
 pub struct DeviceService;
 
+/// Where the proxy's additions go in a GetCapabilities `Capabilities` element, found by local
+/// names so any prefix (or none) the camera uses works
+struct CapabilitiesLayout {
+    /// Just after the `Capabilities` start tag
+    open: usize,
+    /// Start of the `Capabilities` end tag
+    close: usize,
+    has_analytics: bool,
+    /// The `Extension` child, when it is the last one
+    extension: Option<Extension>,
+}
+
+enum Extension {
+    /// `<tt:Extension/>`: its byte range and qualified name
+    Empty { range: std::ops::Range<usize>, qualified_name: String },
+    /// Start of its end tag, and of the end tag of its `Extensions` child when that is its last
+    Open { close: usize, extensions_close: Option<usize> },
+}
+
+impl CapabilitiesLayout {
+    fn find(xml: &str) -> Option<Self> {
+        let mut reader = Reader::from_str(xml);
+        // Depth below Capabilities, once inside it
+        let mut depth: Option<usize> = None;
+        let mut layout = CapabilitiesLayout { open: 0, close: 0, has_analytics: false, extension: None };
+        // Local names of the open Capabilities child and of its last child seen
+        let mut child = Vec::new();
+        let mut grandchild = Vec::new();
+        let mut grandchild_close = None;
+
+        loop {
+            let start = reader.buffer_position() as usize;
+            let event = reader.read_event().ok()?;
+            let end = reader.buffer_position() as usize;
+            match (event, depth) {
+                (Event::Start(e), None) if e.local_name().as_ref() == b"Capabilities" => {
+                    layout.open = end;
+                    depth = Some(0);
+                }
+                (Event::Start(e), Some(d)) => {
+                    if d == 0 {
+                        layout.has_analytics |= e.local_name().as_ref() == b"Analytics";
+                        layout.extension = None;
+                        child = e.local_name().as_ref().to_vec();
+                        grandchild.clear();
+                        grandchild_close = None;
+                    } else if d == 1 {
+                        grandchild = e.local_name().as_ref().to_vec();
+                    }
+                    depth = Some(d + 1);
+                }
+                (Event::Empty(e), Some(d)) => {
+                    if d == 0 {
+                        layout.has_analytics |= e.local_name().as_ref() == b"Analytics";
+                        layout.extension = (e.local_name().as_ref() == b"Extension").then(|| Extension::Empty {
+                            range: start..end,
+                            qualified_name: String::from_utf8_lossy(e.name().as_ref()).to_string(),
+                        });
+                        child.clear();
+                    } else if d == 1 {
+                        grandchild = e.local_name().as_ref().to_vec();
+                        grandchild_close = None;
+                    }
+                }
+                (Event::End(_), Some(0)) => {
+                    layout.close = start;
+                    return Some(layout);
+                }
+                (Event::End(_), Some(d)) => {
+                    if d == 1 && child == b"Extension" {
+                        let extensions_close = if grandchild == b"Extensions" { grandchild_close } else { None };
+                        layout.extension = Some(Extension::Open { close: start, extensions_close });
+                    } else if d == 2 {
+                        grandchild_close = Some(start);
+                    }
+                    depth = Some(d - 1);
+                }
+                (Event::Eof, _) => return None,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// How the camera keeps its time (`tt:SetDateTimeType`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateTimeType {
+    Manual,
+    Ntp,
+}
+
+impl DateTimeType {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "Manual" => Some(DateTimeType::Manual),
+            "NTP" => Some(DateTimeType::Ntp),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            DateTimeType::Manual => "Manual",
+            DateTimeType::Ntp => "NTP",
+        }
+    }
+}
+
+/// The settings of a SetSystemDateAndTime request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemDateAndTime {
+    pub date_time_type: DateTimeType,
+    pub daylight_savings: bool,
+    /// POSIX TZ string, e.g. `CST-8`
+    pub time_zone: Option<String>,
+    /// Only sent in `Manual` mode; NTP cameras take their time from the server
+    pub utc_date_time: Option<NaiveDateTime>,
+}
+
 impl DeviceService {
+    /// The camera's date and time, also learning its clock skew. Cameras must answer it
+    /// unauthenticated, so a camera that refuses the token (its clock may be the reason) is
+    /// asked again without one
     pub async fn get_system_date_and_time(camera: &CameraClient) -> Result<String> {
         let request_body = r#"<tds:GetSystemDateAndTime xmlns:tds="http://www.onvif.org/ver10/device/wsdl"/>"#;
 
-        let response = camera
+        let mut response = camera
             .send_soap_request("/onvif/device_service", request_body)
             .await?;
+        if soap::is_fault(&response) {
+            tracing::debug!("Camera {} refused an authenticated GetSystemDateAndTime, retrying without", camera.camera_id());
+            response = camera.send_soap_request_no_auth("/onvif/device_service", request_body).await?;
+        }
+
+        if let Some(camera_time) = Self::parse_utc_date_time(&response) {
+            let skew = (camera_time - Utc::now()).num_seconds();
+            camera.update_learned_state(|state| state.clock_skew_secs = Some(skew)).await;
+        }
 
         Ok(response)
     }
 
+    pub async fn set_system_date_and_time(camera: &CameraClient, settings: &SystemDateAndTime) -> Result<String> {
+        camera
+            .send_soap_request("/onvif/device_service", &Self::set_system_date_and_time_body(settings))
+            .await
+    }
+
+    fn set_system_date_and_time_body(settings: &SystemDateAndTime) -> String {
+        let mut body = format!(
+            r#"<tds:SetSystemDateAndTime xmlns:tds="http://www.onvif.org/ver10/device/wsdl" xmlns:tt="http://www.onvif.org/ver10/schema"><tds:DateTimeType>{}</tds:DateTimeType><tds:DaylightSavings>{}</tds:DaylightSavings>"#,
+            settings.date_time_type.as_str(),
+            settings.daylight_savings
+        );
+        if let Some(tz) = &settings.time_zone {
+            body.push_str(&format!("<tds:TimeZone><tt:TZ>{}</tt:TZ></tds:TimeZone>", escape(tz)));
+        }
+        if let Some(utc) = settings.utc_date_time.filter(|_| settings.date_time_type == DateTimeType::Manual) {
+            body.push_str(&format!(
+                "<tds:UTCDateTime><tt:Time><tt:Hour>{}</tt:Hour><tt:Minute>{}</tt:Minute><tt:Second>{}</tt:Second></tt:Time><tt:Date><tt:Year>{}</tt:Year><tt:Month>{}</tt:Month><tt:Day>{}</tt:Day></tt:Date></tds:UTCDateTime>",
+                utc.hour(),
+                utc.minute(),
+                utc.second(),
+                utc.year(),
+                utc.month(),
+                utc.day()
+            ));
+        }
+        body.push_str("</tds:SetSystemDateAndTime>");
+        body
+    }
+
     pub async fn get_device_information(camera: &CameraClient, _base_url: &str) -> Result<String> {
         let request_body = r#"<tds:GetDeviceInformation xmlns:tds="http://www.onvif.org/ver10/device/wsdl"/>"#;
 
@@ -22,12 +194,75 @@ impl DeviceService {
             .await?;
 
         // Fix namespace issues in Reolink response
-        let fixed_response = Self::fix_device_info_namespaces(&response);
+        let mut fixed_response = Self::fix_device_info_namespaces(&response);
+
+        // Present the camera as a different brand/model if configured (VMS licensing)
+        if let Some(manufacturer) = &camera.config().report_manufacturer {
+            fixed_response = replace_element_text(&fixed_response, "Manufacturer", manufacturer);
+        }
+        if let Some(model) = &camera.config().report_model {
+            fixed_response = replace_element_text(&fixed_response, "Model", model);
+        }
 
         Ok(fixed_response)
     }
 
-    pub async fn get_capabilities(camera: &CameraClient, base_url: &str) -> Result<String> {
+    /// The camera's scopes, completed with the ones WS-Discovery advertises for kinds the camera
+    /// leaves out (commonly `name` and `hardware`); all of them when the camera faults
+    pub async fn get_scopes(camera: &CameraClient) -> Result<String> {
+        let request_body = r#"<tds:GetScopes xmlns:tds="http://www.onvif.org/ver10/device/wsdl"/>"#;
+
+        let response = camera
+            .send_soap_request("/onvif/device_service", request_body)
+            .await?;
+
+        Ok(Self::complete_scopes(&response, &discovery::camera_scopes(camera.config())))
+    }
+
+    pub async fn set_scopes(camera: &CameraClient, soap_body: &str) -> Result<String> {
+        camera.send_soap_request("/onvif/device_service", soap_body).await
+    }
+
+    /// Add the `advertised` scopes whose kind (the URI up to its last `/`) the response lacks,
+    /// as `Fixed` scopes; a response that has them all is returned unchanged
+    fn complete_scopes(response: &str, advertised: &[String]) -> String {
+        let mut scopes: Vec<(String, String)> = if soap::is_fault(response) {
+            Vec::new()
+        } else {
+            element_texts(response, "ScopeDef").into_iter().zip(element_texts(response, "ScopeItem")).collect()
+        };
+        let kind = |scope: &str| scope.rsplit_once('/').map(|(kind, _)| kind.to_string()).unwrap_or_default();
+
+        let missing: Vec<&String> = advertised
+            .iter()
+            .filter(|scope| !scopes.iter().any(|(_, item)| kind(item) == kind(scope)))
+            .collect();
+        if missing.is_empty() && !soap::is_fault(response) {
+            return response.to_string();
+        }
+        scopes.extend(missing.into_iter().map(|scope| ("Fixed".to_string(), scope.clone())));
+
+        let items: String = scopes
+            .iter()
+            .map(|(def, item)| {
+                format!(
+                    "<tds:Scopes><tt:ScopeDef>{}</tt:ScopeDef><tt:ScopeItem>{}</tt:ScopeItem></tds:Scopes>",
+                    escape(def),
+                    escape(item)
+                )
+            })
+            .collect();
+        soap::build_envelope(
+            &[("tds", "http://www.onvif.org/ver10/device/wsdl"), ("tt", "http://www.onvif.org/ver10/schema")],
+            &format!("<tds:GetScopesResponse>{}</tds:GetScopesResponse>", items),
+        )
+    }
+
+    pub async fn get_capabilities(
+        camera: &CameraClient,
+        base_url: &str,
+        service_base_urls: &HashMap<String, String>,
+    ) -> Result<String> {
         let request_body = r#"<tds:GetCapabilities xmlns:tds="http://www.onvif.org/ver10/device/wsdl"><tds:Category>All</tds:Category></tds:GetCapabilities>"#;
 
         let response = camera
@@ -35,22 +270,102 @@ impl DeviceService {
             .await?;
 
         // Rewrite XAddr URLs to point to our proxy instead of the camera
-        let fixed_response = Self::rewrite_capability_urls(&response, &camera.config().id, base_url);
+        let fixed_response = Self::rewrite_capability_urls(&response, &camera.config().id, base_url, service_base_urls);
+        let mut fixed_response = Self::advertise_proxy_events(&fixed_response);
+
+        if camera.config().advertise_proxy_capabilities {
+            let analytics_base = service_base_urls
+                .get("analytics_service")
+                .map(|url| url.trim_end_matches('/'))
+                .unwrap_or(base_url);
+            let analytics_xaddr = format!("{}/onvif/{}/analytics_service", analytics_base, camera.config().id);
+            let synthetic_motion = camera.config().event_method.uses_cgi();
+            fixed_response = Self::add_proxy_capabilities(&fixed_response, &analytics_xaddr, synthetic_motion);
+        }
 
         Ok(fixed_response)
     }
 
-    pub async fn get_services(camera: &CameraClient, base_url: &str) -> Result<String> {
+    pub async fn get_services(
+        camera: &CameraClient,
+        base_url: &str,
+        service_base_urls: &HashMap<String, String>,
+    ) -> Result<String> {
         let request_body = r#"<tds:GetServices xmlns:tds="http://www.onvif.org/ver10/device/wsdl"><tds:IncludeCapability>true</tds:IncludeCapability></tds:GetServices>"#;
 
         let response = camera
             .send_soap_request("/onvif/device_service", request_body)
             .await?;
 
+        let services = element_texts(&response, "Namespace");
+        if !services.is_empty() {
+            camera.update_learned_state(|state| state.services = services).await;
+        }
+
         // Rewrite service URLs to point to our proxy
-        let fixed_response = Self::rewrite_service_urls(&response, &camera.config().id, base_url);
+        let fixed_response = Self::rewrite_service_urls(&response, &camera.config().id, base_url, service_base_urls);
 
-        Ok(fixed_response)
+        Ok(Self::advertise_proxy_events(&fixed_response))
+    }
+
+    pub async fn get_dot11_capabilities(camera: &CameraClient) -> Result<String> {
+        let request_body = r#"<tds:GetDot11Capabilities xmlns:tds="http://www.onvif.org/ver10/device/wsdl"/>"#;
+
+        let response = camera
+            .send_soap_request("/onvif/device_service", request_body)
+            .await?;
+
+        // Wired cameras fault (or answer with junk); report no 802.11 support instead
+        if !response.contains("GetDot11CapabilitiesResponse") {
+            tracing::debug!("Camera {} has no 802.11 support, reporting empty Dot11 capabilities", camera.camera_id());
+            return Ok(Self::wired_dot11_capabilities());
+        }
+
+        Ok(response)
+    }
+
+    /// Returns `None` when the camera isn't wireless, which callers report as a `ter:NotDot11` fault
+    pub async fn get_dot11_status(camera: &CameraClient, interface_token: &str) -> Result<Option<String>> {
+        let request_body = format!(
+            r#"<tds:GetDot11Status xmlns:tds="http://www.onvif.org/ver10/device/wsdl"><tds:InterfaceToken>{}</tds:InterfaceToken></tds:GetDot11Status>"#,
+            quick_xml::escape::escape(interface_token)
+        );
+
+        let response = camera
+            .send_soap_request("/onvif/device_service", &request_body)
+            .await?;
+
+        if !response.contains("GetDot11StatusResponse") {
+            return Ok(None);
+        }
+
+        Ok(Some(response))
+    }
+
+    /// Camera clock from the UTCDateTime section of a GetSystemDateAndTime response
+    fn parse_utc_date_time(xml: &str) -> Option<DateTime<Utc>> {
+        let start = xml.find("UTCDateTime>")?;
+        let section = &xml[start..];
+        let field = |name: &str| -> Option<u32> { element_texts(section, name).first()?.parse().ok() };
+
+        let date = NaiveDate::from_ymd_opt(field("Year")? as i32, field("Month")?, field("Day")?)?;
+        let time = date.and_hms_opt(field("Hour")?, field("Minute")?, field("Second")?)?;
+        Some(time.and_utc())
+    }
+
+    fn wired_dot11_capabilities() -> String {
+        soap::build_envelope(
+            &[("tds", "http://www.onvif.org/ver10/device/wsdl"), ("tt", "http://www.onvif.org/ver10/schema")],
+            r#"<tds:GetDot11CapabilitiesResponse>
+  <tds:Capabilities>
+    <tt:TKIP>false</tt:TKIP>
+    <tt:ScanAvailableNetworks>false</tt:ScanAvailableNetworks>
+    <tt:MultipleConfiguration>false</tt:MultipleConfiguration>
+    <tt:AdHocStationMode>false</tt:AdHocStationMode>
+    <tt:WEP>false</tt:WEP>
+  </tds:Capabilities>
+</tds:GetDot11CapabilitiesResponse>"#,
+        )
     }
 
     fn fix_device_info_namespaces(xml: &str) -> String {
@@ -60,24 +375,23 @@ impl DeviceService {
 
         // Add missing tt namespace if not present
         if !fixed.contains("xmlns:tt=") && fixed.contains("<tt:") {
-            fixed = fixed.replace(
-                "<SOAP-ENV:Envelope",
-                r#"<SOAP-ENV:Envelope xmlns:tt="http://www.onvif.org/ver10/schema""#,
-            );
+            fixed = xmlns::ensure_namespace(&fixed, "tt", "http://www.onvif.org/ver10/schema");
         }
 
         // Add missing tds namespace if not present
         if !fixed.contains("xmlns:tds=") && fixed.contains("<tds:") {
-            fixed = fixed.replace(
-                "<SOAP-ENV:Envelope",
-                r#"<SOAP-ENV:Envelope xmlns:tds="http://www.onvif.org/ver10/device/wsdl""#,
-            );
+            fixed = xmlns::ensure_namespace(&fixed, "tds", "http://www.onvif.org/ver10/device/wsdl");
         }
 
         fixed
     }
 
-    fn rewrite_capability_urls(xml: &str, camera_id: &str, base_url: &str) -> String {
+    fn rewrite_capability_urls(
+        xml: &str,
+        camera_id: &str,
+        base_url: &str,
+        service_base_urls: &HashMap<String, String>,
+    ) -> String {
         // Replace camera's internal URLs with our proxy URLs
         // This ensures iSpy talks to us instead of trying to reach the camera directly
         let mut result = xml.to_string();
@@ -95,6 +409,12 @@ impl DeviceService {
         ];
 
         for service in services {
+            // A per-service override lets e.g. media be served from a different host/scheme
+            let service_base = service_base_urls
+                .get(service)
+                .map(|url| url.trim_end_matches('/'))
+                .unwrap_or(base_url);
+
             // Match full URL patterns like: http://192.168.30.11:8000/onvif/device_service
             // We need to replace the entire URL, not just the path, to avoid concatenation
             // Look for pattern: http://[anything]/onvif/[service]
@@ -110,7 +430,7 @@ impl DeviceService {
                 if let Some(url_start) = before_path.rfind("http://").or_else(|| before_path.rfind("https://")) {
                     // Extract and replace the full URL
                     let old_url = &result[url_start..abs_pos + path_pattern.len()];
-                    let new_url = format!("{}/onvif/{}/{}", base_url, camera_id, service);
+                    let new_url = format!("{}/onvif/{}/{}", service_base, camera_id, service);
                     result = result.replace(old_url, &new_url);
                     search_start = url_start + new_url.len();
                 } else {
@@ -122,8 +442,367 @@ impl DeviceService {
         result
     }
 
-    fn rewrite_service_urls(xml: &str, camera_id: &str, base_url: &str) -> String {
+    /// Replace the camera's (often wrong) event capabilities with what the proxy implements,
+    /// so clients don't try subscription flows the proxy can't serve
+    fn advertise_proxy_events(xml: &str) -> String {
+        let capabilities = EventsCapabilities::proxy(String::new());
+        let mut result = xml.to_string();
+
+        for (name, supported) in capabilities.flags() {
+            let value = if supported { "true" } else { "false" };
+            // GetCapabilities: <tt:WSPullPointSupport>false</tt:WSPullPointSupport>
+            result = replace_element_text(&result, name, value);
+            // GetServices: <tev:Capabilities WSPullPointSupport="false" .../>
+            result = replace_attribute_value(&result, name, value);
+        }
+
+        result
+    }
+
+    /// Advertise features the proxy provides on top of the camera: an Analytics section when the
+    /// camera reports none, and a vendor entry under Capabilities/Extension/Extensions
+    fn add_proxy_capabilities(xml: &str, analytics_xaddr: &str, synthetic_motion: bool) -> String {
+        let Some(layout) = CapabilitiesLayout::find(xml) else {
+            return xml.to_string();
+        };
+
+        let proxy_entry = format!(
+            r#"<{}:ProxyCapabilities xmlns:{}="{}" SyntheticMotionEvents="{}" AnalyticsService="true"/>"#,
+            PROXY_CAPABILITIES_PREFIX, PROXY_CAPABILITIES_PREFIX, PROXY_CAPABILITIES_NS, synthetic_motion
+        );
+        // Capabilities/Extension is the last child; Extensions is the last child of Extension
+        let (range, insertion) = match &layout.extension {
+            Some(Extension::Empty { range, qualified_name }) => {
+                (range.clone(), format!("<{}><tt:Extensions>{}</tt:Extensions></{}>", qualified_name, proxy_entry, qualified_name))
+            }
+            Some(Extension::Open { extensions_close: Some(extensions_close), .. }) => (*extensions_close..*extensions_close, proxy_entry),
+            Some(Extension::Open { close, extensions_close: None }) => {
+                (*close..*close, format!("<tt:Extensions>{}</tt:Extensions>", proxy_entry))
+            }
+            None => (layout.close..layout.close, format!("<tt:Extension><tt:Extensions>{}</tt:Extensions></tt:Extension>", proxy_entry)),
+        };
+        let mut result = format!("{}{}{}", &xml[..range.start], insertion, &xml[range.end..]);
+
+        // Analytics is the first child of Capabilities; inserted last, as it comes before the rest
+        if !layout.has_analytics {
+            result.insert_str(
+                layout.open,
+                &format!(
+                    "<tt:Analytics><tt:XAddr>{}</tt:XAddr><tt:RuleSupport>true</tt:RuleSupport><tt:AnalyticsModuleSupport>true</tt:AnalyticsModuleSupport></tt:Analytics>",
+                    quick_xml::escape::escape(analytics_xaddr)
+                ),
+            );
+        }
+
+        // The added elements use tt:, whatever prefix the camera gave the schema namespace
+        xmlns::ensure_namespace(&result, "tt", "http://www.onvif.org/ver10/schema")
+    }
+
+    fn rewrite_service_urls(
+        xml: &str,
+        camera_id: &str,
+        base_url: &str,
+        service_base_urls: &HashMap<String, String>,
+    ) -> String {
         // Similar to capability URLs, rewrite service URLs
-        Self::rewrite_capability_urls(xml, camera_id, base_url)
+        Self::rewrite_capability_urls(xml, camera_id, base_url, service_base_urls)
+    }
+}
+
+/// Replace the text content of every element with the given local name, whatever its prefix
+fn replace_element_text(xml: &str, local_name: &str, value: &str) -> String {
+    let escaped = quick_xml::escape::escape(value);
+    let mut result = String::with_capacity(xml.len());
+    let mut rest = xml;
+
+    while let Some(tag_start) = rest.find('<') {
+        let after = &rest[tag_start + 1..];
+        let name_len = after
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(after.len());
+        let qualified_name = &after[..name_len];
+        let matches = qualified_name.rsplit(':').next() == Some(local_name);
+
+        let tag_end = match after.find('>') {
+            Some(pos) => tag_start + 1 + pos,
+            None => break,
+        };
+
+        // Only rewrite non-empty start tags; self-closing and closing tags pass through
+        if !matches || rest[..tag_end].ends_with('/') {
+            result.push_str(&rest[..=tag_end]);
+            rest = &rest[tag_end + 1..];
+            continue;
+        }
+
+        let close_tag = format!("</{}>", qualified_name);
+        match rest[tag_end..].find(&close_tag) {
+            Some(close_pos) => {
+                result.push_str(&rest[..=tag_end]);
+                result.push_str(&escaped);
+                rest = &rest[tag_end + close_pos..];
+            }
+            None => {
+                result.push_str(&rest[..=tag_end]);
+                rest = &rest[tag_end + 1..];
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Text content of every element with the given local name, whatever its prefix
+fn element_texts(xml: &str, local_name: &str) -> Vec<String> {
+    let mut texts = Vec::new();
+    let mut rest = xml;
+
+    while let Some(tag_start) = rest.find('<') {
+        let after = &rest[tag_start + 1..];
+        let name_len = after
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(after.len());
+        let qualified_name = &after[..name_len];
+        let Some(tag_end) = after.find('>') else {
+            break;
+        };
+        rest = &after[tag_end + 1..];
+
+        if qualified_name.rsplit(':').next() != Some(local_name) || after[..tag_end].ends_with('/') {
+            continue;
+        }
+        if let Some(close) = rest.find(&format!("</{}>", qualified_name)) {
+            texts.push(rest[..close].trim().to_string());
+        }
+    }
+
+    texts
+}
+
+/// Replace the value of every attribute with the given (unprefixed) name
+fn replace_attribute_value(xml: &str, name: &str, value: &str) -> String {
+    let mut result = String::with_capacity(xml.len());
+    let mut rest = xml;
+    let needle = format!(" {}=", name);
+
+    while let Some(pos) = rest.find(&needle) {
+        let value_start = pos + needle.len();
+        let quote = match rest[value_start..].chars().next() {
+            Some(q @ ('"' | '\'')) => q,
+            _ => {
+                result.push_str(&rest[..value_start]);
+                rest = &rest[value_start..];
+                continue;
+            }
+        };
+
+        match rest[value_start + 1..].find(quote) {
+            Some(len) => {
+                result.push_str(&rest[..=value_start]);
+                result.push_str(value);
+                rest = &rest[value_start + 1 + len..];
+            }
+            None => break,
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraConfig;
+
+    #[tokio::test]
+    async fn test_scopes_from_config() {
+        // The simulator faults on GetScopes, so every scope comes from the config
+        let camera = CameraClient::new(CameraConfig {
+            id: "sim".to_string(),
+            name: "Front Door".to_string(),
+            model: "simulated".to_string(),
+            report_model: Some("RLC-810A".to_string()),
+            ..Default::default()
+        });
+        let response = DeviceService::get_scopes(&camera).await.unwrap();
+        assert!(response.contains("<tds:GetScopesResponse>"), "{}", response);
+        assert!(response.contains("<tt:ScopeDef>Fixed</tt:ScopeDef><tt:ScopeItem>onvif://www.onvif.org/name/Front%20Door</tt:ScopeItem>"));
+        assert!(response.contains("<tt:ScopeItem>onvif://www.onvif.org/hardware/RLC-810A</tt:ScopeItem>"));
+
+        // Camera scopes are kept; only the kinds the camera lacks are added
+        let camera_response = soap::build_envelope(
+            &[("tds", "http://www.onvif.org/ver10/device/wsdl"), ("tt", "http://www.onvif.org/ver10/schema")],
+            "<tds:GetScopesResponse><tds:Scopes><tt:ScopeDef>Configurable</tt:ScopeDef><tt:ScopeItem>onvif://www.onvif.org/name/Garage</tt:ScopeItem></tds:Scopes><tds:Scopes><tt:ScopeDef>Fixed</tt:ScopeDef><tt:ScopeItem>onvif://www.onvif.org/type/video_encoder</tt:ScopeItem></tds:Scopes></tds:GetScopesResponse>",
+        );
+        let completed = DeviceService::complete_scopes(&camera_response, &discovery::camera_scopes(camera.config()));
+        assert!(completed.contains("<tt:ScopeDef>Configurable</tt:ScopeDef><tt:ScopeItem>onvif://www.onvif.org/name/Garage</tt:ScopeItem>"));
+        assert!(!completed.contains("Front%20Door"));
+        assert!(completed.contains("onvif://www.onvif.org/hardware/RLC-810A"));
+        assert!(completed.contains("onvif://www.onvif.org/Profile/Streaming"));
+        assert_eq!(completed.matches("type/").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dot11_on_wired_camera() {
+        // The simulator faults on Dot11 requests, like a wired Reolink
+        let camera = CameraClient::new(CameraConfig {
+            id: "sim".to_string(),
+            model: "simulated".to_string(),
+            ..Default::default()
+        });
+
+        let capabilities = DeviceService::get_dot11_capabilities(&camera).await.unwrap();
+        assert!(capabilities.contains("<tds:GetDot11CapabilitiesResponse>"));
+        assert!(capabilities.contains("<tt:ScanAvailableNetworks>false</tt:ScanAvailableNetworks>"));
+
+        assert!(DeviceService::get_dot11_status(&camera, "wlan0").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rewrite_capability_urls_with_service_override() {
+        let xml = r#"<tt:Device><tt:XAddr>http://192.168.1.10:8000/onvif/device_service</tt:XAddr></tt:Device><tt:Media><tt:XAddr>http://192.168.1.10:8000/onvif/media_service</tt:XAddr></tt:Media>"#;
+        let mut overrides = HashMap::new();
+        overrides.insert("media_service".to_string(), "https://cdn.example.com/".to_string());
+
+        let result = DeviceService::rewrite_capability_urls(xml, "cam1", "http://proxy:8000", &overrides);
+
+        assert!(result.contains("<tt:XAddr>http://proxy:8000/onvif/cam1/device_service</tt:XAddr>"));
+        assert!(result.contains("<tt:XAddr>https://cdn.example.com/onvif/cam1/media_service</tt:XAddr>"));
+    }
+
+    #[test]
+    fn test_advertise_proxy_events() {
+        let capabilities = r#"<tt:Events><tt:XAddr>http://proxy/onvif/cam1/event_service</tt:XAddr><tt:WSSubscriptionPolicySupport>true</tt:WSSubscriptionPolicySupport><tt:WSPullPointSupport>false</tt:WSPullPointSupport><tt:WSPausableSubscriptionManagerInterfaceSupport>true</tt:WSPausableSubscriptionManagerInterfaceSupport></tt:Events>"#;
+        let result = DeviceService::advertise_proxy_events(capabilities);
+        assert!(result.contains("<tt:WSSubscriptionPolicySupport>false</tt:WSSubscriptionPolicySupport>"));
+        assert!(result.contains("<tt:WSPullPointSupport>true</tt:WSPullPointSupport>"));
+        assert!(result.contains("<tt:WSPausableSubscriptionManagerInterfaceSupport>false</tt:WSPausableSubscriptionManagerInterfaceSupport>"));
+
+        let services = r#"<tev:Capabilities WSSubscriptionPolicySupport="true" WSPullPointSupport='false' WSPausableSubscriptionManagerInterfaceSupport="true" MaxPullPoints="4"/>"#;
+        let result = DeviceService::advertise_proxy_events(services);
+        assert_eq!(
+            result,
+            r#"<tev:Capabilities WSSubscriptionPolicySupport="false" WSPullPointSupport='true' WSPausableSubscriptionManagerInterfaceSupport="false" MaxPullPoints="4"/>"#
+        );
+    }
+
+    #[test]
+    fn test_parse_utc_date_time() {
+        let xml = r#"<tds:SystemDateAndTime><tt:LocalDateTime><tt:Time><tt:Hour>3</tt:Hour><tt:Minute>0</tt:Minute><tt:Second>0</tt:Second></tt:Time><tt:Date><tt:Year>2020</tt:Year><tt:Month>1</tt:Month><tt:Day>1</tt:Day></tt:Date></tt:LocalDateTime><tt:UTCDateTime><tt:Time><tt:Hour>14</tt:Hour><tt:Minute>5</tt:Minute><tt:Second>9</tt:Second></tt:Time><tt:Date><tt:Year>2024</tt:Year><tt:Month>2</tt:Month><tt:Day>29</tt:Day></tt:Date></tt:UTCDateTime></tds:SystemDateAndTime>"#;
+
+        let parsed = DeviceService::parse_utc_date_time(xml).unwrap();
+        assert_eq!(parsed.to_rfc3339(), "2024-02-29T14:05:09+00:00");
+        assert!(DeviceService::parse_utc_date_time("<tds:SystemDateAndTime/>").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_learns_clock_skew() {
+        let camera = CameraClient::new(CameraConfig {
+            id: "sim".to_string(),
+            model: "simulated".to_string(),
+            ..Default::default()
+        });
+
+        DeviceService::get_system_date_and_time(&camera).await.unwrap();
+        let skew = camera.learned_state().await.clock_skew_secs.unwrap();
+        assert!(skew.abs() <= 1, "simulator clock is the proxy clock, got {}s", skew);
+    }
+
+    /// A camera an hour ahead refuses the proxy's tokens until its clock is learned
+    #[tokio::test]
+    async fn test_clock_skew_corrected() {
+        use axum::{routing::post, Router};
+
+        let mock = Router::new().route(
+            "/onvif/device_service",
+            post(|body: String| async move {
+                let camera_now = Utc::now() + chrono::Duration::hours(1);
+                let created = body
+                    .split_once("<wsu:Created>")
+                    .and_then(|(_, rest)| rest.split_once('<'))
+                    .and_then(|(created, _)| DateTime::parse_from_rfc3339(created).ok());
+                let token_valid = created.is_some_and(|created| (camera_now - created.with_timezone(&Utc)).num_seconds().abs() < 300);
+
+                if body.contains("GetSystemDateAndTime") && (created.is_none() || token_valid) {
+                    let field = |format: &str| camera_now.format(format).to_string();
+                    return soap::build_envelope(
+                        &[("tds", "http://www.onvif.org/ver10/device/wsdl"), ("tt", "http://www.onvif.org/ver10/schema")],
+                        &format!(
+                            "<tds:GetSystemDateAndTimeResponse><tds:SystemDateAndTime><tt:DateTimeType>NTP</tt:DateTimeType><tt:DaylightSavings>false</tt:DaylightSavings><tt:TimeZone><tt:TZ>CST-8</tt:TZ></tt:TimeZone><tt:UTCDateTime><tt:Time><tt:Hour>{}</tt:Hour><tt:Minute>{}</tt:Minute><tt:Second>{}</tt:Second></tt:Time><tt:Date><tt:Year>{}</tt:Year><tt:Month>{}</tt:Month><tt:Day>{}</tt:Day></tt:Date></tt:UTCDateTime><tt:LocalDateTime><tt:Time><tt:Hour>0</tt:Hour><tt:Minute>0</tt:Minute><tt:Second>0</tt:Second></tt:Time><tt:Date><tt:Year>2026</tt:Year><tt:Month>1</tt:Month><tt:Day>1</tt:Day></tt:Date></tt:LocalDateTime></tds:SystemDateAndTime></tds:GetSystemDateAndTimeResponse>",
+                            field("%H"), field("%M"), field("%S"), field("%Y"), field("%m"), field("%d")
+                        ),
+                    );
+                }
+                if token_valid {
+                    return soap::build_envelope(&[("tds", "http://www.onvif.org/ver10/device/wsdl")], "<tds:GetHostnameResponse/>");
+                }
+                soap::build_fault(soap::FaultCode::Sender, "NotAuthorized", "Sender not Authorized")
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await.unwrap() });
+
+        let camera = CameraClient::new(CameraConfig {
+            id: "cam1".to_string(),
+            address: address.to_string(),
+            correct_clock_skew: true,
+            ..Default::default()
+        });
+        let hostname = r#"<tds:GetHostname xmlns:tds="http://www.onvif.org/ver10/device/wsdl"/>"#;
+        assert!(soap::is_fault(&camera.send_soap_request("/onvif/device_service", hostname).await.unwrap()));
+
+        // Refused with the token, answered without it; the response is passed on as it came
+        let response = DeviceService::get_system_date_and_time(&camera).await.unwrap();
+        assert!(response.contains("<tds:GetSystemDateAndTimeResponse><tds:SystemDateAndTime><tt:DateTimeType>NTP</tt:DateTimeType>"));
+        assert!(response.contains("</tt:UTCDateTime><tt:LocalDateTime>"));
+        let skew = camera.learned_state().await.clock_skew_secs.unwrap();
+        assert!((skew - 3600).abs() <= 2, "{}", skew);
+
+        assert!(!soap::is_fault(&camera.send_soap_request("/onvif/device_service", hostname).await.unwrap()));
+    }
+
+    #[test]
+    fn test_add_proxy_capabilities() {
+        let xaddr = "http://proxy/onvif/cam1/analytics_service";
+
+        let bare = r#"<tds:Capabilities><tt:Device><tt:XAddr>x</tt:XAddr><tt:Extension/></tt:Device></tds:Capabilities>"#;
+        let result = DeviceService::add_proxy_capabilities(bare, xaddr, true);
+        assert!(result.starts_with(r#"<tds:Capabilities><tt:Analytics><tt:XAddr>http://proxy/onvif/cam1/analytics_service</tt:XAddr>"#));
+        assert!(result.ends_with(r#"</tt:Device><tt:Extension><tt:Extensions><opx:ProxyCapabilities xmlns:opx="urn:onvif-proxy:capabilities" SyntheticMotionEvents="true" AnalyticsService="true"/></tt:Extensions></tt:Extension></tds:Capabilities>"#));
+
+        // An existing Extension (and Analytics section) is reused
+        let extended = r#"<tds:Capabilities><tt:Analytics><tt:XAddr>a</tt:XAddr></tt:Analytics><tt:Extension><tt:DeviceIO/></tt:Extension></tds:Capabilities>"#;
+        let result = DeviceService::add_proxy_capabilities(extended, xaddr, false);
+        assert_eq!(result.matches(":Analytics>").count(), 2);
+        assert!(result.contains(r#"<tt:Extension><tt:DeviceIO/><tt:Extensions><opx:ProxyCapabilities"#));
+        assert!(result.contains(r#"SyntheticMotionEvents="false""#));
+        assert_eq!(result.matches("<tt:Extension>").count(), 1);
+
+        // Found by local name, whatever the prefixes; the tt prefix used is declared
+        let prefixed = r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"><env:Body><dev:GetCapabilitiesResponse xmlns:dev="http://www.onvif.org/ver10/device/wsdl"><dev:Capabilities><sch:Device xmlns:sch="http://www.onvif.org/ver10/schema"/><sch:Extension xmlns:sch="http://www.onvif.org/ver10/schema"><sch:Extensions><sch:Other/></sch:Extensions></sch:Extension></dev:Capabilities></dev:GetCapabilitiesResponse></env:Body></env:Envelope>"#;
+        let result = DeviceService::add_proxy_capabilities(prefixed, xaddr, true);
+        assert!(result.contains(r#"xmlns:tt="http://www.onvif.org/ver10/schema""#));
+        assert!(result.contains("<dev:Capabilities><tt:Analytics><tt:XAddr>"));
+        assert!(result.contains(r#"<sch:Extensions><sch:Other/><opx:ProxyCapabilities"#));
+        assert!(result.ends_with("</sch:Extensions></sch:Extension></dev:Capabilities></dev:GetCapabilitiesResponse></env:Body></env:Envelope>"));
+
+        let empty_extension = r#"<Capabilities xmlns="http://www.onvif.org/ver10/device/wsdl"><Device/><Extension/></Capabilities>"#;
+        let result = DeviceService::add_proxy_capabilities(empty_extension, xaddr, true);
+        assert!(result.ends_with(r#"<Device/><Extension><tt:Extensions><opx:ProxyCapabilities xmlns:opx="urn:onvif-proxy:capabilities" SyntheticMotionEvents="true" AnalyticsService="true"/></tt:Extensions></Extension></Capabilities>"#));
+    }
+
+    #[test]
+    fn test_replace_element_text() {
+        let xml = r#"<tds:GetDeviceInformationResponse><tds:Manufacturer>Reolink</tds:Manufacturer><tds:Model>RLC-810A</tds:Model><tds:HardwareId>IPC_523128M8MP</tds:HardwareId></tds:GetDeviceInformationResponse>"#;
+
+        let result = replace_element_text(xml, "Manufacturer", "Generic & Co");
+        let result = replace_element_text(&result, "Model", "ONVIF Camera");
+
+        assert!(result.contains("<tds:Manufacturer>Generic &amp; Co</tds:Manufacturer>"));
+        assert!(result.contains("<tds:Model>ONVIF Camera</tds:Model>"));
+        assert!(result.contains("<tds:HardwareId>IPC_523128M8MP</tds:HardwareId>"));
     }
 }
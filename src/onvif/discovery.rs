@@ -0,0 +1,543 @@
+use anyhow::{bail, Context, Result};
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+use crate::camera::{CameraConfig, CameraManager};
+
+const DISCOVERY_PORT: u16 = 3702;
+const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+/// Larger subnets are only probed by multicast; unicast probes reach cameras on other VLANs
+const MAX_UNICAST_HOSTS: u32 = 1024;
+
+/// An IPv4 network in CIDR notation, e.g. `192.168.1.0/24`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet {
+    network: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Subnet {
+    fn mask(&self) -> u32 {
+        if self.prefix_len == 0 {
+            0
+        } else {
+            u32::MAX << (32 - self.prefix_len)
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => u32::from(ip) & self.mask() == u32::from(self.network),
+            IpAddr::V6(_) => false,
+        }
+    }
+
+    /// Usable host addresses (network and broadcast addresses excluded for /30 and larger)
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        let network = u32::from(self.network);
+        let size = 1u64 << (32 - self.prefix_len);
+        let (first, last) = if size > 2 {
+            (network as u64 + 1, network as u64 + size - 2)
+        } else {
+            (network as u64, network as u64 + size - 1)
+        };
+        (first..=last).map(|ip| Ipv4Addr::from(ip as u32))
+    }
+
+    pub fn host_count(&self) -> u32 {
+        match 32 - self.prefix_len {
+            0 => 1,
+            1 => 2,
+            bits => ((1u64 << bits) - 2).min(u32::MAX as u64) as u32,
+        }
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (ip, prefix_len) = s.split_once('/').unwrap_or((s, "32"));
+        let ip: Ipv4Addr = ip.parse().with_context(|| format!("Invalid subnet address: {}", ip))?;
+        let prefix_len: u8 = prefix_len
+            .parse()
+            .with_context(|| format!("Invalid prefix length: {}", prefix_len))?;
+        if prefix_len > 32 {
+            bail!("Invalid prefix length: {}", prefix_len);
+        }
+
+        let mut subnet = Self { network: ip, prefix_len };
+        subnet.network = Ipv4Addr::from(u32::from(ip) & subnet.mask());
+        Ok(subnet)
+    }
+}
+
+impl std::fmt::Display for Subnet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// A device that answered a WS-Discovery probe
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredDevice {
+    /// Address the ProbeMatch came from
+    pub source: IpAddr,
+    /// WS-Addressing endpoint reference, usually `urn:uuid:...`
+    pub endpoint: String,
+    pub xaddrs: Vec<String>,
+    pub scopes: Vec<String>,
+}
+
+impl DiscoveredDevice {
+    /// Value of an `onvif://www.onvif.org/<kind>/<value>` scope, percent-decoded
+    fn scope(&self, kind: &str) -> Option<String> {
+        let prefix = format!("onvif://www.onvif.org/{}/", kind);
+        self.scopes
+            .iter()
+            .find_map(|scope| scope.strip_prefix(&prefix))
+            .map(percent_decode)
+    }
+
+    pub fn name(&self) -> Option<String> {
+        self.scope("name")
+    }
+
+    pub fn hardware(&self) -> Option<String> {
+        self.scope("hardware")
+    }
+
+    /// Best guess at the manufacturer; Reolink uses a non-standard `MfrName` scope
+    pub fn manufacturer(&self) -> Option<String> {
+        self.scope("MfrName").or_else(|| self.scope("manufacturer"))
+    }
+
+    pub fn is_reolink(&self) -> bool {
+        let mentions = |value: Option<String>| value.is_some_and(|v| v.to_lowercase().contains("reolink"));
+        mentions(self.manufacturer()) || mentions(self.name())
+    }
+
+    /// `host:port` of the device service, as used for `camera.address`
+    pub fn address(&self) -> String {
+        self.xaddrs
+            .iter()
+            .find_map(|xaddr| {
+                let url = reqwest::Url::parse(xaddr).ok()?;
+                // IPv6 hosts come back bracketed, ready to append a port
+                let host = url.host_str()?;
+                let port = url.port_or_known_default()?;
+                Some(format!("{}:{}", host, port))
+            })
+            .unwrap_or_else(|| format!("{}:80", self.source))
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = value.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// WS-Discovery Probe for ONVIF network video transmitters
+pub fn probe_message(message_id: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:a="http://schemas.xmlsoap.org/ws/2004/08/addressing" xmlns:d="http://schemas.xmlsoap.org/ws/2005/04/discovery" xmlns:dn="http://www.onvif.org/ver10/network/wsdl">
+  <s:Header>
+    <a:Action s:mustUnderstand="1">http://schemas.xmlsoap.org/ws/2005/04/discovery/Probe</a:Action>
+    <a:MessageID>urn:uuid:{}</a:MessageID>
+    <a:To s:mustUnderstand="1">urn:schemas-xmlsoap-org:ws:2005:04:discovery</a:To>
+  </s:Header>
+  <s:Body>
+    <d:Probe>
+      <d:Types>dn:NetworkVideoTransmitter</d:Types>
+    </d:Probe>
+  </s:Body>
+</s:Envelope>"#,
+        message_id
+    )
+}
+
+/// Parse the ProbeMatch entries of a ProbeMatches response
+pub fn parse_probe_matches(xml: &str, source: IpAddr) -> Vec<DiscoveredDevice> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut devices = Vec::new();
+    let mut current: Option<DiscoveredDevice> = None;
+    let mut element = String::new();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                element = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                if element == "ProbeMatch" {
+                    current = Some(DiscoveredDevice {
+                        source,
+                        endpoint: String::new(),
+                        xaddrs: Vec::new(),
+                        scopes: Vec::new(),
+                    });
+                }
+            }
+            Ok(Event::Text(e)) => {
+                let Some(device) = current.as_mut() else {
+                    continue;
+                };
+                let text = e.unescape().unwrap_or_default();
+                let words = || text.split_whitespace().map(str::to_string);
+                match element.as_str() {
+                    "Address" => device.endpoint = text.trim().to_string(),
+                    "XAddrs" => device.xaddrs.extend(words()),
+                    "Scopes" => device.scopes.extend(words()),
+                    _ => {}
+                }
+            }
+            Ok(Event::End(e)) => {
+                if e.local_name().as_ref() == b"ProbeMatch" {
+                    devices.extend(current.take());
+                }
+                element.clear();
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    devices
+}
+
+/// Probe types the responder answers, by local name (`dn:NetworkVideoTransmitter`, `tds:Device`)
+const ANSWERED_TYPES: &[&str] = &["NetworkVideoTransmitter", "Device"];
+
+/// A WS-Discovery Probe received from a client
+#[derive(Debug, Clone, PartialEq)]
+pub struct Probe {
+    pub message_id: String,
+    /// Requested types, as written (`dn:NetworkVideoTransmitter`); empty matches any device
+    pub types: Vec<String>,
+}
+
+impl Probe {
+    pub fn parse(xml: &str) -> Option<Self> {
+        let mut reader = Reader::from_str(xml);
+        reader.config_mut().trim_text(true);
+
+        let mut message_id = String::new();
+        let mut types = Vec::new();
+        let mut is_probe = false;
+        let mut element = String::new();
+
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(e)) => {
+                    element = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                    is_probe |= element == "Probe";
+                }
+                Ok(Event::Empty(e)) => is_probe |= e.local_name().as_ref() == b"Probe",
+                Ok(Event::Text(e)) => {
+                    let text = e.unescape().unwrap_or_default();
+                    match element.as_str() {
+                        "MessageID" => message_id = text.trim().to_string(),
+                        "Types" => types.extend(text.split_whitespace().map(str::to_string)),
+                        _ => {}
+                    }
+                }
+                Ok(Event::End(_)) => element.clear(),
+                Ok(Event::Eof) => break,
+                Err(_) => return None,
+                _ => {}
+            }
+        }
+
+        is_probe.then_some(Self { message_id, types })
+    }
+
+    /// Whether the proxied cameras are what the client is looking for
+    pub fn wants_cameras(&self) -> bool {
+        self.types.is_empty()
+            || self.types.iter().any(|t| {
+                let local_name = t.rsplit(':').next().unwrap_or(t);
+                ANSWERED_TYPES.contains(&local_name)
+            })
+    }
+}
+
+/// One camera as advertised in a ProbeMatch
+pub struct AdvertisedCamera {
+    pub uuid: String,
+    pub scopes: Vec<String>,
+    pub xaddr: String,
+}
+
+/// Scopes advertised for a camera, in ProbeMatches and in GetScopes when the camera has none of
+/// its own: the device types, and `name`/`hardware` from the configured name (the id when unset)
+/// and `report_model` (the model when unset)
+pub fn camera_scopes(config: &CameraConfig) -> Vec<String> {
+    let name = if config.name.is_empty() { &config.id } else { &config.name };
+    let hardware = config.report_model.as_ref().unwrap_or(&config.model);
+    vec![
+        "onvif://www.onvif.org/type/video_encoder".to_string(),
+        "onvif://www.onvif.org/type/Network_Video_Transmitter".to_string(),
+        "onvif://www.onvif.org/Profile/Streaming".to_string(),
+        format!("onvif://www.onvif.org/name/{}", percent_encode(name)),
+        format!("onvif://www.onvif.org/hardware/{}", percent_encode(hardware)),
+    ]
+}
+
+/// ProbeMatches answering `relates_to`, one ProbeMatch per camera
+pub fn probe_matches(relates_to: &str, cameras: &[AdvertisedCamera]) -> String {
+    let matches: String = cameras
+        .iter()
+        .map(|camera| {
+            format!(
+                "<d:ProbeMatch><a:EndpointReference><a:Address>urn:uuid:{}</a:Address></a:EndpointReference><d:Types>dn:NetworkVideoTransmitter tds:Device</d:Types><d:Scopes>{}</d:Scopes><d:XAddrs>{}</d:XAddrs><d:MetadataVersion>1</d:MetadataVersion></d:ProbeMatch>",
+                camera.uuid,
+                escape(&camera.scopes.join(" ")),
+                escape(&camera.xaddr)
+            )
+        })
+        .collect();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:a="http://schemas.xmlsoap.org/ws/2004/08/addressing" xmlns:d="http://schemas.xmlsoap.org/ws/2005/04/discovery" xmlns:dn="http://www.onvif.org/ver10/network/wsdl" xmlns:tds="http://www.onvif.org/ver10/device/wsdl">
+  <s:Header>
+    <a:Action s:mustUnderstand="1">http://schemas.xmlsoap.org/ws/2005/04/discovery/ProbeMatches</a:Action>
+    <a:MessageID>urn:uuid:{}</a:MessageID>
+    <a:RelatesTo>{}</a:RelatesTo>
+    <a:To s:mustUnderstand="1">http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous</a:To>
+  </s:Header>
+  <s:Body><d:ProbeMatches>{}</d:ProbeMatches></s:Body>
+</s:Envelope>"#,
+        uuid::Uuid::new_v4(),
+        escape(relates_to),
+        matches
+    )
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Answer WS-Discovery probes on the multicast group for every camera of `camera_manager`,
+/// advertising the proxy's device service for each
+pub async fn run_responder(camera_manager: CameraManager, base_url: String) -> Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT))
+        .await
+        .with_context(|| format!("Failed to bind WS-Discovery port {}", DISCOVERY_PORT))?;
+    socket
+        .join_multicast_v4(MULTICAST_ADDR, Ipv4Addr::UNSPECIFIED)
+        .context("Failed to join the WS-Discovery multicast group")?;
+    tracing::info!("Answering WS-Discovery probes on {}:{}", MULTICAST_ADDR, DISCOVERY_PORT);
+
+    answer_probes(socket, camera_manager, base_url).await
+}
+
+async fn answer_probes(socket: UdpSocket, camera_manager: CameraManager, base_url: String) -> Result<()> {
+    let mut buf = vec![0u8; 65535];
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::debug!("WS-Discovery receive failed: {}", e);
+                continue;
+            }
+        };
+
+        let Some(probe) = Probe::parse(&String::from_utf8_lossy(&buf[..len])) else {
+            continue;
+        };
+        if !probe.wants_cameras() {
+            continue;
+        }
+
+        let mut cameras = Vec::new();
+        for camera in camera_manager.cameras().await {
+            cameras.push(AdvertisedCamera {
+                uuid: camera.device_uuid().await,
+                scopes: camera_scopes(camera.config()),
+                xaddr: format!("{}/onvif/{}/device_service", base_url.trim_end_matches('/'), camera.camera_id()),
+            });
+        }
+        if cameras.is_empty() {
+            continue;
+        }
+
+        tracing::debug!("Answering WS-Discovery probe from {} with {} cameras", from, cameras.len());
+        let reply = probe_matches(&probe.message_id, &cameras);
+        if let Err(e) = socket.send_to(reply.as_bytes(), from).await {
+            tracing::debug!("WS-Discovery reply to {} failed: {}", from, e);
+        }
+    }
+}
+
+/// Probe for ONVIF devices by multicast, and by unicast to every host of `subnet`
+/// when it is small enough; only devices inside `subnet` are returned when one is given
+pub async fn scan(subnet: Option<Subnet>, wait: Duration) -> Result<Vec<DiscoveredDevice>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .await
+        .context("Failed to bind discovery socket")?;
+    let probe = probe_message(&uuid::Uuid::new_v4().to_string());
+
+    socket
+        .send_to(probe.as_bytes(), (MULTICAST_ADDR, DISCOVERY_PORT))
+        .await
+        .context("Failed to send multicast probe")?;
+
+    if let Some(subnet) = subnet.filter(|s| s.host_count() <= MAX_UNICAST_HOSTS) {
+        for host in subnet.hosts() {
+            if let Err(e) = socket.send_to(probe.as_bytes(), (host, DISCOVERY_PORT)).await {
+                tracing::debug!("Probe to {} failed: {}", host, e);
+            }
+        }
+    }
+
+    let mut found: HashMap<String, DiscoveredDevice> = HashMap::new();
+    let mut buf = vec![0u8; 65535];
+    let deadline = tokio::time::Instant::now() + wait;
+
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, from): (usize, SocketAddr) = match received {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::debug!("Discovery receive failed: {}", e);
+                continue;
+            }
+        };
+
+        if subnet.is_some_and(|s| !s.contains(from.ip())) {
+            continue;
+        }
+
+        let xml = String::from_utf8_lossy(&buf[..len]);
+        for device in parse_probe_matches(&xml, from.ip()) {
+            // The same device answers both the multicast and the unicast probe
+            let key = if device.endpoint.is_empty() {
+                device.source.to_string()
+            } else {
+                device.endpoint.clone()
+            };
+            found.entry(key).or_insert(device);
+        }
+    }
+
+    let mut devices: Vec<DiscoveredDevice> = found.into_values().collect();
+    devices.sort_by_key(|d| d.source);
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subnet() {
+        let subnet: Subnet = "192.168.1.77/24".parse().unwrap();
+        assert_eq!(subnet.to_string(), "192.168.1.0/24");
+        assert!(subnet.contains("192.168.1.10".parse().unwrap()));
+        assert!(!subnet.contains("192.168.2.10".parse().unwrap()));
+        assert_eq!(subnet.host_count(), 254);
+        assert_eq!(subnet.hosts().next(), Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(subnet.hosts().last(), Some(Ipv4Addr::new(192, 168, 1, 254)));
+
+        assert!("192.168.1.0/33".parse::<Subnet>().is_err());
+        assert!("camera.local/24".parse::<Subnet>().is_err());
+    }
+
+    #[test]
+    fn test_parse_probe_matches() {
+        let response = r#"<?xml version="1.0" encoding="UTF-8"?>
+<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope" xmlns:wsa="http://schemas.xmlsoap.org/ws/2004/08/addressing" xmlns:d="http://schemas.xmlsoap.org/ws/2005/04/discovery">
+<SOAP-ENV:Body><d:ProbeMatches><d:ProbeMatch>
+<wsa:EndpointReference><wsa:Address>urn:uuid:2419d68a-2dd2-21b2-a205-ec71dbc8f5a2</wsa:Address></wsa:EndpointReference>
+<d:Types>dn:NetworkVideoTransmitter</d:Types>
+<d:Scopes>onvif://www.onvif.org/type/video_encoder onvif://www.onvif.org/name/Front%20Door onvif://www.onvif.org/hardware/RLC-810A onvif://www.onvif.org/MfrName/Reolink</d:Scopes>
+<d:XAddrs>http://192.168.1.100:8000/onvif/device_service</d:XAddrs>
+</d:ProbeMatch></d:ProbeMatches></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+
+        let devices = parse_probe_matches(response, "192.168.1.100".parse().unwrap());
+
+        assert_eq!(devices.len(), 1);
+        let device = &devices[0];
+        assert_eq!(device.endpoint, "urn:uuid:2419d68a-2dd2-21b2-a205-ec71dbc8f5a2");
+        assert_eq!(device.name().as_deref(), Some("Front Door"));
+        assert_eq!(device.hardware().as_deref(), Some("RLC-810A"));
+        assert!(device.is_reolink());
+        assert_eq!(device.address(), "192.168.1.100:8000");
+    }
+
+    #[tokio::test]
+    async fn test_answer_probes() {
+        let camera_manager = CameraManager::new();
+        for (id, name) in [("front", "Front Door"), ("back", "")] {
+            camera_manager
+                .add_camera(crate::camera::CameraConfig {
+                    id: id.to_string(),
+                    name: name.to_string(),
+                    model: "simulated".to_string(),
+                    ..Default::default()
+                })
+                .await;
+        }
+        let responder = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = responder.local_addr().unwrap();
+        tokio::spawn(answer_probes(responder, camera_manager.clone(), "http://proxy:8000/".to_string()));
+
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let message_id = uuid::Uuid::new_v4().to_string();
+        client.send_to(probe_message(&message_id).as_bytes(), address).await.unwrap();
+
+        let mut buf = vec![0u8; 65535];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buf)).await.unwrap().unwrap();
+        let reply = String::from_utf8_lossy(&buf[..len]).into_owned();
+        assert!(reply.contains(&format!("<a:RelatesTo>urn:uuid:{}</a:RelatesTo>", message_id)));
+
+        let mut devices = parse_probe_matches(&reply, address.ip());
+        devices.sort_by(|a, b| a.xaddrs.cmp(&b.xaddrs));
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].xaddrs, ["http://proxy:8000/onvif/back/device_service"]);
+        assert_eq!(devices[0].name().as_deref(), Some("back"));
+        assert_eq!(devices[1].xaddrs, ["http://proxy:8000/onvif/front/device_service"]);
+        assert_eq!(devices[1].name().as_deref(), Some("Front Door"));
+        assert_eq!(devices[1].hardware().as_deref(), Some("simulated"));
+        let uuid = camera_manager.get_camera("front").await.unwrap().device_uuid().await;
+        assert_eq!(devices[1].endpoint, format!("urn:uuid:{}", uuid));
+    }
+
+    #[test]
+    fn test_probe_types() {
+        let probe = |types: &str| Probe::parse(&probe_message("1").replace("dn:NetworkVideoTransmitter", types)).unwrap();
+        assert!(probe("dn:NetworkVideoTransmitter").wants_cameras());
+        assert!(probe("tds:Device").wants_cameras());
+        assert!(probe("").wants_cameras());
+        assert!(!probe("wsdp:Printer").wants_cameras());
+        assert_eq!(probe("tds:Device").message_id, "urn:uuid:1");
+
+        assert!(Probe::parse(&probe_message("1").replace("Probe>", "Resolve>")).is_none());
+    }
+}
@@ -1,39 +1,230 @@
-use crate::camera::CameraClient;
+use crate::camera::{CameraClient, CameraConfig};
+use crate::config::EventsConfig;
+use crate::camera::queue::Priority;
+use crate::camera::reolink_cgi;
+use crate::onvif::notification::{ItemList, Message, MessageHolder, NotificationMessage, PullMessagesResponse, SimpleItem, Topic};
+use crate::onvif::{soap, xmlns};
+use crate::translator::reolink::ReolinkEventTranslator;
 use anyhow::Result;
 use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, Notify, RwLock};
 use uuid::Uuid;
 use chrono::Utc;
 
+/// Renew the camera-side subscription when its lease has less than this left
+const CAMERA_LEASE_MARGIN: chrono::Duration = chrono::Duration::seconds(60);
+/// Failed PullMessages in a row before the camera subscription is assumed lost
+const MAX_PULL_FAILURES: u32 = 3;
+/// Don't recreate the camera subscription more often than this
+const MIN_RESUBSCRIBE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How often subscriptions past their termination time are removed
+const REAP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Events buffered per external consumer before the oldest are dropped
+const CONSUMER_BUFFER: usize = 256;
+
+const UNSUBSCRIBE_REQUEST: &str = r#"<tev:Unsubscribe xmlns:tev="http://www.onvif.org/ver10/events/wsdl"/>"#;
+
+const MOTION_TOPIC: &str = "tns1:RuleEngine/CellMotionDetector/Motion";
+
+/// Reolink AI classes (GetAiState keys) and the topics their events get, the ones Reolink's own
+/// ONVIF events use
+const AI_TOPICS: &[(&str, &str)] = &[
+    ("people", "tns1:RuleEngine/MyRuleDetector/PeopleDetect"),
+    ("vehicle", "tns1:RuleEngine/MyRuleDetector/VehicleDetect"),
+    ("dog_cat", "tns1:RuleEngine/MyRuleDetector/DogCatDetect"),
+];
+
+/// Last event per (camera id, topic), kept across subscriptions for `events.replay_last_state`
+type LastStates = Arc<RwLock<HashMap<(String, String), NotificationMessage>>>;
+
 #[derive(Debug, Clone)]
 pub struct CachedEvent {
-    pub event_xml: String,
+    pub message: NotificationMessage,
     pub received_at: chrono::DateTime<Utc>,
 }
 
+impl CachedEvent {
+    /// The message's `UtcTime`, or when the proxy received it if there is none
+    pub fn event_time(&self) -> chrono::DateTime<Utc> {
+        self.message
+            .message()
+            .and_then(|message| message.utc_time.as_deref())
+            .and_then(|time| chrono::DateTime::parse_from_rfc3339(time).ok())
+            .map_or(self.received_at, |time| time.with_timezone(&Utc))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Subscription {
     pub _subscription_ref: String,
     pub camera_id: String,
-    pub camera_subscription_url: String,  // Original subscription URL from camera
+    /// The camera-side subscription, replaced if the camera drops it
+    pub camera_subscription: Arc<RwLock<CameraSubscription>>,
     pub _created_at: chrono::DateTime<Utc>,
     pub expires_at: chrono::DateTime<Utc>,
     pub event_cache: Arc<RwLock<VecDeque<CachedEvent>>>,  // Cache of events from camera
     pub last_poll: Arc<RwLock<chrono::DateTime<Utc>>>,  // Last time we polled the camera
+    /// Where queued events are remembered, when `events.replay_last_state` is on
+    pub last_states: Option<LastStates>,
+    /// Wakes a waiting PullMessages when an event is queued
+    pub event_arrived: Arc<Notify>,
+    /// Stops the background polling once the subscription is unsubscribed or reaped
+    pub shutdown: Arc<Shutdown>,
+}
+
+/// A PullPoint subscription on the camera backing a proxy subscription. Tracked apart from
+/// the client-facing one so it can be recreated without the client's `subscription_ref` changing
+#[derive(Debug, Clone)]
+pub struct CameraSubscription {
+    pub url: String,
+    pub expires_at: chrono::DateTime<Utc>,
+    /// Lease length asked of the camera on creation and renewal
+    pub lease: chrono::Duration,
+    /// There is no subscription on the camera (`events.local_subscription`); renewals and
+    /// unsubscribes are answered by the proxy
+    pub local: bool,
+}
+
+/// Shutdown signal for a subscription's background tasks
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    closed: AtomicBool,
+    notify: Notify,
+}
+
+impl Shutdown {
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Sleep until `deadline`; false if closed before (or by) then
+    pub async fn sleep_until(&self, deadline: tokio::time::Instant) -> bool {
+        let closed = self.notify.notified();
+        tokio::pin!(closed);
+        closed.as_mut().enable();
+        if self.is_closed() {
+            return false;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => !self.is_closed(),
+            _ = closed => false,
+        }
+    }
+}
+
+impl Subscription {
+    pub async fn camera_subscription_url(&self) -> String {
+        self.camera_subscription.read().await.url.clone()
+    }
+}
+
+/// Source metadata for synthetic motion events, from the camera's `event_*` options
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticEventMetadata {
+    pub rule_name: String,
+    pub source_item_name: String,
+    pub source_token: String,
+    pub analytics_item_name: String,
+    pub analytics_token: String,
+}
+
+impl SyntheticEventMetadata {
+    pub fn from_config(config: &CameraConfig) -> Self {
+        let or = |value: &Option<String>, default: &str| value.clone().unwrap_or_else(|| default.to_string());
+        Self {
+            rule_name: or(&config.event_rule_name, "MotionDetectorRule"),
+            source_item_name: or(&config.event_source_item_name, "VideoSourceConfigurationToken"),
+            source_token: or(&config.event_source_token, &config.id),
+            analytics_item_name: or(&config.event_analytics_item_name, "VideoAnalyticsConfigurationToken"),
+            analytics_token: or(&config.event_analytics_token, &config.id),
+        }
+    }
+}
+
+/// An event published to external consumers (SSE, MQTT, push integrations)
+#[derive(Debug, Clone)]
+pub struct PublishedEvent {
+    pub camera_id: String,
+    pub event: CachedEvent,
+}
+
+/// Receiving end of the event fan-out. Each consumer has its own bounded buffer;
+/// a consumer that falls behind loses its oldest events instead of slowing the pollers.
+pub struct EventConsumer {
+    name: String,
+    receiver: broadcast::Receiver<PublishedEvent>,
+}
+
+impl EventConsumer {
+    /// Wait for the next event. Returns `None` once the events service is gone.
+    pub async fn recv(&mut self) -> Option<PublishedEvent> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Event consumer {} is too slow, dropped {} oldest events", self.name, skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
 }
 
 pub struct EventsService {
     subscriptions: Arc<RwLock<HashMap<String, Subscription>>>,
+    event_bus: broadcast::Sender<PublishedEvent>,
+    config: EventsConfig,
+    last_states: LastStates,
 }
 
 impl EventsService {
     pub fn new() -> Self {
+        Self::with_config(EventsConfig::default())
+    }
+
+    pub fn with_config(config: EventsConfig) -> Self {
+        let (event_bus, _) = broadcast::channel(CONSUMER_BUFFER);
+
         Self {
             subscriptions: Arc::new(RwLock::new(HashMap::new())),
+            event_bus,
+            config,
+            last_states: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Lease for a client-requested `InitialTerminationTime`/`TerminationTime`, clamped to the config
+    fn grant_lease(&self, requested: Option<&str>) -> chrono::Duration {
+        let requested_secs = requested.and_then(|value| Self::parse_termination_time(value, Utc::now()));
+        self.config.grant(requested_secs)
+    }
+
+    /// Register an external consumer of camera events
+    pub fn subscribe_consumer(&self, name: &str) -> EventConsumer {
+        EventConsumer {
+            name: name.to_string(),
+            receiver: self.event_bus.subscribe(),
         }
     }
 
+    fn publish(event_bus: &broadcast::Sender<PublishedEvent>, camera_id: &str, event: &CachedEvent) {
+        // Never blocks; an error only means nobody is listening
+        let _ = event_bus.send(PublishedEvent {
+            camera_id: camera_id.to_string(),
+            event: event.clone(),
+        });
+    }
+
     pub async fn get_event_properties(camera: &CameraClient) -> Result<String> {
         let request_body = r#"<tev:GetEventProperties xmlns:tev="http://www.onvif.org/ver10/events/wsdl"/>"#;
 
@@ -51,44 +242,264 @@ impl EventsService {
         &self,
         camera: &CameraClient,
         base_url: &str,
+        requested_termination: Option<&str>,
     ) -> Result<String> {
-        let request_body = r#"<tev:CreatePullPointSubscription xmlns:tev="http://www.onvif.org/ver10/events/wsdl">
-  <tev:InitialTerminationTime>PT600S</tev:InitialTerminationTime>
-</tev:CreatePullPointSubscription>"#;
-
-        let response = camera
-            .send_soap_request("/onvif/event_service", request_body)
-            .await?;
-
-        // Extract camera's subscription URL from response
-        let camera_subscription_url = Self::extract_subscription_url(&response);
+        let lease = self.grant_lease(requested_termination);
+        // The relayed ONVIF events need the camera's own subscription
+        let local = self.config.local_subscription && !camera.config().event_method.uses_onvif();
+        let (response, camera_subscription) = if local {
+            Self::local_subscription(lease)
+        } else {
+            Self::subscribe_camera(camera, lease).await?
+        };
+        let expires_at = Utc::now() + lease;
 
         // Extract subscription reference from response and rewrite it to point to our proxy
         let subscription_ref = Uuid::new_v4().to_string();
         let subscription = Subscription {
             _subscription_ref: subscription_ref.clone(),
             camera_id: camera.camera_id().to_string(),
-            camera_subscription_url: camera_subscription_url.clone(),
+            camera_subscription: Arc::new(RwLock::new(camera_subscription)),
             _created_at: Utc::now(),
-            expires_at: Utc::now() + chrono::Duration::seconds(600),
+            expires_at,
             event_cache: Arc::new(RwLock::new(VecDeque::new())),
             last_poll: Arc::new(RwLock::new(Utc::now())),
+            last_states: self.config.replay_last_state.then(|| self.last_states.clone()),
+            event_arrived: Arc::new(Notify::new()),
+            shutdown: Arc::new(Shutdown::default()),
         };
 
+        if self.config.replay_last_state {
+            self.replay_last_states(&subscription).await;
+        }
         self.subscriptions.write().await.insert(subscription_ref.clone(), subscription.clone());
 
-        // Start background polling task for this subscription
-        let camera_clone = camera.clone();
-        let subscription_clone = subscription.clone();
-        tokio::spawn(async move {
-            Self::poll_camera_events_background(camera_clone, subscription_clone).await;
-        });
+        // Start background polling task(s) for this subscription. They outlive the caller's
+        // request, so they use the configured credentials rather than a passed-through token
+        let event_method = camera.config().event_method;
+        let background_camera = camera.with_caller_token(None);
+        if event_method.uses_cgi() {
+            let camera_clone = background_camera.clone();
+            let subscription_clone = subscription.clone();
+            let event_bus = self.event_bus.clone();
+            let subscriptions = self.subscriptions.clone();
+            tokio::spawn(async move {
+                Self::poll_camera_events_background(camera_clone, subscription_clone, event_bus, subscriptions).await;
+            });
+        }
+        if event_method.uses_onvif() {
+            let camera_clone = background_camera;
+            let subscription_clone = subscription.clone();
+            let event_bus = self.event_bus.clone();
+            let subscriptions = self.subscriptions.clone();
+            tokio::spawn(async move {
+                Self::poll_camera_pull_messages(camera_clone, subscription_clone, event_bus, subscriptions).await;
+            });
+        }
 
         // Rewrite the subscription reference URL to point to our proxy
         let proxy_subscription_url = format!("{}/onvif/{}/subscription/{}", base_url, camera.camera_id(), subscription_ref);
         let fixed_response = Self::rewrite_subscription_ref(&response, &proxy_subscription_url);
 
-        Ok(fixed_response)
+        Ok(Self::rewrite_termination_time(&fixed_response, expires_at))
+    }
+
+    /// Queue the camera's remembered states on a new subscription as `Initialized` events, so
+    /// the client starts from the last known state instead of waiting for the next change
+    async fn replay_last_states(&self, subscription: &Subscription) {
+        let now = Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let states = self.last_states.read().await;
+        let mut cache = subscription.event_cache.write().await;
+
+        for ((camera_id, _), message) in states.iter() {
+            if *camera_id != subscription.camera_id {
+                continue;
+            }
+            let mut message = message.clone();
+            if let Some(holder) = message.message.as_mut() {
+                holder.message.property_operation = Some("Initialized".to_string());
+                holder.message.utc_time = Some(now.clone());
+            }
+            Self::insert_in_order(&mut cache, CachedEvent { message, received_at: Utc::now() });
+        }
+        if !cache.is_empty() {
+            tracing::debug!("Replaying {} last-known state(s) on camera {}", cache.len(), subscription.camera_id);
+        }
+    }
+
+    /// The motion state remembered for the subscription's camera, if replay is on
+    async fn remembered_motion(subscription: &Subscription) -> Option<bool> {
+        Self::remembered_state(subscription, MOTION_TOPIC, "IsMotion").await
+    }
+
+    async fn remembered_state(subscription: &Subscription, topic: &str, item_name: &str) -> Option<bool> {
+        let states = subscription.last_states.as_ref()?.read().await;
+        let message = states.get(&(subscription.camera_id.clone(), topic.to_string()))?;
+        let data = message.message()?.data.as_ref()?;
+        data.items.iter().find(|item| item.name == item_name).map(|item| item.value == "true")
+    }
+
+    /// Create a PullPoint subscription on the camera
+    async fn subscribe_camera(camera: &CameraClient, lease: chrono::Duration) -> Result<(String, CameraSubscription)> {
+        let request_body = format!(
+            r#"<tev:CreatePullPointSubscription xmlns:tev="http://www.onvif.org/ver10/events/wsdl">
+  <tev:InitialTerminationTime>PT{}S</tev:InitialTerminationTime>
+</tev:CreatePullPointSubscription>"#,
+            lease.num_seconds()
+        );
+
+        let response = camera
+            .send_soap_request("/onvif/event_service", &request_body)
+            .await?;
+        if soap::is_fault(&response) {
+            anyhow::bail!("Camera refused CreatePullPointSubscription");
+        }
+
+        let camera_subscription = CameraSubscription {
+            url: Self::extract_subscription_url(&response),
+            expires_at: Self::extract_termination_time(&response).unwrap_or_else(|| Utc::now() + lease),
+            lease,
+            local: false,
+        };
+
+        Ok((response, camera_subscription))
+    }
+
+    /// A CreatePullPointSubscriptionResponse made up by the proxy, for `events.local_subscription`.
+    /// The address and termination time are filled in like a camera's
+    fn local_subscription(lease: chrono::Duration) -> (String, CameraSubscription) {
+        let now = Utc::now();
+        let response = soap::build_envelope(
+            &[
+                ("tev", "http://www.onvif.org/ver10/events/wsdl"),
+                ("wsa", "http://www.w3.org/2005/08/addressing"),
+                ("wsnt", "http://docs.oasis-open.org/wsn/b-2"),
+            ],
+            &format!(
+                "<tev:CreatePullPointSubscriptionResponse><tev:SubscriptionReference><wsa:Address></wsa:Address></tev:SubscriptionReference><wsnt:CurrentTime>{}</wsnt:CurrentTime><wsnt:TerminationTime></wsnt:TerminationTime></tev:CreatePullPointSubscriptionResponse>",
+                now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            ),
+        );
+        let camera_subscription = CameraSubscription {
+            url: String::new(),
+            expires_at: now + lease,
+            lease,
+            local: true,
+        };
+        (response, camera_subscription)
+    }
+
+    /// Replace a dropped camera-side subscription; the client keeps using the same proxy subscription
+    async fn resubscribe_camera(camera: &CameraClient, subscription: &Subscription) -> bool {
+        let lease = subscription.camera_subscription.read().await.lease;
+        match Self::subscribe_camera(camera, lease).await {
+            Ok((_, camera_subscription)) => {
+                tracing::info!(
+                    "Recreated camera subscription for {} on camera {}",
+                    subscription._subscription_ref,
+                    subscription.camera_id
+                );
+                *subscription.camera_subscription.write().await = camera_subscription;
+                true
+            }
+            Err(e) => {
+                tracing::warn!("Failed to recreate subscription on camera {}: {:#}", subscription.camera_id, e);
+                false
+            }
+        }
+    }
+
+    /// Renew the camera-side lease ahead of its expiry, independently of the client's own Renew
+    async fn renew_camera_lease(camera: &CameraClient, subscription: &Subscription) -> Result<()> {
+        let CameraSubscription { url, lease, .. } = subscription.camera_subscription.read().await.clone();
+        let response = camera
+            .send_soap_request(Self::subscription_path(&url), &Self::renew_request(lease))
+            .await?;
+        if soap::is_fault(&response) {
+            anyhow::bail!("Camera refused Renew");
+        }
+
+        subscription.camera_subscription.write().await.expires_at = Self::extract_termination_time(&response)
+            .unwrap_or_else(|| Utc::now() + lease);
+        Ok(())
+    }
+
+    /// Remove the subscriptions past their termination time every `REAP_INTERVAL`, so clients
+    /// that go away without unsubscribing don't leave their camera polling running
+    pub fn spawn_reaper(&self) -> tokio::task::JoinHandle<()> {
+        self.spawn_reaper_every(REAP_INTERVAL)
+    }
+
+    fn spawn_reaper_every(&self, interval: std::time::Duration) -> tokio::task::JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                service.reap_expired().await;
+            }
+        })
+    }
+
+    /// Remove and stop the subscriptions past their termination time; returns how many
+    pub async fn reap_expired(&self) -> usize {
+        let now = Utc::now();
+        let mut subscriptions = self.subscriptions.write().await;
+        let expired: Vec<String> = subscriptions
+            .iter()
+            .filter(|(_, subscription)| subscription.expires_at <= now)
+            .map(|(subscription_ref, _)| subscription_ref.clone())
+            .collect();
+
+        for subscription_ref in &expired {
+            if let Some(subscription) = subscriptions.remove(subscription_ref) {
+                tracing::info!("Subscription {} on camera {} expired, removing it", subscription_ref, subscription.camera_id);
+                subscription.shutdown.close();
+            }
+        }
+        expired.len()
+    }
+
+    /// Remove and stop every subscription on `camera`, e.g. when the camera is removed; the
+    /// camera-side subscriptions are unsubscribed in the background. Returns how many
+    pub async fn close_camera_subscriptions(&self, camera: &CameraClient) -> usize {
+        let closed: Vec<Subscription> = {
+            let mut subscriptions = self.subscriptions.write().await;
+            let refs: Vec<String> = subscriptions
+                .iter()
+                .filter(|(_, subscription)| subscription.camera_id == camera.camera_id())
+                .map(|(subscription_ref, _)| subscription_ref.clone())
+                .collect();
+            refs.iter().filter_map(|subscription_ref| subscriptions.remove(subscription_ref)).collect()
+        };
+
+        for subscription in &closed {
+            tracing::info!("Closing subscription {} of camera {}", subscription._subscription_ref, camera.camera_id());
+            subscription.shutdown.close();
+
+            let CameraSubscription { url, local, .. } = subscription.camera_subscription.read().await.clone();
+            if local {
+                continue;
+            }
+            let camera = camera.clone();
+            tokio::spawn(async move {
+                if let Err(e) = camera.send_soap_request(Self::subscription_path(&url), UNSUBSCRIBE_REQUEST).await {
+                    tracing::debug!("Could not unsubscribe from camera {}: {}", camera.camera_id(), e);
+                }
+            });
+        }
+        closed.len()
+    }
+
+    /// Unexpired client subscriptions on `camera_id`
+    pub async fn active_subscriptions(&self, camera_id: &str) -> usize {
+        let now = Utc::now();
+        self.subscriptions
+            .read()
+            .await
+            .values()
+            .filter(|subscription| subscription.camera_id == camera_id && subscription.expires_at > now)
+            .count()
     }
 
     pub async fn get_subscription(&self, subscription_ref: &str) -> Option<Subscription> {
@@ -104,75 +515,116 @@ impl EventsService {
         let subscription = self.get_subscription(subscription_ref).await
             .ok_or_else(|| anyhow::anyhow!("Subscription not found"))?;
 
-        // Parse timeout to determine how long to wait for events
-        let timeout_secs = Self::parse_iso_duration(timeout).unwrap_or(1);
-        let deadline = Utc::now() + chrono::Duration::seconds(timeout_secs);
+        // Parse timeout to determine how long to wait for events, at most max_pull_timeout_secs
+        let timeout_secs = (Self::parse_iso_duration(timeout).unwrap_or(1).max(0) as u64).min(self.config.max_pull_timeout_secs);
+        let deadline = tokio::time::Instant::now()
+            .checked_add(std::time::Duration::from_secs(timeout_secs))
+            .ok_or_else(|| anyhow::anyhow!("PullMessages Timeout out of range"))?;
 
-        // Check cache for events, waiting up to timeout if needed
+        // Long poll: queued events (up to MessageLimit) are returned straight away; with none
+        // queued, the first one to arrive before the Timeout is returned as soon as it does,
+        // otherwise the response is empty at the Timeout. The cache is oldest-first, so a
+        // MessageLimit cut leaves the newest events for the next pull
         let mut events = Vec::new();
-        let start = std::time::Instant::now();
+        let limit = message_limit.max(0) as usize;
+
+        loop {
+            // Registered before looking at the cache, so an event queued in between still wakes us
+            let arrived = subscription.event_arrived.notified();
+            tokio::pin!(arrived);
+            arrived.as_mut().enable();
 
-        while events.len() < message_limit as usize && Utc::now() < deadline {
             {
                 let mut cache = subscription.event_cache.write().await;
-                while events.len() < message_limit as usize {
-                    if let Some(event) = cache.pop_front() {
-                        events.push(event);
-                    } else {
-                        break;
-                    }
-                }
+                let take = limit.min(cache.len());
+                events.extend(cache.drain(..take));
             }
 
-            if events.is_empty() && start.elapsed().as_secs() < timeout_secs as u64 {
-                // Wait a bit for events to arrive
-                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-            } else {
+            if !events.is_empty() || limit == 0 || tokio::time::timeout_at(deadline, arrived).await.is_err() {
                 break;
             }
         }
 
         // Build PullMessages response
-        let response = Self::build_pull_messages_response(&events);
-
-        Ok(response)
+        // Report the subscription's real expiry, which a Renew may have moved since
+        let termination_time = self
+            .get_subscription(subscription_ref)
+            .await
+            .map_or(subscription.expires_at, |s| s.expires_at);
+        Self::build_pull_messages_response(&events, termination_time)
     }
 
-    async fn poll_camera_events_background(camera: CameraClient, subscription: Subscription) {
+    async fn poll_camera_events_background(
+        camera: CameraClient,
+        subscription: Subscription,
+        event_bus: broadcast::Sender<PublishedEvent>,
+        subscriptions: Arc<RwLock<HashMap<String, Subscription>>>,
+    ) {
         tracing::info!("Starting background event polling for subscription on camera {} (querying motion alarm state)", subscription.camera_id);
 
-        // Track previous motion state
-        let mut last_motion_state: Option<bool> = None;
+        let metadata = SyntheticEventMetadata::from_config(camera.config());
+
+        // Track previous motion state; a replayed state counts as already reported
+        let mut last_motion_state: Option<bool> = Self::remembered_motion(&subscription).await;
+        let smart_detection = camera.config().enable_smart_detection;
+        let mut last_ai_states: HashMap<&str, bool> = HashMap::new();
+        for (class, topic) in AI_TOPICS {
+            if let Some(active) = Self::remembered_state(&subscription, topic, "State").await {
+                last_ai_states.insert(class, active);
+            }
+        }
 
         loop {
             // Poll camera every 500ms for responsive motion detection
-            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+            let next_poll = tokio::time::Instant::now() + tokio::time::Duration::from_millis(500);
+            let open = subscription.shutdown.sleep_until(next_poll).await;
+
+            if !open || !subscriptions.read().await.contains_key(&subscription._subscription_ref) {
+                tracing::debug!("Subscription {} gone, stopping CGI polling", subscription._subscription_ref);
+                return;
+            }
 
             // Query camera for current motion alarm state
             // Reolink cameras expose motion state via GetEventProperties with current state
             match Self::query_motion_state(&camera).await {
                 Ok(motion_detected) => {
+                    camera.report_success();
                     if last_motion_state != Some(motion_detected) {
                         tracing::info!("Motion state changed on camera {}: {}", subscription.camera_id, motion_detected);
 
                         // Generate ONVIF motion event for state change
-                        let event_xml = Self::generate_motion_event(&subscription.camera_id, motion_detected);
-                        let mut cache = subscription.event_cache.write().await;
-                        cache.push_back(CachedEvent {
-                            event_xml,
-                            received_at: Utc::now(),
-                        });
-
-                        // Limit cache size
-                        while cache.len() > 100 {
-                            cache.pop_front();
+                        if motion_detected {
+                            camera.record_motion().await;
                         }
+                        let message = Self::generate_motion_event(&metadata, motion_detected);
+                        Self::queue_event(&subscription, &event_bus, message).await;
 
                         last_motion_state = Some(motion_detected);
                     }
                 }
                 Err(e) => {
-                    tracing::debug!("Failed to query motion state: {}", e);
+                    camera.report_error(&format!("motion state query failed: {:#}", e));
+                }
+            }
+
+            // Person/vehicle/animal detections, each on its own topic
+            if smart_detection {
+                match Self::query_ai_state(&camera).await {
+                    Ok(states) => {
+                        for (class, topic) in AI_TOPICS {
+                            let Some(&active) = states.get(*class) else {
+                                continue;
+                            };
+                            if last_ai_states.insert(class, active) != Some(active) {
+                                tracing::info!("{} detection changed on camera {}: {}", class, subscription.camera_id, active);
+                                let message = Self::generate_state_event(&metadata, topic, "State", active);
+                                Self::queue_event(&subscription, &event_bus, message).await;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        camera.report_error(&format!("AI state query failed: {:#}", e));
+                    }
                 }
             }
 
@@ -180,276 +632,401 @@ impl EventsService {
         }
     }
 
-    async fn query_motion_state(camera: &CameraClient) -> Result<bool> {
-        // Reolink cameras have broken ONVIF PullPoint but support proprietary CGI API
-        // Query motion detection state via Reolink's CGI interface (HTTPS, GET method)
+    /// Relay the camera's own PullMessages (for firmware where PullPoint works), translated
+    async fn poll_camera_pull_messages(
+        camera: CameraClient,
+        subscription: Subscription,
+        event_bus: broadcast::Sender<PublishedEvent>,
+        subscriptions: Arc<RwLock<HashMap<String, Subscription>>>,
+    ) {
+        tracing::info!("Starting ONVIF PullMessages relay for subscription on camera {}", subscription.camera_id);
+
+        let request_body = r#"<tev:PullMessages xmlns:tev="http://www.onvif.org/ver10/events/wsdl">
+  <tev:Timeout>PT5S</tev:Timeout>
+  <tev:MessageLimit>10</tev:MessageLimit>
+</tev:PullMessages>"#;
+        let mut consecutive_failures = 0;
+        let mut last_resubscribe: Option<tokio::time::Instant> = None;
 
-        // Extract IP address from camera config (strip port if present)
-        // CGI API uses HTTPS with credentials in URL query params
-        let camera_address = &camera.config().address;
-        let camera_ip = if let Some(host) = camera_address.split(':').next() {
-            host
-        } else {
-            camera_address.as_str()
+        loop {
+            let current = subscriptions.read().await.get(&subscription._subscription_ref).map(|s| s.expires_at);
+            let client_expires_at = match current.filter(|_| !subscription.shutdown.is_closed()) {
+                Some(expires_at) => expires_at,
+                None => {
+                    tracing::debug!("Subscription {} gone, stopping PullMessages relay", subscription._subscription_ref);
+                    return;
+                }
+            };
+
+            // Cameras that ignore the Timeout and answer immediately would otherwise be polled in a tight loop
+            let next_poll = tokio::time::Instant::now() + tokio::time::Duration::from_millis(500);
+
+            // A camera may grant a shorter lease than asked for; keep it alive while the client is subscribed
+            let camera_expires_at = subscription.camera_subscription.read().await.expires_at;
+            if camera_expires_at - Utc::now() < CAMERA_LEASE_MARGIN && client_expires_at > Utc::now() {
+                if let Err(e) = Self::renew_camera_lease(&camera, &subscription).await {
+                    tracing::debug!("Renewing camera subscription on {} failed: {:#}", subscription.camera_id, e);
+                    consecutive_failures = MAX_PULL_FAILURES;
+                }
+            }
+
+            let needs_resubscribe = consecutive_failures >= MAX_PULL_FAILURES
+                && last_resubscribe.is_none_or(|at| at.elapsed() >= MIN_RESUBSCRIBE_INTERVAL);
+            if needs_resubscribe {
+                last_resubscribe = Some(tokio::time::Instant::now());
+                if Self::resubscribe_camera(&camera, &subscription).await {
+                    consecutive_failures = 0;
+                } else {
+                    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+                    continue;
+                }
+            }
+
+            let subscription_url = subscription.camera_subscription_url().await;
+            match camera.send_soap_request(Self::subscription_path(&subscription_url), request_body).await {
+                Ok(response) if soap::is_fault(&response) => {
+                    // Typically the camera rebooted or let the lease lapse and no longer knows the subscription
+                    tracing::warn!("Camera {} rejected PullMessages, recreating its subscription", subscription.camera_id);
+                    consecutive_failures = MAX_PULL_FAILURES;
+                }
+                Ok(response) => {
+                    consecutive_failures = 0;
+                    let translated = Self::translate_event_messages(&response);
+                    for message in Self::extract_events_from_response(&translated).unwrap_or_default() {
+                        Self::queue_event(&subscription, &event_bus, message).await;
+                    }
+                }
+                Err(e) => {
+                    tracing::debug!("PullMessages from camera {} failed: {}", subscription.camera_id, e);
+                    consecutive_failures += 1;
+                    // Don't hammer a camera that isn't answering
+                    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+                }
+            }
+
+            *subscription.last_poll.write().await = Utc::now();
+            subscription.shutdown.sleep_until(next_poll).await;
+        }
+    }
+
+    async fn queue_event(subscription: &Subscription, event_bus: &broadcast::Sender<PublishedEvent>, message: NotificationMessage) {
+        if let Some(last_states) = &subscription.last_states {
+            let key = (subscription.camera_id.clone(), message.topic.expression.clone());
+            last_states.write().await.insert(key, message.clone());
+        }
+
+        let event = CachedEvent {
+            message,
+            received_at: Utc::now(),
         };
 
-        let username = &camera.config().username;
-        let password = &camera.config().password;
-        let cgi_url = format!(
-            "https://{}/cgi-bin/api.cgi?cmd=GetMdState&channel=0&user={}&password={}",
-            camera_ip, username, password
-        );
+        // The pull-point cache is filled first; external consumers can't hold it up
+        let mut cache = subscription.event_cache.write().await;
+        Self::insert_in_order(&mut cache, event.clone());
+        Self::publish(event_bus, &subscription.camera_id, &event);
 
-        // Use HTTP client with SSL verification disabled (cameras use self-signed certs)
-        let client = reqwest::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .timeout(std::time::Duration::from_secs(5))
-            .build()?;
+        // Limit cache size
+        while cache.len() > 100 {
+            cache.pop_front();
+        }
+        subscription.event_arrived.notify_waiters();
+    }
 
-        let response = client
-            .get(&cgi_url)
-            .send()
-            .await?;
+    /// Keep the cache oldest-first by event time, so pulls see state changes in the order they
+    /// happened. An event stamped before ones already queued (a relayed camera event racing a
+    /// CGI one) goes in its place rather than at the end; equal times keep arrival order
+    fn insert_in_order(cache: &mut VecDeque<CachedEvent>, event: CachedEvent) {
+        let time = event.event_time();
+        let position = cache
+            .iter()
+            .rposition(|queued| queued.event_time() <= time)
+            .map_or(0, |i| i + 1);
+        cache.insert(position, event);
+    }
+
+    /// Path of the camera's subscription manager, from the address it returned
+    fn subscription_path(camera_subscription_url: &str) -> &str {
+        if let Some(idx) = camera_subscription_url.find("/onvif/") {
+            &camera_subscription_url[idx..]
+        } else {
+            "/onvif/event_service"
+        }
+    }
+
+    async fn query_motion_state(camera: &CameraClient) -> Result<bool> {
+        if camera.config().is_simulated() {
+            return Ok(camera.simulated_motion_active().await);
+        }
+
+        // Reolink cameras have broken ONVIF PullPoint but support proprietary CGI API
+        // Query motion detection state via Reolink's CGI interface, with a login token rather
+        // than the password on every poll
 
-        let response_text = response.text().await?;
+        // Background polling yields to interactive requests when the camera is busy
+        let _permit = camera.acquire_slot(Priority::Background).await;
+
+        let response_text = reolink_cgi::send_with_token(camera, "GetMdState", Self::md_state_param(camera.config())).await?;
 
-        // Parse JSON response to check motion state
-        // Response format: [{"cmd":"GetMdState","code":0,"value":{"state":1}}]
         // state: 0 = no motion, 1 = motion detected
-        let has_motion = response_text.contains(r#""state":1"#) ||
-                        response_text.contains(r#""state": 1"#);
+        reolink_cgi::parse_md_state(&response_text)
+    }
+
+    /// Alarm state per AI class; a simulated camera sees a person whenever it sees motion
+    async fn query_ai_state(camera: &CameraClient) -> Result<HashMap<String, bool>> {
+        if camera.config().is_simulated() {
+            let motion = camera.simulated_motion_active().await;
+            return Ok(HashMap::from([
+                ("people".to_string(), motion),
+                ("vehicle".to_string(), false),
+                ("dog_cat".to_string(), false),
+            ]));
+        }
+
+        let _permit = camera.acquire_slot(Priority::Background).await;
+        let response_text = reolink_cgi::send_with_token(camera, "GetAiState", Self::md_state_param(camera.config())).await?;
+        reolink_cgi::parse_ai_state(&response_text)
+    }
 
-        Ok(has_motion)
+    /// GetMdState parameters for the camera's channel. On an NVR each channel's camera entry
+    /// shares the address and queries its own channel
+    fn md_state_param(config: &CameraConfig) -> serde_json::Value {
+        serde_json::json!({ "channel": config.channel })
     }
 
-    fn generate_motion_event(camera_id: &str, motion_active: bool) -> String {
+    fn generate_motion_event(metadata: &SyntheticEventMetadata, motion_active: bool) -> NotificationMessage {
+        Self::generate_state_event(metadata, MOTION_TOPIC, "IsMotion", motion_active)
+    }
+
+    /// A boolean state event on `topic`, with the camera's source metadata
+    fn generate_state_event(metadata: &SyntheticEventMetadata, topic: &str, item_name: &str, active: bool) -> NotificationMessage {
         let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
-        format!(
-            r#"<wsnt:NotificationMessage>
-  <wsnt:Topic Dialect="http://www.onvif.org/ver10/tev/topicExpression/ConcreteSet">tns1:RuleEngine/CellMotionDetector/Motion</wsnt:Topic>
-  <wsnt:Message>
-    <tt:Message UtcTime="{}">
-      <tt:Source>
-        <tt:SimpleItem Name="VideoSourceConfigurationToken" Value="{}"/>
-        <tt:SimpleItem Name="VideoAnalyticsConfigurationToken" Value="{}"/>
-        <tt:SimpleItem Name="Rule" Value="MotionDetectorRule"/>
-      </tt:Source>
-      <tt:Data>
-        <tt:SimpleItem Name="IsMotion" Value="{}"/>
-      </tt:Data>
-    </tt:Message>
-  </wsnt:Message>
-</wsnt:NotificationMessage>"#,
-            now, camera_id, camera_id, motion_active
-        )
+        NotificationMessage {
+            topic: Topic {
+                dialect: Some("http://www.onvif.org/ver10/tev/topicExpression/ConcreteSet".to_string()),
+                expression: topic.to_string(),
+            },
+            message: Some(MessageHolder {
+                message: Message {
+                    utc_time: Some(now),
+                    source: Some(ItemList {
+                        items: vec![
+                            SimpleItem::new(&metadata.source_item_name, &metadata.source_token),
+                            SimpleItem::new(&metadata.analytics_item_name, &metadata.analytics_token),
+                            SimpleItem::new("Rule", &metadata.rule_name),
+                        ],
+                    }),
+                    data: Some(ItemList {
+                        items: vec![SimpleItem::new(item_name, active.to_string())],
+                    }),
+                    ..Default::default()
+                },
+            }),
+        }
     }
 
     pub async fn renew_subscription(
         &self,
         camera: &CameraClient,
-        camera_subscription_url: &str,
-        subscription_ref: &str,
+        subscription: &Subscription,
+        requested_termination: Option<&str>,
     ) -> Result<String> {
-        let request_body = r#"<tev:Renew xmlns:tev="http://www.onvif.org/ver10/events/wsdl">
-  <tev:TerminationTime>PT600S</tev:TerminationTime>
-</tev:Renew>"#;
-
-        // Parse the subscription URL to extract the path
-        let subscription_path = if let Some(idx) = camera_subscription_url.find("/onvif/") {
-            &camera_subscription_url[idx..]
+        let lease = self.grant_lease(requested_termination);
+        let CameraSubscription { url: camera_subscription_url, local, .. } = subscription.camera_subscription.read().await.clone();
+        let subscription_path = Self::subscription_path(&camera_subscription_url);
+
+        let response = if local {
+            let now = Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+            soap::build_envelope(
+                &[("wsnt", "http://docs.oasis-open.org/wsn/b-2")],
+                &format!("<wsnt:RenewResponse><wsnt:TerminationTime></wsnt:TerminationTime><wsnt:CurrentTime>{}</wsnt:CurrentTime></wsnt:RenewResponse>", now),
+            )
         } else {
-            "/onvif/event_service"
+            // Reolink needs WS-Security even on subscription endpoints
+            camera
+                .send_soap_request(subscription_path, &Self::renew_request(lease))
+                .await?
         };
 
-        // Reolink needs WS-Security even on subscription endpoints
-        let response = camera
-            .send_soap_request(subscription_path, request_body)
-            .await?;
-
         // Update subscription expiry time
-        if let Some(sub) = self.subscriptions.write().await.get_mut(subscription_ref) {
-            sub.expires_at = Utc::now() + chrono::Duration::seconds(600);
+        let expires_at = Utc::now() + lease;
+        if let Some(sub) = self.subscriptions.write().await.get_mut(&subscription._subscription_ref) {
+            sub.expires_at = expires_at;
+        }
+        {
+            let mut camera_subscription = subscription.camera_subscription.write().await;
+            camera_subscription.lease = lease;
+            if let Some(camera_expires_at) = Self::extract_termination_time(&response) {
+                camera_subscription.expires_at = camera_expires_at;
+            }
         }
 
-        Ok(response)
+        // The client sees the proxy's lease, whatever the camera granted
+        Ok(Self::rewrite_termination_time(&response, expires_at))
     }
 
     pub async fn unsubscribe(
         &self,
         camera: &CameraClient,
-        camera_subscription_url: &str,
-        subscription_ref: &str,
+        subscription: &Subscription,
     ) -> Result<String> {
-        let request_body = r#"<tev:Unsubscribe xmlns:tev="http://www.onvif.org/ver10/events/wsdl"/>"#;
+        let CameraSubscription { url: camera_subscription_url, local, .. } = subscription.camera_subscription.read().await.clone();
+        let subscription_path = Self::subscription_path(&camera_subscription_url);
 
-        // Parse the subscription URL to extract the path
-        let subscription_path = if let Some(idx) = camera_subscription_url.find("/onvif/") {
-            &camera_subscription_url[idx..]
+        let response = if local {
+            soap::build_envelope(&[("wsnt", "http://docs.oasis-open.org/wsn/b-2")], "<wsnt:UnsubscribeResponse/>")
         } else {
-            "/onvif/event_service"
+            camera
+                .send_soap_request(subscription_path, UNSUBSCRIBE_REQUEST)
+                .await?
         };
 
-        let response = camera
-            .send_soap_request(subscription_path, request_body)
-            .await?;
-
         // Remove subscription from our tracking
-        self.subscriptions.write().await.remove(subscription_ref);
+        self.subscriptions.write().await.remove(&subscription._subscription_ref);
+        subscription.shutdown.close();
 
         Ok(response)
     }
 
     fn normalize_event_properties(xml: &str) -> String {
-        let mut fixed = xml.to_string();
+        // Map Reolink-specific topics to standard ONVIF topics
+        let mut fixed = Self::map_reolink_topics(xml);
 
         // Ensure proper ONVIF event namespaces
         if !fixed.contains("xmlns:tns1=") {
-            fixed = fixed.replace(
-                "<SOAP-ENV:Envelope",
-                r#"<SOAP-ENV:Envelope xmlns:tns1="http://www.onvif.org/ver10/topics""#,
-            );
+            fixed = xmlns::ensure_namespace(&fixed, "tns1", "http://www.onvif.org/ver10/topics");
         }
 
-        // Map Reolink-specific topics to standard ONVIF topics
-        // Reolink uses proprietary event topics for smart detection
-        fixed = Self::map_reolink_topics(&fixed);
-
         fixed
     }
 
+    /// Reolink's `reo:` topic prefix as the ONVIF `tns1:`. Only `Topic`/`TopicExpression` text is
+    /// rewritten; elements, declarations and data that happen to use the prefix are left alone
     fn map_reolink_topics(xml: &str) -> String {
-        let mut result = xml.to_string();
-
-        // Common Reolink smart detection events and their ONVIF equivalents
-        let topic_mappings = vec![
-            // Reolink person detection -> ONVIF CellMotionDetector
-            ("RuleEngine/MyRuleDetector/PeopleDetect", "RuleEngine/CellMotionDetector/Motion"),
-            ("RuleEngine/MyRuleDetector/VehicleDetect", "RuleEngine/CellMotionDetector/Motion"),
-            ("RuleEngine/MyRuleDetector/DogCatDetect", "RuleEngine/CellMotionDetector/Motion"),
-            // Reolink uses custom namespaces, map to tns1
-            ("xmlns:reo=", "xmlns:tns1="),
-            ("<reo:", "<tns1:"),
-            ("</reo:", "</tns1:"),
-        ];
-
-        for (reolink_pattern, onvif_pattern) in topic_mappings {
-            result = result.replace(reolink_pattern, onvif_pattern);
-        }
-
-        result
+        // Smart detection topics (PeopleDetect, VehicleDetect, DogCatDetect) are kept: they are
+        // the ones GetAiState events are raised on, and folding them into Motion would lose them
+        ReolinkEventTranslator::map_topics(xml, |topic| {
+            topic
+                .split('|')
+                .map(|expression| match expression.trim_start().strip_prefix("reo:") {
+                    Some(path) => format!("tns1:{}", path),
+                    None => expression.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("|")
+        })
     }
 
     fn translate_event_messages(xml: &str) -> String {
-        let mut fixed = xml.to_string();
+        // Translate Reolink topics to ONVIF ones first, so the namespaces they need get declared
+        let mut fixed = Self::map_reolink_topics(xml);
 
         // Add missing namespaces for event messages
         if !fixed.contains("xmlns:tns1=") && fixed.contains("tns1:") {
-            fixed = fixed.replace(
-                "<SOAP-ENV:Envelope",
-                r#"<SOAP-ENV:Envelope xmlns:tns1="http://www.onvif.org/ver10/topics""#,
-            );
+            fixed = xmlns::ensure_namespace(&fixed, "tns1", "http://www.onvif.org/ver10/topics");
         }
 
         if !fixed.contains("xmlns:tt=") && fixed.contains("tt:") {
-            fixed = fixed.replace(
-                "<SOAP-ENV:Envelope",
-                r#"<SOAP-ENV:Envelope xmlns:tt="http://www.onvif.org/ver10/schema""#,
-            );
+            fixed = xmlns::ensure_namespace(&fixed, "tt", "http://www.onvif.org/ver10/schema");
         }
 
-        // Translate Reolink event data to ONVIF format
-        fixed = Self::map_reolink_topics(&fixed);
-
-        // Ensure event messages have proper SimpleItem structure
-        fixed = Self::normalize_event_data(&fixed);
-
         fixed
     }
 
-    fn normalize_event_data(xml: &str) -> String {
-        // Reolink sometimes returns event data in non-standard format
-        // This function normalizes it to ONVIF's expected SimpleItem structure
-        let mut result = xml.to_string();
-
-        // Add IsMotion SimpleItem if missing (required by many NVRs)
-        if result.contains("Motion") && !result.contains("IsMotion") {
-            // This is a simplified approach - in production, you'd parse XML properly
-            result = result.replace(
-                "</tev:Data>",
-                r#"<tt:SimpleItem Name="IsMotion" Value="true"/></tev:Data>"#,
-            );
-        }
-
-        result
-    }
-
-    fn parse_iso_duration(duration: &str) -> Option<i64> {
-        // Parse ISO 8601 duration like PT5S, PT1M, etc.
-        if !duration.starts_with("PT") {
+    /// Seconds in an ISO 8601 duration such as `PT5S`, `PT1M30S`, `PT1H` or `P1DT1S`, each unit
+    /// at most once and in order. Fractions (`PT0.5S`) are rounded up to the next whole second, so
+    /// a PullMessages `Timeout` or a lease is never cut shorter than asked, nor a short nonzero
+    /// wait turned into none.
+    pub(crate) fn parse_iso_duration(duration: &str) -> Option<i64> {
+        let rest = duration.strip_prefix('P')?;
+        let (days, time) = match rest.split_once('T') {
+            Some((days, time)) => (days, time),
+            None => (rest, ""),
+        };
+        if days.is_empty() && time.is_empty() {
             return None;
         }
-        let duration = &duration[2..];
-        if duration.ends_with('S') {
-            duration[..duration.len()-1].parse().ok()
-        } else if duration.ends_with('M') {
-            duration[..duration.len()-1].parse::<i64>().ok().map(|m| m * 60)
-        } else {
-            None
-        }
-    }
 
-    fn extract_events_from_response(xml: &str) -> Option<Vec<String>> {
-        // Extract individual NotificationMessage elements from PullMessagesResponse
-        let mut events = Vec::new();
-        let mut search_start = 0;
-
-        loop {
-            if let Some(msg_start) = xml[search_start..].find("<wsnt:NotificationMessage>") {
-                let abs_start = search_start + msg_start;
-                if let Some(msg_end) = xml[abs_start..].find("</wsnt:NotificationMessage>") {
-                    let abs_end = abs_start + msg_end + "</wsnt:NotificationMessage>".len();
-                    events.push(xml[abs_start..abs_end].to_string());
-                    search_start = abs_end;
-                } else {
-                    break;
+        let mut total = 0f64;
+        for (part, units) in [(days, &[('D', 86400)][..]), (time, &[('H', 3600), ('M', 60), ('S', 1)][..])] {
+            let mut number = String::new();
+            let mut units = units.iter();
+            for c in part.chars() {
+                if c.is_ascii_digit() || c == '.' {
+                    number.push(c);
+                    continue;
                 }
-            } else {
-                break;
+                let &(_, scale) = units.by_ref().find(|(unit, _)| *unit == c)?;
+                let value: f64 = number.parse().ok()?;
+                total += value * scale as f64;
+                number.clear();
+            }
+            if !number.is_empty() {
+                return None;
             }
         }
-
-        if events.is_empty() {
-            None
-        } else {
-            Some(events)
-        }
+        (total <= i64::MAX as f64).then(|| total.ceil() as i64)
     }
 
-    fn build_pull_messages_response(events: &[CachedEvent]) -> String {
-        let mut messages = String::new();
-        for event in events {
-            messages.push_str(&event.event_xml);
-            messages.push('\n');
+    /// Seconds from `now` for a termination time given as a duration (`PT600S`) or an absolute time
+    fn parse_termination_time(value: &str, now: chrono::DateTime<Utc>) -> Option<i64> {
+        if value.starts_with('P') {
+            return Self::parse_iso_duration(value);
         }
+        chrono::DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|time| (time.with_timezone(&Utc) - now).num_seconds())
+    }
 
-        let current_time = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
-        let termination_time = (chrono::Utc::now() + chrono::Duration::seconds(600)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    fn renew_request(lease: chrono::Duration) -> String {
+        format!(
+            r#"<tev:Renew xmlns:tev="http://www.onvif.org/ver10/events/wsdl">
+  <tev:TerminationTime>PT{}S</tev:TerminationTime>
+</tev:Renew>"#,
+            lease.num_seconds()
+        )
+    }
 
+    /// Replace the TerminationTime in a camera's CreatePullPointSubscription or Renew response
+    fn rewrite_termination_time(xml: &str, termination_time: chrono::DateTime<Utc>) -> String {
+        let Some(start) = xml.find("TerminationTime>").map(|i| i + "TerminationTime>".len()) else {
+            return xml.to_string();
+        };
+        let Some(end) = xml[start..].find('<').map(|i| start + i) else {
+            return xml.to_string();
+        };
         format!(
-            r#"<?xml version="1.0" encoding="UTF-8"?>
-<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope" xmlns:tev="http://www.onvif.org/ver10/events/wsdl" xmlns:wsnt="http://docs.oasis-open.org/wsn/b-2" xmlns:tt="http://www.onvif.org/ver10/schema" xmlns:tns1="http://www.onvif.org/ver10/topics">
-<SOAP-ENV:Body>
-<tev:PullMessagesResponse>
-  <tev:CurrentTime>{}</tev:CurrentTime>
-  <tev:TerminationTime>{}</tev:TerminationTime>
-  {}
-</tev:PullMessagesResponse>
-</SOAP-ENV:Body>
-</SOAP-ENV:Envelope>"#,
-            current_time, termination_time, messages
+            "{}{}{}",
+            &xml[..start],
+            termination_time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            &xml[end..]
         )
     }
 
+    fn extract_events_from_response(xml: &str) -> Option<Vec<NotificationMessage>> {
+        let response = PullMessagesResponse::parse(xml).ok()?;
+        (!response.messages.is_empty()).then_some(response.messages)
+    }
+
+    /// PullMessagesResponse; an idle pull still carries CurrentTime/TerminationTime so
+    /// clients treat it as a keepalive rather than tearing the subscription down
+    fn build_pull_messages_response(events: &[CachedEvent], termination_time: chrono::DateTime<Utc>) -> Result<String> {
+        PullMessagesResponse {
+            current_time: Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            termination_time: termination_time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            messages: events.iter().map(|event| event.message.clone()).collect(),
+        }
+        .to_envelope()
+    }
+
+    /// TerminationTime of a CreatePullPointSubscription or Renew response
+    fn extract_termination_time(xml: &str) -> Option<chrono::DateTime<Utc>> {
+        let start = xml.find("TerminationTime>")? + "TerminationTime>".len();
+        let end = start + xml[start..].find('<')?;
+        chrono::DateTime::parse_from_rfc3339(xml[start..end].trim())
+            .ok()
+            .map(|time| time.with_timezone(&Utc))
+    }
+
     fn extract_subscription_url(xml: &str) -> String {
         // Extract the camera's subscription URL from the response
         for prefix in &["wsa5:", "wsa:", ""] {
@@ -504,6 +1081,516 @@ impl Clone for EventsService {
     fn clone(&self) -> Self {
         Self {
             subscriptions: Arc::clone(&self.subscriptions),
+            event_bus: self.event_bus.clone(),
+            config: self.config.clone(),
+            last_states: Arc::clone(&self.last_states),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(n: usize) -> CachedEvent {
+        CachedEvent {
+            message: NotificationMessage {
+                topic: Topic {
+                    dialect: None,
+                    expression: format!("tns1:Test/Event{}", n),
+                },
+                message: None,
+            },
+            received_at: Utc::now(),
+        }
+    }
+
+    fn to_xml(message: NotificationMessage) -> String {
+        PullMessagesResponse {
+            messages: vec![message],
+            ..Default::default()
+        }
+        .to_envelope()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_relay_camera_pull_messages() {
+        let response = r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope" xmlns:wsnt="http://docs.oasis-open.org/wsn/b-2"><SOAP-ENV:Body><tev:PullMessagesResponse><wsnt:NotificationMessage><wsnt:Topic>tns1:RuleEngine/MyRuleDetector/PeopleDetect</wsnt:Topic><wsnt:Message><tt:Message><tt:Data><tt:SimpleItem Name="State" Value="true"/></tt:Data></tt:Message></wsnt:Message></wsnt:NotificationMessage><wsnt:NotificationMessage><wsnt:Topic>tns1:RuleEngine/CellMotionDetector/Motion</wsnt:Topic></wsnt:NotificationMessage></tev:PullMessagesResponse></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+
+        let translated = EventsService::translate_event_messages(response);
+        let events = EventsService::extract_events_from_response(&translated).unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].topic.expression, "tns1:RuleEngine/MyRuleDetector/PeopleDetect");
+        assert_eq!(events[0].message().unwrap().data.as_ref().unwrap().get("State"), Some("true"));
+        assert!(EventsService::extract_events_from_response("<tev:PullMessagesResponse/>").is_none());
+    }
+
+    /// Only topic text loses the `reo:` prefix; the same text in data and `reo:` elements stay
+    #[test]
+    fn test_reolink_topics_mapped_in_topics_only() {
+        let response = r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope" xmlns:wsnt="http://docs.oasis-open.org/wsn/b-2" xmlns:reo="http://www.reolink.com/onvif"><SOAP-ENV:Body><tev:PullMessagesResponse><wsnt:NotificationMessage><wsnt:Topic>reo:RuleEngine/MyRuleDetector/PeopleDetect</wsnt:Topic><wsnt:Message><tt:Message><tt:Source><tt:SimpleItem Name="Rule" Value="reo:RuleEngine/MyRuleDetector/PeopleDetect"/></tt:Source><tt:Data><tt:SimpleItem Name="State" Value="true"/></tt:Data><reo:Extra>1</reo:Extra></tt:Message></wsnt:Message></wsnt:NotificationMessage></tev:PullMessagesResponse></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+
+        let translated = EventsService::translate_event_messages(response);
+        assert!(translated.contains("<wsnt:Topic>tns1:RuleEngine/MyRuleDetector/PeopleDetect</wsnt:Topic>"), "{}", translated);
+        assert!(translated.contains(r#"xmlns:tns1="http://www.onvif.org/ver10/topics""#), "{}", translated);
+        assert!(translated.contains(r#"Value="reo:RuleEngine/MyRuleDetector/PeopleDetect""#), "{}", translated);
+        assert!(translated.contains(r#"xmlns:reo="http://www.reolink.com/onvif""#) && translated.contains("<reo:Extra>1</reo:Extra>"), "{}", translated);
+
+        let events = EventsService::extract_events_from_response(&translated).unwrap();
+        assert_eq!(events[0].topic.expression, "tns1:RuleEngine/MyRuleDetector/PeopleDetect");
+    }
+
+    #[test]
+    fn test_event_method_from_config() {
+        use crate::config::EventMethod;
+
+        let method: EventMethod = serde_yaml::from_str("both").unwrap();
+        assert!(method.uses_cgi() && method.uses_onvif());
+        assert_eq!(EventMethod::default(), EventMethod::Cgi);
+
+        let config: CameraConfig = serde_yaml::from_str("id: cam1\nname: Cam 1\naddress: 192.168.1.10\nusername: admin\nevent_source: pullpoint").unwrap();
+        assert_eq!(config.event_method, EventMethod::Onvif);
+    }
+
+    #[tokio::test]
+    async fn test_pull_point_relay_fills_cache() {
+        use axum::{routing::post, Router};
+
+        // Fake camera: a working PullPoint reporting a person and a tamper event
+        let address = Arc::new(std::sync::OnceLock::<std::net::SocketAddr>::new());
+        let subscription_address = address.clone();
+        let mock = Router::new()
+            .route(
+                "/onvif/event_service",
+                post(move || {
+                    let address = subscription_address.clone();
+                    async move {
+                        format!(
+                            r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:wsa="http://www.w3.org/2005/08/addressing" xmlns:tev="http://www.onvif.org/ver10/events/wsdl"><s:Body><tev:CreatePullPointSubscriptionResponse><tev:SubscriptionReference><wsa:Address>http://{}/onvif/Subscription?Idx=1</wsa:Address></tev:SubscriptionReference></tev:CreatePullPointSubscriptionResponse></s:Body></s:Envelope>"#,
+                            address.get().unwrap()
+                        )
+                    }
+                }),
+            )
+            .route(
+                "/onvif/Subscription",
+                post(|| async {
+                    r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:wsnt="http://docs.oasis-open.org/wsn/b-2" xmlns:tev="http://www.onvif.org/ver10/events/wsdl" xmlns:tt="http://www.onvif.org/ver10/schema"><s:Body><tev:PullMessagesResponse><wsnt:NotificationMessage><wsnt:Topic>tns1:RuleEngine/MyRuleDetector/PeopleDetect</wsnt:Topic><wsnt:Message><tt:Message UtcTime="2026-01-01T00:00:00Z"><tt:Data><tt:SimpleItem Name="State" Value="true"/></tt:Data></tt:Message></wsnt:Message></wsnt:NotificationMessage><wsnt:NotificationMessage><wsnt:Topic>tns1:VideoSource/GlobalSceneChange/ImagingService</wsnt:Topic><wsnt:Message><tt:Message UtcTime="2026-01-01T00:00:01Z"><tt:Data><tt:SimpleItem Name="State" Value="true"/></tt:Data></tt:Message></wsnt:Message></wsnt:NotificationMessage></tev:PullMessagesResponse></s:Body></s:Envelope>"#
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        address.set(listener.local_addr().unwrap()).unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await.unwrap() });
+
+        let camera = CameraClient::new(CameraConfig {
+            id: "cam1".to_string(),
+            address: address.get().unwrap().to_string(),
+            event_method: crate::config::EventMethod::Onvif,
+            ..Default::default()
+        });
+        let service = EventsService::new();
+        let response = service.create_pull_point_subscription(&camera, "http://proxy", Some("PT60S")).await.unwrap();
+        let url = EventsService::extract_subscription_url(&response);
+        let subscription = service.get_subscription(url.rsplit('/').next().unwrap()).await.unwrap();
+
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+        while subscription.event_cache.read().await.len() < 2 && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        let cache = subscription.event_cache.read().await;
+        let topics: Vec<_> = cache.iter().take(2).map(|event| event.message.topic.expression.as_str()).collect();
+        assert_eq!(topics, ["tns1:RuleEngine/MyRuleDetector/PeopleDetect", "tns1:VideoSource/GlobalSceneChange/ImagingService"]);
+        drop(cache);
+        subscription.shutdown.close();
+    }
+
+    #[tokio::test]
+    async fn test_slow_consumer_drops_oldest() {
+        let service = EventsService::new();
+        let mut slow = service.subscribe_consumer("slow");
+
+        // Publishing never blocks, even with a consumer that isn't reading
+        for n in 0..CONSUMER_BUFFER + 10 {
+            EventsService::publish(&service.event_bus, "cam1", &event(n));
         }
+
+        // The oldest 10 were dropped; the consumer resumes with the oldest retained event
+        let first = slow.recv().await.unwrap();
+        assert_eq!(first.camera_id, "cam1");
+        assert_eq!(first.event.message.topic.expression, "tns1:Test/Event10");
+    }
+
+    #[tokio::test]
+    async fn test_consumer_closed_with_service() {
+        let service = EventsService::new();
+        let mut consumer = service.subscribe_consumer("test");
+        drop(service);
+
+        assert!(consumer.recv().await.is_none());
+    }
+
+    #[test]
+    fn test_synthetic_event_metadata() {
+        let mut config = CameraConfig {
+            id: "cam1".to_string(),
+            ..Default::default()
+        };
+        let default_event = to_xml(EventsService::generate_motion_event(&SyntheticEventMetadata::from_config(&config), true));
+        assert!(default_event.contains(r#"<tt:SimpleItem Name="VideoSourceConfigurationToken" Value="cam1"/>"#));
+        assert!(default_event.contains(r#"<tt:SimpleItem Name="Rule" Value="MotionDetectorRule"/>"#));
+
+        config.event_rule_name = Some("MyMotionDetectorRule".to_string());
+        config.event_source_token = Some("VideoSourceConfig_0".to_string());
+        config.event_analytics_item_name = Some("AnalyticsToken".to_string());
+        let custom = to_xml(EventsService::generate_motion_event(&SyntheticEventMetadata::from_config(&config), false));
+        assert!(custom.contains(r#"<tt:SimpleItem Name="VideoSourceConfigurationToken" Value="VideoSourceConfig_0"/>"#));
+        assert!(custom.contains(r#"<tt:SimpleItem Name="AnalyticsToken" Value="cam1"/>"#));
+        assert!(custom.contains(r#"<tt:SimpleItem Name="Rule" Value="MyMotionDetectorRule"/>"#));
+        assert!(custom.contains(r#"<tt:SimpleItem Name="IsMotion" Value="false"/>"#));
+    }
+
+    #[test]
+    fn test_empty_pull_messages_response() {
+        let expires_at = Utc::now() + chrono::Duration::seconds(60);
+        let response = EventsService::build_pull_messages_response(&[], expires_at).unwrap();
+
+        let envelope = crate::onvif::soap::SoapEnvelope::parse(&response).unwrap();
+        assert_eq!(envelope.body.action, "PullMessagesResponse");
+        assert!(response.contains("<tev:CurrentTime>"));
+        assert!(response.contains(&format!(
+            "<tev:TerminationTime>{}</tev:TerminationTime>\n</tev:PullMessagesResponse>",
+            expires_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        )));
+        assert!(!response.lines().any(|line| line.trim().is_empty()));
+
+        let with_events = EventsService::build_pull_messages_response(&[event(1), event(2)], expires_at).unwrap();
+        let parsed = PullMessagesResponse::parse(&with_events).unwrap();
+        let topics: Vec<&str> = parsed.messages.iter().map(|m| m.topic.expression.as_str()).collect();
+        assert_eq!(topics, vec!["tns1:Test/Event1", "tns1:Test/Event2"]);
+    }
+
+    #[test]
+    fn test_camera_subscription_lease() {
+        let created = r#"<tev:CreatePullPointSubscriptionResponse><tev:SubscriptionReference><wsa5:Address>http://192.168.1.100:8000/onvif/Subscription?Idx=3</wsa5:Address></tev:SubscriptionReference><wsnt:CurrentTime>2026-01-01T00:00:00Z</wsnt:CurrentTime><wsnt:TerminationTime>2026-01-01T00:01:00Z</wsnt:TerminationTime></tev:CreatePullPointSubscriptionResponse>"#;
+
+        assert_eq!(
+            EventsService::extract_termination_time(created),
+            Some("2026-01-01T00:01:00Z".parse().unwrap())
+        );
+        assert_eq!(EventsService::extract_subscription_url(created), "http://192.168.1.100:8000/onvif/Subscription?Idx=3");
+        assert_eq!(EventsService::extract_termination_time("<tev:PullMessagesResponse/>"), None);
+
+        assert!(soap::is_fault(&soap::build_fault(soap::FaultCode::Receiver, "ResourceUnknown", "no such subscription")));
+        assert!(!soap::is_fault(&EventsService::build_pull_messages_response(&[], Utc::now()).unwrap()));
+    }
+
+    #[test]
+    fn test_parse_iso_duration() {
+        assert_eq!(EventsService::parse_iso_duration("PT1M30S"), Some(90));
+        assert_eq!(EventsService::parse_iso_duration("PT1H"), Some(3600));
+        assert_eq!(EventsService::parse_iso_duration("PT0S"), Some(0));
+        assert_eq!(EventsService::parse_iso_duration("PT90S"), Some(90));
+        assert_eq!(EventsService::parse_iso_duration("PT1H2M3S"), Some(3723));
+        assert_eq!(EventsService::parse_iso_duration("PT0.5S"), Some(1));
+        assert_eq!(EventsService::parse_iso_duration("PT1.2M"), Some(72));
+
+        for malformed in ["", "P", "PT", "5S", "PT5", "PTS", "PT-5S", "PT1S1M", "PT1M1M", "PT1.2.3S", "pt5s", "PT 5S"] {
+            assert_eq!(EventsService::parse_iso_duration(malformed), None, "{:?}", malformed);
+        }
+    }
+
+    #[test]
+    fn test_termination_time_clamping() {
+        let now = Utc::now();
+        assert_eq!(EventsService::parse_termination_time("PT600S", now), Some(600));
+        assert_eq!(EventsService::parse_termination_time("PT1H30M", now), Some(5400));
+        assert_eq!(EventsService::parse_termination_time("P1DT1S", now), Some(86401));
+        assert_eq!(EventsService::parse_termination_time("PT0.5S", now), Some(1));
+        assert_eq!(EventsService::parse_termination_time("PT10X", now), None);
+        let absolute = (now + chrono::Duration::seconds(120)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        assert!((119..=120).contains(&EventsService::parse_termination_time(&absolute, now).unwrap()));
+
+        let service = EventsService::with_config(EventsConfig {
+            default_termination_secs: 300,
+            max_termination_secs: 900,
+            min_termination_secs: 30,
+            ..Default::default()
+        });
+        assert_eq!(service.grant_lease(None), chrono::Duration::seconds(300));
+        assert_eq!(service.grant_lease(Some("PT1H")), chrono::Duration::seconds(900));
+        assert_eq!(service.grant_lease(Some("PT1S")), chrono::Duration::seconds(30));
+        assert_eq!(service.grant_lease(Some("garbage")), chrono::Duration::seconds(300));
+
+        let renewed = r#"<wsnt:RenewResponse><wsnt:TerminationTime>2026-01-01T00:10:00Z</wsnt:TerminationTime><wsnt:CurrentTime>2026-01-01T00:00:00Z</wsnt:CurrentTime></wsnt:RenewResponse>"#;
+        let granted = chrono::DateTime::parse_from_rfc3339("2026-01-01T00:15:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(
+            EventsService::rewrite_termination_time(renewed, granted),
+            renewed.replace("2026-01-01T00:10:00Z", "2026-01-01T00:15:00Z")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pull_messages_ordered_batches() {
+        let service = EventsService::new();
+        let subscription = Subscription {
+            _subscription_ref: "sub".to_string(),
+            camera_id: "cam1".to_string(),
+            camera_subscription: Arc::new(RwLock::new(CameraSubscription {
+                url: String::new(),
+                expires_at: Utc::now(),
+                lease: chrono::Duration::seconds(60),
+                local: false,
+            })),
+            _created_at: Utc::now(),
+            expires_at: Utc::now() + chrono::Duration::seconds(60),
+            event_cache: Arc::new(RwLock::new(VecDeque::new())),
+            last_poll: Arc::new(RwLock::new(Utc::now())),
+            last_states: None,
+            event_arrived: Arc::new(Notify::new()),
+            shutdown: Arc::new(Shutdown::default()),
+        };
+        service.subscriptions.write().await.insert("sub".to_string(), subscription.clone());
+
+        let start = Utc::now();
+        let at = |secs: i64, topic: &str| {
+            let mut message = EventsService::generate_motion_event(&SyntheticEventMetadata::from_config(&CameraConfig::default()), true);
+            message.topic.expression = topic.to_string();
+            message.message.as_mut().unwrap().message.utc_time =
+                Some((start + chrono::Duration::seconds(secs)).to_rfc3339_opts(chrono::SecondsFormat::Millis, true));
+            message
+        };
+        // Motion on, motion off, then a person detection stamped between them arrives late
+        for message in [at(0, "motion-on"), at(2, "motion-off"), at(1, "person"), at(3, "motion-on-again")] {
+            EventsService::queue_event(&subscription, &service.event_bus, message).await;
+        }
+
+        let topics = |xml: &str| -> Vec<(String, String)> {
+            PullMessagesResponse::parse(xml)
+                .unwrap()
+                .messages
+                .iter()
+                .map(|m| (m.topic.expression.clone(), m.message().unwrap().utc_time.clone().unwrap()))
+                .collect()
+        };
+
+        // MessageLimit keeps the oldest; the rest wait for the next pull
+        let first = topics(&service.pull_messages("sub", "PT0S", 2).await.unwrap());
+        let second = topics(&service.pull_messages("sub", "PT0S", 10).await.unwrap());
+        let order: Vec<&str> = first.iter().chain(&second).map(|(topic, _)| topic.as_str()).collect();
+        assert_eq!(order, ["motion-on", "person", "motion-off", "motion-on-again"]);
+
+        let times: Vec<&String> = first.iter().chain(&second).map(|(_, time)| time).collect();
+        assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[tokio::test]
+    async fn test_local_subscription() {
+        // Nothing listens here, so any request to the camera would fail
+        let camera = CameraClient::new(CameraConfig {
+            id: "cam1".to_string(),
+            address: "127.0.0.1:9".to_string(),
+            ..Default::default()
+        });
+        let service = EventsService::with_config(EventsConfig {
+            local_subscription: true,
+            ..Default::default()
+        });
+
+        let response = service.create_pull_point_subscription(&camera, "http://proxy", Some("PT120S")).await.unwrap();
+        let url = EventsService::extract_subscription_url(&response);
+        assert!(url.starts_with("http://proxy/onvif/cam1/subscription/"));
+        let expires = EventsService::extract_termination_time(&response).unwrap();
+        assert!((expires - Utc::now() - chrono::Duration::seconds(120)).num_seconds().abs() <= 1);
+
+        let subscription = service.get_subscription(url.rsplit('/').next().unwrap()).await.unwrap();
+        assert!(subscription.camera_subscription.read().await.local);
+        let renewed = service.renew_subscription(&camera, &subscription, Some("PT300S")).await.unwrap();
+        assert!(EventsService::extract_termination_time(&renewed).unwrap() > expires);
+        assert!(service.unsubscribe(&camera, &subscription).await.unwrap().contains("UnsubscribeResponse"));
+        assert_eq!(service.active_subscriptions("cam1").await, 0);
+
+        // Relayed ONVIF events still need the camera's subscription
+        let relayed = CameraClient::new(CameraConfig {
+            event_method: crate::config::EventMethod::Both,
+            ..camera.config().clone()
+        });
+        assert!(service.create_pull_point_subscription(&relayed, "http://proxy", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expired_subscriptions_reaped() {
+        let camera = CameraClient::new(CameraConfig {
+            id: "sim".to_string(),
+            model: "simulated".to_string(),
+            ..Default::default()
+        });
+        let service = EventsService::with_config(EventsConfig {
+            min_termination_secs: 0,
+            ..Default::default()
+        });
+        let response = service.create_pull_point_subscription(&camera, "http://proxy", Some("PT1S")).await.unwrap();
+        let subscription_ref = EventsService::extract_subscription_url(&response).rsplit('/').next().unwrap().to_string();
+        let subscription = service.get_subscription(&subscription_ref).await.unwrap();
+        let reaper = service.spawn_reaper_every(std::time::Duration::from_millis(100));
+
+        // Still polling before the lease ends
+        tokio::time::sleep(std::time::Duration::from_millis(700)).await;
+        assert_eq!(service.active_subscriptions("sim").await, 1);
+        assert!(*subscription.last_poll.read().await > subscription._created_at);
+
+        tokio::time::sleep(std::time::Duration::from_millis(800)).await;
+        assert!(service.subscriptions.read().await.is_empty());
+        assert!(subscription.shutdown.is_closed());
+
+        // The CGI poller has stopped
+        let last_poll = *subscription.last_poll.read().await;
+        tokio::time::sleep(std::time::Duration::from_millis(1200)).await;
+        assert_eq!(*subscription.last_poll.read().await, last_poll);
+        reaper.abort();
+    }
+
+    #[tokio::test]
+    async fn test_pull_messages_long_poll() {
+        let service = EventsService::with_config(EventsConfig { max_pull_timeout_secs: 2, ..Default::default() });
+        let subscription = Subscription {
+            _subscription_ref: "sub".to_string(),
+            camera_id: "cam1".to_string(),
+            camera_subscription: Arc::new(RwLock::new(CameraSubscription {
+                url: String::new(),
+                expires_at: Utc::now(),
+                lease: chrono::Duration::seconds(60),
+                local: false,
+            })),
+            _created_at: Utc::now(),
+            expires_at: Utc::now() + chrono::Duration::seconds(60),
+            event_cache: Arc::new(RwLock::new(VecDeque::new())),
+            last_poll: Arc::new(RwLock::new(Utc::now())),
+            last_states: None,
+            event_arrived: Arc::new(Notify::new()),
+            shutdown: Arc::new(Shutdown::default()),
+        };
+        service.subscriptions.write().await.insert("sub".to_string(), subscription.clone());
+        let event = || EventsService::generate_motion_event(&SyntheticEventMetadata::from_config(&CameraConfig::default()), true);
+        let count = |xml: &str| PullMessagesResponse::parse(xml).unwrap().messages.len();
+
+        // Nothing queued: empty once the Timeout has passed
+        let started = std::time::Instant::now();
+        assert_eq!(count(&service.pull_messages("sub", "PT1S", 10).await.unwrap()), 0);
+        assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+
+        // The first event to arrive is returned straight away, without waiting to fill MessageLimit
+        let (queued, bus) = (subscription.clone(), service.event_bus.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            EventsService::queue_event(&queued, &bus, event()).await;
+        });
+        let started = std::time::Instant::now();
+        assert_eq!(count(&service.pull_messages("sub", "PT10S", 10).await.unwrap()), 1);
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+
+        // Queued events come back immediately, up to MessageLimit
+        for _ in 0..3 {
+            EventsService::queue_event(&subscription, &service.event_bus, event()).await;
+        }
+        let started = std::time::Instant::now();
+        assert_eq!(count(&service.pull_messages("sub", "PT10S", 2).await.unwrap()), 2);
+        assert_eq!(count(&service.pull_messages("sub", "PT10S", 0).await.unwrap()), 0);
+        assert_eq!(count(&service.pull_messages("sub", "PT10S", 2).await.unwrap()), 1);
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+
+        // Timeouts past max_pull_timeout_secs are cut to it, however large
+        let started = std::time::Instant::now();
+        assert_eq!(count(&service.pull_messages("sub", "PT9223372036854775807S", 10).await.unwrap()), 0);
+        assert!((2..4).contains(&started.elapsed().as_secs()));
+    }
+
+    #[tokio::test]
+    async fn test_replay_last_state() {
+        let camera = CameraClient::new(CameraConfig {
+            id: "sim".to_string(),
+            model: "simulated".to_string(),
+            ..Default::default()
+        });
+        let service = EventsService::with_config(EventsConfig {
+            replay_last_state: true,
+            ..Default::default()
+        });
+        let subscribe = || async {
+            let response = service.create_pull_point_subscription(&camera, "http://proxy", None).await.unwrap();
+            EventsService::extract_subscription_url(&response).rsplit('/').next().unwrap().to_string()
+        };
+
+        // The first subscription sees motion start
+        let first = subscribe().await;
+        camera.trigger_simulated_motion(chrono::Duration::seconds(60)).await;
+        let mut seen = String::new();
+        for _ in 0..20 {
+            seen.push_str(&service.pull_messages(&first, "PT0S", 10).await.unwrap());
+            if seen.contains(r#"Value="true""#) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        }
+        assert!(seen.contains(r#"Value="true""#));
+
+        // A later subscription gets it straight away, as Initialized
+        let second = subscribe().await;
+        let replayed = PullMessagesResponse::parse(&service.pull_messages(&second, "PT0S", 10).await.unwrap()).unwrap();
+        assert_eq!(replayed.messages.len(), 1);
+        let message = replayed.messages[0].message().unwrap();
+        assert_eq!(message.property_operation.as_deref(), Some("Initialized"));
+        assert_eq!(message.data.as_ref().unwrap().items[0].value, "true");
+    }
+
+    #[tokio::test]
+    async fn test_nvr_channels_independent() {
+        let channel = |id: &str, channel: u8| {
+            CameraClient::new(CameraConfig {
+                id: id.to_string(),
+                address: "192.168.1.50:443".to_string(),
+                username: "admin".to_string(),
+                model: "simulated".to_string(),
+                channel,
+                ..Default::default()
+            })
+        };
+        let (front, back) = (channel("front", 0), channel("back", 3));
+        assert_eq!(EventsService::md_state_param(front.config()), serde_json::json!({ "channel": 0 }));
+        assert_eq!(EventsService::md_state_param(back.config()), serde_json::json!({ "channel": 3 }));
+
+        let service = EventsService::with_config(EventsConfig {
+            replay_last_state: true,
+            ..Default::default()
+        });
+        let subscribe = |camera: &CameraClient| {
+            let (service, camera) = (service.clone(), camera.clone());
+            async move {
+                let response = service.create_pull_point_subscription(&camera, "http://proxy", None).await.unwrap();
+                EventsService::extract_subscription_url(&response).rsplit('/').next().unwrap().to_string()
+            }
+        };
+        let front_sub = subscribe(&front).await;
+        let back_sub = subscribe(&back).await;
+
+        // Each channel's subscription only gets its own channel's events
+        let (mut front_seen, mut back_seen) = (String::new(), String::new());
+        for _ in 0..20 {
+            front_seen.push_str(&service.pull_messages(&front_sub, "PT0S", 10).await.unwrap());
+            back_seen.push_str(&service.pull_messages(&back_sub, "PT0S", 10).await.unwrap());
+            if front_seen.contains("IsMotion") && back_seen.contains("IsMotion") {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        }
+        let source = |id: &str| format!(r#"<tt:SimpleItem Name="VideoSourceConfigurationToken" Value="{}"/>"#, id);
+        assert!(front_seen.contains(&source("front")) && !front_seen.contains(&source("back")));
+        assert!(back_seen.contains(&source("back")) && !back_seen.contains(&source("front")));
+
+        // The remembered state is per channel too
+        let replayed = service.pull_messages(&subscribe(&front).await, "PT0S", 10).await.unwrap();
+        assert!(replayed.contains(&source("front")) && !replayed.contains(&source("back")));
     }
 }
@@ -0,0 +1,160 @@
+use crate::camera::reolink_cgi::{self, Image};
+use crate::camera::CameraClient;
+use crate::config::ImagingBackend;
+use crate::onvif::soap;
+use anyhow::Result;
+use quick_xml::{events::Event, Reader};
+
+const NS_TIMG: (&str, &str) = ("timg", "http://www.onvif.org/ver20/imaging/wsdl");
+const NS_TT: (&str, &str) = ("tt", "http://www.onvif.org/ver10/schema");
+
+/// ONVIF range reported for every setting when imaging is backed by the CGI
+const ONVIF_MAX: f64 = 100.0;
+/// Reolink's CGI range for every setting (0..=255)
+const REOLINK_MAX: f64 = 255.0;
+
+/// Imaging requests, answered by the camera's ONVIF imaging service or, since Reolink's is
+/// often incomplete, by the GetImage/SetImage CGI (`imaging_backend`)
+pub struct ImagingService;
+
+impl ImagingService {
+    /// Actions the CGI backend can answer
+    pub fn is_cgi_action(action: &str) -> bool {
+        matches!(action, "GetImagingSettings" | "SetImagingSettings" | "GetOptions")
+    }
+
+    pub async fn handle(camera: &CameraClient, action: &str, soap_body: &str) -> Result<String> {
+        match camera.config().imaging_backend {
+            ImagingBackend::Onvif => Self::forward(camera, soap_body).await,
+            ImagingBackend::Cgi => Self::cgi(camera, action, soap_body).await,
+            ImagingBackend::Auto if !Self::is_cgi_action(action) => Self::forward(camera, soap_body).await,
+            ImagingBackend::Auto => match Self::forward(camera, soap_body).await {
+                Ok(xml) if !soap::is_fault(&xml) => Ok(xml),
+                Ok(_) => {
+                    tracing::debug!("Camera {} faulted on {}, using the CGI", camera.camera_id(), action);
+                    Self::cgi(camera, action, soap_body).await
+                }
+                Err(e) => {
+                    tracing::debug!("ONVIF {} failed on camera {} ({:#}), using the CGI", action, camera.camera_id(), e);
+                    Self::cgi(camera, action, soap_body).await
+                }
+            },
+        }
+    }
+
+    async fn forward(camera: &CameraClient, soap_body: &str) -> Result<String> {
+        camera.send_soap_request("/onvif/imaging_service", soap_body).await
+    }
+
+    async fn cgi(camera: &CameraClient, action: &str, soap_body: &str) -> Result<String> {
+        if action == "GetOptions" {
+            return Ok(Self::options_response());
+        }
+
+        let config = camera.config();
+        let reply = reolink_cgi::send_command(config, "GetImage", serde_json::json!({ "channel": config.channel })).await?;
+        let image = reolink_cgi::parse_image(&reply)?;
+
+        match action {
+            "GetImagingSettings" => Ok(Self::settings_response(&image)),
+            "SetImagingSettings" => {
+                let image = Image { channel: config.channel, ..Self::apply_settings(image, soap_body) };
+                let reply = reolink_cgi::send_command(config, "SetImage", serde_json::json!({ "Image": image })).await?;
+                reolink_cgi::parse_set(&reply, "SetImage")?;
+                Ok(soap::build_envelope(&[NS_TIMG], "<timg:SetImagingSettingsResponse/>"))
+            }
+            _ => anyhow::bail!("{} is not available through the CGI", action),
+        }
+    }
+
+    fn settings_response(image: &Image) -> String {
+        let body = format!(
+            "<timg:GetImagingSettingsResponse><timg:ImagingSettings>\
+             <tt:Brightness>{}</tt:Brightness>\
+             <tt:ColorSaturation>{}</tt:ColorSaturation>\
+             <tt:Contrast>{}</tt:Contrast>\
+             <tt:Sharpness>{}</tt:Sharpness>\
+             </timg:ImagingSettings></timg:GetImagingSettingsResponse>",
+            to_onvif(image.bright),
+            to_onvif(image.saturation),
+            to_onvif(image.contrast),
+            to_onvif(image.sharpen)
+        );
+        soap::build_envelope(&[NS_TIMG, NS_TT], &body)
+    }
+
+    fn options_response() -> String {
+        let range = |name: &str| format!("<tt:{name}><tt:Min>0</tt:Min><tt:Max>{}</tt:Max></tt:{name}>", ONVIF_MAX, name = name);
+        let body = format!(
+            "<timg:GetOptionsResponse><timg:ImagingOptions>{}{}{}{}</timg:ImagingOptions></timg:GetOptionsResponse>",
+            range("Brightness"),
+            range("ColorSaturation"),
+            range("Contrast"),
+            range("Sharpness")
+        );
+        soap::build_envelope(&[NS_TIMG, NS_TT], &body)
+    }
+
+    /// Apply the settings a SetImagingSettings request carries; the rest keep their values
+    fn apply_settings(mut image: Image, soap_body: &str) -> Image {
+        let settings = [
+            ("Brightness", &mut image.bright),
+            ("ColorSaturation", &mut image.saturation),
+            ("Contrast", &mut image.contrast),
+            ("Sharpness", &mut image.sharpen),
+        ];
+        for (name, value) in settings {
+            if let Some(setting) = setting_value(soap_body, name) {
+                *value = to_reolink(setting);
+            }
+        }
+        image
+    }
+}
+
+/// A numeric setting from a request body, by local name: `<tt:Brightness>` as well as `<Brightness>`
+fn setting_value(soap_body: &str, name: &str) -> Option<f64> {
+    let mut reader = Reader::from_str(soap_body);
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(e) if e.local_name().as_ref() == name.as_bytes() => {
+                let text = reader.read_text(e.name()).ok()?;
+                return text.trim().parse().ok();
+            }
+            Event::Eof => return None,
+            _ => {}
+        }
+    }
+}
+
+/// Reolink 0..=255 to ONVIF 0..100, to one decimal
+fn to_onvif(value: u8) -> f64 {
+    (f64::from(value) * ONVIF_MAX / REOLINK_MAX * 10.0).round() / 10.0
+}
+
+fn to_reolink(value: f64) -> u8 {
+    (value.clamp(0.0, ONVIF_MAX) * REOLINK_MAX / ONVIF_MAX).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cgi_imaging_translation() {
+        assert_eq!(to_onvif(0), 0.0);
+        assert_eq!(to_onvif(255), 100.0);
+        assert_eq!(to_onvif(128), 50.2);
+        assert_eq!(to_reolink(50.2), 128);
+        assert_eq!(to_reolink(150.0), 255);
+
+        let image = Image { channel: 0, bright: 128, contrast: 128, saturation: 128, hue: 128, sharpen: 128 };
+        let response = ImagingService::settings_response(&image);
+        assert!(response.contains("<tt:Brightness>50.2</tt:Brightness>"));
+        assert!(response.contains("<tt:Sharpness>50.2</tt:Sharpness>"));
+
+        let request = r#"<timg:SetImagingSettings xmlns:timg="http://www.onvif.org/ver20/imaging/wsdl" xmlns:tt="http://www.onvif.org/ver10/schema"><timg:VideoSourceToken>000</timg:VideoSourceToken><timg:ImagingSettings><tt:Brightness>100</tt:Brightness><tt:Contrast> 0 </tt:Contrast></timg:ImagingSettings></timg:SetImagingSettings>"#;
+        let updated = ImagingService::apply_settings(image, request);
+        assert_eq!((updated.bright, updated.contrast, updated.saturation, updated.hue), (255, 0, 128, 128));
+    }
+}
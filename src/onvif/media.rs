@@ -1,5 +1,17 @@
-use crate::camera::CameraClient;
-use anyhow::Result;
+use crate::camera::queue::Priority;
+use crate::camera::{reolink_cgi, CameraClient, CameraConfig};
+use crate::config::ProfileOrder;
+use crate::onvif::types::Profile;
+use crate::onvif::{soap, xmlns};
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Reolink cameras encode a main and a sub stream (both H.264/H.265) per channel
+const REOLINK_ENCODER_INSTANCES: u32 = 2;
+
+/// Names given by `rename_profiles`, highest resolution first
+const FRIENDLY_PROFILE_NAMES: &[&str] = &["MainStream", "SubStream", "ExtStream"];
 
 pub struct MediaService;
 
@@ -13,14 +25,246 @@ impl MediaService {
 
         // Fix namespace issues and normalize profile structure
         let mut fixed_response = Self::normalize_profiles(&response);
+        if !soap::is_fault(&response) {
+            *camera.profiles().write().await = Some(Arc::new(Self::parse_profiles(&fixed_response)));
+        }
 
         // Fix any localhost URLs in the profile URIs
         fixed_response = Self::fix_stream_uri_response(&fixed_response, camera);
 
-        Ok(fixed_response)
+        Ok(Self::arrange_profiles(&fixed_response, camera.config()))
+    }
+
+    /// Apply the camera's `hidden_profiles`, `profile_order` and `rename_profiles` to a
+    /// GetProfiles response (Media or Media2)
+    pub fn arrange_profiles(xml: &str, config: &CameraConfig) -> String {
+        let visible = Self::hide_profiles(xml, &config.hidden_profiles);
+        if config.profile_order == ProfileOrder::AsIs && !config.rename_profiles {
+            return visible;
+        }
+
+        // Split into the text between profiles and the profiles themselves
+        let mut between = Vec::new();
+        let mut profiles = Vec::new();
+        let mut rest = visible.as_str();
+        while let Some((start, end)) = Self::next_profiles_element(rest) {
+            between.push(&rest[..start]);
+            profiles.push(rest[start..end].to_string());
+            rest = &rest[end..];
+        }
+
+        if config.rename_profiles {
+            let mut by_resolution: Vec<usize> = (0..profiles.len()).collect();
+            by_resolution.retain(|&i| Self::profile_resolution(&profiles[i]) > 0);
+            by_resolution.sort_by_key(|&i| std::cmp::Reverse(Self::profile_resolution(&profiles[i])));
+            for (&i, name) in by_resolution.iter().zip(FRIENDLY_PROFILE_NAMES) {
+                profiles[i] = Self::rename_profile(&profiles[i], name);
+            }
+        }
+
+        match config.profile_order {
+            ProfileOrder::AsIs => {}
+            ProfileOrder::ResolutionDesc => profiles.sort_by_key(|p| std::cmp::Reverse(Self::profile_resolution(p))),
+            ProfileOrder::ResolutionAsc => profiles.sort_by_key(|p| Self::profile_resolution(p)),
+        }
+
+        let mut result = String::with_capacity(visible.len());
+        for (text, profile) in between.into_iter().zip(&profiles) {
+            result.push_str(text);
+            result.push_str(profile);
+        }
+        result.push_str(rest);
+        result
+    }
+
+    /// Width × height of the profile's video encoder (the first `Width`/`Height` elements;
+    /// the video source's Bounds carries them as attributes), 0 when it has none
+    fn profile_resolution(profile: &str) -> u64 {
+        let value = |name: &str| -> Option<u64> {
+            let start = profile.find(&format!(":{}>", name)).or_else(|| profile.find(&format!("<{}>", name)))?;
+            let start = start + profile[start..].find('>')? + 1;
+            let end = start + profile[start..].find('<')?;
+            profile[start..end].trim().parse().ok()
+        };
+        value("Width").unwrap_or(0) * value("Height").unwrap_or(0)
+    }
+
+    /// Replace the profile's own `Name`
+    fn rename_profile(profile: &str, name: &str) -> String {
+        match Self::profile_name_range(profile) {
+            Some((start, end)) => format!("{}{}{}", &profile[..start], name, &profile[end..]),
+            None => profile.to_string(),
+        }
+    }
+
+    pub async fn get_profile(camera: &CameraClient, profile_token: &str) -> Result<String> {
+        let request_body = format!(
+            r#"<trt:GetProfile xmlns:trt="http://www.onvif.org/ver10/media/wsdl">
+  <trt:ProfileToken>{}</trt:ProfileToken>
+</trt:GetProfile>"#,
+            quick_xml::escape::escape(profile_token)
+        );
+
+        let response = camera
+            .send_soap_request("/onvif/media_service", &request_body)
+            .await?;
+
+        let fixed_response = Self::normalize_profiles(&response);
+        Ok(Self::fix_stream_uri_response(&fixed_response, camera))
+    }
+
+    /// Whether a profile is listed in the camera's `hidden_profiles`, by token or by name.
+    /// Names come from the cached profiles (see `find_profile`); an error means it can't be told
+    pub async fn is_profile_hidden(camera: &CameraClient, profile_token: &str) -> Result<bool> {
+        let hidden = &camera.config().hidden_profiles;
+        if hidden.is_empty() {
+            return Ok(false);
+        }
+        if hidden.iter().any(|h| h == profile_token) {
+            return Ok(true);
+        }
+
+        let profile = Self::find_profile(camera, profile_token).await?;
+        Ok(profile.is_some_and(|profile| hidden.contains(&profile.name)))
+    }
+
+    /// The camera's profile with `profile_token`, typed. Profiles are kept from the last
+    /// GetProfiles (a client's, or one sent here); a token not among them fetches them again,
+    /// since profiles may have been created since
+    pub async fn find_profile(camera: &CameraClient, profile_token: &str) -> Result<Option<Profile>> {
+        let find = |profiles: &[Profile]| profiles.iter().find(|profile| profile.token == profile_token).cloned();
+        if let Some(profile) = camera.profiles().read().await.as_deref().and_then(|profiles| find(profiles)) {
+            return Ok(Some(profile));
+        }
+
+        let request_body = r#"<trt:GetProfiles xmlns:trt="http://www.onvif.org/ver10/media/wsdl"/>"#;
+        let response = camera.send_soap_request("/onvif/media_service", request_body).await?;
+        if soap::is_fault(&response) {
+            anyhow::bail!("Camera {} faulted GetProfiles", camera.camera_id());
+        }
+        let profiles = Self::parse_profiles(&response);
+        let profile = find(&profiles);
+        *camera.profiles().write().await = Some(Arc::new(profiles));
+        Ok(profile)
+    }
+
+    /// The `Profiles` elements of a GetProfiles response that parse
+    fn parse_profiles(xml: &str) -> Vec<Profile> {
+        let mut profiles = Vec::new();
+        let mut rest = xml;
+        while let Some((start, end)) = Self::next_profiles_element(rest) {
+            profiles.extend(Profile::parse(&rest[start..end]));
+            rest = &rest[end..];
+        }
+        profiles
+    }
+
+    /// Remove `Profiles` elements (Media `trt:` or Media2 `tr2:`) whose token or name is in `hidden`
+    pub fn hide_profiles(xml: &str, hidden: &[String]) -> String {
+        if hidden.is_empty() {
+            return xml.to_string();
+        }
+
+        let mut result = String::with_capacity(xml.len());
+        let mut rest = xml;
+        while let Some((start, end)) = Self::next_profiles_element(rest) {
+            let element = &rest[start..end];
+            let token = Self::attribute(element, "token");
+            let name = Self::profile_name(element);
+            let is_hidden = hidden
+                .iter()
+                .any(|h| Some(h.as_str()) == token || Some(h.as_str()) == name);
+
+            result.push_str(&rest[..start]);
+            if is_hidden {
+                tracing::debug!("Hiding profile {} from GetProfiles", token.or(name).unwrap_or("?"));
+            } else {
+                result.push_str(element);
+            }
+            rest = &rest[end..];
+        }
+        result.push_str(rest);
+        result
+    }
+
+    /// Byte range of the next `<prefix:Profiles ...>...</prefix:Profiles>` element
+    fn next_profiles_element(xml: &str) -> Option<(usize, usize)> {
+        Self::next_element(xml, "Profiles")
+    }
+
+    /// Byte range of the next `<prefix:{local_name} ...>...</prefix:{local_name}>` element
+    fn next_element(xml: &str, local_name: &str) -> Option<(usize, usize)> {
+        let mut offset = 0;
+        while let Some(found) = xml[offset..].find(local_name) {
+            let name_end = offset + found + local_name.len();
+            let tag_start = xml[..offset + found].rfind('<')?;
+            let qualified = &xml[tag_start + 1..name_end];
+            let is_start_tag = (qualified == local_name || qualified.ends_with(&format!(":{}", local_name)))
+                && !qualified.contains(['/', ' ', '>'])
+                && xml[name_end..].starts_with([' ', '>', '\n', '\t', '\r']);
+            if is_start_tag {
+                let close = format!("</{}>", qualified);
+                let end = name_end + xml[name_end..].find(&close)? + close.len();
+                return Some((tag_start, end));
+            }
+            offset = name_end;
+        }
+        None
+    }
+
+    fn attribute<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+        let start_tag = &element[..element.find('>')?];
+        let pattern = format!(" {}=\"", name);
+        let start = start_tag.find(&pattern)? + pattern.len();
+        let end = start + start_tag[start..].find('"')?;
+        Some(&start_tag[start..end])
+    }
+
+    /// The profile's own `Name`, the first one in the element
+    fn profile_name(xml: &str) -> Option<&str> {
+        Self::profile_name_range(xml).map(|(start, end)| xml[start..end].trim())
+    }
+
+    fn profile_name_range(xml: &str) -> Option<(usize, usize)> {
+        let start = xml.find(":Name>").or_else(|| xml.find("<Name>"))?;
+        let start = start + xml[start..].find('>')? + 1;
+        let end = start + xml[start..].find('<')?;
+        Some((start, end))
     }
 
     pub async fn get_stream_uri(camera: &CameraClient, profile_token: &str, protocol: &str) -> Result<String> {
+        // Still-image profiles (no video encoder) have no RTSP stream; clients building
+        // time-lapse setups expect the HTTP snapshot URL instead
+        if Self::is_image_only_profile(camera, profile_token).await {
+            tracing::debug!("Profile {} has no video encoder, returning snapshot URI as stream URI", profile_token);
+            let snapshot_response = Self::get_snapshot_uri(camera, profile_token).await?;
+            return Ok(Self::snapshot_to_stream_uri_response(&snapshot_response));
+        }
+
+        let wants_flv = protocol == "HTTP" || camera.config().prefer_flv;
+        if wants_flv && camera.config().model == "reolink" {
+            match Self::flv_stream_uri(camera, profile_token).await {
+                Ok(Some((uri, valid_for))) => {
+                    tracing::debug!("Returning HTTP-FLV stream for profile {}", profile_token);
+                    return Ok(Self::stream_uri_response(&uri, valid_for));
+                }
+                Ok(None) => tracing::debug!("HTTP-FLV is disabled on camera {}, using RTSP", camera.camera_id()),
+                Err(e) => tracing::debug!("Could not check HTTP-FLV on camera {}: {}, using RTSP", camera.camera_id(), e),
+            }
+        }
+        // Without FLV, an HTTP request gets the RTSP stream Reolink does serve
+        let protocol = if protocol == "HTTP" && wants_flv { "RTSP" } else { protocol };
+
+        let protocol = if camera.config().force_rtsp_tcp {
+            let forced = Self::tcp_protocol(protocol);
+            if forced != protocol {
+                tracing::debug!("force_rtsp_tcp: requesting {} instead of {}", forced, protocol);
+            }
+            forced
+        } else {
+            protocol
+        };
+
         let request_body = format!(
             r#"<trt:GetStreamUri xmlns:trt="http://www.onvif.org/ver10/media/wsdl">
   <trt:StreamSetup>
@@ -31,7 +275,8 @@ impl MediaService {
   </trt:StreamSetup>
   <trt:ProfileToken>{}</trt:ProfileToken>
 </trt:GetStreamUri>"#,
-            protocol, profile_token
+            quick_xml::escape::escape(protocol),
+            quick_xml::escape::escape(profile_token)
         );
 
         let response = camera
@@ -49,39 +294,327 @@ impl MediaService {
             r#"<trt:GetSnapshotUri xmlns:trt="http://www.onvif.org/ver10/media/wsdl">
   <trt:ProfileToken>{}</trt:ProfileToken>
 </trt:GetSnapshotUri>"#,
-            profile_token
+            quick_xml::escape::escape(profile_token)
         );
 
         let response = camera
             .send_soap_request("/onvif/media_service", &request_body)
             .await?;
 
-        // Fix localhost URLs in snapshot URI
-        let fixed_response = Self::fix_stream_uri_response(&response, camera);
+        Ok(Self::fix_snapshot_uri_response(&response, camera, profile_token))
+    }
 
-        Ok(fixed_response)
+    /// Fix up a ver10 or Media2 GetSnapshotUri response: localhost URLs point at the camera,
+    /// relative ones are made absolute, and Reolink Snap URLs get the channel and stream
+    pub fn fix_snapshot_uri_response(xml: &str, camera: &CameraClient, profile_token: &str) -> String {
+        let fixed = Self::fix_stream_uri_response(xml, camera);
+        let fixed = Self::absolute_snapshot_uri(&fixed, &camera.config().base_url());
+        Self::select_snapshot_stream(&fixed, camera.config(), profile_token)
+    }
+
+    /// The camera's reply to a snapshot request for `profile_token`, with the image not yet read:
+    /// Reolink cameras through the Snap CGI, others by fetching the URI from GetSnapshotUri
+    pub async fn fetch_snapshot(camera: &CameraClient, profile_token: &str) -> Result<reqwest::Response> {
+        if camera.config().model == "reolink" {
+            let snap_type = camera.config().snapshot_streams.get(profile_token);
+            return reolink_cgi::snapshot(camera, snap_type.map(String::as_str)).await;
+        }
+
+        let response = Self::get_snapshot_uri(camera, profile_token).await?;
+        let uri = Self::snapshot_uri(&response).context("GetSnapshotUri response has no Uri")?;
+        camera.get_with_credentials(&uri).await
+    }
+
+    /// The (unescaped) `Uri` of a GetSnapshotUri response
+    fn snapshot_uri(xml: &str) -> Option<String> {
+        let mut reader = quick_xml::Reader::from_str(xml);
+        loop {
+            match reader.read_event().ok()? {
+                quick_xml::events::Event::Start(e) if e.local_name().as_ref() == b"Uri" => {
+                    let text = reader.read_text(e.name()).ok()?;
+                    let uri = text.trim();
+                    return Some(quick_xml::escape::unescape(uri).map_or_else(|_| uri.to_string(), |uri| uri.into_owned()));
+                }
+                quick_xml::events::Event::Eof => return None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Replace the snapshot `Uri` with `proxy_url` (the proxy's snapshot endpoint, for
+    /// `proxy_snapshots`)
+    pub fn proxied_snapshot_uri(xml: &str, proxy_url: &str) -> String {
+        let mut result = String::with_capacity(xml.len());
+        let mut offset = 0;
+
+        while let Some(found) = xml[offset..].find("Uri>") {
+            let start = offset + found + "Uri>".len();
+            result.push_str(&xml[offset..start]);
+            offset = start;
+
+            let is_start_tag = xml[..start].rfind('<').is_some_and(|open| !xml[open + 1..].starts_with('/'));
+            let Some(len) = xml[start..].find('<') else {
+                break;
+            };
+            if is_start_tag {
+                result.push_str(&quick_xml::escape::escape(proxy_url));
+                offset = start + len;
+            }
+        }
+
+        result.push_str(&xml[offset..]);
+        result
+    }
+
+    /// Point a Reolink `cmd=Snap` snapshot `Uri` at the camera's `channel`, and at the stream
+    /// `snapshot_streams` maps the requested profile to (`snapType`). Firmware hands out the
+    /// same channel 0 URL for every profile, so each profile's snapshot would otherwise look alike
+    pub fn select_snapshot_stream(xml: &str, config: &CameraConfig, profile_token: &str) -> String {
+        let stream = config.snapshot_streams.get(profile_token);
+        let mut result = String::with_capacity(xml.len());
+        let mut offset = 0;
+
+        while let Some(found) = xml[offset..].find("Uri>") {
+            let start = offset + found + "Uri>".len();
+            result.push_str(&xml[offset..start]);
+            offset = start;
+
+            let is_start_tag = xml[..start].rfind('<').is_some_and(|open| !xml[open + 1..].starts_with('/'));
+            let Some(len) = xml[start..].find('<') else {
+                break;
+            };
+            let uri = &xml[start..start + len];
+            if !is_start_tag || !uri.contains("cmd=Snap") {
+                continue;
+            }
+
+            let mut uri = set_query_param(uri, "channel", &config.channel.to_string());
+            if let Some(stream) = stream {
+                uri = set_query_param(&uri, "snapType", stream);
+            }
+            result.push_str(&uri);
+            offset = start + len;
+        }
+
+        result.push_str(&xml[offset..]);
+        result
+    }
+
+    /// Resolve a relative snapshot `Uri` (`/cgi-bin/api.cgi?cmd=Snap...`, as some firmware
+    /// returns) against the camera's address, and give a scheme-relative one (`//host/...`) `http:`
+    pub fn absolute_snapshot_uri(xml: &str, camera_base_url: &str) -> String {
+        let mut result = String::with_capacity(xml.len());
+        let mut offset = 0;
+
+        while let Some(found) = xml[offset..].find("Uri>") {
+            let start = offset + found + "Uri>".len();
+            result.push_str(&xml[offset..start]);
+            offset = start;
+
+            // Only the text of a start tag such as <tt:Uri>, not what follows </tt:Uri>
+            let is_start_tag = xml[..start].rfind('<').is_some_and(|open| !xml[open + 1..].starts_with('/'));
+            if !is_start_tag {
+                continue;
+            }
+            let value = xml[start..].trim_start();
+            if value.starts_with("//") {
+                result.push_str("http:");
+            } else if value.starts_with('/') {
+                result.push_str(camera_base_url.trim_end_matches('/'));
+            }
+        }
+
+        result.push_str(&xml[offset..]);
+        result
+    }
+
+    /// Reolink HTTP-FLV URL for a profile (a Reolink extension, not part of ONVIF) and how long
+    /// it stays valid, or None when the camera has its HTTP or RTMP server turned off. The URL
+    /// carries the camera's CGI login token rather than its password, so it lapses with the token
+    async fn flv_stream_uri(camera: &CameraClient, profile_token: &str) -> Result<Option<(String, Duration)>> {
+        let _permit = camera.acquire_slot(Priority::Interactive).await;
+        let body = reolink_cgi::send_with_token(camera, "GetNetPort", serde_json::json!({})).await?;
+        let net_port: serde_json::Value = serde_json::from_str(&body)?;
+        let token = reolink_cgi::login_token(camera).await?;
+
+        let valid_for = token.expires_at.saturating_duration_since(Instant::now());
+        Ok(Self::flv_uri(camera.config(), &net_port, profile_token, &token.name).map(|uri| (uri, valid_for)))
+    }
+
+    /// Build the FLV URL from a GetNetPort response:
+    /// `[{"cmd":"GetNetPort","code":0,"value":{"NetPort":{"httpEnable":1,"httpPort":80,"rtmpEnable":1,"rtmpPort":1935,...}}}]`
+    fn flv_uri(config: &CameraConfig, net_port: &serde_json::Value, profile_token: &str, cgi_token: &str) -> Option<String> {
+        let ports = net_port.pointer("/0/value/NetPort")?;
+        let enabled = |key: &str| ports[key].as_i64() == Some(1);
+        if !enabled("rtmpEnable") {
+            return None;
+        }
+
+        let (scheme, port) = if enabled("httpEnable") {
+            ("http", ports["httpPort"].as_u64().unwrap_or(80))
+        } else if enabled("httpsEnable") {
+            ("https", ports["httpsPort"].as_u64().unwrap_or(443))
+        } else {
+            return None;
+        };
+        let rtmp_port = ports["rtmpPort"].as_u64().unwrap_or(1935);
+
+        let mut uri = reqwest::Url::parse(&format!("{}://{}:{}/flv", scheme, config.url_host(), port)).ok()?;
+        uri.query_pairs_mut()
+            .append_pair("port", &rtmp_port.to_string())
+            .append_pair("app", "bcs")
+            .append_pair("stream", &Self::flv_stream_name(config.channel, profile_token))
+            .append_pair("token", cgi_token);
+        Some(uri.to_string())
+    }
+
+    /// Reolink numbers its ONVIF profiles main, sub, ext (`000`, `001`, `002`)
+    fn flv_stream_name(channel: u8, profile_token: &str) -> String {
+        let token = profile_token.to_ascii_lowercase();
+        let stream = if token.contains("sub") || token.ends_with('1') {
+            "sub"
+        } else if token.contains("ext") || token.ends_with('2') {
+            "ext"
+        } else {
+            "main"
+        };
+        format!("channel{}_{}.bcs", channel, stream)
+    }
+
+    /// A MediaUri valid for `valid_for`; zero means indefinitely, as in ONVIF
+    fn stream_uri_response(uri: &str, valid_for: Duration) -> String {
+        soap::build_envelope(
+            &[
+                ("trt", "http://www.onvif.org/ver10/media/wsdl"),
+                ("tt", "http://www.onvif.org/ver10/schema"),
+            ],
+            &format!(
+                "<trt:GetStreamUriResponse><trt:MediaUri><tt:Uri>{}</tt:Uri><tt:InvalidAfterConnect>false</tt:InvalidAfterConnect><tt:InvalidAfterReboot>false</tt:InvalidAfterReboot><tt:Timeout>PT{}S</tt:Timeout></trt:MediaUri></trt:GetStreamUriResponse>",
+                quick_xml::escape::escape(uri),
+                valid_for.as_secs()
+            ),
+        )
+    }
+
+    /// Reolink cameras generally don't implement this; answer with their usual limit when the
+    /// camera faults, since VMSes stop profile setup on an error
+    pub async fn get_guaranteed_number_of_video_encoder_instances(
+        camera: &CameraClient,
+        configuration_token: &str,
+    ) -> Result<String> {
+        let request_body = format!(
+            r#"<trt:GetGuaranteedNumberOfVideoEncoderInstances xmlns:trt="http://www.onvif.org/ver10/media/wsdl">
+  <trt:ConfigurationToken>{}</trt:ConfigurationToken>
+</trt:GetGuaranteedNumberOfVideoEncoderInstances>"#,
+            quick_xml::escape::escape(configuration_token)
+        );
+
+        match camera.send_soap_request("/onvif/media_service", &request_body).await {
+            Ok(response) if !soap::is_fault(&response) => Ok(response),
+            Ok(_) | Err(_) => {
+                tracing::debug!(
+                    "Camera {} did not answer GetGuaranteedNumberOfVideoEncoderInstances, using the Reolink default",
+                    camera.camera_id()
+                );
+                Ok(Self::guaranteed_encoder_instances_response(REOLINK_ENCODER_INSTANCES))
+            }
+        }
+    }
+
+    fn guaranteed_encoder_instances_response(instances: u32) -> String {
+        soap::build_envelope(
+            &[("trt", "http://www.onvif.org/ver10/media/wsdl")],
+            &format!(
+                "<trt:GetGuaranteedNumberOfVideoEncoderInstancesResponse><trt:TotalNumber>{0}</trt:TotalNumber><trt:H264>{0}</trt:H264></trt:GetGuaranteedNumberOfVideoEncoderInstancesResponse>",
+                instances
+            ),
+        )
+    }
+
+    /// Whether the profile has a video source but no video encoder, from the cached profiles
+    /// (see `find_profile`), so it costs no round trip once they are known
+    async fn is_image_only_profile(camera: &CameraClient, profile_token: &str) -> bool {
+        match Self::find_profile(camera, profile_token).await {
+            Ok(Some(profile)) => Self::profile_is_image_only(&profile),
+            Ok(None) => false,
+            Err(e) => {
+                // Can't tell - treat it as a regular video profile
+                tracing::debug!("Profiles of camera {} unavailable, assuming video profile: {}", camera.camera_id(), e);
+                false
+            }
+        }
+    }
+
+    fn profile_is_image_only(profile: &Profile) -> bool {
+        profile.video_source_configuration.is_some() && profile.video_encoder_configuration.is_none()
+    }
+
+    fn snapshot_to_stream_uri_response(xml: &str) -> String {
+        // Both responses carry a MediaUri, only the wrapper element differs
+        xml.replace("GetSnapshotUriResponse", "GetStreamUriResponse")
     }
 
     fn normalize_profiles(xml: &str) -> String {
-        let mut fixed = xml.to_string();
+        // Reolink sometimes returns profiles without required fields
+        let mut fixed = Self::rebuild_profiles(xml);
 
-        // Ensure proper namespace declarations
-        if !fixed.contains("xmlns:tt=") && fixed.contains("<tt:") {
-            fixed = fixed.replace(
-                "<SOAP-ENV:Envelope",
-                r#"<SOAP-ENV:Envelope xmlns:tt="http://www.onvif.org/ver10/schema""#,
-            );
+        // Ensure proper namespace declarations; rebuilt profiles use `tt:` whatever the camera used
+        if fixed.contains("<tt:") {
+            fixed = xmlns::ensure_namespace(&fixed, "tt", "http://www.onvif.org/ver10/schema");
         }
 
         if !fixed.contains("xmlns:trt=") && fixed.contains("<trt:") {
-            fixed = fixed.replace(
-                "<SOAP-ENV:Envelope",
-                r#"<SOAP-ENV:Envelope xmlns:trt="http://www.onvif.org/ver10/media/wsdl""#,
-            );
+            fixed = xmlns::ensure_namespace(&fixed, "trt", "http://www.onvif.org/ver10/media/wsdl");
         }
 
-        // Reolink sometimes returns profiles without required fields
-        // Add defaults if missing (this is a simplified approach)
+        fixed
+    }
+
+    /// Re-serialize each `Profiles` (GetProfiles) or `Profile` (GetProfile) element from its
+    /// parsed form, which fills in the configuration elements ONVIF requires; elements that
+    /// don't parse are left as they are
+    fn rebuild_profiles(xml: &str) -> String {
+        let mut result = xml.to_string();
+        for local_name in ["Profiles", "Profile"] {
+            let mut rebuilt = String::with_capacity(result.len());
+            let mut rest = result.as_str();
+            while let Some((start, end)) = Self::next_element(rest, local_name) {
+                let element = &rest[start..end];
+                let qualified = &element[1..element.find(|c: char| c.is_whitespace() || c == '>' || c == '/').unwrap_or(1)];
+                rebuilt.push_str(&rest[..start]);
+                match Profile::parse(element) {
+                    Some(profile) => rebuilt.push_str(&profile.to_xml(qualified)),
+                    None => rebuilt.push_str(element),
+                }
+                rest = &rest[end..];
+            }
+            rebuilt.push_str(rest);
+            result = rebuilt;
+        }
+        result
+    }
+
+    /// TCP transport to request instead of `protocol`. Media `UDP` and Media2
+    /// `RtspUnicast`/`RtspMulticast` become `RTSP` (RTP interleaved on the RTSP connection);
+    /// HTTP tunnelling is already TCP
+    pub fn tcp_protocol(protocol: &str) -> &str {
+        match protocol {
+            "UDP" | "RtspUnicast" | "RtspMulticast" => "RTSP",
+            other => other,
+        }
+    }
+
+    /// Rewrite the `Protocol` of a Media2 GetStreamUri request to a TCP transport
+    pub fn force_tcp_media2_request(xml: &str) -> String {
+        let mut fixed = xml.to_string();
+        for protocol in ["RtspUnicast", "RtspMulticast"] {
+            for prefix in ["tr2:", ""] {
+                fixed = fixed.replace(
+                    &format!("<{0}Protocol>{1}</{0}Protocol>", prefix, protocol),
+                    &format!("<{0}Protocol>RTSP</{0}Protocol>", prefix),
+                );
+            }
+        }
         fixed
     }
 
@@ -90,31 +623,280 @@ impl MediaService {
 
         // Ensure MediaUri has proper namespace
         if !fixed.contains("xmlns:tt=") && fixed.contains("<tt:Uri>") {
-            fixed = fixed.replace(
-                "<SOAP-ENV:Envelope",
-                r#"<SOAP-ENV:Envelope xmlns:tt="http://www.onvif.org/ver10/schema""#,
-            );
+            fixed = xmlns::ensure_namespace(&fixed, "tt", "http://www.onvif.org/ver10/schema");
         }
 
         // Fix RTSP URLs - Reolink cameras return 127.0.0.1 or localhost instead of actual IP
         // Extract the camera's actual IP address from the config
-        let camera_address = &camera.config().address;
-        let camera_ip = if let Some(host) = camera_address.split(':').next() {
-            host
-        } else {
-            camera_address.as_str()
+        // Hostnames are kept as-is so clients resolve them (and pick up DHCP changes) themselves
+        let camera_ip = camera.config().url_host();
+
+        // Replace localhost references in RTSP URLs, and HTTP(S) snapshot URLs, with the camera
+        // IP, with or without a port
+        for scheme in ["rtsp", "http", "https"] {
+            for host in ["127.0.0.1", "localhost", "0.0.0.0"] {
+                for separator in [':', '/'] {
+                    fixed = fixed.replace(
+                        &format!("{}://{}{}", scheme, host, separator),
+                        &format!("{}://{}{}", scheme, camera_ip, separator),
+                    );
+                }
+            }
+        }
+
+        fixed
+    }
+}
+
+/// Set `name=value` in a URL query as it appears in XML (`&amp;`-separated), replacing any
+/// value already there
+fn set_query_param(uri: &str, name: &str, value: &str) -> String {
+    let key = format!("{}=", name);
+    let existing = uri.match_indices(&key).map(|(at, _)| at).find(|&at| {
+        let before = &uri[..at];
+        before.ends_with('?') || before.ends_with('&') || before.ends_with("&amp;")
+    });
+
+    match existing {
+        Some(at) => {
+            let value_start = at + key.len();
+            let value_end = uri[value_start..].find('&').map_or(uri.len(), |len| value_start + len);
+            format!("{}{}{}", &uri[..value_start], value, &uri[value_end..])
+        }
+        None => {
+            let separator = if uri.contains('?') { "&amp;" } else { "?" };
+            format!("{}{}{}{}", uri, separator, key, value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_only_profile_detection() {
+        let profiles = MediaService::parse_profiles(concat!(
+            r#"<trt:GetProfilesResponse><trt:Profiles token="000"><tt:Name>mainStream</tt:Name><tt:VideoSourceConfiguration token="VSC"/><tt:VideoEncoderConfiguration token="000"/></trt:Profiles>"#,
+            r#"<trt:Profiles token="snap"><tt:Name>VideoEncoderConfiguration still</tt:Name><tt:VideoSourceConfiguration token="VSC"/></trt:Profiles>"#,
+            r#"<trt:Profiles token="audio"><tt:Name>audio</tt:Name><tt:AudioSourceConfiguration token="ASC"/></trt:Profiles></trt:GetProfilesResponse>"#,
+        ));
+
+        assert_eq!(profiles.len(), 3);
+        assert!(!MediaService::profile_is_image_only(&profiles[0]));
+        // Decided on the elements, not on text that happens to name them
+        assert!(MediaService::profile_is_image_only(&profiles[1]));
+        assert!(!MediaService::profile_is_image_only(&profiles[2]));
+    }
+
+    #[test]
+    fn test_snapshot_to_stream_uri_response() {
+        let snapshot = r#"<trt:GetSnapshotUriResponse><trt:MediaUri><tt:Uri>http://192.168.1.10/cgi-bin/api.cgi?cmd=Snap</tt:Uri></trt:MediaUri></trt:GetSnapshotUriResponse>"#;
+        let result = MediaService::snapshot_to_stream_uri_response(snapshot);
+
+        assert!(result.starts_with("<trt:GetStreamUriResponse>"));
+        assert!(result.ends_with("</trt:GetStreamUriResponse>"));
+        assert!(result.contains("<tt:Uri>http://192.168.1.10/cgi-bin/api.cgi?cmd=Snap</tt:Uri>"));
+    }
+
+    #[test]
+    fn test_absolute_snapshot_uri() {
+        let base = "http://192.168.1.10:80";
+        let relative = "<trt:MediaUri><tt:Uri>/cgi-bin/api.cgi?cmd=Snap&amp;channel=0</tt:Uri><tt:Timeout>PT0S</tt:Timeout></trt:MediaUri>";
+        assert_eq!(
+            MediaService::absolute_snapshot_uri(relative, base),
+            "<trt:MediaUri><tt:Uri>http://192.168.1.10:80/cgi-bin/api.cgi?cmd=Snap&amp;channel=0</tt:Uri><tt:Timeout>PT0S</tt:Timeout></trt:MediaUri>"
+        );
+
+        let scheme_relative = "<tr2:Uri>//192.168.1.10/cgi-bin/api.cgi?cmd=Snap</tr2:Uri>";
+        assert_eq!(
+            MediaService::absolute_snapshot_uri(scheme_relative, base),
+            "<tr2:Uri>http://192.168.1.10/cgi-bin/api.cgi?cmd=Snap</tr2:Uri>"
+        );
+
+        let absolute = "<tt:Uri>http://192.168.1.10/cgi-bin/api.cgi?cmd=Snap</tt:Uri>";
+        assert_eq!(MediaService::absolute_snapshot_uri(absolute, base), absolute);
+    }
+
+    #[test]
+    fn test_fix_snapshot_uri_response() {
+        let camera = CameraClient::new(CameraConfig {
+            id: "cam1".to_string(),
+            address: "192.168.1.10".to_string(),
+            ..Default::default()
+        });
+        // A Media2 response, as Reolink firmware returns it
+        let media2 = r#"<tr2:GetSnapshotUriResponse><tr2:Uri>http://127.0.0.1/cgi-bin/api.cgi?cmd=Snap&amp;channel=0&amp;rs=wuuPhkmUCeI9WG7C</tr2:Uri></tr2:GetSnapshotUriResponse>"#;
+        assert_eq!(
+            MediaService::fix_snapshot_uri_response(media2, &camera, "000"),
+            r#"<tr2:GetSnapshotUriResponse><tr2:Uri>http://192.168.1.10/cgi-bin/api.cgi?cmd=Snap&amp;channel=0&amp;rs=wuuPhkmUCeI9WG7C</tr2:Uri></tr2:GetSnapshotUriResponse>"#
+        );
+
+        assert_eq!(
+            MediaService::proxied_snapshot_uri(media2, "http://proxy:8000/onvif/cam1/snapshot?profile=000&x=1"),
+            r#"<tr2:GetSnapshotUriResponse><tr2:Uri>http://proxy:8000/onvif/cam1/snapshot?profile=000&amp;x=1</tr2:Uri></tr2:GetSnapshotUriResponse>"#
+        );
+    }
+
+    #[test]
+    fn test_select_snapshot_stream() {
+        let mut config = CameraConfig {
+            channel: 2,
+            ..Default::default()
         };
+        config.snapshot_streams.insert("001".to_string(), "sub".to_string());
+        let response = |uri: &str| format!("<trt:MediaUri><tt:Uri>{}</tt:Uri><tt:Timeout>PT0S</tt:Timeout></trt:MediaUri>", uri);
 
-        // Replace localhost references in RTSP URLs with actual camera IP
-        fixed = fixed.replace("rtsp://127.0.0.1:", &format!("rtsp://{}:", camera_ip));
-        fixed = fixed.replace("rtsp://localhost:", &format!("rtsp://{}:", camera_ip));
-        fixed = fixed.replace("rtsp://0.0.0.0:", &format!("rtsp://{}:", camera_ip));
+        let snap = response("http://192.168.1.10/cgi-bin/api.cgi?cmd=Snap&amp;channel=0&amp;rs=abc");
+        assert_eq!(
+            MediaService::select_snapshot_stream(&snap, &config, "000"),
+            response("http://192.168.1.10/cgi-bin/api.cgi?cmd=Snap&amp;channel=2&amp;rs=abc")
+        );
+        assert_eq!(
+            MediaService::select_snapshot_stream(&snap, &config, "001"),
+            response("http://192.168.1.10/cgi-bin/api.cgi?cmd=Snap&amp;channel=2&amp;rs=abc&amp;snapType=sub")
+        );
 
-        // Also fix HTTP URLs for snapshot URIs if they have localhost
-        fixed = fixed.replace("http://127.0.0.1:", &format!("http://{}:", camera_ip));
-        fixed = fixed.replace("http://localhost:", &format!("http://{}:", camera_ip));
-        fixed = fixed.replace("http://0.0.0.0:", &format!("http://{}:", camera_ip));
+        // Missing parameters are added; other snapshot URLs are left alone
+        assert_eq!(
+            MediaService::select_snapshot_stream(&response("http://192.168.1.10/cgi-bin/api.cgi?cmd=Snap"), &config, "000"),
+            response("http://192.168.1.10/cgi-bin/api.cgi?cmd=Snap&amp;channel=2")
+        );
+        let other = response("http://192.168.1.11/snapshot.jpg?channel=1");
+        assert_eq!(MediaService::select_snapshot_stream(&other, &config, "001"), other);
+    }
 
-        fixed
+    #[test]
+    fn test_arrange_profiles() {
+        let profile = |token: &str, name: &str, width: u32, height: u32| {
+            format!(
+                r#"<trt:Profiles token="{}" fixed="true"><tt:Name>{}</tt:Name><tt:VideoSourceConfiguration token="000"><tt:Bounds x="0" y="0" width="2560" height="1440"/></tt:VideoSourceConfiguration><tt:VideoEncoderConfiguration token="{}"><tt:Resolution><tt:Width>{}</tt:Width><tt:Height>{}</tt:Height></tt:Resolution></tt:VideoEncoderConfiguration></trt:Profiles>"#,
+                token, name, token, width, height
+            )
+        };
+        let sub = profile("001", "subStream", 640, 360);
+        let main = profile("000", "mainStream", 2560, 1440);
+        let ext = profile("002", "extStream", 1280, 720);
+        let xml = format!("<trt:GetProfilesResponse>{}
+{}
+{}</trt:GetProfilesResponse>", sub, main, ext);
+
+        let mut config = CameraConfig::default();
+        assert_eq!(MediaService::arrange_profiles(&xml, &config), xml);
+
+        config.profile_order = ProfileOrder::ResolutionDesc;
+        assert_eq!(
+            MediaService::arrange_profiles(&xml, &config),
+            format!("<trt:GetProfilesResponse>{}
+{}
+{}</trt:GetProfilesResponse>", main, ext, sub)
+        );
+
+        config.profile_order = ProfileOrder::ResolutionAsc;
+        config.rename_profiles = true;
+        config.hidden_profiles = vec!["extStream".to_string()];
+        let arranged = MediaService::arrange_profiles(&xml, &config);
+        assert_eq!(
+            arranged,
+            format!(
+                "<trt:GetProfilesResponse>{}
+{}
+</trt:GetProfilesResponse>",
+                sub.replace("subStream", "SubStream"),
+                main.replace("mainStream", "MainStream")
+            )
+        );
+    }
+
+    #[test]
+    fn test_force_rtsp_tcp_protocols() {
+        assert_eq!(MediaService::tcp_protocol("UDP"), "RTSP");
+        assert_eq!(MediaService::tcp_protocol("RTSP"), "RTSP");
+        assert_eq!(MediaService::tcp_protocol("HTTP"), "HTTP");
+        assert_eq!(MediaService::tcp_protocol("RtspMulticast"), "RTSP");
+        assert_eq!(MediaService::tcp_protocol("RtspOverHttp"), "RtspOverHttp");
+
+        let request = r#"<tr2:GetStreamUri xmlns:tr2="http://www.onvif.org/ver20/media/wsdl"><tr2:Protocol>RtspUnicast</tr2:Protocol><tr2:ProfileToken>000</tr2:ProfileToken></tr2:GetStreamUri>"#;
+        let forced = MediaService::force_tcp_media2_request(request);
+        assert!(forced.contains("<tr2:Protocol>RTSP</tr2:Protocol>"));
+        assert!(forced.contains("<tr2:ProfileToken>000</tr2:ProfileToken>"));
+    }
+
+    #[tokio::test]
+    async fn test_guaranteed_encoder_instances_fallback() {
+        // The simulator faults on this action, like most Reolink firmware
+        let camera = CameraClient::new(crate::camera::CameraConfig {
+            id: "sim".to_string(),
+            model: "simulated".to_string(),
+            ..Default::default()
+        });
+
+        let response = MediaService::get_guaranteed_number_of_video_encoder_instances(&camera, "000").await.unwrap();
+        let envelope = soap::SoapEnvelope::parse(&response).unwrap();
+
+        assert_eq!(envelope.body.action, "GetGuaranteedNumberOfVideoEncoderInstancesResponse");
+        assert!(response.contains("<trt:TotalNumber>2</trt:TotalNumber>"));
+    }
+
+    #[test]
+    fn test_flv_uri() {
+        let config = CameraConfig {
+            address: "192.168.1.100:80".to_string(),
+            username: "admin".to_string(),
+            password: "p&ss word".to_string(),
+            channel: 2,
+            ..Default::default()
+        };
+        let net_port = |http: i64, rtmp: i64| {
+            serde_json::json!([{"cmd": "GetNetPort", "code": 0, "value": {"NetPort": {
+                "httpEnable": http, "httpPort": 8080, "httpsEnable": 1, "httpsPort": 443, "rtmpEnable": rtmp, "rtmpPort": 1935
+            }}}])
+        };
+
+        // The camera's channel, and its login token instead of the password
+        assert_eq!(
+            MediaService::flv_uri(&config, &net_port(1, 1), "001", "abc123").as_deref(),
+            Some("http://192.168.1.100:8080/flv?port=1935&app=bcs&stream=channel2_sub.bcs&token=abc123")
+        );
+        assert!(MediaService::flv_uri(&config, &net_port(0, 1), "000", "abc123").unwrap().starts_with("https://192.168.1.100/flv?"));
+        assert_eq!(MediaService::flv_uri(&config, &net_port(1, 0), "000", "abc123"), None);
+        assert_eq!(MediaService::flv_uri(&config, &serde_json::json!([{"code": 1}]), "000", "abc123"), None);
+
+        let response = MediaService::stream_uri_response("http://cam/flv?port=1935&app=bcs", Duration::from_secs(3540));
+        assert!(response.contains("<tt:Uri>http://cam/flv?port=1935&amp;app=bcs</tt:Uri>"));
+        assert!(response.contains("<tt:Timeout>PT3540S</tt:Timeout>"));
+    }
+
+    #[test]
+    fn test_normalize_profiles() {
+        // Schema elements under a prefix of the camera's own; the rebuilt profile uses tt:
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?><s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:trt="http://www.onvif.org/ver10/media/wsdl" xmlns:ns2="http://www.onvif.org/ver10/schema"><s:Body><trt:GetProfilesResponse><trt:Profiles token="001"><ns2:Name>subStream</ns2:Name><ns2:VideoEncoderConfiguration token="001"><ns2:Name>sub</ns2:Name><ns2:Resolution><ns2:Width>640</ns2:Width><ns2:Height>360</ns2:Height></ns2:Resolution></ns2:VideoEncoderConfiguration></trt:Profiles></trt:GetProfilesResponse></s:Body></s:Envelope>"#;
+        let normalized = MediaService::normalize_profiles(xml);
+        assert!(normalized.contains(r#"xmlns:tt="http://www.onvif.org/ver10/schema""#), "{}", normalized);
+        assert!(normalized.contains(r#"<trt:Profiles token="001" fixed="false"><tt:Name>subStream</tt:Name><tt:VideoEncoderConfiguration token="001"><tt:Name>sub</tt:Name><tt:UseCount>1</tt:UseCount><tt:Encoding>H264</tt:Encoding>"#), "{}", normalized);
+        assert!(normalized.contains("<tt:SessionTimeout>PT60S</tt:SessionTimeout></tt:VideoEncoderConfiguration></trt:Profiles></trt:GetProfilesResponse>"));
+        assert_eq!(MediaService::profile_resolution(&normalized), 640 * 360);
+
+        let single = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><trt:GetProfileResponse xmlns:trt="http://www.onvif.org/ver10/media/wsdl"><trt:Profile token="000" fixed="true"><tt:Name>mainStream</tt:Name></trt:Profile></trt:GetProfileResponse></s:Body></s:Envelope>"#;
+        assert!(MediaService::normalize_profiles(single).contains(r#"<trt:Profile token="000" fixed="true"><tt:Name>mainStream</tt:Name></trt:Profile></trt:GetProfileResponse>"#));
+    }
+
+    #[test]
+    fn test_hide_profiles() {
+        let response = r#"<trt:GetProfilesResponse><trt:Profiles token="000" fixed="true"><tt:Name>mainStream</tt:Name></trt:Profiles><trt:Profiles token="001"><tt:Name>subStream</tt:Name></trt:Profiles><trt:Profiles token="002"><tt:Name>extStream</tt:Name></trt:Profiles></trt:GetProfilesResponse>"#;
+
+        let by_token = MediaService::hide_profiles(response, &["002".to_string()]);
+        assert!(by_token.contains(r#"token="001""#));
+        assert!(!by_token.contains("extStream"));
+        assert!(by_token.ends_with("</trt:Profiles></trt:GetProfilesResponse>"));
+
+        let media2 = r#"<tr2:GetProfilesResponse><tr2:Profiles token="000"><tr2:Name>mainStream</tr2:Name></tr2:Profiles><tr2:Profiles token="001"><tr2:Name>subStream</tr2:Name></tr2:Profiles></tr2:GetProfilesResponse>"#;
+        let by_name = MediaService::hide_profiles(media2, &["mainStream".to_string()]);
+        assert_eq!(
+            by_name,
+            r#"<tr2:GetProfilesResponse><tr2:Profiles token="001"><tr2:Name>subStream</tr2:Name></tr2:Profiles></tr2:GetProfilesResponse>"#
+        );
+
+        assert_eq!(MediaService::hide_profiles(response, &[]), response);
     }
 }
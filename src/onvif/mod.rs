@@ -4,3 +4,9 @@ pub mod auth;
 pub mod device;
 pub mod media;
 pub mod events;
+pub mod notification;
+pub mod analytics;
+pub mod imaging;
+pub mod ptz;
+pub mod discovery;
+pub mod xmlns;
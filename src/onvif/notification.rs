@@ -0,0 +1,193 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::onvif::soap;
+
+const NAMESPACES: &[(&str, &str)] = &[
+    ("tev", "http://www.onvif.org/ver10/events/wsdl"),
+    ("wsnt", "http://docs.oasis-open.org/wsn/b-2"),
+    ("tt", "http://www.onvif.org/ver10/schema"),
+    ("tns1", "http://www.onvif.org/ver10/topics"),
+];
+
+// Names are written with the prefixes declared in NAMESPACES; on parsing, quick-xml matches
+// local names, so camera responses using other prefixes deserialize the same way.
+
+/// A PullMessagesResponse, as built for clients and parsed from cameras
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "tev:PullMessagesResponse")]
+pub struct PullMessagesResponse {
+    #[serde(rename = "tev:CurrentTime", alias = "CurrentTime", default)]
+    pub current_time: String,
+    #[serde(rename = "tev:TerminationTime", alias = "TerminationTime", default)]
+    pub termination_time: String,
+    #[serde(rename = "wsnt:NotificationMessage", alias = "NotificationMessage", default)]
+    pub messages: Vec<NotificationMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NotificationMessage {
+    #[serde(rename = "wsnt:Topic", alias = "Topic")]
+    pub topic: Topic,
+    #[serde(rename = "wsnt:Message", alias = "Message", default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<MessageHolder>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Topic {
+    #[serde(rename = "@Dialect", default, skip_serializing_if = "Option::is_none")]
+    pub dialect: Option<String>,
+    #[serde(rename = "$text")]
+    pub expression: String,
+}
+
+/// `wsnt:Message`, which wraps the ONVIF `tt:Message`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessageHolder {
+    #[serde(rename = "tt:Message", alias = "Message")]
+    pub message: Message,
+}
+
+/// An ONVIF event message. Only `SimpleItem`s are kept; `ElementItem`s are dropped
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    #[serde(rename = "@UtcTime", default, skip_serializing_if = "Option::is_none")]
+    pub utc_time: Option<String>,
+    #[serde(rename = "@PropertyOperation", default, skip_serializing_if = "Option::is_none")]
+    pub property_operation: Option<String>,
+    #[serde(rename = "tt:Source", alias = "Source", default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<ItemList>,
+    #[serde(rename = "tt:Key", alias = "Key", default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<ItemList>,
+    #[serde(rename = "tt:Data", alias = "Data", default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<ItemList>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ItemList {
+    #[serde(rename = "tt:SimpleItem", alias = "SimpleItem", default)]
+    pub items: Vec<SimpleItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimpleItem {
+    #[serde(rename = "@Name")]
+    pub name: String,
+    #[serde(rename = "@Value")]
+    pub value: String,
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(rename = "Body")]
+    body: Body,
+}
+
+#[derive(Deserialize)]
+struct Body {
+    #[serde(rename = "PullMessagesResponse")]
+    response: PullMessagesResponse,
+}
+
+impl SimpleItem {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
+
+impl ItemList {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.items.iter().find(|item| item.name == name).map(|item| item.value.as_str())
+    }
+}
+
+impl NotificationMessage {
+    pub fn message(&self) -> Option<&Message> {
+        self.message.as_ref().map(|holder| &holder.message)
+    }
+}
+
+impl PullMessagesResponse {
+    /// Parse a PullMessagesResponse, either bare or inside a SOAP envelope
+    pub fn parse(xml: &str) -> Result<Self> {
+        match quick_xml::de::from_str::<Envelope>(xml) {
+            Ok(envelope) => Ok(envelope.body.response),
+            Err(_) => quick_xml::de::from_str(xml).context("Failed to parse PullMessagesResponse"),
+        }
+    }
+
+    /// The response as a complete SOAP envelope
+    pub fn to_envelope(&self) -> Result<String> {
+        let mut body = String::new();
+        let mut serializer = quick_xml::se::Serializer::new(&mut body);
+        serializer.indent(' ', 2);
+        self.serialize(serializer).context("Failed to serialize PullMessagesResponse")?;
+
+        Ok(soap::build_envelope(NAMESPACES, &body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(topic: &str, is_motion: bool) -> NotificationMessage {
+        NotificationMessage {
+            topic: Topic {
+                dialect: Some("http://www.onvif.org/ver10/tev/topicExpression/ConcreteSet".to_string()),
+                expression: topic.to_string(),
+            },
+            message: Some(MessageHolder {
+                message: Message {
+                    utc_time: Some("2026-01-01T00:00:00.000Z".to_string()),
+                    source: Some(ItemList {
+                        items: vec![SimpleItem::new("VideoSourceConfigurationToken", "cam<1>")],
+                    }),
+                    data: Some(ItemList {
+                        items: vec![SimpleItem::new("IsMotion", is_motion.to_string())],
+                    }),
+                    ..Default::default()
+                },
+            }),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let response = PullMessagesResponse {
+            current_time: "2026-01-01T00:00:00Z".to_string(),
+            termination_time: "2026-01-01T00:10:00Z".to_string(),
+            messages: vec![
+                message("tns1:RuleEngine/CellMotionDetector/Motion", true),
+                message("tns1:RuleEngine/CellMotionDetector/Motion", false),
+                message("tns1:RuleEngine/MyRuleDetector/PeopleDetect", true),
+            ],
+        };
+
+        let xml = response.to_envelope().unwrap();
+        assert!(xml.contains("<tev:PullMessagesResponse>"));
+        assert!(xml.contains(r#"<tt:SimpleItem Name="VideoSourceConfigurationToken" Value="cam&lt;1&gt;"/>"#));
+
+        let parsed = PullMessagesResponse::parse(&xml).unwrap();
+        assert_eq!(parsed, response);
+        let data = parsed.messages[1].message().unwrap().data.as_ref().unwrap();
+        assert_eq!(data.get("IsMotion"), Some("false"));
+    }
+
+    #[test]
+    fn test_empty_response_and_other_prefixes() {
+        let empty = PullMessagesResponse::default().to_envelope().unwrap();
+        assert!(PullMessagesResponse::parse(&empty).unwrap().messages.is_empty());
+
+        let camera = r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"><env:Header/><env:Body><ns2:PullMessagesResponse><ns2:CurrentTime>2026-01-01T00:00:00Z</ns2:CurrentTime><ns3:NotificationMessage><ns3:Topic Dialect="x">tns1:VideoSource/MotionAlarm</ns3:Topic><ns3:Message><ns4:Message UtcTime="2026-01-01T00:00:00Z" PropertyOperation="Changed"><ns4:Data><ns4:SimpleItem Name="State" Value="true"/><ns4:ElementItem Name="Extra"><x/></ns4:ElementItem></ns4:Data></ns4:Message></ns3:Message></ns3:NotificationMessage></ns2:PullMessagesResponse></env:Body></env:Envelope>"#;
+        let parsed = PullMessagesResponse::parse(camera).unwrap();
+        assert_eq!(parsed.messages.len(), 1);
+        assert_eq!(parsed.messages[0].topic.expression, "tns1:VideoSource/MotionAlarm");
+        let message = parsed.messages[0].message().unwrap();
+        assert_eq!(message.property_operation.as_deref(), Some("Changed"));
+        assert_eq!(message.data.as_ref().unwrap().get("State"), Some("true"));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::camera::CameraClient;
+use crate::onvif::soap;
+use anyhow::Result;
+use quick_xml::escape::escape;
+
+const NS_TPTZ: (&str, &str) = ("tptz", "http://www.onvif.org/ver20/ptz/wsdl");
+
+/// PTZ requests. Everything but `GetServiceCapabilities` goes to the camera's PTZ service;
+/// Reolink's capabilities answer is unreliable, so it is derived from the camera's PTZ nodes.
+pub struct PtzService;
+
+/// Requested speeds of a `ContinuousMove`, each -1..=1 in the generic velocity space
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Velocity {
+    pub pan_tilt: Option<(f32, f32)>,
+    pub zoom: Option<f32>,
+}
+
+/// Features advertised in `GetServiceCapabilities`
+#[derive(Debug, Default, PartialEq, Eq)]
+struct PtzServiceCapabilities {
+    eflip: bool,
+    reverse: bool,
+    move_status: bool,
+    status_position: bool,
+}
+
+impl PtzService {
+    pub async fn forward(camera: &CameraClient, soap_body: &str) -> Result<String> {
+        camera.send_soap_request("/onvif/ptz_service", soap_body).await
+    }
+
+    pub async fn get_configurations(camera: &CameraClient) -> Result<String> {
+        Self::forward(camera, &Self::request(r#"<tptz:GetConfigurations/>"#)).await
+    }
+
+    pub async fn get_presets(camera: &CameraClient, profile_token: &str) -> Result<String> {
+        let body = format!("<tptz:GetPresets><tptz:ProfileToken>{}</tptz:ProfileToken></tptz:GetPresets>", escape(profile_token));
+        Self::forward(camera, &Self::request(&body)).await
+    }
+
+    /// `timeout` is an ISO 8601 duration after which the camera stops on its own
+    pub async fn continuous_move(camera: &CameraClient, profile_token: &str, velocity: Velocity, timeout: Option<&str>) -> Result<String> {
+        Self::forward(camera, &Self::continuous_move_body(profile_token, velocity, timeout)).await
+    }
+
+    pub async fn stop(camera: &CameraClient, profile_token: &str, pan_tilt: bool, zoom: bool) -> Result<String> {
+        let body = format!(
+            "<tptz:Stop><tptz:ProfileToken>{}</tptz:ProfileToken><tptz:PanTilt>{}</tptz:PanTilt><tptz:Zoom>{}</tptz:Zoom></tptz:Stop>",
+            escape(profile_token),
+            pan_tilt,
+            zoom
+        );
+        Self::forward(camera, &Self::request(&body)).await
+    }
+
+    pub async fn goto_preset(camera: &CameraClient, profile_token: &str, preset_token: &str) -> Result<String> {
+        let body = format!(
+            "<tptz:GotoPreset><tptz:ProfileToken>{}</tptz:ProfileToken><tptz:PresetToken>{}</tptz:PresetToken></tptz:GotoPreset>",
+            escape(profile_token),
+            escape(preset_token)
+        );
+        Self::forward(camera, &Self::request(&body)).await
+    }
+
+    fn continuous_move_body(profile_token: &str, velocity: Velocity, timeout: Option<&str>) -> String {
+        let mut speeds = String::new();
+        if let Some((x, y)) = velocity.pan_tilt {
+            speeds.push_str(&format!(r#"<tt:PanTilt x="{}" y="{}"/>"#, x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0)));
+        }
+        if let Some(x) = velocity.zoom {
+            speeds.push_str(&format!(r#"<tt:Zoom x="{}"/>"#, x.clamp(-1.0, 1.0)));
+        }
+        let timeout = timeout
+            .map(|timeout| format!("<tptz:Timeout>{}</tptz:Timeout>", escape(timeout)))
+            .unwrap_or_default();
+
+        Self::request(&format!(
+            "<tptz:ContinuousMove><tptz:ProfileToken>{}</tptz:ProfileToken><tptz:Velocity>{}</tptz:Velocity>{}</tptz:ContinuousMove>",
+            escape(profile_token),
+            speeds,
+            timeout
+        ))
+    }
+
+    /// Wrap an operation in the `tptz`/`tt` namespace declarations the camera expects
+    fn request(operation: &str) -> String {
+        let (name, _) = operation[1..].split_once(['>', ' ', '/']).unwrap_or((&operation[1..], ""));
+        let open = format!("<{}", name);
+        operation.replacen(
+            &open,
+            &format!(r#"{} xmlns:tptz="{}" xmlns:tt="http://www.onvif.org/ver10/schema""#, open, NS_TPTZ.1),
+            1,
+        )
+    }
+
+    /// Capabilities backed by what the camera's `GetNodes` reports; all off for a camera
+    /// without PTZ, so clients don't offer controls that can't work
+    pub async fn get_service_capabilities(camera: &CameraClient) -> Result<String> {
+        let request_body = r#"<tptz:GetNodes xmlns:tptz="http://www.onvif.org/ver20/ptz/wsdl"/>"#;
+        let capabilities = match Self::forward(camera, request_body).await {
+            Ok(nodes) if !soap::is_fault(&nodes) => Self::capabilities_from_nodes(&nodes),
+            Ok(_) => {
+                tracing::debug!("Camera {} has no PTZ nodes, advertising no PTZ features", camera.camera_id());
+                PtzServiceCapabilities::default()
+            }
+            Err(e) => {
+                tracing::debug!("GetNodes failed on camera {}: {}, advertising no PTZ features", camera.camera_id(), e);
+                PtzServiceCapabilities::default()
+            }
+        };
+
+        Ok(Self::service_capabilities_response(&capabilities))
+    }
+
+    fn capabilities_from_nodes(nodes: &str) -> PtzServiceCapabilities {
+        if !nodes.contains("PTZNode") {
+            return PtzServiceCapabilities::default();
+        }
+        // A position is only meaningful (and reported by GetStatus) with an absolute space
+        let absolute = nodes.contains("AbsolutePanTiltPositionSpace") || nodes.contains("AbsoluteZoomPositionSpace");
+        PtzServiceCapabilities {
+            eflip: nodes.contains("EFlip"),
+            reverse: nodes.contains("Reverse"),
+            move_status: absolute,
+            status_position: absolute,
+        }
+    }
+
+    fn service_capabilities_response(capabilities: &PtzServiceCapabilities) -> String {
+        // GetCompatibleConfigurations and MoveAndTrack are neither implemented by Reolink nor the proxy
+        let body = format!(
+            r#"<tptz:GetServiceCapabilitiesResponse><tptz:Capabilities EFlip="{}" Reverse="{}" GetCompatibleConfigurations="false" MoveStatus="{}" StatusPosition="{}"/></tptz:GetServiceCapabilitiesResponse>"#,
+            capabilities.eflip, capabilities.reverse, capabilities.move_status, capabilities.status_position
+        );
+        soap::build_envelope(&[NS_TPTZ], &body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_continuous_move_body() {
+        let velocity = Velocity { pan_tilt: Some((0.5, -1.5)), zoom: None };
+        assert_eq!(
+            PtzService::continuous_move_body("000", velocity, Some("PT2S")),
+            r#"<tptz:ContinuousMove xmlns:tptz="http://www.onvif.org/ver20/ptz/wsdl" xmlns:tt="http://www.onvif.org/ver10/schema"><tptz:ProfileToken>000</tptz:ProfileToken><tptz:Velocity><tt:PanTilt x="0.5" y="-1"/></tptz:Velocity><tptz:Timeout>PT2S</tptz:Timeout></tptz:ContinuousMove>"#
+        );
+        let zoom = PtzService::continuous_move_body("000", Velocity { pan_tilt: None, zoom: Some(0.25) }, None);
+        assert!(zoom.contains(r#"<tptz:Velocity><tt:Zoom x="0.25"/></tptz:Velocity></tptz:ContinuousMove>"#));
+        assert_eq!(
+            PtzService::request("<tptz:GetConfigurations/>"),
+            r#"<tptz:GetConfigurations xmlns:tptz="http://www.onvif.org/ver20/ptz/wsdl" xmlns:tt="http://www.onvif.org/ver10/schema"/>"#
+        );
+    }
+
+    #[test]
+    fn test_capabilities_from_nodes() {
+        let nodes = r#"<tptz:GetNodesResponse><tptz:PTZNode token="PTZNODETOKEN" FixedHomePosition="false"><tt:Name>PTZNODE</tt:Name><tt:SupportedPTZSpaces><tt:AbsolutePanTiltPositionSpace><tt:URI>http://www.onvif.org/ver10/tptz/PanTiltSpaces/PositionGenericSpace</tt:URI></tt:AbsolutePanTiltPositionSpace><tt:ContinuousPanTiltVelocitySpace><tt:URI>http://www.onvif.org/ver10/tptz/PanTiltSpaces/VelocityGenericSpace</tt:URI></tt:ContinuousPanTiltVelocitySpace></tt:SupportedPTZSpaces><tt:MaximumNumberOfPresets>64</tt:MaximumNumberOfPresets><tt:HomeSupported>false</tt:HomeSupported></tptz:PTZNode></tptz:GetNodesResponse>"#;
+        let capabilities = PtzService::capabilities_from_nodes(nodes);
+        assert_eq!(
+            capabilities,
+            PtzServiceCapabilities { eflip: false, reverse: false, move_status: true, status_position: true }
+        );
+
+        // Continuous-only cameras (most Reolink PT models) report no position
+        let continuous = nodes.replace("AbsolutePanTiltPositionSpace", "RelativePanTiltTranslationSpace");
+        assert!(!PtzService::capabilities_from_nodes(&continuous).status_position);
+        assert_eq!(PtzService::capabilities_from_nodes("<tptz:GetNodesResponse/>"), PtzServiceCapabilities::default());
+
+        let response = PtzService::service_capabilities_response(&capabilities);
+        assert!(response.contains(r#"<tptz:Capabilities EFlip="false" Reverse="false" GetCompatibleConfigurations="false" MoveStatus="true" StatusPosition="true"/>"#));
+    }
+}
@@ -1,6 +1,7 @@
 use quick_xml::events::Event;
 use quick_xml::Reader;
 use anyhow::{Context, Result};
+use std::sync::OnceLock;
 
 #[derive(Debug, Clone)]
 pub struct SoapEnvelope {
@@ -11,31 +12,212 @@ pub struct SoapEnvelope {
 
 #[derive(Debug, Clone)]
 pub struct SoapHeader {
-    pub _security: Option<WsSecurity>,
+    /// The client's WS-Security UsernameToken, when it sent one
+    pub security: Option<WsSecurity>,
+    /// Top-level Header children, in order
+    pub blocks: Vec<HeaderBlock>,
     pub _raw_xml: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderBlock {
+    /// Local name, e.g. `Security` for `<wsse:Security>`
+    pub name: String,
+    /// Namespace prefix, e.g. `wsse` (empty when unprefixed)
+    pub prefix: String,
+    /// `mustUnderstand="1"` (or `"true"`) was set
+    pub must_understand: bool,
+    /// The block as it appeared in the request
+    pub xml: String,
+}
+
+/// Header blocks the proxy processes or deliberately consumes: WS-Security (checked against
+/// `proxy.auth` when set; the proxy authenticates to the camera with its own credentials) and
+/// WS-Addressing
+pub const UNDERSTOOD_HEADERS: &[&str] = &["Security", "Action", "To", "MessageID", "ReplyTo", "From", "FaultTo", "RelatesTo"];
+
+/// WS-Addressing header blocks, forwarded to the camera for passthrough actions
+const ADDRESSING_HEADERS: &[&str] = &["Action", "To", "MessageID", "ReplyTo", "From", "FaultTo", "RelatesTo"];
+
+impl HeaderBlock {
+    fn from_start(e: &quick_xml::events::BytesStart) -> Self {
+        let must_understand = e.attributes().flatten().any(|attr| {
+            attr.key.local_name().as_ref() == b"mustUnderstand" && matches!(attr.value.as_ref(), b"1" | b"true")
+        });
+        HeaderBlock {
+            name: String::from_utf8_lossy(e.local_name().as_ref()).to_string(),
+            prefix: e.name().prefix().map(|p| String::from_utf8_lossy(p.as_ref()).to_string()).unwrap_or_default(),
+            must_understand,
+            xml: String::new(),
+        }
+    }
+}
+
+/// Declarations (` xmlns:p="..."`) of the Envelope `namespaces` that `xml` uses but the start
+/// tag `tag` of element `name` doesn't declare itself, so the element can stand on its own
+fn missing_declarations(namespaces: &[(String, String)], xml: &str, tag: &str, name: &str) -> String {
+    let mut declarations = String::new();
+    for (key, uri) in namespaces {
+        let used = match key.strip_prefix("xmlns:") {
+            Some(prefix) => uses_prefix(xml, prefix),
+            None => !name.contains(':'),
+        };
+        if used && !tag.contains(&format!("{}=", key)) {
+            declarations.push_str(&format!(r#" {}="{}""#, key, uri));
+        }
+    }
+    declarations
+}
+
+/// Whether `prefix:` starts a name or QName value in `xml` (not just the tail of `xmlns:` etc.)
+fn uses_prefix(xml: &str, prefix: &str) -> bool {
+    let qualified = format!("{}:", prefix);
+    xml.match_indices(&qualified).any(|(at, _)| {
+        xml[..at].chars().next_back().is_some_and(|c| matches!(c, '<' | '/' | '"' | '\'' | '>') || c.is_whitespace())
+    })
+}
+
+/// The text content of an element (all text nodes, trimmed and concatenated)
+fn block_text(xml: &str) -> String {
+    let mut reader = Reader::from_str(xml);
+    let mut text = String::new();
+    loop {
+        match reader.read_event() {
+            Ok(Event::Text(e)) => text.push_str(e.unescape().unwrap_or_default().trim()),
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Deepest nesting accepted inside the Header or Body; ONVIF requests are a few levels deep
+const MAX_DEPTH: usize = 64;
+/// Most elements accepted in one envelope
+const MAX_ELEMENTS: usize = 10_000;
+/// Largest rebuilt Header or Body XML
+const MAX_RAW_XML_BYTES: usize = 1024 * 1024;
+
+/// A request over the parser's limits (deeply nested or enormous documents sent to the open port)
+#[derive(Debug)]
+pub struct LimitExceeded(String);
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SOAP request too large: {}", self.0)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Elements seen so far in one envelope, checked against the limits as they are read
+#[derive(Default)]
+struct ParseBudget {
+    elements: usize,
+}
+
+impl ParseBudget {
+    /// `depth` is the nesting before `event`, `raw_len` the XML rebuilt so far
+    fn check(&mut self, event: &quick_xml::Result<Event>, depth: usize, raw_len: usize) -> Result<()> {
+        let exceeded = |what: String| Err(anyhow::Error::new(LimitExceeded(what)));
+        match event {
+            Ok(Event::Start(_)) if depth + 1 > MAX_DEPTH => return exceeded(format!("nested deeper than {} elements", MAX_DEPTH)),
+            Ok(Event::Start(_)) | Ok(Event::Empty(_)) => self.elements += 1,
+            _ => {}
+        }
+        if self.elements > MAX_ELEMENTS {
+            return exceeded(format!("more than {} elements", MAX_ELEMENTS));
+        }
+        if raw_len > MAX_RAW_XML_BYTES {
+            return exceeded(format!("more than {} bytes", MAX_RAW_XML_BYTES));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct WsSecurity {
-    pub _username: String,
-    pub _password_digest: String,
-    pub _nonce: String,
-    pub _created: String,
+    pub username: String,
+    /// The PasswordDigest, or the cleartext for `PasswordText`
+    pub password: String,
+    /// `Type` is PasswordDigest (the default being PasswordText)
+    pub is_digest: bool,
+    /// Base64, as sent
+    pub nonce: String,
+    pub created: String,
+    /// The whole `Security` block as received, with the Envelope namespaces it uses declared on
+    /// it, for passing the token on to the camera
+    pub xml: String,
+}
+
+impl WsSecurity {
+    /// The `UsernameToken` of a `Security` header block; None without one
+    fn parse(block: &str) -> Option<Self> {
+        let mut reader = Reader::from_str(block);
+        reader.config_mut().trim_text(true);
+
+        let mut token = None;
+        let mut field = None;
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
+                    let name = e.local_name();
+                    if name.as_ref() == b"UsernameToken" {
+                        token = Some(WsSecurity {
+                            username: String::new(),
+                            password: String::new(),
+                            is_digest: false,
+                            nonce: String::new(),
+                            created: String::new(),
+                            xml: String::new(),
+                        });
+                    }
+                    if name.as_ref() == b"Password" {
+                        let is_digest = e.attributes().flatten().any(|attr| {
+                            attr.key.local_name().as_ref() == b"Type" && attr.value.ends_with(b"#PasswordDigest")
+                        });
+                        if let Some(token) = token.as_mut() {
+                            token.is_digest = is_digest;
+                        }
+                    }
+                    field = Some(String::from_utf8_lossy(name.as_ref()).to_string());
+                }
+                Ok(Event::Text(e)) => {
+                    let text = e.unescape().unwrap_or_default().to_string();
+                    if let Some(token) = token.as_mut() {
+                        match field.as_deref() {
+                            Some("Username") => token.username = text,
+                            Some("Password") => token.password = text,
+                            Some("Nonce") => token.nonce = text,
+                            Some("Created") => token.created = text,
+                            _ => {}
+                        }
+                    }
+                }
+                Ok(Event::End(_)) => field = None,
+                Ok(Event::Eof) | Err(_) => return token,
+                _ => {}
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct SoapBody {
     pub action: String,
+    /// Local names of every top-level Body child, in order; more than one means a batched request
+    pub operations: Vec<String>,
     pub _content: String,
     pub _raw_xml: String,
 }
 
 impl SoapEnvelope {
     pub fn parse(xml: &str) -> Result<Self> {
-        let mut reader = Reader::from_str(xml);
+        let mut reader = Reader::from_str(strip_bom(xml));
         reader.config_mut().trim_text(true);
 
         let mut namespaces = Vec::new();
+        let mut budget = ParseBudget::default();
         let mut header = None;
         let mut body = None;
         let mut buf = Vec::new();
@@ -47,25 +229,32 @@ impl SoapEnvelope {
                     match e.local_name().as_ref() {
                         b"Envelope" => {
                             // Extract namespaces from Envelope element
-                            for attr in e.attributes() {
-                                if let Ok(attr) = attr {
-                                    let key = String::from_utf8_lossy(attr.key.as_ref()).to_string();
-                                    let value = String::from_utf8_lossy(&attr.value).to_string();
-                                    if key.starts_with("xmlns") {
-                                        namespaces.push((key, value));
-                                    }
+                            for attr in e.attributes().flatten() {
+                                let key = String::from_utf8_lossy(attr.key.as_ref()).to_string();
+                                let value = String::from_utf8_lossy(&attr.value).to_string();
+                                if key.starts_with("xmlns") {
+                                    namespaces.push((key, value));
                                 }
                             }
                         }
                         b"Header" => {
-                            header = Some(Self::parse_header(&mut reader)?);
+                            header = Some(Self::parse_header(&mut reader, strip_bom(xml), &namespaces, &mut budget)?);
                         }
                         b"Body" => {
-                            body = Some(Self::parse_body(&mut reader)?);
+                            body = Some(Self::parse_body(&mut reader, strip_bom(xml), &namespaces, &mut budget)?);
                         }
                         _ => {}
                     }
                 }
+                // <s:Body/>: nothing to dispatch, as with an empty <s:Body></s:Body>
+                Ok(Event::Empty(e)) if e.local_name().as_ref() == b"Body" => {
+                    body = Some(SoapBody {
+                        action: String::new(),
+                        operations: Vec::new(),
+                        _content: String::new(),
+                        _raw_xml: String::new(),
+                    });
+                }
                 Ok(Event::Eof) => break,
                 Err(e) => return Err(anyhow::anyhow!("XML parsing error: {}", e)),
                 _ => {}
@@ -80,22 +269,47 @@ impl SoapEnvelope {
         })
     }
 
-    fn parse_header(reader: &mut Reader<&[u8]>) -> Result<SoapHeader> {
+    /// `source` is the document `reader` reads from, so each block can be kept verbatim
+    fn parse_header(reader: &mut Reader<&[u8]>, source: &str, namespaces: &[(String, String)], budget: &mut ParseBudget) -> Result<SoapHeader> {
         let mut raw_xml = String::new();
+        let mut blocks: Vec<HeaderBlock> = Vec::new();
         let mut buf = Vec::new();
         let mut depth = 1;
+        let mut block_start = 0;
+        // Slice of `source` between two reader positions
+        let verbatim = |from: usize, to: u64| source.get(from..to as usize).unwrap_or_default().trim().to_string();
 
         loop {
-            match reader.read_event_into(&mut buf) {
+            let position = reader.buffer_position() as usize;
+            let event = reader.read_event_into(&mut buf);
+            budget.check(&event, depth, raw_xml.len())?;
+            match event {
                 Ok(Event::Start(e)) => {
+                    if depth == 1 {
+                        blocks.push(HeaderBlock::from_start(&e));
+                        block_start = position;
+                    }
                     depth += 1;
                     raw_xml.push_str(&format!("<{}>", String::from_utf8_lossy(e.as_ref())));
                 }
+                Ok(Event::Empty(e)) => {
+                    if depth == 1 {
+                        let mut block = HeaderBlock::from_start(&e);
+                        block.xml = verbatim(position, reader.buffer_position());
+                        blocks.push(block);
+                    }
+                    raw_xml.push_str(&format!("<{}/>", String::from_utf8_lossy(e.as_ref())));
+                }
                 Ok(Event::End(e)) => {
                     depth -= 1;
                     if depth == 0 {
                         break;
                     }
+                    if depth == 1 {
+                        if let Some(block) = blocks.last_mut() {
+                            block.xml = verbatim(block_start, reader.buffer_position());
+                        }
+                    }
                     raw_xml.push_str(&format!("</{}>", String::from_utf8_lossy(e.as_ref())));
                 }
                 Ok(Event::Text(e)) => {
@@ -108,71 +322,68 @@ impl SoapEnvelope {
             buf.clear();
         }
 
+        let security = blocks.iter().find(|block| block.name == "Security").and_then(|block| {
+            let mut security = WsSecurity::parse(&block.xml)?;
+            let qualified = if block.prefix.is_empty() { block.name.clone() } else { format!("{}:{}", block.prefix, block.name) };
+            let tag = &block.xml[..block.xml.find('>').unwrap_or(block.xml.len())];
+            let name_end = 1 + qualified.len();
+            security.xml = format!(
+                "{}{}{}",
+                &block.xml[..name_end],
+                missing_declarations(namespaces, &block.xml, tag, &qualified),
+                &block.xml[name_end..]
+            );
+            Some(security)
+        });
         Ok(SoapHeader {
-            _security: None,
+            security,
+            blocks,
             _raw_xml: raw_xml,
         })
     }
 
-    fn parse_body(reader: &mut Reader<&[u8]>) -> Result<SoapBody> {
-        let mut raw_xml = String::new();
-        let mut action = String::new();
+    /// `source` is the document `reader` reads from: the Body's XML is kept verbatim (prefixes,
+    /// attributes, self-closing elements, escaping), with the Envelope's `namespaces` declared
+    /// on each top-level element that uses them, so it can be forwarded on its own
+    fn parse_body(reader: &mut Reader<&[u8]>, source: &str, namespaces: &[(String, String)], budget: &mut ParseBudget) -> Result<SoapBody> {
+        let mut operations = Vec::new();
         let mut content = String::new();
         let mut buf = Vec::new();
         let mut depth = 1;
         let mut capture_content = false;
+        let body_start = reader.buffer_position() as usize;
+        let mut body_end = source.len();
+        // Offset and qualified name of each top-level element's start tag
+        let mut top_level = Vec::new();
 
         loop {
-            match reader.read_event_into(&mut buf) {
+            let position = reader.buffer_position() as usize;
+            let event = reader.read_event_into(&mut buf);
+            budget.check(&event, depth, position - body_start)?;
+            match event {
                 Ok(Event::Start(e)) => {
                     depth += 1;
-                    let tag_name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
-
                     if depth == 2 {
-                        // This is the action element (first element in Body)
-                        action = tag_name.clone();
-                        capture_content = true;
-                    }
-
-                    raw_xml.push_str(&format!("<{}", tag_name));
-                    for attr in e.attributes().flatten() {
-                        let key = String::from_utf8_lossy(attr.key.as_ref());
-                        let value = String::from_utf8_lossy(&attr.value);
-                        raw_xml.push_str(&format!(" {}=\"{}\"", key, value));
+                        // A top-level Body element; the first one is the action
+                        capture_content = operations.is_empty();
+                        operations.push(String::from_utf8_lossy(e.local_name().as_ref()).to_string());
+                        top_level.push((position, String::from_utf8_lossy(e.name().as_ref()).to_string()));
                     }
-                    raw_xml.push('>');
                 }
-                Ok(Event::Empty(e)) => {
-                    // Handle self-closing tags like <GetProfiles/>
-                    let tag_name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
-
-                    if depth == 1 {
-                        // This is a self-closing action element in Body
-                        action = tag_name.clone();
-                    }
-
-                    raw_xml.push_str(&format!("<{}", tag_name));
-                    for attr in e.attributes().flatten() {
-                        let key = String::from_utf8_lossy(attr.key.as_ref());
-                        let value = String::from_utf8_lossy(&attr.value);
-                        raw_xml.push_str(&format!(" {}=\"{}\"", key, value));
-                    }
-                    raw_xml.push_str("/>");
+                Ok(Event::Empty(e)) if depth == 1 => {
+                    // A self-closing top-level Body element like <GetProfiles/>
+                    operations.push(String::from_utf8_lossy(e.local_name().as_ref()).to_string());
+                    top_level.push((position, String::from_utf8_lossy(e.name().as_ref()).to_string()));
                 }
-                Ok(Event::End(e)) => {
+                Ok(Event::End(_)) => {
                     depth -= 1;
                     if depth == 0 {
+                        body_end = position;
                         break;
                     }
-                    let tag_name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
-                    raw_xml.push_str(&format!("</{}>", tag_name));
                 }
-                Ok(Event::Text(e)) => {
-                    let text = e.unescape().unwrap_or_default();
-                    raw_xml.push_str(&text);
-                    if capture_content {
-                        content.push_str(&text);
-                    }
+                Ok(Event::Text(e)) if capture_content && depth > 1 => {
+                    content.push_str(&e.unescape().unwrap_or_default());
                 }
                 Ok(Event::Eof) => break,
                 Err(e) => return Err(anyhow::anyhow!("Body parsing error: {}", e)),
@@ -181,16 +392,300 @@ impl SoapEnvelope {
             buf.clear();
         }
 
+        let verbatim = source.get(body_start..body_end).unwrap_or_default();
+        let mut raw_xml = String::with_capacity(verbatim.len());
+        let mut copied = 0;
+        for (position, name) in &top_level {
+            let tag_start = position - body_start;
+            let name_end = tag_start + 1 + name.len();
+            let tag = &verbatim[tag_start..verbatim[tag_start..].find('>').map_or(verbatim.len(), |end| tag_start + end)];
+            raw_xml.push_str(&verbatim[copied..name_end]);
+            raw_xml.push_str(&missing_declarations(namespaces, verbatim, tag, name));
+            copied = name_end;
+        }
+        raw_xml.push_str(&verbatim[copied..]);
+
         Ok(SoapBody {
-            action,
+            action: operations.first().cloned().unwrap_or_default(),
+            operations,
             _content: content,
-            _raw_xml: raw_xml,
+            _raw_xml: raw_xml.trim().to_string(),
         })
     }
 
     pub fn extract_action(&self) -> String {
         self.body.action.clone()
     }
+
+    /// The caller's WS-Security UsernameToken
+    pub fn security(&self) -> Option<&WsSecurity> {
+        self._header.as_ref()?.security.as_ref()
+    }
+
+    /// Header blocks marked mustUnderstand that the proxy doesn't process (`UNDERSTOOD_HEADERS`)
+    pub fn not_understood(&self) -> Vec<&str> {
+        self._header
+            .iter()
+            .flat_map(|header| &header.blocks)
+            .filter(|block| block.must_understand && !UNDERSTOOD_HEADERS.contains(&block.name.as_str()))
+            .map(|block| block.name.as_str())
+            .collect()
+    }
+
+    /// The client's WS-Addressing header blocks, ready to put in the request to the camera: `To`
+    /// is replaced with `to` (the camera's own address), and prefixes declared on the client's
+    /// Envelope are declared on the block itself
+    pub fn addressing_headers(&self, to: &str) -> String {
+        let Some(header) = &self._header else {
+            return String::new();
+        };
+
+        let mut headers = Vec::new();
+        for block in header.blocks.iter().filter(|block| ADDRESSING_HEADERS.contains(&block.name.as_str())) {
+            let qualified = if block.prefix.is_empty() { block.name.clone() } else { format!("{}:{}", block.prefix, block.name) };
+            let xmlns = if block.prefix.is_empty() { "xmlns".to_string() } else { format!("xmlns:{}", block.prefix) };
+            let declaration = self
+                ._namespaces
+                .iter()
+                .find(|(key, _)| *key == xmlns)
+                .filter(|_| !block.xml.contains(&format!("{}=", xmlns)))
+                .map(|(key, uri)| format!(r#" {}="{}""#, key, uri))
+                .unwrap_or_default();
+
+            if block.name == "To" {
+                headers.push(format!("<{q}{}>{}</{q}>", declaration, quick_xml::escape::escape(to), q = qualified));
+            } else {
+                headers.push(block.xml.replacen(&format!("<{}", qualified), &format!("<{}{}", qualified, declaration), 1));
+            }
+        }
+        headers.join("\n")
+    }
+
+    /// The subscription a request is addressed to: the last path segment of a WS-Addressing `To`
+    /// such as `http://proxy/onvif/cam1/subscription/<id>`, else the text of a reference
+    /// parameter (a header marked `IsReferenceParameter="true"`, or a `ReferenceParameters` block)
+    pub fn addressed_subscription(&self) -> Option<String> {
+        let blocks = &self._header.as_ref()?.blocks;
+
+        let to = blocks
+            .iter()
+            .find(|block| block.name == "To")
+            .map(|block| block_text(&block.xml))
+            .and_then(|to| {
+                let (_, id) = to.trim_end_matches('/').rsplit_once("/subscription/")?;
+                Some(id.to_string())
+            });
+        let reference_parameter = || {
+            blocks
+                .iter()
+                .find(|block| {
+                    let start_tag = &block.xml[..block.xml.find('>').unwrap_or(block.xml.len())];
+                    block.name == "ReferenceParameters"
+                        || start_tag.contains(r#"IsReferenceParameter="true""#)
+                        || start_tag.contains(r#"IsReferenceParameter="1""#)
+                })
+                .map(|block| block_text(&block.xml))
+        };
+
+        to.or_else(reference_parameter).filter(|id| !id.is_empty())
+    }
+
+    /// Whether the Body has anything in it; a Body with text but no operation element is a
+    /// malformed request, not a probe
+    pub fn has_body_content(&self) -> bool {
+        !self.body.operations.is_empty() || !self.body._raw_xml.trim().is_empty()
+    }
+
+    /// ONVIF defines one operation per request; a Body with several can't be dispatched safely
+    pub fn is_batched(&self) -> bool {
+        self.body.operations.len() > 1
+    }
+
+    /// Whether `action`, as announced by the transport, names another operation than the Body
+    /// holds. The Body is what gets forwarded, so such a request must not be taken for `action`
+    pub fn contradicts_action(&self, action: &str) -> bool {
+        !action.is_empty() && self.body.operations.iter().all(|operation| operation != action)
+    }
+
+    /// Action for this request, preferring one announced by the transport (the SOAP 1.1
+    /// `SOAPAction` header or the SOAP 1.2 `action` Content-Type parameter) over the body
+    pub fn resolve_action(&self, soap_action: Option<&str>, content_type: Option<&str>) -> String {
+        soap_action
+            .and_then(action_from_uri)
+            .or_else(|| content_type.and_then(action_from_content_type))
+            .unwrap_or_else(|| self.extract_action())
+    }
+}
+
+/// Local name of the first element of an outgoing request body, e.g.
+/// `<tds:GetDeviceInformation xmlns:tds="..."/>` -> `GetDeviceInformation`
+pub fn body_action(soap_body: &str) -> &str {
+    let Some(start) = soap_body.find('<') else {
+        return "";
+    };
+    let rest = &soap_body[start + 1..];
+    let end = rest
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .unwrap_or(rest.len());
+    let name = &rest[..end];
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// Local action name from an action URI, e.g.
+/// `"http://www.onvif.org/ver10/device/wsdl/GetDeviceInformation"` -> `GetDeviceInformation`.
+/// WS-Addressing style URIs (`.../PullPointSubscription/PullMessagesRequest`) lose the `Request` suffix.
+pub fn action_from_uri(uri: &str) -> Option<String> {
+    let uri = uri.trim().trim_matches('"').trim();
+    let name = uri.rsplit(['/', '#', ':']).next().unwrap_or(uri);
+    let name = name.strip_suffix("Request").unwrap_or(name);
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// Action from the `action` parameter of a SOAP 1.2 Content-Type header
+pub fn action_from_content_type(content_type: &str) -> Option<String> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("action"))
+        .and_then(|(_, value)| action_from_uri(value))
+}
+
+const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>"#;
+
+pub fn strip_bom(xml: &str) -> &str {
+    xml.strip_prefix('\u{feff}').unwrap_or(xml)
+}
+
+/// Strip a leading UTF-8 BOM and make sure an XML document starts with an XML declaration.
+/// Some Reolink firmware sends one or the other, which trips up string matching and clients.
+pub fn normalize_prolog(xml: &str) -> String {
+    let trimmed = strip_bom(xml).trim_start();
+
+    if trimmed.starts_with("<?xml") || !trimmed.starts_with('<') {
+        // Already declared, or not XML at all (empty/plain-text error bodies)
+        return trimmed.to_string();
+    }
+
+    format!("{}\n{}", XML_DECLARATION, trimmed)
+}
+
+pub const SOAP_ENV_NS: &str = "http://www.w3.org/2003/05/soap-envelope";
+pub const ONVIF_ERROR_NS: &str = "http://www.onvif.org/ver10/error";
+
+/// SOAP 1.2 fault code: `Sender` for bad requests, `Receiver` for failures on our side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultCode {
+    Sender,
+    Receiver,
+    /// A mustUnderstand header block was not understood
+    MustUnderstand,
+}
+
+impl FaultCode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FaultCode::Sender => "Sender",
+            FaultCode::Receiver => "Receiver",
+            FaultCode::MustUnderstand => "MustUnderstand",
+        }
+    }
+}
+
+/// Whether a camera response is a SOAP fault (cameras may send faults with a 200 status)
+pub fn is_fault(xml: &str) -> bool {
+    SoapEnvelope::parse(xml).is_ok_and(|envelope| envelope.body.action == "Fault")
+}
+
+pub const DEFAULT_SOAP_PREFIX: &str = "SOAP-ENV";
+
+static SOAP_PREFIX: OnceLock<String> = OnceLock::new();
+
+/// Whether `prefix` can be used as an XML namespace prefix
+pub fn is_valid_prefix(prefix: &str) -> bool {
+    prefix.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && prefix.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Set the envelope prefix used in every generated response (`proxy.soap_prefix`); call once at startup
+pub fn set_soap_prefix(prefix: &str) -> Result<()> {
+    if !is_valid_prefix(prefix) {
+        anyhow::bail!("Invalid SOAP prefix: {:?}", prefix);
+    }
+
+    if SOAP_PREFIX.set(prefix.to_string()).is_err() && soap_prefix() != prefix {
+        anyhow::bail!("SOAP prefix is already set to {}", soap_prefix());
+    }
+    Ok(())
+}
+
+pub fn soap_prefix() -> &'static str {
+    SOAP_PREFIX.get().map_or(DEFAULT_SOAP_PREFIX, String::as_str)
+}
+
+/// Wrap a response body in a SOAP envelope generated by the proxy itself.
+pub fn build_envelope(namespaces: &[(&str, &str)], body: &str) -> String {
+    build_envelope_with_prefix(soap_prefix(), namespaces, body)
+}
+
+fn build_envelope_with_prefix(env: &str, namespaces: &[(&str, &str)], body: &str) -> String {
+    let mut declarations = format!(r#"xmlns:{}="{}""#, env, SOAP_ENV_NS);
+    for (prefix, uri) in namespaces {
+        declarations.push_str(&format!(r#" xmlns:{}="{}""#, prefix, uri));
+    }
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<{env}:Envelope {}>
+<{env}:Body>
+{}
+</{env}:Body>
+</{env}:Envelope>"#,
+        declarations,
+        body,
+        env = env
+    )
+}
+
+/// Build a SOAP 1.2 fault envelope with an ONVIF `ter:` subcode, e.g. `ActionNotSupported`;
+/// an empty subcode leaves the Subcode out.
+pub fn build_fault(code: FaultCode, subcode: &str, reason: &str) -> String {
+    build_fault_with_prefix(soap_prefix(), code, subcode, reason)
+}
+
+fn build_fault_with_prefix(env: &str, code: FaultCode, subcode: &str, reason: &str) -> String {
+    let subcode = if subcode.is_empty() {
+        String::new()
+    } else {
+        format!(
+            r#"
+    <{env}:Subcode>
+      <{env}:Value>ter:{}</{env}:Value>
+    </{env}:Subcode>"#,
+            subcode,
+            env = env
+        )
+    };
+    let body = format!(
+        r#"<{env}:Fault>
+  <{env}:Code>
+    <{env}:Value>{env}:{}</{env}:Value>{}
+  </{env}:Code>
+  <{env}:Reason>
+    <{env}:Text xml:lang="en">{}</{env}:Text>
+  </{env}:Reason>
+</{env}:Fault>"#,
+        code.as_str(),
+        subcode,
+        quick_xml::escape::escape(reason),
+        env = env
+    );
+
+    build_envelope_with_prefix(env, &[("ter", ONVIF_ERROR_NS)], &body)
 }
 
 #[cfg(test)]
@@ -209,4 +704,254 @@ mod tests {
         let envelope = SoapEnvelope::parse(xml).unwrap();
         assert_eq!(envelope.body.action, "GetDeviceInformation");
     }
+
+    #[test]
+    fn test_parse_empty_body() {
+        let xml = r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope"><SOAP-ENV:Body></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+
+        let envelope = SoapEnvelope::parse(xml).unwrap();
+        assert_eq!(envelope.body.action, "");
+        assert!(envelope.body.operations.is_empty());
+        assert!(!envelope.is_batched());
+        assert!(!envelope.has_body_content());
+
+        let self_closing = r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope"><SOAP-ENV:Body/></SOAP-ENV:Envelope>"#;
+        assert!(!SoapEnvelope::parse(self_closing).unwrap().has_body_content());
+
+        let text_only = r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope"><SOAP-ENV:Body>GetProfiles</SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+        let envelope = SoapEnvelope::parse(text_only).unwrap();
+        assert_eq!(envelope.body.action, "");
+        assert!(envelope.has_body_content());
+    }
+
+    #[test]
+    fn test_parse_body_with_two_children() {
+        // The second (self-closing) operation must not replace the first as the action
+        let xml = r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope"><SOAP-ENV:Body><trt:GetStreamUri><trt:ProfileToken>000</trt:ProfileToken></trt:GetStreamUri><tds:SystemReboot/></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+
+        let envelope = SoapEnvelope::parse(xml).unwrap();
+        assert_eq!(envelope.body.action, "GetStreamUri");
+        assert_eq!(envelope.body.operations, vec!["GetStreamUri", "SystemReboot"]);
+        assert!(envelope.is_batched());
+        assert_eq!(envelope.body._content, "000");
+    }
+
+    #[test]
+    fn test_body_raw_xml_round_trip() {
+        let body = r#"<trt:GetStreamUri xmlns:trt="http://www.onvif.org/ver10/media/wsdl"><trt:StreamSetup><tt:Stream>RTP-Unicast</tt:Stream><tt:Transport><tt:Protocol>RTSP</tt:Protocol></tt:Transport></trt:StreamSetup><trt:ProfileToken token="000" fixed="true"/><tt:Name>a &amp; b</tt:Name></trt:GetStreamUri>"#;
+        let xml = format!(
+            r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:tt="http://www.onvif.org/ver10/schema"><s:Body>{}</s:Body></s:Envelope>"#,
+            body
+        );
+
+        let envelope = SoapEnvelope::parse(&xml).unwrap();
+        // The Envelope's tt prefix comes along; trt is already declared on the element
+        assert_eq!(
+            envelope.body._raw_xml,
+            body.replacen("<trt:GetStreamUri ", r#"<trt:GetStreamUri xmlns:tt="http://www.onvif.org/ver10/schema" "#, 1)
+        );
+        assert_eq!(envelope.body.action, "GetStreamUri");
+
+        let xml = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:tr2="http://www.onvif.org/ver20/media/wsdl"><s:Body><tr2:GetProfiles Type="All"/></s:Body></s:Envelope>"#;
+        assert_eq!(
+            SoapEnvelope::parse(xml).unwrap().body._raw_xml,
+            r#"<tr2:GetProfiles xmlns:tr2="http://www.onvif.org/ver20/media/wsdl" Type="All"/>"#
+        );
+    }
+
+    #[test]
+    fn test_parse_with_bom() {
+        let xml = "\u{feff}<?xml version=\"1.0\" encoding=\"UTF-8\"?><SOAP-ENV:Envelope xmlns:SOAP-ENV=\"http://www.w3.org/2003/05/soap-envelope\"><SOAP-ENV:Body><GetProfiles/></SOAP-ENV:Body></SOAP-ENV:Envelope>";
+
+        let envelope = SoapEnvelope::parse(xml).unwrap();
+        assert_eq!(envelope.body.action, "GetProfiles");
+    }
+
+    #[test]
+    fn test_must_understand_headers() {
+        let xml = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Header><wsse:Security s:mustUnderstand="1"><wsse:UsernameToken><wsse:Username>admin</wsse:Username></wsse:UsernameToken></wsse:Security><wsa:To s:mustUnderstand="true">http://proxy/onvif</wsa:To><x:Session s:mustUnderstand="1"/><x:Trace>1</x:Trace><x:Tenant s:mustUnderstand="0"/></s:Header><s:Body><GetProfiles/></s:Body></s:Envelope>"#;
+
+        let envelope = SoapEnvelope::parse(xml).unwrap();
+        let names: Vec<_> = envelope._header.as_ref().unwrap().blocks.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, vec!["Security", "To", "Session", "Trace", "Tenant"]);
+        assert_eq!(envelope.not_understood(), vec!["Session"]);
+
+        let fault = build_fault(FaultCode::MustUnderstand, "", "Header Session not understood");
+        assert!(fault.contains("<SOAP-ENV:Value>SOAP-ENV:MustUnderstand</SOAP-ENV:Value>"));
+        assert!(!fault.contains("Subcode"));
+    }
+
+    #[test]
+    fn test_addressing_headers() {
+        let xml = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:a="http://www.w3.org/2005/08/addressing"><s:Header><a:Action s:mustUnderstand="1">http://www.onvif.org/ver10/events/wsdl/EventPortType/GetEventPropertiesRequest</a:Action><a:MessageID>urn:uuid:1234</a:MessageID><a:ReplyTo><a:Address>http://www.w3.org/2005/08/addressing/anonymous</a:Address></a:ReplyTo><a:To s:mustUnderstand="1">http://proxy:8000/onvif/cam1/events_service</a:To><wsse:Security xmlns:wsse="urn:wsse"><wsse:UsernameToken/></wsse:Security></s:Header><s:Body><GetEventProperties/></s:Body></s:Envelope>"#;
+
+        let envelope = SoapEnvelope::parse(xml).unwrap();
+        let headers = envelope.addressing_headers("http://192.168.1.10/onvif/event_service");
+        let ns = r#"xmlns:a="http://www.w3.org/2005/08/addressing""#;
+        assert_eq!(
+            headers,
+            [
+                format!(r#"<a:Action {} s:mustUnderstand="1">http://www.onvif.org/ver10/events/wsdl/EventPortType/GetEventPropertiesRequest</a:Action>"#, ns),
+                format!("<a:MessageID {}>urn:uuid:1234</a:MessageID>", ns),
+                format!("<a:ReplyTo {}><a:Address>http://www.w3.org/2005/08/addressing/anonymous</a:Address></a:ReplyTo>", ns),
+                format!("<a:To {}>http://192.168.1.10/onvif/event_service</a:To>", ns),
+            ]
+            .join("\n")
+        );
+
+        let plain = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><GetEventProperties/></s:Body></s:Envelope>"#;
+        assert_eq!(SoapEnvelope::parse(plain).unwrap().addressing_headers("http://camera"), "");
+    }
+
+    #[test]
+    fn test_addressed_subscription() {
+        let envelope = |header: &str| {
+            SoapEnvelope::parse(&format!(
+                r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:wsa="http://www.w3.org/2005/08/addressing"><s:Header>{}</s:Header><s:Body><wsnt:Renew xmlns:wsnt="http://docs.oasis-open.org/wsn/b-2"/></s:Body></s:Envelope>"#,
+                header
+            ))
+            .unwrap()
+        };
+
+        let to = envelope("<wsa:Action>http://docs.oasis-open.org/wsn/bw-2/SubscriptionManager/RenewRequest</wsa:Action><wsa:To> http://proxy:8000/onvif/cam1/subscription/abc-123 </wsa:To>");
+        assert_eq!(to.addressed_subscription().as_deref(), Some("abc-123"));
+
+        let parameter = envelope(r#"<wsa:To>http://proxy:8000/onvif/cam1/event_service</wsa:To><opx:SubscriptionId xmlns:opx="urn:onvif-proxy" wsa:IsReferenceParameter="true">abc-123</opx:SubscriptionId>"#);
+        assert_eq!(parameter.addressed_subscription().as_deref(), Some("abc-123"));
+        let wrapped = envelope("<wsa:ReferenceParameters><SubscriptionId>abc-123</SubscriptionId></wsa:ReferenceParameters>");
+        assert_eq!(wrapped.addressed_subscription().as_deref(), Some("abc-123"));
+
+        assert_eq!(envelope("<wsa:To>http://proxy:8000/onvif/cam1/event_service</wsa:To>").addressed_subscription(), None);
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let envelope = |body: &str| {
+            format!(r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body>{}</s:Body></s:Envelope>"#, body)
+        };
+        let is_limit = |xml: &str| SoapEnvelope::parse(xml).unwrap_err().downcast_ref::<LimitExceeded>().is_some();
+
+        let nested = |depth: usize| format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+        assert!(SoapEnvelope::parse(&envelope(&nested(MAX_DEPTH - 1))).is_ok());
+        assert!(is_limit(&envelope(&nested(MAX_DEPTH))));
+        // Unclosed nesting is cut off at the same depth
+        assert!(is_limit(&envelope(&"<a>".repeat(100_000))));
+
+        assert!(is_limit(&envelope(&format!("<GetProfiles>{}</GetProfiles>", "<b/>".repeat(MAX_ELEMENTS + 1)))));
+        assert!(is_limit(&format!(
+            r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Header>{}</s:Header><s:Body/></s:Envelope>"#,
+            "<h/>".repeat(MAX_ELEMENTS + 1)
+        )));
+
+        let huge_text = format!("<GetProfiles><x>{}</x><y/></GetProfiles>", "a".repeat(MAX_RAW_XML_BYTES + 1));
+        assert!(is_limit(&envelope(&huge_text)));
+
+        // Ordinary syntax errors are not limit errors
+        assert!(!is_limit(&envelope("<a></b>")));
+    }
+
+    #[test]
+    fn test_resolve_action() {
+        let xml = r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope"><SOAP-ENV:Body><GetProfiles/></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+        let envelope = SoapEnvelope::parse(xml).unwrap();
+
+        assert_eq!(envelope.resolve_action(None, None), "GetProfiles");
+        assert_eq!(
+            envelope.resolve_action(Some("\"http://www.onvif.org/ver10/media/wsdl/GetStreamUri\""), None),
+            "GetStreamUri"
+        );
+        assert_eq!(
+            envelope.resolve_action(
+                None,
+                Some(r#"application/soap+xml; charset=utf-8; action="http://www.onvif.org/ver10/media/wsdl/GetSnapshotUri""#)
+            ),
+            "GetSnapshotUri"
+        );
+        assert_eq!(
+            envelope.resolve_action(Some("http://www.onvif.org/ver10/events/wsdl/PullPointSubscription/PullMessagesRequest"), None),
+            "PullMessages"
+        );
+        // An empty SOAPAction ("") means "no intent given" and falls back to the body
+        assert_eq!(envelope.resolve_action(Some("\"\""), Some("application/soap+xml")), "GetProfiles");
+
+        // A header action other than the body's operation contradicts it; none, or the same, doesn't
+        assert!(!envelope.contradicts_action("GetProfiles"));
+        assert!(!envelope.contradicts_action(""));
+        assert!(envelope.contradicts_action("GetStreamUri"));
+        let empty = SoapEnvelope::parse(r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body/></s:Envelope>"#).unwrap();
+        assert!(empty.contradicts_action("GetDeviceInformation"));
+    }
+
+    #[test]
+    fn test_normalize_prolog() {
+        let declared = r#"<?xml version="1.0" encoding="UTF-8"?><SOAP-ENV:Envelope/>"#;
+        assert_eq!(normalize_prolog(&format!("\u{feff}{}", declared)), declared);
+
+        let undeclared = normalize_prolog("\u{feff}\n<SOAP-ENV:Envelope/>");
+        assert_eq!(undeclared, format!("{}\n<SOAP-ENV:Envelope/>", XML_DECLARATION));
+
+        let envelope = SoapEnvelope::parse(&normalize_prolog(
+            r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope"><SOAP-ENV:Body><GetProfiles/></SOAP-ENV:Body></SOAP-ENV:Envelope>"#,
+        ))
+        .unwrap();
+        assert_eq!(envelope.body.action, "GetProfiles");
+
+        // Non-XML bodies are left alone apart from the BOM
+        assert_eq!(normalize_prolog("\u{feff}Unauthorized"), "Unauthorized");
+    }
+
+    #[test]
+    fn test_build_fault() {
+        let fault = build_fault(FaultCode::Sender, "ActionNotSupported", "Rule <x> unsupported");
+
+        assert!(fault.contains("<SOAP-ENV:Value>SOAP-ENV:Sender</SOAP-ENV:Value>"));
+        assert!(fault.contains("<SOAP-ENV:Value>ter:ActionNotSupported</SOAP-ENV:Value>"));
+        assert!(fault.contains("Rule &lt;x&gt; unsupported"));
+        assert!(fault.contains(r#"xmlns:ter="http://www.onvif.org/ver10/error""#));
+    }
+
+    #[test]
+    fn test_custom_soap_prefix() {
+        let envelope = build_envelope_with_prefix("s", &[("tds", "http://www.onvif.org/ver10/device/wsdl")], "<tds:SystemRebootResponse/>");
+        assert!(envelope.contains(r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope""#));
+        assert!(envelope.contains("<s:Body>"));
+        assert_eq!(SoapEnvelope::parse(&envelope).unwrap().body.action, "SystemRebootResponse");
+
+        let fault = build_fault_with_prefix("soapenv", FaultCode::Sender, "ActionNotSupported", "no");
+        assert!(fault.contains("<soapenv:Value>soapenv:Sender</soapenv:Value>"));
+        assert!(!fault.contains("SOAP-ENV"));
+
+        assert!(set_soap_prefix("1bad").is_err());
+        assert!(set_soap_prefix("s:x").is_err());
+        assert_eq!(soap_prefix(), DEFAULT_SOAP_PREFIX);
+    }
+
+    #[test]
+    fn test_parse_username_token() {
+        let xml = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:wsse="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd" xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd"><s:Header><wsse:Security s:mustUnderstand="1"><wsse:UsernameToken><wsse:Username>admin</wsse:Username><wsse:Password Type="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-username-token-profile-1.0#PasswordDigest">yEGjglrpcgLYIOUsQnUl1Jx4yE4=</wsse:Password><wsse:Nonce EncodingType="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-soap-message-security-1.0#Base64Binary">MDEyMzQ1Njc4OWFiY2RlZg==</wsse:Nonce><wsu:Created>2026-01-01T00:00:00.000Z</wsu:Created></wsse:UsernameToken></wsse:Security></s:Header><s:Body><GetProfiles/></s:Body></s:Envelope>"#;
+        let envelope = SoapEnvelope::parse(xml).unwrap();
+        let security = envelope.security().unwrap();
+        assert_eq!(
+            security,
+            &WsSecurity {
+                username: "admin".to_string(),
+                password: "yEGjglrpcgLYIOUsQnUl1Jx4yE4=".to_string(),
+                is_digest: true,
+                nonce: "MDEyMzQ1Njc4OWFiY2RlZg==".to_string(),
+                created: "2026-01-01T00:00:00.000Z".to_string(),
+                xml: security.xml.clone(),
+            }
+        );
+        // The block as sent, standing on its own
+        assert!(security.xml.starts_with(r#"<wsse:Security xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:wsse="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd" xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd" s:mustUnderstand="1"><wsse:UsernameToken>"#), "{}", security.xml);
+        assert!(security.xml.ends_with("<wsu:Created>2026-01-01T00:00:00.000Z</wsu:Created></wsse:UsernameToken></wsse:Security>"));
+
+        let text = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Header><Security><UsernameToken><Username>admin</Username><Password>pa&amp;ss</Password></UsernameToken></Security></s:Header><s:Body><GetProfiles/></s:Body></s:Envelope>"#;
+        let security = SoapEnvelope::parse(text).unwrap()._header.unwrap().security.unwrap();
+        assert!(!security.is_digest);
+        assert_eq!(security.password, "pa&ss");
+
+        let without = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Header><a:To xmlns:a="urn:a">x</a:To></s:Header><s:Body><GetProfiles/></s:Body></s:Envelope>"#;
+        assert_eq!(SoapEnvelope::parse(without).unwrap()._header.unwrap().security, None);
+    }
 }
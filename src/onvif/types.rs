@@ -1,4 +1,9 @@
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
 use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceInformation {
@@ -59,6 +64,30 @@ pub struct EventsCapabilities {
     pub ws_pausable_subscription_manager_interface_support: bool,
 }
 
+impl EventsCapabilities {
+    /// What the proxy's own events service actually supports: pull-point only
+    pub fn proxy(xaddr: String) -> Self {
+        Self {
+            xaddr,
+            ws_subscription_policy_support: false,
+            ws_pull_point_support: true,
+            ws_pausable_subscription_manager_interface_support: false,
+        }
+    }
+
+    /// Capability names (as used in GetCapabilities elements and GetServices attributes) and values
+    pub fn flags(&self) -> [(&'static str, bool); 3] {
+        [
+            ("WSSubscriptionPolicySupport", self.ws_subscription_policy_support),
+            ("WSPullPointSupport", self.ws_pull_point_support),
+            (
+                "WSPausableSubscriptionManagerInterfaceSupport",
+                self.ws_pausable_subscription_manager_interface_support,
+            ),
+        ]
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImagingCapabilities {
     pub xaddr: String,
@@ -82,19 +111,27 @@ pub struct PtzCapabilities {
     pub xaddr: String,
 }
 
+/// A media profile (`tt:Profile`). The video source and encoder configurations are typed, so a
+/// profile can be re-serialized with every element ONVIF requires of them; configurations the
+/// proxy doesn't model (audio, analytics, metadata, extensions) are kept as the camera sent them
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Profile {
     pub token: String,
+    pub fixed: bool,
     pub name: String,
     pub video_source_configuration: Option<VideoSourceConfiguration>,
     pub video_encoder_configuration: Option<VideoEncoderConfiguration>,
     pub ptz_configuration: Option<PtzConfiguration>,
+    /// Other children of the profile, as (local name, XML) in document order
+    #[serde(default)]
+    pub other_configurations: Vec<(String, String)>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VideoSourceConfiguration {
     pub token: String,
     pub name: String,
+    pub use_count: i32,
     pub source_token: String,
     pub bounds: Bounds,
 }
@@ -111,10 +148,16 @@ pub struct Bounds {
 pub struct VideoEncoderConfiguration {
     pub token: String,
     pub name: String,
+    pub use_count: i32,
     pub encoding: String,
     pub resolution: Resolution,
     pub quality: f32,
     pub rate_control: RateControl,
+    /// Always present for H264 encoders
+    pub h264: Option<H264Configuration>,
+    pub multicast: Multicast,
+    /// ISO 8601 duration
+    pub session_timeout: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -130,11 +173,346 @@ pub struct RateControl {
     pub bitrate_limit: i32,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct H264Configuration {
+    pub gov_length: i32,
+    pub h264_profile: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Multicast {
+    /// IPv4 or IPv6 address
+    pub address: String,
+    pub port: i32,
+    pub ttl: i32,
+    pub auto_start: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PtzConfiguration {
     pub token: String,
     pub name: String,
+    pub use_count: i32,
     pub node_token: String,
+    /// The remaining children (spaces, speeds, limits), kept as the camera sent them
+    #[serde(default)]
+    pub settings: Vec<String>,
+}
+
+/// Values used for required elements a camera leaves out
+const DEFAULT_ENCODING: &str = "H264";
+const DEFAULT_QUALITY: f32 = 5.0;
+const DEFAULT_FRAMERATE_LIMIT: i32 = 25;
+const DEFAULT_GOV_LENGTH: i32 = 50;
+const DEFAULT_H264_PROFILE: &str = "Main";
+const DEFAULT_SESSION_TIMEOUT: &str = "PT60S";
+
+/// Children of `tt:Profile` in schema order, which the serialized profile follows
+const PROFILE_ELEMENTS: &[&str] = &[
+    "Name",
+    "VideoSourceConfiguration",
+    "AudioSourceConfiguration",
+    "VideoEncoderConfiguration",
+    "AudioEncoderConfiguration",
+    "VideoAnalyticsConfiguration",
+    "PTZConfiguration",
+    "MetadataConfiguration",
+    "Extension",
+];
+
+impl Profile {
+    /// Parse a `Profiles`/`Profile` element of a GetProfiles or GetProfile response, whatever
+    /// its prefixes; None when it isn't well-formed. A missing encoder resolution is taken from
+    /// the video source bounds and the other way round
+    pub fn parse(xml: &str) -> Option<Self> {
+        let element = Element::parse(xml)?;
+        let mut profile = Profile {
+            token: element.attribute("token").unwrap_or_default().to_string(),
+            fixed: element.attribute("fixed") == Some("true"),
+            name: element.text_of("Name").unwrap_or_default().to_string(),
+            video_source_configuration: None,
+            video_encoder_configuration: None,
+            ptz_configuration: None,
+            other_configurations: Vec::new(),
+        };
+
+        for child in &element.children {
+            match child.name.as_str() {
+                "Name" => {}
+                "VideoSourceConfiguration" => profile.video_source_configuration = Some(VideoSourceConfiguration::from_element(child)),
+                "VideoEncoderConfiguration" => profile.video_encoder_configuration = Some(VideoEncoderConfiguration::from_element(child)),
+                "PTZConfiguration" => profile.ptz_configuration = Some(PtzConfiguration::from_element(child, xml)),
+                _ => profile.other_configurations.push((child.name.clone(), xml[child.span.clone()].to_string())),
+            }
+        }
+
+        if let (Some(source), Some(encoder)) = (&mut profile.video_source_configuration, &mut profile.video_encoder_configuration) {
+            if encoder.resolution.width == 0 || encoder.resolution.height == 0 {
+                encoder.resolution = Resolution { width: source.bounds.width, height: source.bounds.height };
+            }
+            if source.bounds.width == 0 || source.bounds.height == 0 {
+                source.bounds.width = encoder.resolution.width;
+                source.bounds.height = encoder.resolution.height;
+            }
+        }
+        Some(profile)
+    }
+
+    /// The profile as `<{element} token=".." fixed="..">` (e.g. `trt:Profiles`), its contents
+    /// in the `tt:` schema namespace
+    pub fn to_xml(&self, element: &str) -> String {
+        let mut children: Vec<(&str, String)> = vec![("Name", format!("<tt:Name>{}</tt:Name>", escape(&self.name)))];
+        if let Some(source) = &self.video_source_configuration {
+            children.push(("VideoSourceConfiguration", source.to_xml()));
+        }
+        if let Some(encoder) = &self.video_encoder_configuration {
+            children.push(("VideoEncoderConfiguration", encoder.to_xml()));
+        }
+        if let Some(ptz) = &self.ptz_configuration {
+            children.push(("PTZConfiguration", ptz.to_xml()));
+        }
+        children.extend(self.other_configurations.iter().map(|(name, xml)| (name.as_str(), xml.clone())));
+        // Stable, so repeated and unknown elements keep their order (unknown ones last)
+        children.sort_by_key(|(name, _)| PROFILE_ELEMENTS.iter().position(|known| known == name).unwrap_or(PROFILE_ELEMENTS.len()));
+
+        let contents: String = children.into_iter().map(|(_, xml)| xml).collect();
+        format!(
+            r#"<{element} token="{}" fixed="{}">{}</{element}>"#,
+            escape(&self.token),
+            self.fixed,
+            contents,
+            element = element
+        )
+    }
+}
+
+impl VideoSourceConfiguration {
+    fn from_element(element: &Element) -> Self {
+        let bounds = element.child("Bounds");
+        let bound = |name: &str| bounds.and_then(|b| b.attribute(name)).and_then(|v| v.parse().ok()).unwrap_or(0);
+        Self {
+            token: element.attribute("token").unwrap_or_default().to_string(),
+            name: element.text_of("Name").unwrap_or_default().to_string(),
+            use_count: element.parse_of("UseCount").unwrap_or(1),
+            source_token: element.text_of("SourceToken").unwrap_or_default().to_string(),
+            bounds: Bounds { x: bound("x"), y: bound("y"), width: bound("width"), height: bound("height") },
+        }
+    }
+
+    fn to_xml(&self) -> String {
+        format!(
+            r#"<tt:VideoSourceConfiguration token="{}"><tt:Name>{}</tt:Name><tt:UseCount>{}</tt:UseCount><tt:SourceToken>{}</tt:SourceToken><tt:Bounds x="{}" y="{}" width="{}" height="{}"/></tt:VideoSourceConfiguration>"#,
+            escape(&self.token),
+            escape(&self.name),
+            self.use_count,
+            escape(&self.source_token),
+            self.bounds.x,
+            self.bounds.y,
+            self.bounds.width,
+            self.bounds.height
+        )
+    }
+}
+
+impl VideoEncoderConfiguration {
+    fn from_element(element: &Element) -> Self {
+        let resolution = element.child("Resolution");
+        let rate_control = element.child("RateControl");
+        let h264 = element.child("H264");
+        let multicast = element.child("Multicast");
+
+        let encoding = element.text_of("Encoding").unwrap_or(DEFAULT_ENCODING).to_string();
+        let framerate_limit = rate_control.and_then(|r| r.parse_of("FrameRateLimit")).unwrap_or(DEFAULT_FRAMERATE_LIMIT);
+        let h264 = (h264.is_some() || encoding == "H264").then(|| H264Configuration {
+            gov_length: h264.and_then(|h| h.parse_of("GovLength")).unwrap_or(DEFAULT_GOV_LENGTH),
+            h264_profile: h264.and_then(|h| h.text_of("H264Profile")).unwrap_or(DEFAULT_H264_PROFILE).to_string(),
+        });
+        let address = multicast.and_then(|m| m.child("Address"));
+
+        Self {
+            token: element.attribute("token").unwrap_or_default().to_string(),
+            name: element.text_of("Name").unwrap_or_default().to_string(),
+            use_count: element.parse_of("UseCount").unwrap_or(1),
+            encoding,
+            resolution: Resolution {
+                width: resolution.and_then(|r| r.parse_of("Width")).unwrap_or(0),
+                height: resolution.and_then(|r| r.parse_of("Height")).unwrap_or(0),
+            },
+            quality: element.parse_of("Quality").unwrap_or(DEFAULT_QUALITY),
+            rate_control: RateControl {
+                framerate_limit,
+                encoding_interval: rate_control.and_then(|r| r.parse_of("EncodingInterval")).unwrap_or(1),
+                bitrate_limit: rate_control.and_then(|r| r.parse_of("BitrateLimit")).unwrap_or(0),
+            },
+            h264,
+            multicast: Multicast {
+                address: address
+                    .and_then(|a| a.text_of("IPv4Address").or_else(|| a.text_of("IPv6Address")))
+                    .unwrap_or("0.0.0.0")
+                    .to_string(),
+                port: multicast.and_then(|m| m.parse_of("Port")).unwrap_or(0),
+                ttl: multicast.and_then(|m| m.parse_of("TTL")).unwrap_or(1),
+                auto_start: multicast.and_then(|m| m.text_of("AutoStart")) == Some("true"),
+            },
+            session_timeout: element.text_of("SessionTimeout").unwrap_or(DEFAULT_SESSION_TIMEOUT).to_string(),
+        }
+    }
+
+    fn to_xml(&self) -> String {
+        let h264 = self
+            .h264
+            .as_ref()
+            .map(|h264| {
+                format!(
+                    "<tt:H264><tt:GovLength>{}</tt:GovLength><tt:H264Profile>{}</tt:H264Profile></tt:H264>",
+                    h264.gov_length,
+                    escape(&h264.h264_profile)
+                )
+            })
+            .unwrap_or_default();
+        let (address_type, address_element) =
+            if self.multicast.address.contains(':') { ("IPv6", "IPv6Address") } else { ("IPv4", "IPv4Address") };
+
+        format!(
+            r#"<tt:VideoEncoderConfiguration token="{}"><tt:Name>{}</tt:Name><tt:UseCount>{}</tt:UseCount><tt:Encoding>{}</tt:Encoding><tt:Resolution><tt:Width>{}</tt:Width><tt:Height>{}</tt:Height></tt:Resolution><tt:Quality>{}</tt:Quality><tt:RateControl><tt:FrameRateLimit>{}</tt:FrameRateLimit><tt:EncodingInterval>{}</tt:EncodingInterval><tt:BitrateLimit>{}</tt:BitrateLimit></tt:RateControl>{}<tt:Multicast><tt:Address><tt:Type>{}</tt:Type><tt:{address_element}>{}</tt:{address_element}></tt:Address><tt:Port>{}</tt:Port><tt:TTL>{}</tt:TTL><tt:AutoStart>{}</tt:AutoStart></tt:Multicast><tt:SessionTimeout>{}</tt:SessionTimeout></tt:VideoEncoderConfiguration>"#,
+            escape(&self.token),
+            escape(&self.name),
+            self.use_count,
+            escape(&self.encoding),
+            self.resolution.width,
+            self.resolution.height,
+            self.quality,
+            self.rate_control.framerate_limit,
+            self.rate_control.encoding_interval,
+            self.rate_control.bitrate_limit,
+            h264,
+            address_type,
+            escape(&self.multicast.address),
+            self.multicast.port,
+            self.multicast.ttl,
+            self.multicast.auto_start,
+            escape(&self.session_timeout),
+            address_element = address_element
+        )
+    }
+}
+
+impl PtzConfiguration {
+    fn from_element(element: &Element, source: &str) -> Self {
+        Self {
+            token: element.attribute("token").unwrap_or_default().to_string(),
+            name: element.text_of("Name").unwrap_or_default().to_string(),
+            use_count: element.parse_of("UseCount").unwrap_or(1),
+            node_token: element.text_of("NodeToken").unwrap_or_default().to_string(),
+            settings: element
+                .children
+                .iter()
+                .filter(|child| !["Name", "UseCount", "NodeToken"].contains(&child.name.as_str()))
+                .map(|child| source[child.span.clone()].to_string())
+                .collect(),
+        }
+    }
+
+    fn to_xml(&self) -> String {
+        format!(
+            r#"<tt:PTZConfiguration token="{}"><tt:Name>{}</tt:Name><tt:UseCount>{}</tt:UseCount><tt:NodeToken>{}</tt:NodeToken>{}</tt:PTZConfiguration>"#,
+            escape(&self.token),
+            escape(&self.name),
+            self.use_count,
+            escape(&self.node_token),
+            self.settings.concat()
+        )
+    }
+}
+
+/// An element with its attributes, text and children, names without their prefixes, and its
+/// byte range in the parsed document
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    text: String,
+    children: Vec<Element>,
+    span: Range<usize>,
+}
+
+impl Element {
+    /// The first element of `xml`
+    fn parse(xml: &str) -> Option<Self> {
+        let mut reader = Reader::from_str(xml);
+        let mut open: Vec<Element> = Vec::new();
+
+        loop {
+            let start = reader.buffer_position() as usize;
+            let (element, empty) = match reader.read_event().ok()? {
+                Event::Start(tag) => (Self::new(&tag, start), false),
+                Event::Empty(tag) => (Self::new(&tag, start), true),
+                Event::Text(text) => {
+                    if let Some(parent) = open.last_mut() {
+                        parent.text.push_str(&text.unescape().ok()?);
+                    }
+                    continue;
+                }
+                Event::End(_) => {
+                    let mut element = open.pop()?;
+                    element.span.end = reader.buffer_position() as usize;
+                    match open.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Some(element),
+                    }
+                    continue;
+                }
+                Event::Eof => return None,
+                _ => continue,
+            };
+
+            if !empty {
+                open.push(element);
+                continue;
+            }
+            let mut element = element;
+            element.span.end = reader.buffer_position() as usize;
+            match open.last_mut() {
+                Some(parent) => parent.children.push(element),
+                None => return Some(element),
+            }
+        }
+    }
+
+    fn new(tag: &BytesStart, start: usize) -> Self {
+        let attributes = tag
+            .attributes()
+            .flatten()
+            .map(|attr| {
+                let key = String::from_utf8_lossy(attr.key.local_name().as_ref()).to_string();
+                let value = attr.unescape_value().map(|v| v.to_string()).unwrap_or_default();
+                (key, value)
+            })
+            .collect();
+        Self {
+            name: String::from_utf8_lossy(tag.local_name().as_ref()).to_string(),
+            attributes,
+            text: String::new(),
+            children: Vec::new(),
+            span: start..start,
+        }
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// Trimmed text of the first child named `name`, None when missing or empty
+    fn text_of(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|child| child.text.trim()).filter(|text| !text.is_empty())
+    }
+
+    fn parse_of<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.text_of(name)?.parse().ok()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -144,3 +522,61 @@ pub struct StreamUri {
     pub invalid_after_reboot: bool,
     pub timeout: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encoder children ONVIF requires, in schema order
+    const REQUIRED_ENCODER_ELEMENTS: &[&str] = &[
+        "<tt:Name>",
+        "<tt:UseCount>",
+        "<tt:Encoding>",
+        "<tt:Resolution>",
+        "<tt:Quality>",
+        "<tt:RateControl>",
+        "<tt:Multicast>",
+        "<tt:SessionTimeout>",
+    ];
+
+    #[test]
+    fn test_minimal_reolink_profile() {
+        // Reolink firmware leaving out most of the encoder configuration
+        let xml = r#"<trt:Profiles fixed="true" token="000"><tt:Name>mainStream</tt:Name><tt:VideoEncoderConfiguration token="000"><tt:Name>mainStream</tt:Name><tt:Encoding>H264</tt:Encoding><tt:Quality>4</tt:Quality></tt:VideoEncoderConfiguration><tt:VideoSourceConfiguration token="000"><tt:Name>VideoSource</tt:Name><tt:SourceToken>000</tt:SourceToken><tt:Bounds height="1440" width="2560" y="0" x="0"></tt:Bounds></tt:VideoSourceConfiguration></trt:Profiles>"#;
+        let profile = Profile::parse(xml).unwrap();
+        assert_eq!(profile.token, "000");
+        assert!(profile.fixed);
+        let encoder = profile.video_encoder_configuration.as_ref().unwrap();
+        assert_eq!((encoder.resolution.width, encoder.resolution.height), (2560, 1440));
+        assert_eq!(encoder.quality, 4.0);
+
+        let emitted = profile.to_xml("trt:Profiles");
+        let encoder_start = emitted.find("<tt:VideoEncoderConfiguration").unwrap();
+        // The video source comes first, as the schema wants
+        assert!(emitted.find("<tt:VideoSourceConfiguration").unwrap() < encoder_start);
+        let encoder_xml = &emitted[encoder_start..];
+        let positions: Vec<usize> = REQUIRED_ENCODER_ELEMENTS.iter().map(|e| encoder_xml.find(e).unwrap_or_else(|| panic!("{} missing", e))).collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]), "{}", emitted);
+        assert!(emitted.contains("<tt:Resolution><tt:Width>2560</tt:Width><tt:Height>1440</tt:Height></tt:Resolution>"));
+        assert!(emitted.contains("<tt:H264><tt:GovLength>50</tt:GovLength><tt:H264Profile>Main</tt:H264Profile></tt:H264>"));
+        assert!(emitted.contains("<tt:UseCount>1</tt:UseCount><tt:SourceToken>000</tt:SourceToken>"));
+
+        // Re-parsing the output gives the same document
+        assert_eq!(Profile::parse(&emitted).unwrap().to_xml("trt:Profiles"), emitted);
+    }
+
+    #[test]
+    fn test_unmodeled_configurations_kept() {
+        let xml = r#"<Profiles token="sub" xmlns="http://www.onvif.org/ver10/media/wsdl"><Name>subStream</Name><MetadataConfiguration token="m"><Name>meta</Name></MetadataConfiguration><PTZConfiguration token="ptz"><Name>ptz</Name><NodeToken>node</NodeToken><DefaultPTZTimeout>PT5S</DefaultPTZTimeout></PTZConfiguration><AudioSourceConfiguration token="a"><Name>audio</Name></AudioSourceConfiguration><VideoSourceConfiguration token="vs"><SourceToken>000</SourceToken><Bounds x="0" y="0" width="640" height="360"/></VideoSourceConfiguration></Profiles>"#;
+        let emitted = Profile::parse(xml).unwrap().to_xml("trt:Profiles");
+        let order: Vec<usize> = ["<tt:Name>", "<tt:VideoSourceConfiguration", "<AudioSourceConfiguration", "<tt:PTZConfiguration", "<MetadataConfiguration"]
+            .iter()
+            .map(|e| emitted.find(e).unwrap_or_else(|| panic!("{} missing from {}", e, emitted)))
+            .collect();
+        assert!(order.windows(2).all(|w| w[0] < w[1]), "{}", emitted);
+        assert!(emitted.contains("<tt:NodeToken>node</tt:NodeToken><DefaultPTZTimeout>PT5S</DefaultPTZTimeout></tt:PTZConfiguration>"));
+        assert!(emitted.contains(r#"<MetadataConfiguration token="m"><Name>meta</Name></MetadataConfiguration>"#));
+
+        assert!(Profile::parse("<trt:Profiles token=\"x\"><tt:Name>broken</trt:Profiles>").is_none());
+    }
+}
@@ -0,0 +1,116 @@
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use std::collections::HashSet;
+
+/// Declare `xmlns:{prefix}="{uri}"` on the SOAP Envelope, whatever prefix the Envelope uses
+/// (`SOAP-ENV:`, `soap:`, `s:`, none). The Envelope start tag is parsed and written back with
+/// the new attribute; the rest of the document is left untouched. Nothing changes when the
+/// Envelope already declares the prefix or there is no Envelope
+pub fn ensure_namespace(xml: &str, prefix: &str, uri: &str) -> String {
+    let key = format!("xmlns:{}", prefix);
+    rewrite_envelope(xml, |tag| {
+        let declared = tag.attributes().with_checks(false).flatten().any(|attr| attr.key.as_ref() == key.as_bytes());
+        if declared {
+            return None;
+        }
+        let mut tag = tag.to_owned();
+        tag.push_attribute((key.as_str(), uri));
+        Some(tag)
+    })
+}
+
+/// Drop repeated `xmlns`/`xmlns:*` declarations on the SOAP Envelope (left behind when several
+/// translation steps each add one), keeping the first value per prefix. Other attributes and
+/// their order are kept
+pub fn remove_duplicate_namespaces(xml: &str) -> String {
+    rewrite_envelope(xml, |tag| {
+        let mut seen = HashSet::new();
+        let mut duplicates = false;
+        let mut rebuilt = BytesStart::new(String::from_utf8_lossy(tag.name().as_ref()).to_string());
+        for attr in tag.attributes().with_checks(false).flatten() {
+            let key = attr.key.as_ref();
+            let is_declaration = key == b"xmlns" || key.starts_with(b"xmlns:");
+            if is_declaration && !seen.insert(key.to_vec()) {
+                duplicates = true;
+                continue;
+            }
+            rebuilt.push_attribute(attr);
+        }
+        duplicates.then_some(rebuilt)
+    })
+}
+
+/// Replace the Envelope start tag with what `rewrite` makes of it; None leaves the document
+/// unchanged, as does a document without an Envelope
+fn rewrite_envelope(xml: &str, rewrite: impl FnOnce(&BytesStart) -> Option<BytesStart<'static>>) -> String {
+    let mut reader = Reader::from_str(xml);
+
+    loop {
+        let start = reader.buffer_position() as usize;
+        let (tag, empty) = match reader.read_event() {
+            Ok(Event::Start(e)) => (e, false),
+            Ok(Event::Empty(e)) => (e, true),
+            Ok(Event::Eof) | Err(_) => return xml.to_string(),
+            Ok(_) => continue,
+        };
+        if tag.local_name().as_ref() != b"Envelope" {
+            return xml.to_string();
+        }
+        let end = reader.buffer_position() as usize;
+
+        let Some(tag) = rewrite(&tag) else {
+            return xml.to_string();
+        };
+        let mut writer = Writer::new(Vec::new());
+        let written = if empty { writer.write_event(Event::Empty(tag)) } else { writer.write_event(Event::Start(tag)) };
+        if written.is_err() {
+            return xml.to_string();
+        }
+        let serialized = String::from_utf8_lossy(&writer.into_inner()).to_string();
+        return format!("{}{}{}", &xml[..start], serialized, &xml[end..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TT: &str = "http://www.onvif.org/ver10/schema";
+
+    #[test]
+    fn test_envelope_prefixes() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?><SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope"><SOAP-ENV:Body><tt:Name>a</tt:Name></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+        assert_eq!(
+            ensure_namespace(xml, "tt", TT),
+            xml.replacen(r#"soap-envelope">"#, r#"soap-envelope" xmlns:tt="http://www.onvif.org/ver10/schema">"#, 1)
+        );
+
+        let xml = r#"<soap:Envelope
+    xmlns:soap="http://www.w3.org/2003/05/soap-envelope" encodingStyle="x>y"><soap:Body><tt:Name>a</tt:Name></soap:Body></soap:Envelope>"#;
+        let result = ensure_namespace(xml, "tt", TT);
+        assert!(result.contains(r#"encodingStyle="x>y" xmlns:tt="http://www.onvif.org/ver10/schema"><soap:Body>"#), "{}", result);
+    }
+
+    #[test]
+    fn test_declared_namespace_kept() {
+        let xml = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:tt="http://www.onvif.org/ver10/schema"><s:Body/></s:Envelope>"#;
+        assert_eq!(ensure_namespace(xml, "tt", TT), xml);
+        assert_eq!(ensure_namespace(&ensure_namespace(xml, "tds", "urn:tds"), "tds", "urn:tds").matches("xmlns:tds=").count(), 1);
+
+        // Not a SOAP document
+        assert_eq!(ensure_namespace("<tt:Name>a</tt:Name>", "tt", TT), "<tt:Name>a</tt:Name>");
+    }
+
+    #[test]
+    fn test_remove_duplicate_namespaces() {
+        let xml = r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope" xmlns:tt="http://www.onvif.org/ver10/schema" xmlns:tds="http://www.onvif.org/ver10/device/wsdl" xmlns:tt="urn:other"><SOAP-ENV:Body><tt:Name>a</tt:Name></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+        assert_eq!(
+            remove_duplicate_namespaces(xml),
+            r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope" xmlns:tt="http://www.onvif.org/ver10/schema" xmlns:tds="http://www.onvif.org/ver10/device/wsdl"><SOAP-ENV:Body><tt:Name>a</tt:Name></SOAP-ENV:Body></SOAP-ENV:Envelope>"#
+        );
+
+        // Without duplicates the document is untouched, formatting included
+        let clean = "<s:Envelope\n  xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\"><s:Body/></s:Envelope>";
+        assert_eq!(remove_duplicate_namespaces(clean), clean);
+    }
+}
@@ -0,0 +1,101 @@
+use axum::{
+    extract::Request,
+    http::{HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use std::time::Instant;
+use tracing::Instrument;
+
+use crate::server::routes::request_action;
+use crate::server::soap_request::{camera_id_from_path, parse_request};
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Middleware logging one line per request, with camera, action, status and latency as
+/// separate fields (queryable with `log_format: json`). Everything logged while handling the
+/// request carries its `request_id`, which is also returned in `X-Request-Id`.
+pub async fn log_requests(request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let camera_id = camera_id_from_path(&path).unwrap_or_default();
+
+    let (request, action) = if method == Method::POST && !camera_id.is_empty() {
+        match parse_request(request).await {
+            Ok((request, parsed)) => {
+                let action = parsed
+                    .envelope()
+                    .map(|envelope| request_action(request.headers(), envelope))
+                    .unwrap_or_default();
+                (request, action)
+            }
+            Err(rejection) => return rejection,
+        }
+    } else {
+        (request, String::new())
+    };
+
+    let span = tracing::info_span!("request", request_id = %request_id, camera_id = %camera_id);
+    let mut response = next.run(request).instrument(span).await;
+
+    tracing::info!(
+        target: "onvif_proxy::access",
+        request_id = %request_id,
+        camera_id = %camera_id,
+        action = %action,
+        method = %method,
+        path = %path,
+        status = response.status().as_u16(),
+        latency_ms = started.elapsed().as_secs_f64() * 1000.0,
+        "request"
+    );
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::{routing::post, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_request_id_and_body_preserved() {
+        let app = Router::new()
+            .route("/onvif/:camera_id/device_service", post(|body: String| async move { body }))
+            .layer(axum::middleware::from_fn(log_requests));
+        let envelope = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><GetDeviceInformation xmlns="http://www.onvif.org/ver10/device/wsdl"/></s:Body></s:Envelope>"#;
+
+        let response = app
+            .clone()
+            .oneshot(Request::post("/onvif/cam1/device_service").body(Body::from(envelope)).unwrap())
+            .await
+            .unwrap();
+        assert!(response.headers().contains_key(REQUEST_ID_HEADER));
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, envelope.as_bytes());
+
+        let response = app
+            .oneshot(
+                Request::post("/onvif/cam1/device_service")
+                    .header(REQUEST_ID_HEADER, "abc-123")
+                    .body(Body::from(envelope))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "abc-123");
+    }
+}
@@ -0,0 +1,412 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+use crate::config::CacheConfig;
+use crate::server::routes::{request_action, AppState};
+use crate::server::soap_request::{camera_id_from_path, parse_request};
+
+/// Idempotent read actions that may be served from the cache; everything else
+/// (subscriptions, PullMessages, writes) always reaches the camera
+const CACHEABLE_ACTIONS: &[&str] = &[
+    "GetDeviceInformation",
+    "GetCapabilities",
+    "GetServices",
+    "GetProfiles",
+    "GetProfile",
+    "GetStreamUri",
+    "GetSnapshotUri",
+    "GetVideoSources",
+    "GetEventProperties",
+    "GetAnalyticsConfigurations",
+    "GetSupportedRules",
+];
+
+const MAX_ENTRIES: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    camera_id: String,
+    action: String,
+    /// The request body, so e.g. GetStreamUri for different profiles is cached separately
+    params: String,
+}
+
+#[derive(Clone)]
+struct CachedResponse {
+    body: Arc<[u8]>,
+    content_type: Option<HeaderValue>,
+    etag: String,
+    expires_at: Instant,
+}
+
+impl CachedResponse {
+    fn respond(&self, if_none_match: Option<&str>) -> Response {
+        let etag = HeaderValue::from_str(&self.etag).expect("ETag is a quoted hex digest");
+
+        if if_none_match.is_some_and(|tags| etag_matches(tags, &self.etag)) {
+            return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+        }
+
+        let mut response = (StatusCode::OK, Body::from(self.body.to_vec())).into_response();
+        response.headers_mut().insert(header::ETAG, etag);
+        if let Some(content_type) = &self.content_type {
+            response.headers_mut().insert(header::CONTENT_TYPE, content_type.clone());
+        }
+        response
+    }
+}
+
+/// Response cache for read-only ONVIF actions, keyed by (camera, action, request body)
+#[derive(Clone)]
+pub struct ResponseCache {
+    config: Arc<CacheConfig>,
+    entries: Arc<RwLock<HashMap<CacheKey, CachedResponse>>>,
+}
+
+impl ResponseCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            entries: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// TTL for an action, or `None` when the action must not be cached
+    fn ttl_for(&self, action: &str) -> Option<Duration> {
+        if !self.config.enabled || !CACHEABLE_ACTIONS.contains(&action) {
+            return None;
+        }
+
+        let secs = self
+            .config
+            .ttl_secs
+            .get(action)
+            .copied()
+            .unwrap_or(self.config.default_ttl_secs);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
+    /// Drop every cached response for a camera; returns how many were removed
+    pub async fn invalidate_camera(&self, camera_id: &str) -> usize {
+        let mut entries = self.entries.write().await;
+        let before = entries.len();
+        entries.retain(|key, _| key.camera_id != camera_id);
+        before - entries.len()
+    }
+
+    async fn get(&self, key: &CacheKey) -> Option<CachedResponse> {
+        let entries = self.entries.read().await;
+        entries
+            .get(key)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .cloned()
+    }
+
+    async fn insert(&self, key: CacheKey, entry: CachedResponse) {
+        let mut entries = self.entries.write().await;
+
+        if entries.len() >= MAX_ENTRIES {
+            let now = Instant::now();
+            entries.retain(|_, e| e.expires_at > now);
+            if entries.len() >= MAX_ENTRIES {
+                tracing::debug!("Response cache full, not caching {}", key.action);
+                return;
+            }
+        }
+
+        entries.insert(key, entry);
+    }
+}
+
+/// Middleware serving cacheable actions from `ResponseCache`, with `ETag`/`If-None-Match` support
+pub async fn cache_responses(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(camera_id) = cacheable_camera_id(request.uri().path()) else {
+        return next.run(request).await;
+    };
+    if !state.response_cache.config.enabled {
+        return next.run(request).await;
+    }
+
+    let (request, parsed) = match parse_request(request).await {
+        Ok(read) => read,
+        Err(rejection) => return rejection,
+    };
+
+    let cacheable = parsed.envelope().and_then(|envelope| {
+        let action = request_action(request.headers(), envelope);
+        let ttl = state.response_cache.ttl_for(&action)?;
+        Some((
+            CacheKey {
+                camera_id,
+                action,
+                params: envelope.body._raw_xml.clone(),
+            },
+            ttl,
+        ))
+    });
+
+    let if_none_match = request
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+
+    let Some((key, ttl)) = cacheable else {
+        return next.run(request).await;
+    };
+
+    if let Some(entry) = state.response_cache.get(&key).await {
+        tracing::debug!("Cache hit for {} on camera {}", key.action, key.camera_id);
+        return entry.respond(if_none_match.as_deref());
+    }
+    tracing::trace!("Cache miss for {} on camera {}", key.action, key.camera_id);
+
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("Failed to buffer response for caching: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read response").into_response();
+        }
+    };
+
+    let entry = CachedResponse {
+        etag: etag_for(&body),
+        body: Arc::from(body.as_ref()),
+        content_type: parts.headers.get(header::CONTENT_TYPE).cloned(),
+        expires_at: Instant::now() + ttl,
+    };
+    let response = entry.respond(if_none_match.as_deref());
+    state.response_cache.insert(key, entry).await;
+
+    response
+}
+
+/// `/onvif/{camera_id}/{service}` -> camera id; subscription endpoints are never cached
+fn cacheable_camera_id(path: &str) -> Option<String> {
+    match path.strip_prefix("/onvif/")?.split('/').nth(1) {
+        Some("subscription") | None => None,
+        Some(_) => camera_id_from_path(path),
+    }
+}
+
+fn etag_for(body: &[u8]) -> String {
+    let digest = Sha1::digest(body);
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(enabled: bool) -> ResponseCache {
+        let mut config = CacheConfig {
+            enabled,
+            ..Default::default()
+        };
+        config.ttl_secs.insert("GetStreamUri".to_string(), 0);
+        ResponseCache::new(config)
+    }
+
+    #[test]
+    fn test_ttl_for() {
+        let enabled = cache(true);
+        assert_eq!(enabled.ttl_for("GetProfiles"), Some(Duration::from_secs(CacheConfig::default().default_ttl_secs)));
+        // A zero TTL disables caching for that action
+        assert_eq!(enabled.ttl_for("GetStreamUri"), None);
+        assert_eq!(enabled.ttl_for("PullMessages"), None);
+        assert_eq!(enabled.ttl_for("CreatePullPointSubscription"), None);
+
+        assert_eq!(cache(false).ttl_for("GetProfiles"), None);
+    }
+
+    #[test]
+    fn test_cacheable_camera_id() {
+        assert_eq!(cacheable_camera_id("/onvif/cam1/media_service").as_deref(), Some("cam1"));
+        assert_eq!(cacheable_camera_id("/onvif/cam1/subscription/abc"), None);
+        assert_eq!(cacheable_camera_id("/health"), None);
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let entry = CachedResponse {
+            etag: etag_for(b"<xml/>"),
+            body: Arc::from(&b"<xml/>"[..]),
+            content_type: None,
+            expires_at: Instant::now() + Duration::from_secs(30),
+        };
+
+        assert_eq!(entry.respond(None).status(), StatusCode::OK);
+        assert_eq!(entry.respond(Some("\"other\"")).status(), StatusCode::OK);
+        let tags = format!("\"other\", W/{}", entry.etag);
+        let not_modified = entry.respond(Some(&tags));
+        assert_eq!(not_modified.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(not_modified.headers()[header::ETAG], entry.etag.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_one_upstream_request_within_ttl() {
+        use crate::camera::{CameraConfig, CameraManager};
+        use crate::config::ProxyConfig;
+        use crate::onvif::events::EventsService;
+        use crate::onvif::soap;
+        use crate::server::routes::create_router;
+        use axum::Router;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tower::ServiceExt;
+
+        let upstream = Arc::new(AtomicUsize::new(0));
+        let counted = upstream.clone();
+        let mock = Router::new().fallback(move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+            async {
+                soap::build_envelope(
+                    &[("tds", "http://www.onvif.org/ver10/device/wsdl")],
+                    "<tds:GetDeviceInformationResponse><tds:Manufacturer>Reolink</tds:Manufacturer></tds:GetDeviceInformationResponse>",
+                )
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await.unwrap() });
+
+        let camera_manager = CameraManager::new();
+        camera_manager
+            .add_camera(CameraConfig {
+                id: "cam1".to_string(),
+                address: address.to_string(),
+                ..Default::default()
+            })
+            .await;
+        let proxy_config: ProxyConfig =
+            serde_yaml::from_str("listen_address: 127.0.0.1:8000\nbase_path: /onvif\ncache:\n  enabled: true").unwrap();
+        let router = create_router(AppState {
+            camera_manager,
+            events_service: EventsService::new(),
+            base_url: "http://proxy".to_string(),
+            response_cache: ResponseCache::new(proxy_config.cache.clone()),
+            startup_complete: Default::default(),
+            config_path: None,
+            proxy_config: Arc::new(proxy_config),
+        });
+        let request = |action: &str| {
+            Request::post("/onvif/cam1/device_service")
+                .body(Body::from(format!(
+                    r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><{} xmlns="http://www.onvif.org/ver10/device/wsdl"/></s:Body></s:Envelope>"#,
+                    action
+                )))
+                .unwrap()
+        };
+
+        for _ in 0..2 {
+            let response = router.clone().oneshot(request("GetDeviceInformation")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert!(String::from_utf8_lossy(&body).contains("Reolink"));
+        }
+        assert_eq!(upstream.load(Ordering::SeqCst), 1);
+
+        // Actions outside the allow-list reach the camera every time
+        for _ in 0..2 {
+            router.clone().oneshot(request("GetSystemDateAndTime")).await.unwrap();
+        }
+        assert_eq!(upstream.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_etag_round_trip() {
+        use crate::camera::{CameraConfig, CameraManager};
+        use crate::config::ProxyConfig;
+        use crate::onvif::events::EventsService;
+        use crate::server::routes::create_router;
+        use tower::ServiceExt;
+
+        let camera_manager = CameraManager::new();
+        camera_manager
+            .add_camera(CameraConfig {
+                id: "sim".to_string(),
+                address: "sim.local".to_string(),
+                model: "simulated".to_string(),
+                ..Default::default()
+            })
+            .await;
+
+        let proxy_config = ProxyConfig {
+            listen_address: "127.0.0.1:0".to_string(),
+            listen_socket: None,
+            base_path: "/onvif".to_string(),
+            log_level: "info".to_string(),
+            log_format: Default::default(),
+            base_url: None,
+            service_base_urls: HashMap::new(),
+            admin_token: None,
+            auth: None,
+            state_path: None,
+            soap_prefix: "SOAP-ENV".to_string(),
+            events: Default::default(),
+            request_deadline_secs: None,
+            enable_dashboard: false,
+            strict_soap: false,
+            ws_discovery: false,
+            cache: CacheConfig {
+                enabled: true,
+                ..Default::default()
+            },
+        };
+        let router = create_router(AppState {
+            camera_manager,
+            events_service: EventsService::new(),
+            base_url: "http://proxy".to_string(),
+            response_cache: ResponseCache::new(proxy_config.cache.clone()),
+            startup_complete: Default::default(),
+            config_path: None,
+            proxy_config: Arc::new(proxy_config),
+        });
+
+        let request = |etag: Option<&str>| {
+            let mut builder = Request::post("/onvif/sim/device_service");
+            if let Some(etag) = etag {
+                builder = builder.header(header::IF_NONE_MATCH, etag);
+            }
+            builder
+                .body(Body::from(
+                    r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><GetDeviceInformation xmlns="http://www.onvif.org/ver10/device/wsdl"/></s:Body></s:Envelope>"#,
+                ))
+                .unwrap()
+        };
+
+        let first = router.clone().oneshot(request(None)).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()[header::ETAG].to_str().unwrap().to_string();
+
+        let second = router.clone().oneshot(request(Some(&etag))).await.unwrap();
+        assert_eq!(second.status(), StatusCode::NOT_MODIFIED);
+
+        let third = router.oneshot(request(Some("\"stale\""))).await.unwrap();
+        assert_eq!(third.status(), StatusCode::OK);
+        assert_eq!(third.headers()[header::ETAG].to_str().unwrap(), etag);
+    }
+}
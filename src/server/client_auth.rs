@@ -0,0 +1,208 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::Utc;
+use std::sync::LazyLock;
+
+use crate::onvif::auth::{constant_time_eq, verify_username_token, NonceCache};
+use crate::onvif::soap::FaultCode;
+use crate::server::routes::{request_action, soap_fault, AppState};
+use crate::server::soap_request::{camera_id_from_path, parse_request};
+
+/// Actions ONVIF devices answer before authentication (access class PRE_AUTH), which clients
+/// need to sync their clock and find the services
+const PRE_AUTH_ACTIONS: &[&str] = &[
+    "GetSystemDateAndTime",
+    "GetWsdlUrl",
+    "GetServices",
+    "GetServiceCapabilities",
+    "GetCapabilities",
+    "GetHostname",
+    "GetEndpointReference",
+];
+
+/// Nonces of the digest tokens accepted lately, shared by every camera
+static SEEN_NONCES: LazyLock<NonceCache> = LazyLock::new(NonceCache::default);
+
+/// Middleware requiring `proxy.auth` credentials on the ONVIF endpoints: a WS-Security
+/// UsernameToken in the request, or HTTP Basic (the only choice for snapshot GETs). Requests
+/// without valid credentials get a `ter:NotAuthorized` fault, or a 401 for GETs
+pub async fn authenticate_clients(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(auth) = &state.proxy_config.auth else {
+        return next.run(request).await;
+    };
+    let Some(camera_id) = camera_id_from_path(request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let (username, password) = auth.credentials_for(&camera_id);
+
+    if basic_credentials(request.headers()).is_some_and(|(user, pass)| {
+        // Both fields are compared, so the time taken doesn't tell which one was wrong
+        constant_time_eq(user.as_bytes(), username.as_bytes()) & constant_time_eq(pass.as_bytes(), password.as_bytes())
+    }) {
+        return next.run(request).await;
+    }
+    if request.method() == Method::GET {
+        tracing::warn!(camera_id = %camera_id, "Rejecting unauthenticated {}", request.uri().path());
+        return (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, r#"Basic realm="onvif-proxy""#)], "Unauthorized").into_response();
+    }
+
+    let (request, parsed) = match parse_request(request).await {
+        Ok(read) => read,
+        Err(rejection) => return rejection,
+    };
+
+    // Requests that don't parse are rejected by the service handlers, without reaching the camera
+    let Some(envelope) = parsed.envelope() else {
+        return next.run(request).await;
+    };
+    let action = request_action(request.headers(), envelope);
+    let token = envelope.security();
+    // Pre-auth is decided on the Body, which is what gets forwarded; the SOAPAction header can't
+    // vouch for it. An empty Body without an action is a probe
+    let pre_auth = !envelope.contradicts_action(&action)
+        && envelope.body.operations.iter().all(|operation| PRE_AUTH_ACTIONS.contains(&operation.as_str()));
+    let authorized = pre_auth || match token {
+        Some(token) => {
+            let now = match state.camera_manager.get_camera(&camera_id).await {
+                Some(camera) => camera.camera_clock().await,
+                None => Utc::now(),
+            };
+            verify_username_token(token, username, password, now, &SEEN_NONCES)
+        }
+        None => false,
+    };
+
+    if !authorized {
+        tracing::warn!(
+            camera_id = %camera_id,
+            action = %action,
+            username = token.map(|token| token.username.as_str()).unwrap_or_default(),
+            "Rejecting request with missing or wrong credentials"
+        );
+        return soap_fault(FaultCode::Sender, "NotAuthorized", "Sender not authorized");
+    }
+    next.run(request).await
+}
+
+/// Username and password of an `Authorization: Basic` header
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers.get(header::AUTHORIZATION)?.to_str().ok()?.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(BASE64.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{CameraConfig, CameraManager};
+    use crate::config::ProxyConfig;
+    use crate::onvif::auth::WsSecurityAuth;
+    use crate::onvif::events::EventsService;
+    use crate::server::cache::ResponseCache;
+    use crate::server::routes::create_router;
+    use axum::body::{to_bytes, Body};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_client_credentials() {
+        let camera_manager = CameraManager::new();
+        for id in ["front", "back"] {
+            camera_manager
+                .add_camera(CameraConfig {
+                    id: id.to_string(),
+                    address: "sim.local".to_string(),
+                    model: "simulated".to_string(),
+                    ..Default::default()
+                })
+                .await;
+        }
+        let proxy_config: ProxyConfig = serde_yaml::from_str(
+            "listen_address: 127.0.0.1:8000\nbase_path: /onvif\nauth:\n  username: nvr\n  password: s3cret\n  cameras:\n    back:\n      username: back\n      password: other",
+        )
+        .unwrap();
+        let app = create_router(AppState {
+            camera_manager,
+            events_service: EventsService::new(),
+            base_url: "http://proxy".to_string(),
+            response_cache: ResponseCache::new(proxy_config.cache.clone()),
+            startup_complete: Default::default(),
+            config_path: None,
+            proxy_config: Arc::new(proxy_config),
+        });
+
+        let send_as = |camera: &str, soap_action: Option<&str>, action: &str, security: String, authorization: Option<&str>| {
+            let envelope = format!(
+                r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:wsse="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd" xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd"><s:Header>{}</s:Header><s:Body><{} xmlns="http://www.onvif.org/ver10/device/wsdl"/></s:Body></s:Envelope>"#,
+                security, action
+            );
+            let mut request = Request::post(format!("/onvif/{}/device_service", camera));
+            if let Some(soap_action) = soap_action {
+                request = request.header("SOAPAction", soap_action);
+            }
+            if let Some(authorization) = authorization {
+                request = request.header(header::AUTHORIZATION, authorization);
+            }
+            let request = request.body(Body::from(envelope)).unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, String::from_utf8_lossy(&body).into_owned())
+            }
+        };
+        let send = |camera: &str, action: &str, security: String, authorization: Option<&str>| send_as(camera, None, action, security, authorization);
+        let token = |username: &str, password: &str| WsSecurityAuth::new(username.to_string(), password.to_string()).generate_header();
+
+        let (status, body) = send("front", "GetDeviceInformation", token("nvr", "s3cret"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("GetDeviceInformationResponse"));
+
+        let (status, body) = send("front", "GetDeviceInformation", token("nvr", "wrong"), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("ter:NotAuthorized"));
+        let (status, _) = send("front", "GetDeviceInformation", String::new(), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Per-camera credentials replace the shared ones
+        let (status, _) = send("back", "GetDeviceInformation", token("nvr", "s3cret"), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send("back", "GetDeviceInformation", token("back", "other"), None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // HTTP Basic, and the pre-auth actions without credentials
+        let basic = format!("Basic {}", BASE64.encode("nvr:s3cret"));
+        let (status, _) = send("front", "GetDeviceInformation", String::new(), Some(&basic)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send("front", "GetSystemDateAndTime", String::new(), None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // A pre-auth SOAPAction doesn't let another operation through, nor does a replayed token
+        let soap_action = Some("http://www.onvif.org/ver10/device/wsdl/GetSystemDateAndTime");
+        let (status, body) = send_as("front", soap_action, "SystemReboot", String::new(), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("ter:NotAuthorized"));
+        let captured = token("nvr", "s3cret");
+        let (status, _) = send("front", "GetDeviceInformation", captured.clone(), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send("front", "GetDeviceInformation", captured, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("ter:NotAuthorized"));
+        // With valid credentials, the mismatch is still refused instead of forwarding the body
+        let (status, body) = send_as("front", soap_action, "SystemReboot", token("nvr", "s3cret"), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("InvalidArgs"));
+
+        let snapshot = app.clone().oneshot(Request::get("/onvif/front/snapshot").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(snapshot.status(), StatusCode::UNAUTHORIZED);
+        assert!(snapshot.headers().contains_key(header::WWW_AUTHENTICATE));
+    }
+}
@@ -0,0 +1,75 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::camera::CameraClient;
+use crate::config::ClientProfile;
+use crate::server::routes::AppState;
+use crate::server::soap_request::{camera_id_from_path, ParsedEnvelope, MAX_REQUEST_BYTES};
+
+/// Middleware for cameras with `client_profile: milestone`: tokens in requests are mapped back
+/// to the camera's own and tokens in responses are rewritten to Milestone-safe ones
+pub async fn adapt_tokens(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(camera_id) = camera_id_from_path(request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let camera = match state.camera_manager.get_camera(&camera_id).await {
+        Some(camera) if camera.config().client_profile == ClientProfile::Milestone => camera,
+        _ => return next.run(request).await,
+    };
+
+    let (mut parts, body) = request.into_parts();
+    let body = match to_bytes(body, MAX_REQUEST_BYTES).await {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(_) => return (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response(),
+    };
+    if camera.client_tokens().has_unknown_tokens(&body) {
+        relearn_tokens(&camera).await;
+    }
+    let restored = camera.client_tokens().restore_request(&body);
+    // The envelope the outer layers parsed still has the client's tokens
+    if restored != body {
+        parts.extensions.remove::<ParsedEnvelope>();
+    }
+    let body = restored;
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    let (mut parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("Failed to read response for token rewriting: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read response").into_response();
+        }
+    };
+    let Ok(xml) = std::str::from_utf8(&body) else {
+        return Response::from_parts(parts, Body::from(body));
+    };
+
+    let xml = camera.client_tokens().sanitize_response(xml);
+    parts.headers.insert(header::CONTENT_LENGTH, xml.len().into());
+    Response::from_parts(parts, Body::from(xml))
+}
+
+/// Requests whose responses list the camera's profile, configuration and source tokens
+const TOKEN_LISTINGS: &[(&str, &str)] = &[
+    ("/onvif/media_service", r#"<trt:GetProfiles xmlns:trt="http://www.onvif.org/ver10/media/wsdl"/>"#),
+    ("/onvif/media_service", r#"<trt:GetVideoSources xmlns:trt="http://www.onvif.org/ver10/media/wsdl"/>"#),
+];
+
+/// Rebuild the camera's token map from its listings, for client tokens handed out before the
+/// proxy restarted or the camera was re-added
+async fn relearn_tokens(camera: &CameraClient) {
+    tracing::debug!("Relearning client tokens of camera {}", camera.camera_id());
+    for (service_path, request) in TOKEN_LISTINGS {
+        match camera.send_soap_request(service_path, request).await {
+            Ok(xml) => camera.client_tokens().learn(&xml),
+            Err(e) => tracing::warn!("Could not relearn tokens of camera {}: {:#}", camera.camera_id(), e),
+        }
+    }
+}
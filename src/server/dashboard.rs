@@ -0,0 +1,101 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use quick_xml::escape::escape;
+
+use crate::server::routes::{AppState, CameraHealthReport};
+
+/// `GET /status`: one table row per camera, from the same data as `/health/detailed`
+pub async fn status_page(State(state): State<AppState>) -> Response {
+    if !state.proxy_config.enable_dashboard {
+        return (StatusCode::NOT_FOUND, "Dashboard disabled").into_response();
+    }
+
+    Html(render(&CameraHealthReport::all(&state).await)).into_response()
+}
+
+pub(crate) fn render(reports: &[CameraHealthReport]) -> String {
+    let mut rows = String::new();
+    for report in reports {
+        let (class, status) = match report.online {
+            Some(true) => ("online", "online"),
+            Some(false) => ("offline", "offline"),
+            None => ("unknown", "unknown"),
+        };
+        let name = if report.name.is_empty() { &report.id } else { &report.name };
+        let error = match (&report.last_error, &report.last_failure) {
+            (Some(error), Some(at)) => format!("{} <small>({})</small>", escape(error.as_str()), escape(at.as_str())),
+            (Some(error), None) => escape(error.as_str()).into_owned(),
+            _ => String::new(),
+        };
+
+        rows.push_str(&format!(
+            "<tr><td>{}<br><small>{}</small></td><td class=\"{}\">{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape(name.as_str()),
+            escape(report.id.as_str()),
+            class,
+            status,
+            escape(report.last_motion.as_deref().unwrap_or("-")),
+            report.active_subscriptions,
+            report.consecutive_failures,
+            error
+        ));
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta http-equiv="refresh" content="10">
+<title>onvif-proxy status</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #ccc; padding: 0.4em 0.8em; text-align: left; vertical-align: top; }}
+.online {{ color: #080; }}
+.offline {{ color: #c00; font-weight: bold; }}
+.unknown {{ color: #888; }}
+</style>
+</head>
+<body>
+<h1>onvif-proxy</h1>
+<table>
+<tr><th>Camera</th><th>Status</th><th>Last motion</th><th>Subscriptions</th><th>Failures</th><th>Last error</th></tr>
+{}</table>
+</body>
+</html>
+"#,
+        rows
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let report = CameraHealthReport {
+            id: "camera-01".to_string(),
+            name: "Front <Door>".to_string(),
+            online: Some(false),
+            last_success: None,
+            last_failure: Some("2026-01-01T00:00:00+00:00".to_string()),
+            last_error: Some("connection refused".to_string()),
+            consecutive_failures: 3,
+            deadline_exceeded: 0,
+            last_motion: None,
+            active_subscriptions: 2,
+            queue: None,
+            resolved_addrs: Vec::new(),
+        };
+
+        let html = render(&[report]);
+        assert!(html.contains("Front &lt;Door&gt;"));
+        assert!(html.contains(r#"<td class="offline">offline</td>"#));
+        assert!(html.contains("<td>2</td><td>3</td><td>connection refused <small>(2026-01-01T00:00:00+00:00)</small></td>"));
+    }
+}
@@ -0,0 +1,121 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::time::Duration;
+
+use crate::onvif::events::EventsService;
+use crate::onvif::soap::FaultCode;
+use crate::server::routes::{request_action, soap_fault, AppState};
+use crate::server::soap_request::{camera_id_from_path, parse_request};
+
+/// Middleware answering with a `ter:Timeout` fault once `proxy.request_deadline_secs` has
+/// passed, instead of holding the client while a slow camera and the translation finish.
+/// PullMessages gets its requested `Timeout` on top, since it is meant to wait that long.
+pub async fn enforce_deadline(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(deadline_secs) = state.proxy_config.request_deadline_secs else {
+        return next.run(request).await;
+    };
+    let Some(camera_id) = camera_id_from_path(request.uri().path()) else {
+        return next.run(request).await;
+    };
+
+    let (request, parsed) = match parse_request(request).await {
+        Ok(read) => read,
+        Err(rejection) => return rejection,
+    };
+
+    let envelope = parsed.envelope();
+    let action = envelope
+        .map(|envelope| request_action(request.headers(), envelope))
+        .unwrap_or_default();
+    let wait = envelope
+        .filter(|_| action == "PullMessages")
+        .and_then(|envelope| pull_timeout(&envelope.body._raw_xml))
+        .unwrap_or_default();
+    let deadline = Duration::from_secs(deadline_secs) + wait;
+
+    match tokio::time::timeout(deadline, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!(
+                camera_id = %camera_id,
+                action = %action,
+                deadline_secs = deadline.as_secs(),
+                "Request deadline exceeded"
+            );
+            if let Some(camera) = state.camera_manager.get_camera(&camera_id).await {
+                camera.record_deadline_exceeded().await;
+            }
+            soap_fault(FaultCode::Receiver, "Timeout", "The camera did not answer in time")
+        }
+    }
+}
+
+/// The `Timeout` of a PullMessages request
+fn pull_timeout(body: &str) -> Option<Duration> {
+    let start = body.find("Timeout>")? + "Timeout>".len();
+    let end = start + body[start..].find('<')?;
+    let secs = EventsService::parse_iso_duration(body[start..end].trim())?;
+    Some(Duration::from_secs(secs.max(0) as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{CameraConfig, CameraManager};
+    use crate::config::ProxyConfig;
+    use crate::server::cache::ResponseCache;
+    use axum::body::{to_bytes, Body};
+    use axum::http::StatusCode;
+    use axum::{routing::post, Router};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_deadline_fault() {
+        let camera_manager = CameraManager::new();
+        camera_manager
+            .add_camera(CameraConfig {
+                id: "sim".to_string(),
+                model: "simulated".to_string(),
+                ..Default::default()
+            })
+            .await;
+        let proxy_config: ProxyConfig =
+            serde_yaml::from_str("listen_address: 127.0.0.1:8000\nbase_path: /onvif\nrequest_deadline_secs: 0").unwrap();
+        let state = AppState {
+            camera_manager: camera_manager.clone(),
+            events_service: EventsService::new(),
+            base_url: "http://proxy".to_string(),
+            response_cache: ResponseCache::new(proxy_config.cache.clone()),
+            startup_complete: Default::default(),
+            config_path: None,
+            proxy_config: Arc::new(proxy_config),
+        };
+
+        let slow = || async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            "late"
+        };
+        let app = Router::new()
+            .route("/onvif/:camera_id/device_service", post(slow))
+            .route("/onvif/:camera_id/subscription/:sub_id", post(slow))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), enforce_deadline))
+            .with_state(state);
+        let request = |path: &str, body: &str| Request::post(path).body(Body::from(body.to_string())).unwrap();
+
+        let envelope = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><GetDeviceInformation xmlns="http://www.onvif.org/ver10/device/wsdl"/></s:Body></s:Envelope>"#;
+        let response = app.clone().oneshot(request("/onvif/sim/device_service", envelope)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Timeout"));
+        assert_eq!(camera_manager.get_camera("sim").await.unwrap().health().await.deadline_exceeded, 1);
+
+        // A PullMessages long poll may take its own Timeout on top of the deadline
+        let pull = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><tev:PullMessages xmlns:tev="http://www.onvif.org/ver10/events/wsdl"><tev:Timeout>PT1S</tev:Timeout><tev:MessageLimit>10</tev:MessageLimit></tev:PullMessages></s:Body></s:Envelope>"#;
+        let response = app.oneshot(request("/onvif/sim/subscription/abc", pull)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -1,28 +1,65 @@
 use anyhow::{Context, Result};
+use axum::Router;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use tower_http::trace::TraceLayer;
 
 use crate::camera::CameraManager;
+use crate::config::ProxyConfig;
+use crate::onvif::device::DeviceService;
+use crate::onvif::discovery;
 use crate::onvif::events::EventsService;
+use crate::onvif::soap;
+use crate::server::cache::ResponseCache;
 use crate::server::routes::{create_router, AppState};
 
 pub async fn start_server(
-    listen_addr: String,
+    proxy_config: ProxyConfig,
     base_url: String,
     camera_manager: CameraManager,
+    config_path: Option<std::path::PathBuf>,
 ) -> Result<()> {
-    let addr: SocketAddr = listen_addr
+    let addr: SocketAddr = proxy_config
+        .listen_address
         .parse()
         .context("Failed to parse listen address")?;
 
-    let events_service = EventsService::new();
+    soap::set_soap_prefix(&proxy_config.soap_prefix).context("Invalid proxy.soap_prefix")?;
+
+    let events_service = EventsService::with_config(proxy_config.events.clone());
+    events_service.spawn_reaper();
 
     let state = AppState {
         camera_manager,
         events_service,
         base_url,
+        response_cache: ResponseCache::new(proxy_config.cache.clone()),
+        startup_complete: Arc::new(AtomicBool::new(false)),
+        config_path: config_path.map(Arc::new),
+        proxy_config: Arc::new(proxy_config),
     };
 
+    if state.proxy_config.ws_discovery {
+        let camera_manager = state.camera_manager.clone();
+        // Clients go to the device service first, so its override applies
+        let base_url = state
+            .proxy_config
+            .service_base_urls
+            .get("device_service")
+            .cloned()
+            .unwrap_or_else(|| state.base_url.clone());
+        tokio::spawn(async move {
+            if let Err(e) = discovery::run_responder(camera_manager, base_url).await {
+                tracing::warn!("WS-Discovery disabled: {:#}", e);
+            }
+        });
+    }
+
+    let listen_socket = state.proxy_config.listen_socket.clone();
+    let startup_complete = state.startup_complete.clone();
+    let self_test_cameras = state.camera_manager.clone();
+
     let app = create_router(state)
         .layer(TraceLayer::new_for_http());
 
@@ -33,9 +70,73 @@ pub async fn start_server(
         .await
         .context("Failed to bind to address")?;
 
-    axum::serve(listener, app)
-        .await
-        .context("Server error")?;
+    let unix_server = match &listen_socket {
+        Some(path) => Some(serve_unix(path, app.clone())?),
+        None => None,
+    };
+
+    // Readiness flips once every configured camera has been tried
+    tokio::spawn(async move {
+        startup_self_test(&self_test_cameras).await;
+        startup_complete.store(true, Ordering::SeqCst);
+    });
+
+    let tcp_server = async { axum::serve(listener, app).await.context("Server error") };
+    match unix_server {
+        Some(unix_server) => {
+            tokio::try_join!(tcp_server, unix_server)?;
+        }
+        None => tcp_server.await?,
+    }
 
     Ok(())
 }
+
+/// Bind `path` and return a future serving `app` on it; a stale socket file from a previous run is replaced
+#[cfg(unix)]
+fn serve_unix(path: &str, app: Router) -> Result<impl std::future::Future<Output = Result<()>>> {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto;
+    use tower::Service;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|m| std::os::unix::fs::FileTypeExt::is_socket(&m.file_type())) {
+        std::fs::remove_file(path).with_context(|| format!("Failed to remove stale socket {}", path))?;
+    }
+    let listener = tokio::net::UnixListener::bind(path).with_context(|| format!("Failed to bind to socket {}", path))?;
+    tracing::info!("Also listening on unix:{}", path);
+
+    Ok(async move {
+        loop {
+            let (socket, _) = listener.accept().await.context("Failed to accept on unix socket")?;
+            let app = app.clone();
+
+            tokio::spawn(async move {
+                let service = hyper::service::service_fn(move |request| app.clone().call(request));
+                if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                    .serve_connection_with_upgrades(TokioIo::new(socket), service)
+                    .await
+                {
+                    tracing::debug!("Unix socket connection error: {}", e);
+                }
+            });
+        }
+    })
+}
+
+#[cfg(not(unix))]
+fn serve_unix(_path: &str, _app: Router) -> Result<std::future::Ready<Result<()>>> {
+    anyhow::bail!("listen_socket is only supported on Unix")
+}
+
+/// Contact each camera once so health state is populated before we report ready
+async fn startup_self_test(camera_manager: &CameraManager) {
+    let cameras = camera_manager.cameras().await;
+    let results = futures::future::join_all(cameras.iter().map(DeviceService::get_system_date_and_time)).await;
+
+    for (camera, result) in cameras.iter().zip(results) {
+        match result {
+            Ok(_) => tracing::info!("Startup self-test: camera {} is reachable", camera.camera_id()),
+            Err(e) => tracing::warn!("Startup self-test: camera {} is unreachable: {:#}", camera.camera_id(), e),
+        }
+    }
+}
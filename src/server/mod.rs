@@ -1,4 +1,11 @@
+pub mod access_log;
+pub mod cache;
+pub mod client_auth;
+pub mod client_profile;
+pub mod dashboard;
+pub mod deadline;
 pub mod http;
 pub mod routes;
+pub mod soap_request;
 
 pub use http::start_server;
@@ -1,30 +1,90 @@
 use axum::{
-    extract::{Path, State},
-    http::{StatusCode, header},
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
     response::{IntoResponse, Response},
-    routing::post,
-    Router,
+    routing::{delete, get, post},
+    Extension, Json, Router,
 };
+use quick_xml::{events::Event, Reader};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-use crate::camera::CameraManager;
-use crate::onvif::{device, media, events, soap::SoapEnvelope};
+use crate::camera::{CameraClient, CameraConfig, CameraManager};
+use crate::camera::queue::QueueStats;
+use crate::config::{ImagingBackend, ProxyConfig, UnknownActionBehavior};
+use crate::server::access_log;
+use crate::server::cache::{self, ResponseCache};
+use crate::server::client_auth;
+use crate::server::client_profile;
+use crate::server::dashboard;
+use crate::server::deadline;
+use crate::server::soap_request::ParsedEnvelope;
+use crate::onvif::{analytics, device, imaging, media, events, ptz, soap::{self, FaultCode, SoapEnvelope}};
+use crate::onvif::auth::constant_time_eq;
 use crate::translator::ResponseTranslator;
+use crate::translator::rules::RuleEngine;
+use crate::util::redact_secrets;
 
 // Helper function to create SOAP response with correct content-type
+// Content-Length is always set: some embedded clients (cheap NVRs) can't read chunked replies
 fn soap_response(xml: String) -> Response {
     (
         StatusCode::OK,
-        [(header::CONTENT_TYPE, "application/soap+xml; charset=utf-8")],
+        [
+            (header::CONTENT_TYPE, "application/soap+xml; charset=utf-8".to_string()),
+            (header::CONTENT_LENGTH, xml.len().to_string()),
+        ],
         xml,
     )
         .into_response()
 }
 
+// Helper function to create a SOAP fault response; the HTTP status follows the fault code
+pub(crate) fn soap_fault(code: FaultCode, subcode: &str, reason: &str) -> Response {
+    let status = match code {
+        FaultCode::Sender => StatusCode::BAD_REQUEST,
+        FaultCode::Receiver | FaultCode::MustUnderstand => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    let xml = soap::build_fault(code, subcode, reason);
+    (
+        status,
+        [
+            (header::CONTENT_TYPE, "application/soap+xml; charset=utf-8".to_string()),
+            (header::CONTENT_LENGTH, xml.len().to_string()),
+        ],
+        xml,
+    )
+        .into_response()
+}
+
+// Answer an action the proxy does not handle, as configured by `unknown_action_behavior`
+fn unknown_action(camera: &CameraClient, action: &str) -> Response {
+    match camera.config().unknown_action_behavior {
+        UnknownActionBehavior::Fault => soap_fault(
+            FaultCode::Sender,
+            "ActionNotSupported",
+            &format!("Action not supported: {}", action),
+        ),
+        UnknownActionBehavior::NotImplemented => {
+            (StatusCode::NOT_IMPLEMENTED, format!("Action not implemented: {}", action)).into_response()
+        }
+        UnknownActionBehavior::EmptyOk => soap_response(soap::build_envelope(&[], "")),
+    }
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub camera_manager: CameraManager,
     pub events_service: events::EventsService,
     pub base_url: String,
+    pub proxy_config: Arc<ProxyConfig>,
+    pub response_cache: ResponseCache,
+    /// Set once the listener is bound and every camera has been tried (see `/health/ready`)
+    pub startup_complete: Arc<AtomicBool>,
+    /// Config file that cameras added or removed through `/cameras` are saved to
+    pub config_path: Option<Arc<std::path::PathBuf>>,
 }
 
 pub fn create_router(state: AppState) -> Router {
@@ -37,20 +97,52 @@ pub fn create_router(state: AppState) -> Router {
         .route("/onvif/:camera_id/Media2", post(handle_media2_service))
         // Events service endpoints
         .route("/onvif/:camera_id/event_service", post(handle_events_service))
+        // Analytics service endpoints
+        .route("/onvif/:camera_id/analytics_service", post(handle_analytics_service))
+        // PTZ service endpoints
+        .route("/onvif/:camera_id/ptz_service", post(handle_ptz_service))
+        // Imaging service endpoints
+        .route("/onvif/:camera_id/imaging_service", post(handle_imaging_service))
+        // Snapshots fetched on the client's behalf (`proxy_snapshots`)
+        .route("/onvif/:camera_id/snapshot", axum::routing::get(handle_snapshot))
         // Subscription endpoints
         .route("/onvif/:camera_id/subscription/:sub_id", post(handle_subscription))
         // Health check
         .route("/health", axum::routing::get(health_check))
+        .route("/health/live", axum::routing::get(health_check))
+        .route("/health/ready", axum::routing::get(health_ready))
+        .route("/health/detailed", axum::routing::get(health_detailed))
+        // Status page (404 unless proxy.enable_dashboard)
+        .route("/status", axum::routing::get(dashboard::status_page))
+        // Admin endpoints
+        .route("/admin/cameras/:camera_id/motion", post(trigger_motion))
+        .route("/admin/cameras/:camera_id/refresh", post(refresh_camera))
+        // Camera management (needs proxy.admin_token)
+        .route("/cameras", get(list_cameras).post(add_camera))
+        .route("/cameras/:camera_id", delete(remove_camera))
+        .route("/debug/translate", post(debug_translate))
+        // Milestone-safe tokens for cameras with client_profile: milestone
+        .layer(axum::middleware::from_fn_with_state(state.clone(), client_profile::adapt_tokens))
+        // Fault requests that outlive proxy.request_deadline_secs (no-op unless set)
+        .layer(axum::middleware::from_fn_with_state(state.clone(), deadline::enforce_deadline))
+        // Serve idempotent reads from the response cache (no-op unless enabled)
+        .layer(axum::middleware::from_fn_with_state(state.clone(), cache::cache_responses))
+        // Check client credentials before anything is served, cached responses included (no-op unless proxy.auth is set)
+        .layer(axum::middleware::from_fn_with_state(state.clone(), client_auth::authenticate_clients))
+        // One structured log line per request (outermost, so cache hits are logged too)
+        .layer(axum::middleware::from_fn(access_log::log_requests))
         .with_state(state)
 }
 
 async fn handle_device_service(
     State(state): State<AppState>,
     Path(camera_id): Path<String>,
+    headers: HeaderMap,
+    parsed: Option<Extension<ParsedEnvelope>>,
     body: String,
 ) -> Response {
     tracing::debug!("Device service request for camera: {}", camera_id);
-    tracing::trace!("Request body: {}", body);
+    tracing::trace!("Request body: {}", redact_secrets(&body));
 
     let camera = match state.camera_manager.get_camera(&camera_id).await {
         Some(cam) => cam,
@@ -61,39 +153,105 @@ async fn handle_device_service(
     };
 
     // Parse SOAP request
-    let envelope = match SoapEnvelope::parse(&body) {
-        Ok(env) => env,
-        Err(e) => {
-            tracing::error!("Failed to parse SOAP request: {}", e);
-            return (StatusCode::BAD_REQUEST, format!("Invalid SOAP: {}", e)).into_response();
-        }
+    let envelope = match request_envelope(parsed, &body).result() {
+        Ok(env) => env.clone(),
+        Err(e) => return invalid_soap(e),
     };
+    let camera = camera.with_caller_token(envelope.security());
+
+    if let Some(rejection) = reject_batched(&envelope) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_not_understood(&state, &envelope) {
+        return rejection;
+    }
 
-    let action = envelope.extract_action();
+    let action = request_action(&headers, &envelope);
+    if let Some(rejection) = reject_contradicted_action(&envelope, &action) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_prohibited(&camera, &envelope, &action) {
+        return rejection;
+    }
     tracing::debug!("Device action: {}", action);
 
-    // Handle empty action (probe requests)
+    // Handle empty action: probe requests have an empty Body; anything else is a request we
+    // couldn't make out, which must not be answered as if it were a probe
     if action.is_empty() {
+        if envelope.has_body_content() {
+            tracing::warn!("No operation found in SOAP body: {}", envelope.body._raw_xml);
+            return soap_fault(FaultCode::Sender, "InvalidArgs", "No operation found in the request body");
+        }
         tracing::trace!("Empty action - likely a probe request");
         return (StatusCode::OK, "OK").into_response();
     }
 
+    if camera.config().is_raw_action(&action) {
+        return forward_raw(&camera, "/onvif/device_service", &envelope).await;
+    }
+
     let response = match action.as_str() {
         "GetSystemDateAndTime" => {
             device::DeviceService::get_system_date_and_time(&camera).await
         }
+        "SetSystemDateAndTime" => {
+            let Some(date_time_type) = extract_value(&body, "DateTimeType").and_then(|value| device::DateTimeType::parse(&value)) else {
+                return soap_fault(FaultCode::Sender, "InvalidArgs", "SetSystemDateAndTime requires a DateTimeType of Manual or NTP");
+            };
+            let field = |tag| extract_value(&body, tag).and_then(|value| value.parse::<u32>().ok());
+            let utc_date_time = match (field("Year"), field("Month"), field("Day")) {
+                (None, None, None) => None,
+                (year, month, day) => {
+                    let date_time = chrono::NaiveDate::from_ymd_opt(year.unwrap_or_default() as i32, month.unwrap_or_default(), day.unwrap_or_default())
+                        .and_then(|date| date.and_hms_opt(field("Hour")?, field("Minute")?, field("Second")?));
+                    let Some(date_time) = date_time else {
+                        return soap_fault(FaultCode::Sender, "InvalidDateTime", "The UTCDateTime is not a valid date and time");
+                    };
+                    Some(date_time)
+                }
+            };
+            if date_time_type == device::DateTimeType::Manual && utc_date_time.is_none() {
+                return soap_fault(FaultCode::Sender, "InvalidArgs", "Manual date and time requires a UTCDateTime");
+            }
+            let settings = device::SystemDateAndTime {
+                date_time_type,
+                daylight_savings: extract_value(&body, "DaylightSavings").is_some_and(|value| value == "true" || value == "1"),
+                time_zone: extract_value(&body, "TZ").filter(|tz| !tz.is_empty()),
+                utc_date_time,
+            };
+            device::DeviceService::set_system_date_and_time(&camera, &settings).await
+        }
         "GetDeviceInformation" => {
             device::DeviceService::get_device_information(&camera, &state.base_url).await
         }
+        "GetScopes" => {
+            device::DeviceService::get_scopes(&camera).await
+        }
+        "SetScopes" => {
+            device::DeviceService::set_scopes(&camera, &envelope.body._raw_xml).await
+        }
         "GetCapabilities" => {
-            device::DeviceService::get_capabilities(&camera, &state.base_url).await
+            device::DeviceService::get_capabilities(&camera, &state.base_url, &state.proxy_config.service_base_urls).await
         }
         "GetServices" => {
-            device::DeviceService::get_services(&camera, &state.base_url).await
+            device::DeviceService::get_services(&camera, &state.base_url, &state.proxy_config.service_base_urls).await
+        }
+        "GetDot11Capabilities" => {
+            device::DeviceService::get_dot11_capabilities(&camera).await
+        }
+        "GetDot11Status" => {
+            let interface_token = extract_value(&body, "InterfaceToken").unwrap_or_default();
+            match device::DeviceService::get_dot11_status(&camera, &interface_token).await {
+                Ok(Some(xml)) => Ok(xml),
+                Ok(None) => {
+                    return soap_fault(FaultCode::Sender, "NotDot11", "The interface is not an IEEE 802.11 interface");
+                }
+                Err(e) => Err(e),
+            }
         }
         _ => {
             tracing::warn!("Unknown device action: {}", action);
-            return (StatusCode::NOT_IMPLEMENTED, format!("Action not implemented: {}", action)).into_response();
+            return unknown_action(&camera, &action);
         }
     };
 
@@ -102,7 +260,7 @@ async fn handle_device_service(
             tracing::trace!("Raw device response: {}", xml);
             // Apply translation quirks
             let quirks = camera.config().quirks.clone();
-            let translated = match ResponseTranslator::translate(&xml, &camera.config().model, &quirks) {
+            let translated = match ResponseTranslator::translate(&xml, &camera.config().model, &quirks, camera.rules()) {
                 Ok(t) => t,
                 Err(e) => {
                     tracing::error!("Translation failed: {}", e);
@@ -123,10 +281,23 @@ async fn handle_device_service(
 async fn handle_media_service(
     State(state): State<AppState>,
     Path(camera_id): Path<String>,
+    headers: HeaderMap,
+    parsed: Option<Extension<ParsedEnvelope>>,
     body: String,
 ) -> Response {
     tracing::debug!("Media service request for camera: {}", camera_id);
-    tracing::trace!("Request body: {}", body);
+    tracing::trace!("Request body: {}", redact_secrets(&body));
+
+    // iSpy sometimes sends Media2 (ver20) actions to the ver10 endpoint; those take the Media2
+    // path, checks and fixups included. The action name alone doesn't tell the versions apart,
+    // so the ver20 namespace is looked for in the SOAPAction/Content-Type headers and the body
+    let ver20_header = ["soapaction", header::CONTENT_TYPE.as_str()]
+        .iter()
+        .any(|name| headers.get(*name).and_then(|v| v.to_str().ok()).is_some_and(|v| v.contains("ver20/media")));
+    if ver20_header || body.contains("http://www.onvif.org/ver20/media/wsdl") {
+        tracing::debug!("Detected Media2 (ver20) action on Media ver10 endpoint, routing to Media2");
+        return handle_media2_service(State(state), Path(camera_id), headers, parsed, body).await;
+    }
 
     let camera = match state.camera_manager.get_camera(&camera_id).await {
         Some(cam) => cam,
@@ -135,39 +306,44 @@ async fn handle_media_service(
         }
     };
 
-    let envelope = match SoapEnvelope::parse(&body) {
-        Ok(env) => env,
-        Err(e) => {
-            return (StatusCode::BAD_REQUEST, format!("Invalid SOAP: {}", e)).into_response();
-        }
+    let envelope = match request_envelope(parsed, &body).result() {
+        Ok(env) => env.clone(),
+        Err(e) => return invalid_soap(e),
     };
+    let camera = camera.with_caller_token(envelope.security());
+
+    if let Some(rejection) = reject_batched(&envelope) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_not_understood(&state, &envelope) {
+        return rejection;
+    }
 
-    let action = envelope.extract_action();
+    let action = request_action(&headers, &envelope);
+    if let Some(rejection) = reject_contradicted_action(&envelope, &action) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_prohibited(&camera, &envelope, &action) {
+        return rejection;
+    }
     tracing::debug!("Media action: {}", action);
 
-    // Check if this is a Media2 (ver20) request sent to Media ver10 endpoint
-    // iSpy sometimes sends ver20 actions to the ver10 endpoint
-    if action.contains("ver20/media") || body.contains("http://www.onvif.org/ver20/media/wsdl") {
-        tracing::debug!("Detected Media2 (ver20) action on Media ver10 endpoint, routing to Media2");
-        let soap_body = &envelope.body._raw_xml;
-        let response = camera.send_soap_request("/onvif/Media2", soap_body).await;
+    if camera.config().is_raw_action(&action) {
+        return forward_raw(&camera, "/onvif/media_service", &envelope).await;
+    }
 
-        return match response {
-            Ok(xml) => {
-                tracing::trace!("Raw Media2 response: {}", xml);
-                soap_response(xml)
-            }
-            Err(e) => {
-                tracing::error!("Media2 service error: {}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
-            }
-        };
+    if let Some(rejection) = reject_hidden_profile(&camera, &action, &body).await {
+        return rejection;
     }
 
     let response = match action.as_str() {
         "GetProfiles" => {
             media::MediaService::get_profiles(&camera).await
         }
+        "GetProfile" => {
+            let profile_token = extract_value(&body, "ProfileToken").unwrap_or("000".to_string());
+            media::MediaService::get_profile(&camera, &profile_token).await
+        }
         "GetStreamUri" => {
             // Extract profile token and protocol from request
             let profile_token = extract_value(&body, "ProfileToken").unwrap_or("000".to_string());
@@ -177,11 +353,17 @@ async fn handle_media_service(
         }
         "GetSnapshotUri" => {
             let profile_token = extract_value(&body, "ProfileToken").unwrap_or("000".to_string());
-            media::MediaService::get_snapshot_uri(&camera, &profile_token).await
+            media::MediaService::get_snapshot_uri(&camera, &profile_token)
+                .await
+                .map(|xml| proxy_snapshot_uri(&state, &camera, &profile_token, xml))
+        }
+        "GetGuaranteedNumberOfVideoEncoderInstances" => {
+            let configuration_token = extract_value(&body, "ConfigurationToken").unwrap_or("000".to_string());
+            media::MediaService::get_guaranteed_number_of_video_encoder_instances(&camera, &configuration_token).await
         }
         _ => {
             tracing::warn!("Unknown media action: {}", action);
-            return (StatusCode::NOT_IMPLEMENTED, format!("Action not implemented: {}", action)).into_response();
+            return unknown_action(&camera, &action);
         }
     };
 
@@ -189,7 +371,7 @@ async fn handle_media_service(
         Ok(xml) => {
             tracing::trace!("Raw media response: {}", xml);
             let quirks = camera.config().quirks.clone();
-            let translated = match ResponseTranslator::translate(&xml, &camera.config().model, &quirks) {
+            let translated = match ResponseTranslator::translate(&xml, &camera.config().model, &quirks, camera.rules()) {
                 Ok(t) => t,
                 Err(e) => {
                     tracing::error!("Translation failed: {}", e);
@@ -210,10 +392,12 @@ async fn handle_media_service(
 async fn handle_media2_service(
     State(state): State<AppState>,
     Path(camera_id): Path<String>,
+    headers: HeaderMap,
+    parsed: Option<Extension<ParsedEnvelope>>,
     body: String,
 ) -> Response {
     tracing::debug!("Media2 service request for camera: {}", camera_id);
-    tracing::trace!("Request body: {}", body);
+    tracing::trace!("Request body: {}", redact_secrets(&body));
 
     let camera = match state.camera_manager.get_camera(&camera_id).await {
         Some(cam) => cam,
@@ -223,27 +407,58 @@ async fn handle_media2_service(
     };
 
     // Parse the SOAP envelope to extract the body content
-    let envelope = match SoapEnvelope::parse(&body) {
-        Ok(env) => env,
-        Err(e) => {
-            tracing::error!("Failed to parse SOAP request: {}", e);
-            return (StatusCode::BAD_REQUEST, format!("Invalid SOAP: {}", e)).into_response();
-        }
+    let envelope = match request_envelope(parsed, &body).result() {
+        Ok(env) => env.clone(),
+        Err(e) => return invalid_soap(e),
     };
+    let camera = camera.with_caller_token(envelope.security());
+
+    if let Some(rejection) = reject_batched(&envelope) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_not_understood(&state, &envelope) {
+        return rejection;
+    }
 
-    let action = envelope.extract_action();
+    let action = request_action(&headers, &envelope);
+    if let Some(rejection) = reject_contradicted_action(&envelope, &action) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_prohibited(&camera, &envelope, &action) {
+        return rejection;
+    }
     tracing::debug!("Media2 action: {}", action);
 
+    if camera.config().is_raw_action(&action) {
+        return forward_raw(&camera, "/onvif/Media2", &envelope).await;
+    }
+
+    if let Some(rejection) = reject_hidden_profile(&camera, &action, &body).await {
+        return rejection;
+    }
+
     // Extract the body content to forward to the camera
     // Media2 uses ver20 structure, passthrough to camera's Media2 endpoint
-    let soap_body = &envelope.body._raw_xml;
-    let response = camera.send_soap_request("/onvif/Media2", soap_body).await;
+    let soap_body = if action == "GetStreamUri" && camera.config().force_rtsp_tcp {
+        media::MediaService::force_tcp_media2_request(&envelope.body._raw_xml)
+    } else {
+        envelope.body._raw_xml.clone()
+    };
+    let response = camera.send_soap_request("/onvif/Media2", &soap_body).await;
 
     match response {
         Ok(xml) => {
             tracing::trace!("Raw Media2 response: {}", xml);
             // Fix localhost URLs in Media2 responses (GetProfiles, GetStreamUri, etc.)
-            let fixed_xml = fix_localhost_urls(&xml, &camera);
+            let mut fixed_xml = fix_localhost_urls(&xml, &camera);
+            if action == "GetProfiles" {
+                fixed_xml = media::MediaService::arrange_profiles(&fixed_xml, camera.config());
+            }
+            if action == "GetSnapshotUri" {
+                let profile_token = extract_value(&envelope.body._raw_xml, "ProfileToken").unwrap_or_default();
+                fixed_xml = media::MediaService::fix_snapshot_uri_response(&fixed_xml, &camera, &profile_token);
+                fixed_xml = proxy_snapshot_uri(&state, &camera, &profile_token, fixed_xml);
+            }
             soap_response(fixed_xml)
         }
         Err(e) => {
@@ -256,10 +471,12 @@ async fn handle_media2_service(
 async fn handle_events_service(
     State(state): State<AppState>,
     Path(camera_id): Path<String>,
+    headers: HeaderMap,
+    parsed: Option<Extension<ParsedEnvelope>>,
     body: String,
 ) -> Response {
     tracing::debug!("Events service request for camera: {}", camera_id);
-    tracing::trace!("Request body: {}", body);
+    tracing::trace!("Request body: {}", redact_secrets(&body));
 
     let camera = match state.camera_manager.get_camera(&camera_id).await {
         Some(cam) => cam,
@@ -268,38 +485,71 @@ async fn handle_events_service(
         }
     };
 
-    let envelope = match SoapEnvelope::parse(&body) {
-        Ok(env) => env,
-        Err(e) => {
-            return (StatusCode::BAD_REQUEST, format!("Invalid SOAP: {}", e)).into_response();
-        }
+    let envelope = match request_envelope(parsed, &body).result() {
+        Ok(env) => env.clone(),
+        Err(e) => return invalid_soap(e),
     };
+    let camera = camera.with_caller_token(envelope.security());
+
+    if let Some(rejection) = reject_batched(&envelope) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_not_understood(&state, &envelope) {
+        return rejection;
+    }
 
-    let action = envelope.extract_action();
+    let action = request_action(&headers, &envelope);
+    if let Some(rejection) = reject_contradicted_action(&envelope, &action) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_prohibited(&camera, &envelope, &action) {
+        return rejection;
+    }
     tracing::debug!("Events action: {}", action);
 
+    if camera.config().is_raw_action(&action) {
+        return forward_raw(&camera, "/onvif/event_service", &envelope).await;
+    }
+
     let response = match action.as_str() {
         "GetEventProperties" => {
             events::EventsService::get_event_properties(&camera).await
         }
         "CreatePullPointSubscription" => {
-            state.events_service.create_pull_point_subscription(&camera, &state.base_url).await
+            let requested = extract_value(&body, "InitialTerminationTime");
+            state
+                .events_service
+                .create_pull_point_subscription(&camera, &state.base_url, requested.as_deref())
+                .await
         }
-        // PullMessages, Renew, and Unsubscribe should be called on the subscription endpoint, not here
+        // Meant for the subscription endpoint, but some clients post them here and address the
+        // subscription in the WS-Addressing header instead
         "PullMessages" | "Renew" | "Unsubscribe" => {
-            tracing::warn!("Action {} should be called on subscription endpoint, not event_service", action);
-            return (StatusCode::BAD_REQUEST, format!("Action {} should be called on subscription endpoint", action)).into_response();
+            let Some(sub_id) = envelope.addressed_subscription() else {
+                tracing::warn!("Action {} should be called on subscription endpoint, not event_service", action);
+                return (StatusCode::BAD_REQUEST, format!("Action {} should be called on subscription endpoint", action)).into_response();
+            };
+            let subscription = state
+                .events_service
+                .get_subscription(&sub_id)
+                .await
+                .filter(|subscription| subscription.camera_id == camera_id);
+            let Some(subscription) = subscription else {
+                tracing::error!("Subscription not found: {}", sub_id);
+                return (StatusCode::NOT_FOUND, "Subscription not found").into_response();
+            };
+            return subscription_action(&state, &camera, &sub_id, &subscription, &action, &body).await;
         }
         _ => {
             tracing::warn!("Unknown events action: {}", action);
-            return (StatusCode::NOT_IMPLEMENTED, format!("Action not implemented: {}", action)).into_response();
+            return unknown_action(&camera, &action);
         }
     };
 
     match response {
         Ok(xml) => {
             let quirks = camera.config().quirks.clone();
-            let translated = match ResponseTranslator::translate(&xml, &camera.config().model, &quirks) {
+            let translated = match ResponseTranslator::translate(&xml, &camera.config().model, &quirks, camera.rules()) {
                 Ok(t) => t,
                 Err(e) => {
                     tracing::error!("Translation failed: {}", e);
@@ -316,9 +566,274 @@ async fn handle_events_service(
     }
 }
 
+async fn handle_analytics_service(
+    State(state): State<AppState>,
+    Path(camera_id): Path<String>,
+    headers: HeaderMap,
+    parsed: Option<Extension<ParsedEnvelope>>,
+    body: String,
+) -> Response {
+    tracing::debug!("Analytics service request for camera: {}", camera_id);
+    tracing::trace!("Request body: {}", redact_secrets(&body));
+
+    let camera = match state.camera_manager.get_camera(&camera_id).await {
+        Some(cam) => cam,
+        None => {
+            return (StatusCode::NOT_FOUND, "Camera not found").into_response();
+        }
+    };
+
+    let envelope = match request_envelope(parsed, &body).result() {
+        Ok(env) => env.clone(),
+        Err(e) => return invalid_soap(e),
+    };
+    let camera = camera.with_caller_token(envelope.security());
+
+    if let Some(rejection) = reject_batched(&envelope) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_not_understood(&state, &envelope) {
+        return rejection;
+    }
+
+    let action = request_action(&headers, &envelope);
+    if let Some(rejection) = reject_contradicted_action(&envelope, &action) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_prohibited(&camera, &envelope, &action) {
+        return rejection;
+    }
+    tracing::debug!("Analytics action: {}", action);
+
+    if camera.config().is_raw_action(&action) {
+        return forward_raw(&camera, "/onvif/analytics_service", &envelope).await;
+    }
+
+    let soap_body = &envelope.body._raw_xml;
+
+    if analytics::AnalyticsService::is_write_action(&action) {
+        if !camera.config().enable_analytics_writes {
+            tracing::warn!("Rejected {} for camera {}: analytics writes are disabled", action, camera_id);
+            return soap_fault(
+                FaultCode::Sender,
+                "ActionNotSupported",
+                &format!("{} is disabled for this camera (enable_analytics_writes is off)", action),
+            );
+        }
+
+        let unsupported = match camera.config().model.as_str() {
+            "reolink" => analytics::AnalyticsService::unsupported_rule_type(soap_body),
+            _ => None,
+        };
+        if let Some(rule_type) = unsupported {
+            tracing::warn!("Rejected {} for camera {}: unsupported rule type {}", action, camera_id, rule_type);
+            return soap_fault(
+                FaultCode::Sender,
+                "InvalidRule",
+                &format!("Rule type {} cannot be represented by this camera", rule_type),
+            );
+        }
+    } else if !analytics::AnalyticsService::is_read_action(&action) {
+        tracing::warn!("Unknown analytics action: {}", action);
+        return unknown_action(&camera, &action);
+    }
+
+    let response = analytics::AnalyticsService::forward(&camera, soap_body).await;
+
+    match response {
+        Ok(xml) => {
+            tracing::trace!("Raw analytics response: {}", xml);
+            let quirks = camera.config().quirks.clone();
+            let translated = match ResponseTranslator::translate(&xml, &camera.config().model, &quirks, camera.rules()) {
+                Ok(t) => t,
+                Err(e) => {
+                    tracing::error!("Translation failed: {}", e);
+                    xml
+                }
+            };
+
+            soap_response(translated)
+        }
+        Err(e) => {
+            tracing::error!("Analytics service error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+        }
+    }
+}
+
+async fn handle_ptz_service(
+    State(state): State<AppState>,
+    Path(camera_id): Path<String>,
+    headers: HeaderMap,
+    parsed: Option<Extension<ParsedEnvelope>>,
+    body: String,
+) -> Response {
+    tracing::debug!("PTZ service request for camera: {}", camera_id);
+    tracing::trace!("Request body: {}", redact_secrets(&body));
+
+    let camera = match state.camera_manager.get_camera(&camera_id).await {
+        Some(cam) => cam,
+        None => {
+            return (StatusCode::NOT_FOUND, "Camera not found").into_response();
+        }
+    };
+
+    let envelope = match request_envelope(parsed, &body).result() {
+        Ok(env) => env.clone(),
+        Err(e) => return invalid_soap(e),
+    };
+    let camera = camera.with_caller_token(envelope.security());
+
+    if let Some(rejection) = reject_batched(&envelope) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_not_understood(&state, &envelope) {
+        return rejection;
+    }
+
+    let action = request_action(&headers, &envelope);
+    if let Some(rejection) = reject_contradicted_action(&envelope, &action) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_prohibited(&camera, &envelope, &action) {
+        return rejection;
+    }
+    tracing::debug!("PTZ action: {}", action);
+
+    if camera.config().is_raw_action(&action) {
+        return forward_raw(&camera, "/onvif/ptz_service", &envelope).await;
+    }
+
+    let response = match action.as_str() {
+        "GetServiceCapabilities" => ptz::PtzService::get_service_capabilities(&camera).await,
+        "GetConfigurations" => ptz::PtzService::get_configurations(&camera).await,
+        "GetPresets" | "ContinuousMove" | "Stop" | "GotoPreset" => {
+            let Some(profile_token) = extract_value(&body, "ProfileToken") else {
+                return soap_fault(FaultCode::Sender, "InvalidArgs", &format!("{} requires a ProfileToken", action));
+            };
+            match action.as_str() {
+                "GetPresets" => ptz::PtzService::get_presets(&camera, &profile_token).await,
+                "ContinuousMove" => {
+                    let pan_tilt = extract_attribute(&body, "PanTilt", "x")
+                        .zip(extract_attribute(&body, "PanTilt", "y"))
+                        .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)));
+                    let zoom = extract_attribute(&body, "Zoom", "x").and_then(|x| x.parse().ok());
+                    let timeout = extract_value(&body, "Timeout");
+                    let velocity = ptz::Velocity { pan_tilt, zoom };
+                    ptz::PtzService::continuous_move(&camera, &profile_token, velocity, timeout.as_deref()).await
+                }
+                "Stop" => {
+                    // Either flag left out means stop that movement too
+                    let stops = |tag| extract_value(&body, tag).is_none_or(|value| value == "true" || value == "1");
+                    ptz::PtzService::stop(&camera, &profile_token, stops("PanTilt"), stops("Zoom")).await
+                }
+                _ => {
+                    let Some(preset_token) = extract_value(&body, "PresetToken") else {
+                        return soap_fault(FaultCode::Sender, "InvalidArgs", "GotoPreset requires a PresetToken");
+                    };
+                    ptz::PtzService::goto_preset(&camera, &profile_token, &preset_token).await
+                }
+            }
+        }
+        _ => ptz::PtzService::forward(&camera, &envelope.body._raw_xml).await,
+    };
+
+    match response {
+        Ok(xml) => {
+            tracing::trace!("Raw PTZ response: {}", xml);
+            let quirks = camera.config().quirks.clone();
+            let translated = match ResponseTranslator::translate(&xml, &camera.config().model, &quirks, camera.rules()) {
+                Ok(t) => t,
+                Err(e) => {
+                    tracing::error!("Translation failed: {}", e);
+                    xml
+                }
+            };
+
+            soap_response(translated)
+        }
+        Err(e) => {
+            tracing::error!("PTZ service error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+        }
+    }
+}
+
+async fn handle_imaging_service(
+    State(state): State<AppState>,
+    Path(camera_id): Path<String>,
+    headers: HeaderMap,
+    parsed: Option<Extension<ParsedEnvelope>>,
+    body: String,
+) -> Response {
+    tracing::debug!("Imaging service request for camera: {}", camera_id);
+    tracing::trace!("Request body: {}", redact_secrets(&body));
+
+    let camera = match state.camera_manager.get_camera(&camera_id).await {
+        Some(cam) => cam,
+        None => {
+            return (StatusCode::NOT_FOUND, "Camera not found").into_response();
+        }
+    };
+
+    let envelope = match request_envelope(parsed, &body).result() {
+        Ok(env) => env.clone(),
+        Err(e) => return invalid_soap(e),
+    };
+    let camera = camera.with_caller_token(envelope.security());
+
+    if let Some(rejection) = reject_batched(&envelope) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_not_understood(&state, &envelope) {
+        return rejection;
+    }
+
+    let action = request_action(&headers, &envelope);
+    if let Some(rejection) = reject_contradicted_action(&envelope, &action) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_prohibited(&camera, &envelope, &action) {
+        return rejection;
+    }
+    tracing::debug!("Imaging action: {}", action);
+
+    if camera.config().is_raw_action(&action) {
+        return forward_raw(&camera, "/onvif/imaging_service", &envelope).await;
+    }
+
+    // The CGI only covers the settings themselves (no focus, presets or status)
+    if camera.config().imaging_backend == ImagingBackend::Cgi && !imaging::ImagingService::is_cgi_action(&action) {
+        tracing::warn!("Imaging action {} is not available with imaging_backend: cgi", action);
+        return unknown_action(&camera, &action);
+    }
+
+    match imaging::ImagingService::handle(&camera, &action, &envelope.body._raw_xml).await {
+        Ok(xml) => {
+            tracing::trace!("Raw imaging response: {}", xml);
+            let quirks = camera.config().quirks.clone();
+            let translated = match ResponseTranslator::translate(&xml, &camera.config().model, &quirks, camera.rules()) {
+                Ok(t) => t,
+                Err(e) => {
+                    tracing::error!("Translation failed: {}", e);
+                    xml
+                }
+            };
+
+            soap_response(translated)
+        }
+        Err(e) => {
+            tracing::error!("Imaging service error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+        }
+    }
+}
+
 async fn handle_subscription(
     State(state): State<AppState>,
     Path((camera_id, sub_id)): Path<(String, String)>,
+    headers: HeaderMap,
+    parsed: Option<Extension<ParsedEnvelope>>,
     body: String,
 ) -> Response {
     tracing::info!("Subscription request for camera: {}, subscription: {}", camera_id, sub_id);
@@ -339,31 +854,58 @@ async fn handle_subscription(
         }
     };
 
-    let envelope = match SoapEnvelope::parse(&body) {
-        Ok(env) => env,
-        Err(e) => {
-            return (StatusCode::BAD_REQUEST, format!("Invalid SOAP: {}", e)).into_response();
-        }
+    let envelope = match request_envelope(parsed, &body).result() {
+        Ok(env) => env.clone(),
+        Err(e) => return invalid_soap(e),
     };
+    let camera = camera.with_caller_token(envelope.security());
 
-    let action = envelope.extract_action();
+    if let Some(rejection) = reject_batched(&envelope) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_not_understood(&state, &envelope) {
+        return rejection;
+    }
 
-    let response = match action.as_str() {
+    let action = request_action(&headers, &envelope);
+    if let Some(rejection) = reject_contradicted_action(&envelope, &action) {
+        return rejection;
+    }
+    if let Some(rejection) = reject_prohibited(&camera, &envelope, &action) {
+        return rejection;
+    }
+
+    subscription_action(&state, &camera, &sub_id, &subscription, &action, &body).await
+}
+
+// Helper function to answer PullMessages, Renew and Unsubscribe for a subscription, whether it
+// was addressed by the subscription endpoint's path or by the request's header
+async fn subscription_action(
+    state: &AppState,
+    camera: &CameraClient,
+    sub_id: &str,
+    subscription: &events::Subscription,
+    action: &str,
+    body: &str,
+) -> Response {
+    let response = match action {
         "PullMessages" => {
-            let timeout = extract_value(&body, "Timeout").unwrap_or("PT1S".to_string());
-            let message_limit = extract_value(&body, "MessageLimit")
+            let timeout = extract_value(body, "Timeout").unwrap_or("PT1S".to_string());
+            let message_limit = extract_value(body, "MessageLimit")
                 .and_then(|v| v.parse().ok())
                 .unwrap_or(10);
-            state.events_service.pull_messages(&sub_id, &timeout, message_limit).await
+            state.events_service.pull_messages(sub_id, &timeout, message_limit).await
         }
         "Renew" => {
-            state.events_service.renew_subscription(&camera, &subscription.camera_subscription_url, &sub_id).await
+            let requested = extract_value(body, "TerminationTime");
+            state.events_service.renew_subscription(camera, subscription, requested.as_deref()).await
         }
         "Unsubscribe" => {
-            state.events_service.unsubscribe(&camera, &subscription.camera_subscription_url, &sub_id).await
+            state.events_service.unsubscribe(camera, subscription).await
         }
         _ => {
-            return (StatusCode::NOT_IMPLEMENTED, format!("Action not implemented: {}", action)).into_response();
+            tracing::warn!("Unknown subscription action: {}", action);
+            return unknown_action(camera, action);
         }
     };
 
@@ -376,59 +918,1395 @@ async fn handle_subscription(
     }
 }
 
-async fn health_check() -> impl IntoResponse {
-    (StatusCode::OK, "OK")
-}
+// Helper function to refuse profile requests for the camera's hidden_profiles, as if they did not exist.
+// When the profile's name can't be looked up the request is refused too, rather than let through
+async fn reject_hidden_profile(camera: &CameraClient, action: &str, body: &str) -> Option<Response> {
+    if !matches!(action, "GetProfile" | "GetStreamUri" | "GetSnapshotUri") {
+        return None;
+    }
+    let profile_token = extract_value(body, "ProfileToken")?;
+    match media::MediaService::is_profile_hidden(camera, &profile_token).await {
+        Ok(false) => return None,
+        Ok(true) => {}
+        Err(e) => {
+            tracing::warn!("Could not check profile {} on camera {} against hidden_profiles: {:#}", profile_token, camera.camera_id(), e);
+            return Some(soap_fault(FaultCode::Receiver, "ActionFailed", "The profile could not be checked"));
+        }
+    }
 
-// Helper function to fix localhost URLs in responses
-fn fix_localhost_urls(xml: &str, camera: &crate::camera::CameraClient) -> String {
-    let mut fixed = xml.to_string();
+    tracing::debug!("Refused {} for hidden profile {} on camera {}", action, profile_token, camera.camera_id());
+    Some(soap_fault(FaultCode::Sender, "NoProfile", "The requested profile does not exist"))
+}
 
-    // Extract the camera's actual IP address from the config
-    let camera_address = &camera.config().address;
-    let camera_ip = if let Some(host) = camera_address.split(':').next() {
-        host
-    } else {
-        camera_address.as_str()
-    };
+// Helper function to fault requests whose SOAPAction/Content-Type action names another operation
+// than the Body; the Body is what gets forwarded, so the header must not decide how it is treated
+fn reject_contradicted_action(envelope: &SoapEnvelope, action: &str) -> Option<Response> {
+    if !envelope.contradicts_action(action) {
+        return None;
+    }
 
-    // Replace localhost references in RTSP URLs with actual camera IP
-    fixed = fixed.replace("rtsp://127.0.0.1:", &format!("rtsp://{}:", camera_ip));
-    fixed = fixed.replace("rtsp://localhost:", &format!("rtsp://{}:", camera_ip));
-    fixed = fixed.replace("rtsp://0.0.0.0:", &format!("rtsp://{}:", camera_ip));
+    tracing::warn!("Rejecting request announced as {} with body {:?}", action, envelope.body.operations);
+    Some(soap_fault(
+        FaultCode::Sender,
+        "InvalidArgs",
+        &format!("The request's action {} does not match its body", action),
+    ))
+}
 
-    // Also fix HTTP URLs for snapshot URIs if they have localhost
-    fixed = fixed.replace("http://127.0.0.1:", &format!("http://{}:", camera_ip));
-    fixed = fixed.replace("http://localhost:", &format!("http://{}:", camera_ip));
-    fixed = fixed.replace("http://0.0.0.0:", &format!("http://{}:", camera_ip));
+// Helper function to refuse requests blocked by the camera's allowed_actions/denied_actions. Every
+// operation in the Body is checked as well as the announced action, since the Body is what gets forwarded
+fn reject_prohibited(camera: &CameraClient, envelope: &SoapEnvelope, action: &str) -> Option<Response> {
+    let blocked = std::iter::once(action)
+        .chain(envelope.body.operations.iter().map(String::as_str))
+        .find(|action| !action.is_empty() && !camera.config().is_action_allowed(action))?;
 
-    fixed
+    tracing::warn!("Blocked {} for camera {} (allowed_actions/denied_actions)", blocked, camera.camera_id());
+    Some(soap_fault(
+        FaultCode::Sender,
+        "OperationProhibited",
+        &format!("{} is not permitted on this camera", blocked),
+    ))
 }
 
-// Helper function to extract values from XML (simplified)
-fn extract_value(xml: &str, tag: &str) -> Option<String> {
-    let start_tag = format!("<{}>", tag);
-    let end_tag = format!("</{}>", tag);
+// Helper function to take the envelope parsed by the middleware, parsing the body only when no
+// layer did (or one rewrote it)
+fn request_envelope(parsed: Option<Extension<ParsedEnvelope>>, body: &str) -> ParsedEnvelope {
+    parsed.map_or_else(|| ParsedEnvelope::parse(body.as_bytes()), |Extension(parsed)| parsed)
+}
 
-    if let Some(start) = xml.find(&start_tag) {
-        let content_start = start + start_tag.len();
-        if let Some(end) = xml[content_start..].find(&end_tag) {
-            return Some(xml[content_start..content_start + end].trim().to_string());
-        }
+// Helper function to answer a request that didn't parse; documents over the parser's limits
+// get a SOAP fault, other parse errors a plain 400
+fn invalid_soap(e: &anyhow::Error) -> Response {
+    if let Some(limit) = e.downcast_ref::<soap::LimitExceeded>() {
+        tracing::warn!("Rejecting SOAP request: {}", limit);
+        return soap_fault(FaultCode::Sender, "InvalidArgs", &limit.to_string());
     }
+    tracing::error!("Failed to parse SOAP request: {}", e);
+    (StatusCode::BAD_REQUEST, format!("Invalid SOAP: {}", e)).into_response()
+}
+
+// Helper function to refuse bodies carrying more than one operation
+fn reject_batched(envelope: &SoapEnvelope) -> Option<Response> {
+    if !envelope.is_batched() {
+        return None;
+    }
+
+    tracing::warn!("Rejecting SOAP body with multiple operations: {:?}", envelope.body.operations);
+    Some(soap_fault(
+        FaultCode::Sender,
+        "InvalidArgs",
+        &format!(
+            "Request body must contain exactly one operation, found {}",
+            envelope.body.operations.join(", ")
+        ),
+    ))
+}
+
+// Helper function to fault mustUnderstand headers the proxy doesn't process when `strict_soap` is set;
+// otherwise they are ignored, as before
+fn reject_not_understood(state: &AppState, envelope: &SoapEnvelope) -> Option<Response> {
+    let not_understood = envelope.not_understood();
+    if not_understood.is_empty() {
+        return None;
+    }
+
+    if !state.proxy_config.strict_soap {
+        tracing::debug!("Ignoring mustUnderstand header(s) {:?} (proxy.strict_soap is off)", not_understood);
+        return None;
+    }
+
+    tracing::warn!("Rejecting request with mustUnderstand header(s) {:?}", not_understood);
+    Some(soap_fault(
+        FaultCode::MustUnderstand,
+        "",
+        &format!("Header not understood: {}", not_understood.join(", ")),
+    ))
+}
+
+// Helper function to pick the action from the SOAPAction/Content-Type headers, falling back to the body
+pub(crate) fn request_action(headers: &HeaderMap, envelope: &SoapEnvelope) -> String {
+    let header_str = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    envelope.resolve_action(header_str("soapaction"), header_str(header::CONTENT_TYPE.as_str()))
+}
+
+// Forward the request body and return the camera's response verbatim, for actions
+// listed in the camera's raw_actions (bypasses all translation and URL fixing).
+// The client's WS-Addressing headers go along, with `To` pointed at the camera.
+async fn forward_raw(camera: &CameraClient, service_path: &str, envelope: &SoapEnvelope) -> Response {
+    tracing::debug!("Forwarding {} to camera {} without translation", envelope.body.action, camera.camera_id());
+
+    let headers = envelope.addressing_headers(&camera.service_url(service_path));
+    match camera.send_soap_request_verbatim(service_path, &envelope.body._raw_xml, &headers).await {
+        Ok(xml) => soap_response(xml),
+        Err(e) => {
+            tracing::error!("Raw forward error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+        }
+    }
+}
+
+async fn health_check() -> impl IntoResponse {
+    (StatusCode::OK, "OK")
+}
+
+#[derive(Serialize)]
+struct ReadinessReport {
+    ready: bool,
+    cameras: usize,
+    cameras_attempted: usize,
+    cameras_online: usize,
+}
+
+/// 503 until startup has finished trying every configured camera
+async fn health_ready(State(state): State<AppState>) -> impl IntoResponse {
+    let ready = state.startup_complete.load(Ordering::SeqCst);
+    let cameras = state.camera_manager.cameras().await;
+
+    let mut report = ReadinessReport {
+        ready,
+        cameras: cameras.len(),
+        cameras_attempted: 0,
+        cameras_online: 0,
+    };
+    for camera in &cameras {
+        let online = camera.health().await.online;
+        report.cameras_attempted += usize::from(online.is_some());
+        report.cameras_online += usize::from(online == Some(true));
+    }
+
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(report))
+}
+
+#[derive(Serialize)]
+pub(crate) struct CameraHealthReport {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) online: Option<bool>,
+    pub(crate) last_success: Option<String>,
+    pub(crate) last_failure: Option<String>,
+    pub(crate) last_error: Option<String>,
+    pub(crate) consecutive_failures: u32,
+    pub(crate) deadline_exceeded: u64,
+    pub(crate) last_motion: Option<String>,
+    pub(crate) active_subscriptions: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) queue: Option<QueueStats>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) resolved_addrs: Vec<String>,
+}
+
+impl CameraHealthReport {
+    async fn for_camera(camera: &CameraClient, events_service: &events::EventsService) -> Self {
+        let health = camera.health().await;
+        Self {
+            id: camera.camera_id().to_string(),
+            name: camera.config().name.clone(),
+            online: health.online,
+            last_success: health.last_success.map(|t| t.to_rfc3339()),
+            last_failure: health.last_failure.map(|t| t.to_rfc3339()),
+            last_error: health.last_error,
+            consecutive_failures: health.consecutive_failures,
+            deadline_exceeded: health.deadline_exceeded,
+            last_motion: health.last_motion.map(|t| t.to_rfc3339()),
+            active_subscriptions: events_service.active_subscriptions(camera.camera_id()).await,
+            queue: camera.queue_stats(),
+            resolved_addrs: health.resolved_addrs,
+        }
+    }
+
+    /// Reports for every camera, as served by `/health/detailed`
+    pub(crate) async fn all(state: &AppState) -> Vec<Self> {
+        let mut reports = Vec::new();
+        for camera in state.camera_manager.cameras().await {
+            reports.push(Self::for_camera(&camera, &state.events_service).await);
+        }
+        reports
+    }
+}
+
+async fn health_detailed(State(state): State<AppState>) -> impl IntoResponse {
+    Json(CameraHealthReport::all(&state).await)
+}
+
+/// Check the `Authorization: Bearer` header against `proxy.admin_token`
+fn authorize_admin(state: &AppState, headers: &HeaderMap) -> Result<(), (StatusCode, &'static str)> {
+    let Some(expected) = state.proxy_config.admin_token.as_deref() else {
+        return Err((StatusCode::FORBIDDEN, "Admin API disabled"));
+    };
+
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    if !provided.is_some_and(|provided| constant_time_eq(provided.as_bytes(), expected.as_bytes())) {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid admin token"));
+    }
+
+    Ok(())
+}
+
+/// With `proxy_snapshots`, point a GetSnapshotUri response at the proxy's snapshot endpoint
+fn proxy_snapshot_uri(state: &AppState, camera: &CameraClient, profile_token: &str, xml: String) -> String {
+    // A simulated camera has no image to fetch
+    if !camera.config().proxy_snapshots || camera.config().is_simulated() {
+        return xml;
+    }
+    let Ok(mut url) = reqwest::Url::parse(&format!("{}/onvif/{}/snapshot", state.base_url, camera.camera_id())) else {
+        return xml;
+    };
+    url.query_pairs_mut().append_pair("profile", profile_token);
+    media::MediaService::proxied_snapshot_uri(&xml, url.as_str())
+}
+
+#[derive(Deserialize)]
+struct SnapshotQuery {
+    #[serde(default)]
+    profile: Option<String>,
+}
+
+/// The camera's current snapshot, streamed with the camera's status and `Content-Type`
+async fn handle_snapshot(
+    State(state): State<AppState>,
+    Path(camera_id): Path<String>,
+    Query(query): Query<SnapshotQuery>,
+) -> Response {
+    let camera = match state.camera_manager.get_camera(&camera_id).await {
+        Some(cam) => cam,
+        None => return (StatusCode::NOT_FOUND, "Camera not found").into_response(),
+    };
+    if !camera.config().proxy_snapshots {
+        return (StatusCode::NOT_FOUND, "Snapshot proxying is disabled for this camera").into_response();
+    }
+
+    let profile_token = query.profile.unwrap_or_else(|| "000".to_string());
+    match media::MediaService::is_profile_hidden(&camera, &profile_token).await {
+        Ok(false) => {}
+        Ok(true) => return (StatusCode::NOT_FOUND, "Profile not found").into_response(),
+        Err(e) => return (StatusCode::BAD_GATEWAY, format!("Error: {:#}", e)).into_response(),
+    }
+    match media::MediaService::fetch_snapshot(&camera, &profile_token).await {
+        Ok(upstream) => {
+            let status = StatusCode::from_u16(upstream.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+            let content_type = upstream
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("image/jpeg")
+                .to_string();
+            (status, [(header::CONTENT_TYPE, content_type)], axum::body::Body::from_stream(upstream.bytes_stream())).into_response()
+        }
+        Err(e) => {
+            camera.report_error(&format!("snapshot failed: {:#}", e));
+            (StatusCode::BAD_GATEWAY, format!("Error: {:#}", e)).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MotionTrigger {
+    #[serde(default = "default_motion_secs")]
+    seconds: i64,
+}
+
+fn default_motion_secs() -> i64 {
+    30
+}
+
+/// Longest simulated motion the admin API triggers
+const MAX_MOTION_SECS: i64 = 24 * 60 * 60;
+
+async fn trigger_motion(
+    State(state): State<AppState>,
+    Path(camera_id): Path<String>,
+    Query(trigger): Query<MotionTrigger>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(rejection) = authorize_admin(&state, &headers) {
+        return rejection.into_response();
+    }
+
+    let camera = match state.camera_manager.get_camera(&camera_id).await {
+        Some(cam) => cam,
+        None => return (StatusCode::NOT_FOUND, "Camera not found").into_response(),
+    };
+
+    if !camera.config().is_simulated() {
+        return (StatusCode::BAD_REQUEST, "Motion can only be triggered on simulated cameras").into_response();
+    }
+
+    if !(0..=MAX_MOTION_SECS).contains(&trigger.seconds) {
+        return (StatusCode::BAD_REQUEST, format!("seconds must be between 0 and {}", MAX_MOTION_SECS)).into_response();
+    }
+
+    tracing::info!("Triggering simulated motion on camera {} for {}s", camera_id, trigger.seconds);
+    camera.trigger_simulated_motion(chrono::Duration::seconds(trigger.seconds)).await;
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Rebuild a camera's client and probe it again, e.g. after its credentials or firmware changed
+async fn refresh_camera(
+    State(state): State<AppState>,
+    Path(camera_id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(rejection) = authorize_admin(&state, &headers) {
+        return rejection.into_response();
+    }
+
+    let Some(camera) = state.camera_manager.refresh_camera(&camera_id).await else {
+        return (StatusCode::NOT_FOUND, "Camera not found").into_response();
+    };
+    let dropped = state.response_cache.invalidate_camera(&camera_id).await;
+    tracing::debug!("Dropped {} cached responses for camera {}", dropped, camera_id);
+
+    // Re-learns the clock skew and records the camera's health
+    if let Err(e) = device::DeviceService::get_system_date_and_time(&camera).await {
+        tracing::warn!("Camera {} is unreachable after refresh: {:#}", camera_id, e);
+    }
+
+    Json(CameraHealthReport::for_camera(&camera, &state.events_service).await).into_response()
+}
+
+/// Configured cameras, sorted by id, with their secrets redacted
+async fn list_cameras(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(rejection) = authorize_admin(&state, &headers) {
+        return rejection.into_response();
+    }
+
+    let cameras: Vec<CameraConfig> = state.camera_manager.cameras().await.iter().map(|camera| camera.config().redacted()).collect();
+    Json(cameras).into_response()
+}
+
+/// Add a camera from a JSON `CameraConfig`; it is served at `/onvif/{id}/...` right away
+async fn add_camera(State(state): State<AppState>, headers: HeaderMap, body: String) -> Response {
+    if let Err(rejection) = authorize_admin(&state, &headers) {
+        return rejection.into_response();
+    }
+
+    let config: CameraConfig = match serde_json::from_str(&body) {
+        Ok(config) => config,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid camera config: {}", e)).into_response(),
+    };
+    // The same checks as cameras in the config file get at startup
+    let report = config.validate();
+    for warning in &report.warnings {
+        tracing::warn!("Config: {}", warning);
+    }
+    if !report.is_ok() {
+        return (StatusCode::BAD_REQUEST, format!("Invalid camera config: {}", report.errors.join("; "))).into_response();
+    }
+
+    let redacted = config.redacted();
+    if !state.camera_manager.add_camera_if_absent(config).await {
+        return (StatusCode::CONFLICT, "Camera already exists").into_response();
+    }
+    if let Err(e) = save_cameras(&state).await {
+        tracing::error!("Camera {} added but not saved: {:#}", redacted.id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Camera added but not saved: {:#}", e)).into_response();
+    }
+    (StatusCode::CREATED, Json(redacted)).into_response()
+}
+
+async fn remove_camera(
+    State(state): State<AppState>,
+    Path(camera_id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(rejection) = authorize_admin(&state, &headers) {
+        return rejection.into_response();
+    }
+
+    let Some(camera) = state.camera_manager.get_camera(&camera_id).await else {
+        return (StatusCode::NOT_FOUND, "Camera not found").into_response();
+    };
+    if !state.camera_manager.remove_camera(&camera_id).await {
+        return (StatusCode::NOT_FOUND, "Camera not found").into_response();
+    }
+    // Their polling would go on against a camera that is no longer served
+    state.events_service.close_camera_subscriptions(&camera).await;
+    // A camera added later under the same id must not be answered from these
+    state.response_cache.invalidate_camera(&camera_id).await;
+    if let Err(e) = save_cameras(&state).await {
+        tracing::error!("Camera {} removed but not saved: {:#}", camera_id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Camera removed but not saved: {:#}", e)).into_response();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Write the current cameras to `config_path`, if the proxy was started from a file
+async fn save_cameras(state: &AppState) -> anyhow::Result<()> {
+    // Held from the snapshot to the rename, so the last change made is the last one written
+    static SAVING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    let Some(path) = state.config_path.clone() else {
+        return Ok(());
+    };
+    let _saving = SAVING.lock().await;
+    let config = crate::config::AppConfig {
+        proxy: (*state.proxy_config).clone(),
+        cameras: state.camera_manager.cameras().await.iter().map(|camera| camera.config().clone()).collect(),
+    };
+    tokio::task::spawn_blocking(move || config.save_to_file(path.as_path())).await?
+}
+
+#[derive(Deserialize)]
+struct TranslateParams {
+    /// Camera whose model and quirks are used; overridden by `model`/`quirks`
+    camera: Option<String>,
+    model: Option<String>,
+    /// Comma-separated quirk list
+    quirks: Option<String>,
+}
+
+/// Run a posted XML document through the translator and report every step
+async fn debug_translate(
+    State(state): State<AppState>,
+    Query(params): Query<TranslateParams>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    if let Err(rejection) = authorize_admin(&state, &headers) {
+        return rejection.into_response();
+    }
+
+    let (mut model, mut quirks) = ("reolink".to_string(), Vec::new());
+    let mut camera = None;
+    if let Some(camera_id) = &params.camera {
+        match state.camera_manager.get_camera(camera_id).await {
+            Some(found) => {
+                model = found.config().model.clone();
+                quirks = found.config().quirks.clone();
+                camera = Some(found);
+            }
+            None => return (StatusCode::NOT_FOUND, "Camera not found").into_response(),
+        }
+    }
+    if let Some(m) = params.model {
+        model = m;
+    }
+    if let Some(q) = params.quirks {
+        quirks = q.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+    }
+
+    // The camera's rules run as they do on its responses; without a camera there are none
+    let no_rules = RuleEngine::new();
+    let rules = camera.as_ref().map_or(&no_rules, |camera| camera.rules());
+    Json(ResponseTranslator::translate_with_report(&body, &model, &quirks, rules)).into_response()
+}
+
+// Helper function to fix localhost URLs in responses
+fn fix_localhost_urls(xml: &str, camera: &CameraClient) -> String {
+    let mut fixed = xml.to_string();
+
+    // Extract the camera's actual IP address from the config
+    let camera_ip = camera.config().url_host();
+
+    // Replace localhost references in RTSP URLs with actual camera IP
+    fixed = fixed.replace("rtsp://127.0.0.1:", &format!("rtsp://{}:", camera_ip));
+    fixed = fixed.replace("rtsp://localhost:", &format!("rtsp://{}:", camera_ip));
+    fixed = fixed.replace("rtsp://0.0.0.0:", &format!("rtsp://{}:", camera_ip));
+
+    // Also fix HTTP URLs for snapshot URIs if they have localhost
+    fixed = fixed.replace("http://127.0.0.1:", &format!("http://{}:", camera_ip));
+    fixed = fixed.replace("http://localhost:", &format!("http://{}:", camera_ip));
+    fixed = fixed.replace("http://0.0.0.0:", &format!("http://{}:", camera_ip));
+
+    fixed
+}
+
+// Helper function to read a request value by local name, whatever its prefix: the text of the
+// first `<Tag>` element, else a `Tag="..."` attribute on any element (as in
+// `<tr2:GetStreamUri ProfileToken="000">`)
+fn extract_value(xml: &str, tag: &str) -> Option<String> {
+    let mut reader = Reader::from_str(xml);
+    let mut from_attribute = None;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if e.local_name().as_ref() == tag.as_bytes() => {
+                return element_text(&mut reader).or(from_attribute);
+            }
+            Ok(Event::Empty(e)) if e.local_name().as_ref() == tag.as_bytes() => return Some(String::new()),
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) if from_attribute.is_none() => {
+                from_attribute = attribute_value(&e, tag);
+            }
+            Ok(Event::Eof) | Err(_) => return from_attribute,
+            _ => {}
+        }
+    }
+}
+
+// Helper function to read an attribute of the first element with the given local name, whatever
+// its prefix, e.g. `x` of `<tt:PanTilt x="0.5" y="0"/>`
+fn extract_attribute(xml: &str, element: &str, attribute: &str) -> Option<String> {
+    let mut reader = Reader::from_str(xml);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e))
+                if e.local_name().as_ref() == element.as_bytes() =>
+            {
+                return attribute_value(&e, attribute);
+            }
+            Ok(Event::Eof) | Err(_) => return None,
+            _ => {}
+        }
+    }
+}
+
+// Helper function to read an element's attribute by local name, trimmed
+fn attribute_value(element: &quick_xml::events::BytesStart, name: &str) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|a| a.key.local_name().as_ref() == name.as_bytes())
+        .and_then(|a| a.unescape_value().ok())
+        .map(|value| value.trim().to_string())
+}
+
+// Helper function to collect the text up to the end of the element just started, trimmed;
+// None if the document ends first
+fn element_text(reader: &mut Reader<&[u8]>) -> Option<String> {
+    let mut text = String::new();
+    let mut depth = 0;
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(_) => depth += 1,
+            Event::End(_) if depth == 0 => return Some(text.trim().to_string()),
+            Event::End(_) => depth -= 1,
+            Event::Text(e) => text.push_str(&e.unescape().ok()?),
+            Event::CData(e) => text.push_str(&String::from_utf8_lossy(&e)),
+            Event::Eof => return None,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{CameraConfig, CameraManager};
+    use crate::server::cache::ResponseCache;
+    use axum::body::{to_bytes, Body};
+    use axum::extract::Request;
+    use tower::ServiceExt;
+
+    /// Serve `mock` as a camera on a local port; its address
+    async fn serve_mock(mock: Router) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await.unwrap() });
+        address
+    }
+
+    /// The proxy's state with `cameras` added, served at `http://proxy:8000` with a minimal config
+    async fn app_state(cameras: Vec<CameraConfig>) -> AppState {
+        let camera_manager = CameraManager::new();
+        for camera in cameras {
+            camera_manager.add_camera(camera).await;
+        }
+        let proxy_config: ProxyConfig = serde_yaml::from_str("listen_address: 127.0.0.1:8000\nbase_path: /onvif").unwrap();
+        AppState {
+            camera_manager,
+            events_service: events::EventsService::new(),
+            base_url: "http://proxy:8000".to_string(),
+            response_cache: ResponseCache::new(proxy_config.cache.clone()),
+            startup_complete: Default::default(),
+            config_path: None,
+            proxy_config: Arc::new(proxy_config),
+        }
+    }
+
+    #[test]
+    fn test_extract_value() {
+        // Element text, under any prefix and padded with whitespace
+        let text = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><tr2:GetStreamUri xmlns:tr2="http://www.onvif.org/ver20/media/wsdl"><tr2:Protocol>RTSP</tr2:Protocol><media2:ProfileToken xmlns:media2="http://www.onvif.org/ver20/media/wsdl">
+            Profile_1
+        </media2:ProfileToken></tr2:GetStreamUri></s:Body></s:Envelope>"#;
+        assert_eq!(extract_value(text, "ProfileToken").as_deref(), Some("Profile_1"));
+        assert_eq!(extract_value(text, "Protocol").as_deref(), Some("RTSP"));
 
-    // Try with namespace prefix
-    for prefix in &["trt:", "tev:", "tds:", "tt:"] {
-        let start_tag = format!("<{}{}>", prefix, tag);
-        let end_tag = format!("</{}{}>", prefix, tag);
+        // A token held in an attribute, as iSpy sends it
+        let attribute = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><tr2:GetStreamUri xmlns:tr2="http://www.onvif.org/ver20/media/wsdl" ProfileToken=" 001 "><tr2:Protocol>RTSP</tr2:Protocol></tr2:GetStreamUri></s:Body></s:Envelope>"#;
+        assert_eq!(extract_value(attribute, "ProfileToken").as_deref(), Some("001"));
 
-        if let Some(start) = xml.find(&start_tag) {
-            let content_start = start + start_tag.len();
-            if let Some(end) = xml[content_start..].find(&end_tag) {
-                return Some(xml[content_start..content_start + end].trim().to_string());
+        // Missing, or present only as a longer name
+        let missing = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><trt:GetStreamUri xmlns:trt="http://www.onvif.org/ver10/media/wsdl"><trt:VideoSourceProfileToken>002</trt:VideoSourceProfileToken><trt:Empty/></trt:GetStreamUri></s:Body></s:Envelope>"#;
+        assert_eq!(extract_value(missing, "ProfileToken"), None);
+        assert_eq!(extract_value(missing, "Empty").as_deref(), Some(""));
+        assert_eq!(extract_value("<a>&lt;escaped&gt;</a>", "a").as_deref(), Some("<escaped>"));
+    }
+
+    /// Media2 GetStreamUri sent to the ver10 media endpoint, detected from the body namespace
+    /// and from the SOAPAction header, is forwarded to the camera's Media2 service
+    #[tokio::test]
+    async fn test_ver20_get_stream_uri_on_media_endpoint() {
+        // Mock camera recording which service each request was sent to
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let mock = Router::new().fallback(move |request: Request| {
+            let recorded = recorded.clone();
+            async move {
+                recorded.lock().unwrap().push(request.uri().path().to_string());
+                soap::build_envelope(
+                    &[("tr2", "http://www.onvif.org/ver20/media/wsdl")],
+                    "<tr2:GetStreamUriResponse><tr2:Uri>rtsp://192.168.1.10:554/h264Preview_01_main</tr2:Uri></tr2:GetStreamUriResponse>",
+                )
             }
+        });
+        let address = serve_mock(mock).await;
+
+        let camera = CameraConfig {
+            id: "cam1".to_string(),
+            address: address.to_string(),
+            username: "admin".to_string(),
+            password: "secret".to_string(),
+            ..Default::default()
+        };
+        let app = create_router(app_state(vec![camera]).await);
+
+        let by_body = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><tr2:GetStreamUri xmlns:tr2="http://www.onvif.org/ver20/media/wsdl"><tr2:Protocol>RTSP</tr2:Protocol><tr2:ProfileToken>000</tr2:ProfileToken></tr2:GetStreamUri></s:Body></s:Envelope>"#;
+        // No namespace in the body, only the ver20 SOAPAction
+        let by_header = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><GetStreamUri><Protocol>RTSP</Protocol><ProfileToken>000</ProfileToken></GetStreamUri></s:Body></s:Envelope>"#;
+
+        for (body, soap_action) in [(by_body, None), (by_header, Some("http://www.onvif.org/ver20/media/wsdl/GetStreamUri"))] {
+            let mut request = Request::post("/onvif/cam1/media_service");
+            if let Some(soap_action) = soap_action {
+                request = request.header("SOAPAction", soap_action);
+            }
+            let response = app.clone().oneshot(request.body(Body::from(body)).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let xml = String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+            let envelope = SoapEnvelope::parse(&xml).unwrap();
+            assert_eq!(envelope.body.action, "GetStreamUriResponse");
+            assert!(envelope.body._content.contains("rtsp://192.168.1.10:554/h264Preview_01_main"));
         }
+
+        assert_eq!(*requests.lock().unwrap(), vec!["/onvif/Media2", "/onvif/Media2"]);
     }
 
-    None
+    /// ContinuousMove is rebuilt from the request's profile, velocity and timeout before it goes to
+    /// the camera's PTZ service
+    #[tokio::test]
+    async fn test_ptz_continuous_move() {
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let mock = Router::new().fallback(move |request: Request| {
+            let recorded = recorded.clone();
+            async move {
+                let path = request.uri().path().to_string();
+                let body = String::from_utf8(to_bytes(request.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+                recorded.lock().unwrap().push((path, body));
+                soap::build_envelope(&[("tptz", "http://www.onvif.org/ver20/ptz/wsdl")], "<tptz:ContinuousMoveResponse/>")
+            }
+        });
+        let address = serve_mock(mock).await;
+
+        let camera = CameraConfig {
+            id: "cam1".to_string(),
+            address: address.to_string(),
+            ..Default::default()
+        };
+        let app = create_router(app_state(vec![camera]).await);
+
+        let envelope = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><ContinuousMove xmlns="http://www.onvif.org/ver20/ptz/wsdl"><ProfileToken>000</ProfileToken><Velocity><PanTilt xmlns="http://www.onvif.org/ver10/schema" x="-0.5" y="0.25" space="http://www.onvif.org/ver10/tptz/PanTiltSpaces/VelocityGenericSpace"/></Velocity><Timeout>PT1S</Timeout></ContinuousMove></s:Body></s:Envelope>"#;
+        let response = app.oneshot(Request::post("/onvif/cam1/ptz_service").body(Body::from(envelope)).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, "/onvif/ptz_service");
+        assert!(
+            requests[0].1.contains(r#"<tptz:ContinuousMove xmlns:tptz="http://www.onvif.org/ver20/ptz/wsdl" xmlns:tt="http://www.onvif.org/ver10/schema"><tptz:ProfileToken>000</tptz:ProfileToken><tptz:Velocity><tt:PanTilt x="-0.5" y="0.25"/></tptz:Velocity><tptz:Timeout>PT1S</tptz:Timeout></tptz:ContinuousMove>"#),
+            "{}",
+            requests[0].1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_system_date_and_time() {
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let mock = Router::new().fallback(move |request: Request| {
+            let recorded = recorded.clone();
+            async move {
+                let body = String::from_utf8(to_bytes(request.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+                recorded.lock().unwrap().push(body);
+                soap::build_envelope(&[("tds", "http://www.onvif.org/ver10/device/wsdl")], "<tds:SetSystemDateAndTimeResponse/>")
+            }
+        });
+        let address = serve_mock(mock).await;
+
+        let camera = CameraConfig {
+            id: "cam1".to_string(),
+            address: address.to_string(),
+            ..Default::default()
+        };
+        let app = create_router(app_state(vec![camera]).await);
+        let set = |settings: &str| {
+            let envelope = format!(
+                r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:tds="http://www.onvif.org/ver10/device/wsdl" xmlns:tt="http://www.onvif.org/ver10/schema"><s:Body><tds:SetSystemDateAndTime>{}</tds:SetSystemDateAndTime></s:Body></s:Envelope>"#,
+                settings
+            );
+            let request = Request::post("/onvif/cam1/device_service").body(Body::from(envelope)).unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                (status, String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap())
+            }
+        };
+
+        let (status, body) = set("<tds:DateTimeType>Manual</tds:DateTimeType><tds:DaylightSavings>true</tds:DaylightSavings><tds:TimeZone><tt:TZ>CET-1CEST,M3.5.0,M10.5.0/3</tt:TZ></tds:TimeZone><tds:UTCDateTime><tt:Time><tt:Hour>13</tt:Hour><tt:Minute>4</tt:Minute><tt:Second>5</tt:Second></tt:Time><tt:Date><tt:Year>2026</tt:Year><tt:Month>3</tt:Month><tt:Day>9</tt:Day></tt:Date></tds:UTCDateTime>").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("SetSystemDateAndTimeResponse"));
+        let (status, _) = set("<tds:DateTimeType>NTP</tds:DateTimeType><tds:DaylightSavings>false</tds:DaylightSavings>").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let forwarded = requests.lock().unwrap().clone();
+        assert!(forwarded[0].contains("<tds:DateTimeType>Manual</tds:DateTimeType><tds:DaylightSavings>true</tds:DaylightSavings><tds:TimeZone><tt:TZ>CET-1CEST,M3.5.0,M10.5.0/3</tt:TZ></tds:TimeZone><tds:UTCDateTime><tt:Time><tt:Hour>13</tt:Hour><tt:Minute>4</tt:Minute><tt:Second>5</tt:Second></tt:Time><tt:Date><tt:Year>2026</tt:Year><tt:Month>3</tt:Month><tt:Day>9</tt:Day></tt:Date></tds:UTCDateTime></tds:SetSystemDateAndTime>"), "{}", forwarded[0]);
+        assert!(forwarded[1].contains("<tds:DateTimeType>NTP</tds:DateTimeType><tds:DaylightSavings>false</tds:DaylightSavings></tds:SetSystemDateAndTime>"), "{}", forwarded[1]);
+
+        // Manual without a valid time is refused before reaching the camera
+        let (status, body) = set("<tds:DateTimeType>Manual</tds:DateTimeType><tds:DaylightSavings>false</tds:DaylightSavings>").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("InvalidArgs"));
+        let (_, body) = set("<tds:DateTimeType>Manual</tds:DateTimeType><tds:UTCDateTime><tt:Time><tt:Hour>25</tt:Hour><tt:Minute>0</tt:Minute><tt:Second>0</tt:Second></tt:Time><tt:Date><tt:Year>2026</tt:Year><tt:Month>2</tt:Month><tt:Day>30</tt:Day></tt:Date></tds:UTCDateTime>").await;
+        assert!(body.contains("InvalidDateTime"));
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    /// Media2 requests reach the camera with their prefixes and namespace declarations intact
+    #[tokio::test]
+    async fn test_media2_forwarded_verbatim() {
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let mock = Router::new().fallback(move |request: Request| {
+            let recorded = recorded.clone();
+            async move {
+                let body = String::from_utf8(to_bytes(request.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+                recorded.lock().unwrap().push(body);
+                soap::build_envelope(&[("tr2", "http://www.onvif.org/ver20/media/wsdl")], "<tr2:GetStreamUriResponse><tr2:Uri>rtsp://camera/</tr2:Uri></tr2:GetStreamUriResponse>")
+            }
+        });
+        let address = serve_mock(mock).await;
+
+        let camera = CameraConfig {
+            id: "cam1".to_string(),
+            address: address.to_string(),
+            ..Default::default()
+        };
+        let app = create_router(app_state(vec![camera]).await);
+
+        let bodies = [
+            r#"<tr2:GetProfiles><tr2:Type>All</tr2:Type></tr2:GetProfiles>"#,
+            r#"<tr2:GetStreamUri><tr2:Protocol>RTSP</tr2:Protocol><tr2:ProfileToken>000</tr2:ProfileToken></tr2:GetStreamUri>"#,
+        ];
+        for body in bodies {
+            let envelope = format!(
+                r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:tr2="http://www.onvif.org/ver20/media/wsdl"><s:Body>{}</s:Body></s:Envelope>"#,
+                body
+            );
+            let response = app.clone().oneshot(Request::post("/onvif/cam1/Media2").body(Body::from(envelope)).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        for (forwarded, body) in requests.iter().zip(bodies) {
+            let expected = body.replacen(">", r#" xmlns:tr2="http://www.onvif.org/ver20/media/wsdl">"#, 1);
+            assert!(forwarded.contains(&expected), "{}", forwarded);
+        }
+    }
+
+    /// Media2 snapshot URIs are pointed at the proxy, which then serves the camera's JPEG, or
+    /// without `proxy_snapshots` at the camera
+    #[tokio::test]
+    async fn test_media2_snapshot_uri() {
+        let mock = Router::new().fallback(|request: Request| async move {
+            let query = request.uri().query().unwrap_or_default().to_string();
+            match request.uri().path() {
+                "/onvif/Media2" => soap::build_envelope(
+                    &[("tr2", "http://www.onvif.org/ver20/media/wsdl")],
+                    "<tr2:GetSnapshotUriResponse><tr2:Uri>http://localhost/cgi-bin/api.cgi?cmd=Snap&amp;channel=0</tr2:Uri></tr2:GetSnapshotUriResponse>",
+                )
+                .into_response(),
+                _ if query.contains("cmd=Login") => {
+                    r#"[{"cmd":"Login","code":0,"value":{"Token":{"leaseTime":3600,"name":"tok"}}}]"#.into_response()
+                }
+                _ if query.contains("cmd=Snap") && query.contains("token=tok") && query.contains("snapType=sub") => {
+                    ([(header::CONTENT_TYPE, "image/jpeg")], "JPEG").into_response()
+                }
+                _ => StatusCode::NOT_FOUND.into_response(),
+            }
+        });
+        let address = serve_mock(mock).await;
+
+        let camera = CameraConfig {
+            id: "cam1".to_string(),
+            address: address.to_string(),
+            cgi_base_url: Some(format!("http://{}", address)),
+            snapshot_streams: [("001".to_string(), "sub".to_string())].into(),
+            proxy_snapshots: false,
+            ..Default::default()
+        };
+        let cam2 = CameraConfig {
+            id: "cam2".to_string(),
+            proxy_snapshots: true,
+            ..camera.clone()
+        };
+        let app = create_router(app_state(vec![camera, cam2]).await);
+
+        let envelope = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><tr2:GetSnapshotUri xmlns:tr2="http://www.onvif.org/ver20/media/wsdl"><tr2:ProfileToken>001</tr2:ProfileToken></tr2:GetSnapshotUri></s:Body></s:Envelope>"#;
+        let snapshot_uri = |camera_id: &str| {
+            let app = app.clone();
+            let request = Request::post(format!("/onvif/{}/Media2", camera_id)).body(Body::from(envelope)).unwrap();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+            }
+        };
+
+        let direct = snapshot_uri("cam1").await;
+        assert!(
+            direct.contains("<tr2:Uri>http://127.0.0.1/cgi-bin/api.cgi?cmd=Snap&amp;channel=0&amp;snapType=sub</tr2:Uri>"),
+            "{}",
+            direct
+        );
+        let proxied = snapshot_uri("cam2").await;
+        assert!(proxied.contains("<tr2:Uri>http://proxy:8000/onvif/cam2/snapshot?profile=001</tr2:Uri>"), "{}", proxied);
+
+        let response = app.clone().oneshot(Request::get("/onvif/cam2/snapshot?profile=001").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/jpeg");
+        assert_eq!(to_bytes(response.into_body(), usize::MAX).await.unwrap(), "JPEG");
+        // Only cameras with proxy_snapshots serve snapshots
+        let response = app.oneshot(Request::get("/onvif/cam1/snapshot").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// A generic camera's snapshot URL is fetched with its credentials; the bytes, type and status
+    /// are passed on
+    #[tokio::test]
+    async fn test_snapshot_proxied_from_camera() {
+        const JPEG: &[u8] = b"\xff\xd8\xff\xe0 not really a jpeg \xff\xd9";
+        let address = Arc::new(std::sync::OnceLock::<std::net::SocketAddr>::new());
+        let snapshot_address = address.clone();
+        let mock = Router::new().fallback(move |request: Request| {
+            let address = snapshot_address.clone();
+            async move {
+                let authorized = request.headers().get(header::AUTHORIZATION).and_then(|value| value.to_str().ok())
+                    == Some("Basic YWRtaW46c2VjcmV0");
+                match request.uri().path() {
+                    "/onvif/media_service" => soap::build_envelope(
+                        &[("trt", "http://www.onvif.org/ver10/media/wsdl"), ("tt", "http://www.onvif.org/ver10/schema")],
+                        &format!(
+                            "<trt:GetSnapshotUriResponse><trt:MediaUri><tt:Uri>http://{}/snap.jpg?profile=1&amp;size=full</tt:Uri></trt:MediaUri></trt:GetSnapshotUriResponse>",
+                            address.get().unwrap()
+                        ),
+                    )
+                    .into_response(),
+                    "/snap.jpg" if !authorized => StatusCode::UNAUTHORIZED.into_response(),
+                    "/snap.jpg" if request.uri().query() == Some("profile=1&size=full") => {
+                        ([(header::CONTENT_TYPE, "image/jpeg")], JPEG).into_response()
+                    }
+                    _ => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+                }
+            }
+        });
+        address.set(serve_mock(mock).await).unwrap();
+
+        let camera = CameraConfig {
+            id: "cam1".to_string(),
+            address: address.get().unwrap().to_string(),
+            model: "generic".to_string(),
+            username: "admin".to_string(),
+            password: "secret".to_string(),
+            ..Default::default()
+        };
+        let cam2 = CameraConfig {
+            id: "cam2".to_string(),
+            password: "wrong".to_string(),
+            ..camera.clone()
+        };
+        let app = create_router(app_state(vec![camera, cam2]).await);
+
+        let envelope = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><trt:GetSnapshotUri xmlns:trt="http://www.onvif.org/ver10/media/wsdl"><trt:ProfileToken>000</trt:ProfileToken></trt:GetSnapshotUri></s:Body></s:Envelope>"#;
+        let response = app.clone().oneshot(Request::post("/onvif/cam1/media_service").body(Body::from(envelope)).unwrap()).await.unwrap();
+        let body = String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+        assert!(body.contains("<tt:Uri>http://proxy:8000/onvif/cam1/snapshot?profile=000</tt:Uri>"), "{}", body);
+
+        let response = app.clone().oneshot(Request::get("/onvif/cam1/snapshot?profile=000").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/jpeg");
+        assert_eq!(to_bytes(response.into_body(), usize::MAX).await.unwrap(), JPEG);
+
+        // The camera's refusal is passed on
+        let response = app.oneshot(Request::get("/onvif/cam2/snapshot").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    /// Rule writes go to the camera's ONVIF analytics service untranslated; only Reolink cameras
+    /// get the rule types their firmware can't represent refused
+    #[tokio::test]
+    async fn test_analytics_rule_writes() {
+        let mock = Router::new().route(
+            "/onvif/analytics_service",
+            post(|| async {
+                soap::build_envelope(&[("tan", "http://www.onvif.org/ver20/analytics/wsdl")], "<tan:CreateRulesResponse/>")
+            }),
+        );
+        let address = serve_mock(mock).await;
+
+        let camera = CameraConfig {
+            id: "generic".to_string(),
+            address: address.to_string(),
+            model: "generic".to_string(),
+            enable_analytics_writes: true,
+            ..Default::default()
+        };
+        let reolink = CameraConfig { id: "reolink".to_string(), model: "reolink".to_string(), ..camera.clone() };
+        let app = create_router(app_state(vec![camera, reolink]).await);
+        let create_rules = |camera_id: &str| {
+            let envelope = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><tan:CreateRules xmlns:tan="http://www.onvif.org/ver20/analytics/wsdl" xmlns:tt="http://www.onvif.org/ver10/schema"><tan:ConfigurationToken>VAC</tan:ConfigurationToken><tan:Rule Name="Loiter" Type="tt:LoiteringDetector"/></tan:CreateRules></s:Body></s:Envelope>"#;
+            let request = Request::post(format!("/onvif/{}/analytics_service", camera_id)).body(Body::from(envelope)).unwrap();
+            let app = app.clone();
+            async move { String::from_utf8(to_bytes(app.oneshot(request).await.unwrap().into_body(), usize::MAX).await.unwrap().to_vec()).unwrap() }
+        };
+
+        assert!(create_rules("generic").await.contains("CreateRulesResponse"));
+        assert!(create_rules("reolink").await.contains("InvalidRule"));
+    }
+
+    /// Profiles in hidden_profiles, by token or by name, are refused on every route, with the
+    /// names looked up once; when they can't be looked up the request is refused as well
+    #[tokio::test]
+    async fn test_hidden_profiles_refused() {
+        let get_profiles = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counted = get_profiles.clone();
+        let mock = Router::new().fallback(move |request: Request| {
+            let counted = counted.clone();
+            async move {
+                let body = String::from_utf8(to_bytes(request.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+                let content = if body.contains("GetProfiles") {
+                    counted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    r#"<trt:GetProfilesResponse><trt:Profiles token="000"><tt:Name>mainStream</tt:Name></trt:Profiles><trt:Profiles token="001"><tt:Name>subStream</tt:Name></trt:Profiles></trt:GetProfilesResponse>"#
+                } else {
+                    "<trt:GetStreamUriResponse><trt:MediaUri><tt:Uri>rtsp://camera/stream</tt:Uri></trt:MediaUri></trt:GetStreamUriResponse>"
+                };
+                soap::build_envelope(&[("trt", "http://www.onvif.org/ver10/media/wsdl"), ("tt", "http://www.onvif.org/ver10/schema")], content)
+            }
+        });
+        let address = serve_mock(mock).await;
+        let unreachable = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+
+        let camera = CameraConfig {
+            id: "cam1".to_string(),
+            address: address.to_string(),
+            model: "generic".to_string(),
+            hidden_profiles: vec!["subStream".to_string(), "002".to_string()],
+            ..Default::default()
+        };
+        let cam2 = CameraConfig { id: "cam2".to_string(), address: unreachable.to_string(), ..camera.clone() };
+        let app = create_router(app_state(vec![camera, cam2]).await);
+        let get_stream_uri = |camera_id: &str, token: &str| {
+            let envelope = format!(
+                r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><trt:GetStreamUri xmlns:trt="http://www.onvif.org/ver10/media/wsdl"><trt:ProfileToken>{}</trt:ProfileToken></trt:GetStreamUri></s:Body></s:Envelope>"#,
+                token
+            );
+            app.clone().oneshot(Request::post(format!("/onvif/{}/media_service", camera_id)).body(Body::from(envelope)).unwrap())
+        };
+        let body = |response: Response| async { String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap() };
+
+        assert!(body(get_stream_uri("cam1", "000").await.unwrap()).await.contains("rtsp://camera/stream"));
+        for token in ["001", "001", "002"] {
+            assert!(body(get_stream_uri("cam1", token).await.unwrap()).await.contains("NoProfile"));
+        }
+        // A Media2 request posted to the ver10 endpoint is checked the same way
+        let envelope = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><tr2:GetStreamUri xmlns:tr2="http://www.onvif.org/ver20/media/wsdl"><tr2:Protocol>RTSP</tr2:Protocol><tr2:ProfileToken>001</tr2:ProfileToken></tr2:GetStreamUri></s:Body></s:Envelope>"#;
+        let response = app.clone().oneshot(Request::post("/onvif/cam1/media_service").body(Body::from(envelope)).unwrap()).await.unwrap();
+        let response = body(response).await;
+        assert!(response.contains("NoProfile"));
+        assert!(!response.contains("rtsp://"));
+        assert_eq!(get_profiles.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let response = app.clone().oneshot(Request::get("/onvif/cam1/snapshot?profile=001").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Names that can't be looked up don't let the request through
+        let response = get_stream_uri("cam2", "001").await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!body(response).await.contains("rtsp://"));
+    }
+
+    /// Renew and Unsubscribe posted to the event service find their subscription through the
+    /// WS-Addressing header
+    #[tokio::test]
+    async fn test_subscription_addressed_on_event_service() {
+        let camera = CameraConfig {
+            id: "sim".to_string(),
+            model: "simulated".to_string(),
+            ..Default::default()
+        };
+        let state = app_state(vec![camera]).await;
+        let events_service = state.events_service.clone();
+        let app = create_router(state);
+        let post = |header: String, operation: &'static str| {
+            let app = app.clone();
+            async move {
+                let body = format!(
+                    r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:wsa="http://www.w3.org/2005/08/addressing" xmlns:wsnt="http://docs.oasis-open.org/wsn/b-2" xmlns:tev="http://www.onvif.org/ver10/events/wsdl"><s:Header>{}</s:Header><s:Body>{}</s:Body></s:Envelope>"#,
+                    header, operation
+                );
+                let response = app.oneshot(Request::post("/onvif/sim/event_service").body(Body::from(body)).unwrap()).await.unwrap();
+                let status = response.status();
+                (status, String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap())
+            }
+        };
+
+        let (status, created) = post(String::new(), "<tev:CreatePullPointSubscription/>").await;
+        assert_eq!(status, StatusCode::OK);
+        let start = created.find("http://proxy:8000/onvif/sim/subscription/").unwrap();
+        let url = &created[start..start + created[start..].find('<').unwrap()];
+        let sub_id = url.rsplit('/').next().unwrap().to_string();
+
+        let renew = "<wsnt:Renew><wsnt:TerminationTime>PT120S</wsnt:TerminationTime></wsnt:Renew>";
+        let (status, renewed) = post(format!("<wsa:To>{}</wsa:To>", url), renew).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(renewed.contains("RenewResponse"), "{}", renewed);
+
+        // No addressing at all is still refused
+        let (status, _) = post(String::new(), renew).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let reference = format!(r#"<SubscriptionId wsa:IsReferenceParameter="true">{}</SubscriptionId>"#, sub_id);
+        let (status, unsubscribed) = post(reference.clone(), "<wsnt:Unsubscribe/>").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(unsubscribed.contains("UnsubscribeResponse"), "{}", unsubscribed);
+        assert!(events_service.get_subscription(&sub_id).await.is_none());
+        let (status, _) = post(reference, renew).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    /// With credential_mode: passthrough, the client's token reaches the camera on event
+    /// requests, while the proxy's own pulls keep the configured credentials
+    #[tokio::test]
+    async fn test_events_pass_caller_token() {
+        let requests = Arc::new(std::sync::Mutex::new(Vec::<String>::new()));
+        let recorded = requests.clone();
+        let address = Arc::new(std::sync::OnceLock::<std::net::SocketAddr>::new());
+        let subscription_address = address.clone();
+        let mock = Router::new().fallback(move |request: Request| {
+            let (recorded, address) = (recorded.clone(), subscription_address.clone());
+            async move {
+                let body = String::from_utf8(to_bytes(request.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+                recorded.lock().unwrap().push(body.clone());
+                let content = if body.contains("CreatePullPointSubscription") {
+                    format!(
+                        "<tev:CreatePullPointSubscriptionResponse><tev:SubscriptionReference><wsa:Address>http://{}/onvif/Subscription?Idx=1</wsa:Address></tev:SubscriptionReference></tev:CreatePullPointSubscriptionResponse>",
+                        address.get().unwrap()
+                    )
+                } else if body.contains("Renew") {
+                    "<wsnt:RenewResponse><wsnt:TerminationTime>2030-01-01T00:00:00Z</wsnt:TerminationTime></wsnt:RenewResponse>".to_string()
+                } else if body.contains("Unsubscribe") {
+                    "<wsnt:UnsubscribeResponse/>".to_string()
+                } else {
+                    "<tev:PullMessagesResponse/>".to_string()
+                };
+                soap::build_envelope(
+                    &[("tev", "http://www.onvif.org/ver10/events/wsdl"), ("wsnt", "http://docs.oasis-open.org/wsn/b-2"), ("wsa", "http://www.w3.org/2005/08/addressing")],
+                    &content,
+                )
+            }
+        });
+        address.set(serve_mock(mock).await).unwrap();
+
+        let camera = CameraConfig {
+            id: "cam1".to_string(),
+            address: address.get().unwrap().to_string(),
+            username: "admin".to_string(),
+            password: "secret".to_string(),
+            event_method: crate::config::EventMethod::Onvif,
+            credential_mode: crate::config::CredentialMode::Passthrough,
+            ..Default::default()
+        };
+        let app = create_router(app_state(vec![camera]).await);
+        let post = |path: String, operation: &'static str| {
+            let app = app.clone();
+            async move {
+                let body = format!(
+                    r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:wsnt="http://docs.oasis-open.org/wsn/b-2" xmlns:tev="http://www.onvif.org/ver10/events/wsdl"><s:Header><wsse:Security xmlns:wsse="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd"><wsse:UsernameToken><wsse:Username>viewer</wsse:Username><wsse:Password>camera-password</wsse:Password></wsse:UsernameToken></wsse:Security></s:Header><s:Body>{}</s:Body></s:Envelope>"#,
+                    operation
+                );
+                let response = app.oneshot(Request::post(path).body(Body::from(body)).unwrap()).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+            }
+        };
+        let sent = |operation: &str| {
+            requests.lock().unwrap().iter().filter(|body| body.contains(operation)).cloned().collect::<Vec<_>>()
+        };
+
+        let created = post("/onvif/cam1/event_service".to_string(), "<tev:CreatePullPointSubscription/>").await;
+        let start = created.find("http://proxy:8000/onvif/cam1/subscription/").unwrap();
+        let sub_id = created[start..start + created[start..].find('<').unwrap()].rsplit('/').next().unwrap().to_string();
+        post(format!("/onvif/cam1/subscription/{}", sub_id), "<wsnt:Renew><wsnt:TerminationTime>PT120S</wsnt:TerminationTime></wsnt:Renew>").await;
+
+        // The relay's own pull on the camera's subscription
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+        while sent("PullMessages").is_empty() && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        post(format!("/onvif/cam1/subscription/{}", sub_id), "<wsnt:Unsubscribe/>").await;
+
+        for operation in ["CreatePullPointSubscription", ":Renew", "Unsubscribe"] {
+            let sent = sent(operation);
+            assert!(!sent.is_empty() && sent.iter().all(|body| body.contains("<wsse:Username>viewer</wsse:Username>")), "{}", operation);
+        }
+        let pulls = sent("PullMessages");
+        assert!(!pulls.is_empty());
+        assert!(pulls.iter().all(|body| body.contains("<wsse:Username>admin</wsse:Username>")));
+    }
+
+    /// Replies on the wire carry Content-Length instead of chunked encoding
+    #[tokio::test]
+    async fn test_content_length_on_the_wire() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let camera = CameraConfig {
+            id: "sim".to_string(),
+            model: "simulated".to_string(),
+            client_profile: crate::config::ClientProfile::Milestone,
+            ..Default::default()
+        };
+        let app = create_router(app_state(vec![camera]).await);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let exchange = |body: &'static str| async move {
+            let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+            let request = format!(
+                "POST /onvif/sim/media_service HTTP/1.1\r\nHost: proxy\r\nContent-Type: application/soap+xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+        let check = |response: &str| {
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            let head = head.to_ascii_lowercase();
+            assert!(!head.contains("transfer-encoding"), "{}", head);
+            assert!(head.contains(&format!("content-length: {}\r\n", body.len())), "{}", head);
+        };
+
+        // A translated reply (token rewriting rebuilds the body) and a fault
+        let response = exchange(r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><trt:GetProfiles xmlns:trt="http://www.onvif.org/ver10/media/wsdl"/></s:Body></s:Envelope>"#).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        check(&response);
+        let response = exchange(r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><trt:Bogus xmlns:trt="http://www.onvif.org/ver10/media/wsdl"/></s:Body></s:Envelope>"#).await;
+        assert!(!response.starts_with("HTTP/1.1 200"));
+        check(&response);
+    }
+
+    /// Client tokens handed out before a restart still reach the right camera profile: the token
+    /// map is rebuilt from the camera's listings when a request carries one it doesn't know
+    #[tokio::test]
+    async fn test_client_tokens_relearned() {
+        let mock = Router::new().route(
+            "/onvif/media_service",
+            post(|body: String| async move {
+                let content = if body.contains("GetProfiles") {
+                    r#"<trt:GetProfilesResponse><trt:Profiles token="Profile_1" fixed="true"><tt:Name>main</tt:Name></trt:Profiles></trt:GetProfilesResponse>"#.to_string()
+                } else if body.contains("GetVideoSources") {
+                    "<trt:GetVideoSourcesResponse/>".to_string()
+                } else {
+                    format!("<trt:GetStreamUriResponse><trt:MediaUri><tt:Uri>rtsp://camera/{}</tt:Uri></trt:MediaUri></trt:GetStreamUriResponse>", extract_value(&body, "ProfileToken").unwrap())
+                };
+                soap::build_envelope(&[("trt", "http://www.onvif.org/ver10/media/wsdl"), ("tt", "http://www.onvif.org/ver10/schema")], &content)
+            }),
+        );
+        let address = serve_mock(mock).await;
+
+        let camera = CameraConfig {
+            id: "cam1".to_string(),
+            address: address.to_string(),
+            model: "generic".to_string(),
+            client_profile: crate::config::ClientProfile::Milestone,
+            ..Default::default()
+        };
+        let app = create_router(app_state(vec![camera]).await);
+
+        let envelope = format!(
+            r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><trt:GetStreamUri xmlns:trt="http://www.onvif.org/ver10/media/wsdl"><trt:ProfileToken>{}</trt:ProfileToken></trt:GetStreamUri></s:Body></s:Envelope>"#,
+            crate::translator::tokens::TokenMap::client_token("Profile_1")
+        );
+        let response = app.oneshot(Request::post("/onvif/cam1/media_service").body(Body::from(envelope)).unwrap()).await.unwrap();
+        let body = String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+        assert!(body.contains("rtsp://camera/Profile_1"), "{}", body);
+    }
+
+    #[test]
+    fn test_unknown_action_behavior() {
+        let camera = |behavior| {
+            CameraClient::new(CameraConfig {
+                unknown_action_behavior: behavior,
+                ..Default::default()
+            })
+        };
+
+        let fault = unknown_action(&camera(UnknownActionBehavior::default()), "SystemReboot");
+        assert_eq!(fault.status(), StatusCode::BAD_REQUEST);
+
+        let not_implemented = unknown_action(&camera(UnknownActionBehavior::NotImplemented), "SystemReboot");
+        assert_eq!(not_implemented.status(), StatusCode::NOT_IMPLEMENTED);
+
+        let empty = unknown_action(&camera(UnknownActionBehavior::EmptyOk), "SystemReboot");
+        assert_eq!(empty.status(), StatusCode::OK);
+
+        let behavior: UnknownActionBehavior = serde_yaml::from_str("empty_ok").unwrap();
+        assert_eq!(behavior, UnknownActionBehavior::EmptyOk);
+    }
+
+    #[test]
+    fn test_prohibited_actions() {
+        let camera = CameraClient::new(CameraConfig {
+            allowed_actions: vec!["GetProfiles".to_string(), "SystemReboot".to_string()],
+            denied_actions: vec!["SystemReboot".to_string()],
+            ..Default::default()
+        });
+
+        let envelope = |operation: &str| {
+            SoapEnvelope::parse(&soap::build_envelope(&[], &format!("<{} xmlns=\"http://www.onvif.org/ver10/device/wsdl\"/>", operation))).unwrap()
+        };
+        let prohibited = |camera: &CameraClient, action: &str| reject_prohibited(camera, &envelope(action), action);
+
+        assert!(prohibited(&camera, "GetProfiles").is_none());
+        // Denied wins over allowed
+        assert_eq!(prohibited(&camera, "SystemReboot").unwrap().status(), StatusCode::BAD_REQUEST);
+        assert!(prohibited(&camera, "SetNetworkInterfaces").is_some());
+        // Probes (no action) are never blocked
+        let probe = SoapEnvelope::parse(&soap::build_envelope(&[], "")).unwrap();
+        assert!(reject_prohibited(&camera, &probe, "").is_none());
+        // The Body is checked too, whatever action the headers announce
+        assert!(reject_prohibited(&camera, &envelope("SystemReboot"), "GetProfiles").is_some());
+
+        let open = CameraClient::new(CameraConfig::default());
+        assert!(prohibited(&open, "SystemReboot").is_none());
+    }
+
+    /// Cameras added through the management API are served right away, and gone once deleted
+    #[tokio::test]
+    async fn test_manage_cameras() {
+        let proxy_config: ProxyConfig = serde_yaml::from_str("listen_address: 127.0.0.1:8000\nbase_path: /onvif\nadmin_token: admin-secret").unwrap();
+        let state = AppState { proxy_config: Arc::new(proxy_config), ..app_state(vec![]).await };
+        let events_service = state.events_service.clone();
+        let app = create_router(state);
+        let call = |method: &'static str, uri: &'static str, body: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(header::AUTHORIZATION, "Bearer admin-secret")
+                    .body(Body::from(body))
+                    .unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                (status, String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap())
+            }
+        };
+        let get_device_information = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><tds:GetDeviceInformation xmlns:tds="http://www.onvif.org/ver10/device/wsdl"/></s:Body></s:Envelope>"#;
+        let camera = r#"{"id": "sim", "name": "Simulated", "address": "", "username": "admin", "password": "hunter2", "model": "simulated"}"#;
+
+        let response = app.clone().oneshot(Request::get("/cameras").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let wrong_token = Request::get("/cameras").header(header::AUTHORIZATION, "Bearer admin-secreT").body(Body::empty()).unwrap();
+        assert_eq!(app.clone().oneshot(wrong_token).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+        let (status, added) = call("POST", "/cameras", camera).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", added);
+        assert!(added.contains(r#""password":"***""#), "{}", added);
+        let (status, device_information) = call("POST", "/onvif/sim/device_service", get_device_information).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(device_information.contains("GetDeviceInformationResponse"), "{}", device_information);
+
+        assert_eq!(call("POST", "/cameras", camera).await.0, StatusCode::CONFLICT);
+        assert_eq!(call("POST", "/cameras", r#"{"id": "a/b"}"#).await.0, StatusCode::BAD_REQUEST);
+        let (status, invalid) = call("POST", "/cameras", r#"{"id": "cam2", "name": "Cam 2", "address": "http://192.168.1.10", "username": "admin"}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(invalid.contains("without a scheme"), "{}", invalid);
+
+        assert_eq!(call("POST", "/admin/cameras/sim/motion?seconds=30", "").await.0, StatusCode::NO_CONTENT);
+        assert_eq!(call("POST", "/admin/cameras/sim/motion?seconds=9223372036854775807", "").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(call("POST", "/admin/cameras/sim/motion?seconds=-1", "").await.0, StatusCode::BAD_REQUEST);
+
+        let (status, listed) = call("GET", "/cameras", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let listed: serde_json::Value = serde_json::from_str(&listed).unwrap();
+        assert_eq!(listed[0]["id"], "sim");
+        assert_eq!(listed[0]["password"], "***");
+        assert!(!listed.to_string().contains("hunter2"));
+
+        // Removing the camera ends its event subscriptions
+        let subscribe = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><tev:CreatePullPointSubscription xmlns:tev="http://www.onvif.org/ver10/events/wsdl"/></s:Body></s:Envelope>"#;
+        let (status, subscribed) = call("POST", "/onvif/sim/event_service", subscribe).await;
+        assert_eq!(status, StatusCode::OK, "{}", subscribed);
+        assert_eq!(events_service.active_subscriptions("sim").await, 1);
+
+        assert_eq!(call("DELETE", "/cameras/sim", "").await.0, StatusCode::NO_CONTENT);
+        assert_eq!(events_service.active_subscriptions("sim").await, 0);
+        assert_eq!(call("DELETE", "/cameras/sim", "").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(call("POST", "/onvif/sim/device_service", get_device_information).await.0, StatusCode::NOT_FOUND);
+    }
+
+    /// Cameras added or removed through the management API are written back to the config file
+    #[tokio::test]
+    async fn test_managed_cameras_saved() {
+        let path = std::env::temp_dir().join(format!("onvif-proxy-config-{}.yaml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "proxy:\n  listen_address: 127.0.0.1:8000\n  base_path: /onvif\n  admin_token: admin-secret\ncameras: []\n").unwrap();
+        #[cfg(unix)]
+        std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o600)).unwrap();
+        let config = crate::config::AppConfig::load_from_file(&path).unwrap();
+        let app = create_router(AppState {
+            config_path: Some(Arc::new(path.clone())),
+            proxy_config: Arc::new(config.proxy),
+            ..app_state(vec![]).await
+        });
+        let call = |method: &'static str, uri: &'static str, body: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(header::AUTHORIZATION, "Bearer admin-secret")
+                    .body(Body::from(body))
+                    .unwrap();
+                app.oneshot(request).await.unwrap().status()
+            }
+        };
+
+        let camera = r#"{"id": "garage", "name": "Garage", "address": "192.168.1.101", "username": "admin", "password": "hunter2", "quirks": ["translate_smart_events"]}"#;
+        assert_eq!(call("POST", "/cameras", camera).await, StatusCode::CREATED);
+        let saved = crate::config::AppConfig::load_from_file(&path).unwrap();
+        assert_eq!(saved.proxy.admin_token.as_deref(), Some("admin-secret"));
+        assert_eq!(saved.cameras.len(), 1);
+        assert_eq!(saved.cameras[0].id, "garage");
+        // The file is the source of truth, so secrets are kept
+        assert_eq!(saved.cameras[0].password, "hunter2");
+        assert_eq!(saved.cameras[0].quirks, vec!["translate_smart_events"]);
+        // Unset options are left out, and the file stays readable by its owner only
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("null"));
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&path).unwrap().permissions()) & 0o777, 0o600);
+
+        assert_eq!(call("DELETE", "/cameras/garage", "").await, StatusCode::NO_CONTENT);
+        assert!(crate::config::AppConfig::load_from_file(&path).unwrap().cameras.is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
 }
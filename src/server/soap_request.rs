@@ -0,0 +1,91 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+use crate::onvif::soap::SoapEnvelope;
+
+/// Largest request body the proxy reads
+pub const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+/// A request body parsed as a SOAP envelope. The first layer that needs it parses the body and
+/// keeps the result in the request extensions, so later layers and the handler don't parse again
+#[derive(Clone)]
+pub struct ParsedEnvelope(Result<Arc<SoapEnvelope>, Arc<anyhow::Error>>);
+
+impl ParsedEnvelope {
+    pub fn parse(bytes: &[u8]) -> Self {
+        let parsed = std::str::from_utf8(bytes)
+            .map_err(anyhow::Error::from)
+            .and_then(SoapEnvelope::parse);
+        Self(parsed.map(Arc::new).map_err(Arc::new))
+    }
+
+    /// The envelope, or the reason the body isn't one
+    pub fn result(&self) -> Result<&Arc<SoapEnvelope>, &anyhow::Error> {
+        self.0.as_ref().map_err(|e| e.as_ref())
+    }
+
+    /// The envelope; None if the body didn't parse
+    pub fn envelope(&self) -> Option<&SoapEnvelope> {
+        self.0.as_deref().ok()
+    }
+}
+
+/// Buffer and parse the body of `request`, unless an earlier layer already did, and hand back an
+/// equivalent request carrying the `ParsedEnvelope`. Bodies over `MAX_REQUEST_BYTES` get a 413
+pub async fn parse_request(request: Request) -> Result<(Request, ParsedEnvelope), Response> {
+    if let Some(parsed) = request.extensions().get::<ParsedEnvelope>().cloned() {
+        return Ok((request, parsed));
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let bytes = to_bytes(body, MAX_REQUEST_BYTES)
+        .await
+        .map_err(|_| (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response())?;
+    let parsed = ParsedEnvelope::parse(&bytes);
+    parts.extensions.insert(parsed.clone());
+    Ok((Request::from_parts(parts, Body::from(bytes)), parsed))
+}
+
+/// `/onvif/{camera_id}/...` -> camera id
+pub fn camera_id_from_path(path: &str) -> Option<String> {
+    path.strip_prefix("/onvif/")?
+        .split('/')
+        .next()
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_camera_id_from_path() {
+        assert_eq!(camera_id_from_path("/onvif/cam1/media_service").as_deref(), Some("cam1"));
+        assert_eq!(camera_id_from_path("/onvif/cam1/subscription/abc").as_deref(), Some("cam1"));
+        assert_eq!(camera_id_from_path("/onvif/"), None);
+        assert_eq!(camera_id_from_path("/health"), None);
+    }
+
+    #[tokio::test]
+    async fn test_parsed_once() {
+        let envelope = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><GetProfiles xmlns="http://www.onvif.org/ver10/media/wsdl"/></s:Body></s:Envelope>"#;
+        let request = Request::post("/onvif/cam1/media_service").body(Body::from(envelope)).unwrap();
+
+        let (request, parsed) = parse_request(request).await.unwrap();
+        assert_eq!(parsed.envelope().unwrap().body.action, "GetProfiles");
+        // A later layer gets the same envelope, and the body is still there for the handler
+        let (request, again) = parse_request(request).await.unwrap();
+        assert!(Arc::ptr_eq(parsed.result().unwrap(), again.result().unwrap()));
+        let body = to_bytes(request.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, envelope.as_bytes());
+
+        let (_, invalid) = parse_request(Request::post("/").body(Body::from("not xml")).unwrap()).await.unwrap();
+        assert!(invalid.result().is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use crate::translator::pipeline::TranslationPipeline;
+use crate::translator::reolink::ReolinkEventTranslator;
+use crate::translator::response::ModelTranslator;
+
+/// Translator for `model: generic`: ONVIF cameras of any brand whose responses only need their
+/// namespace declarations fixed. `add_missing_namespaces` always runs; the Reolink event topic
+/// remapping and `State` injection never do, since they would corrupt real ONVIF events
+pub struct GenericTranslator;
+
+impl GenericTranslator {
+    /// Quirks that only touch namespace declarations or drop an unreachable section, and so are
+    /// safe on any camera
+    pub const SAFE_QUIRKS: &'static [&'static str] = &["fix_device_info_namespace", "add_missing_namespaces", "fix_analytics_xaddr"];
+}
+
+impl ModelTranslator for GenericTranslator {
+    fn pipeline(&self, quirks: &[String]) -> TranslationPipeline {
+        let mut selected = Vec::new();
+        for quirk in quirks {
+            if Self::SAFE_QUIRKS.contains(&quirk.as_str()) {
+                selected.push(quirk.clone());
+            } else {
+                tracing::debug!("Quirk {} is not applied to generic cameras", quirk);
+            }
+        }
+        if !selected.iter().any(|quirk| quirk == "add_missing_namespaces") {
+            selected.push("add_missing_namespaces".to_string());
+        }
+        // The steps themselves are model-independent
+        ReolinkEventTranslator::pipeline(&selected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::translator::response::ResponseTranslator;
+    use crate::translator::rules::RuleEngine;
+
+    #[test]
+    fn test_generic_fixes_namespaces_only() {
+        let xml = r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope" xmlns:wsnt="http://docs.oasis-open.org/wsn/b-2"><SOAP-ENV:Body><wsnt:NotificationMessage><wsnt:Topic>tns1:RuleEngine/MyRuleDetector/PeopleDetect</wsnt:Topic><wsnt:Message><tt:Message><tt:Data><tt:SimpleItem Name="IsMotion" Value="true"/></tt:Data></tt:Message></wsnt:Message></wsnt:NotificationMessage></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+        let quirks = ["translate_smart_events".to_string(), "fix_device_info_namespace".to_string()];
+
+        let translated = ResponseTranslator::translate(xml, "generic", &quirks, &RuleEngine::new()).unwrap();
+        assert!(translated.contains(r#"xmlns:tt="http://www.onvif.org/ver10/schema""#), "{}", translated);
+        assert!(translated.contains("tns1:RuleEngine/MyRuleDetector/PeopleDetect"), "{}", translated);
+        assert!(!translated.contains(r#"Name="State""#), "{}", translated);
+
+        let report = ResponseTranslator::translate_with_report(xml, "generic", &quirks, &RuleEngine::new());
+        assert_eq!(
+            report.steps.iter().map(|step| step.name).collect::<Vec<_>>(),
+            vec!["fix_device_info_namespace", "add_missing_namespaces", "rules", "remove_duplicate_namespaces"]
+        );
+    }
+}
@@ -1,5 +1,8 @@
+pub mod generic;
+pub mod pipeline;
 pub mod response;
 pub mod rules;
 pub mod reolink;
+pub mod tokens;
 
 pub use response::ResponseTranslator;
@@ -0,0 +1,104 @@
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// A translation step: takes the current XML and returns the rewritten XML
+pub type StepFn = fn(&str) -> String;
+
+struct TranslationStep {
+    name: &'static str,
+    apply: StepFn,
+}
+
+/// What a single step did to the response
+#[derive(Debug, Clone, Serialize)]
+pub struct StepReport {
+    pub name: &'static str,
+    pub changed: bool,
+    #[serde(rename = "duration_us", serialize_with = "serialize_micros")]
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TranslationReport {
+    pub output: String,
+    pub steps: Vec<StepReport>,
+}
+
+/// Ordered list of named translation steps; the order steps are added is the order they run
+#[derive(Default)]
+pub struct TranslationPipeline {
+    steps: Vec<TranslationStep>,
+}
+
+impl TranslationPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn step(mut self, name: &'static str, apply: StepFn) -> Self {
+        self.steps.push(TranslationStep { name, apply });
+        self
+    }
+
+    pub fn step_names(&self) -> Vec<&'static str> {
+        self.steps.iter().map(|s| s.name).collect()
+    }
+
+    pub fn run(&self, xml: &str) -> TranslationReport {
+        let report = TranslationReport {
+            output: xml.to_string(),
+            steps: Vec::with_capacity(self.steps.len()),
+        };
+        self.steps.iter().fold(report, |report, step| report.then(step.name, step.apply))
+    }
+}
+
+impl TranslationReport {
+    /// Run one more step on the output and report it like the pipeline's own, for steps that
+    /// need more than the XML (a camera's `rules`)
+    pub fn then(mut self, name: &'static str, apply: impl FnOnce(&str) -> String) -> Self {
+        let started = Instant::now();
+        let result = apply(&self.output);
+        let duration = started.elapsed();
+        let changed = result != self.output;
+
+        tracing::debug!(
+            "Translation step {}: changed={} in {}us",
+            name,
+            changed,
+            duration.as_micros()
+        );
+
+        self.output = result;
+        self.steps.push(StepReport {
+            name,
+            changed,
+            duration,
+        });
+        self
+    }
+}
+
+fn serialize_micros<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_micros() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_reports_each_step() {
+        let pipeline = TranslationPipeline::new()
+            .step("upper", |xml| xml.to_uppercase())
+            .step("noop", |xml| xml.to_string())
+            .step("wrap", |xml| format!("<{}>", xml));
+
+        let report = pipeline.run("a");
+
+        assert_eq!(report.output, "<A>");
+        assert_eq!(pipeline.step_names(), vec!["upper", "noop", "wrap"]);
+        let changed: Vec<bool> = report.steps.iter().map(|s| s.changed).collect();
+        assert_eq!(changed, vec![true, false, true]);
+    }
+}
@@ -1,25 +1,38 @@
-use anyhow::Result;
+use crate::onvif::xmlns;
+use crate::translator::pipeline::TranslationPipeline;
+use crate::translator::response::ModelTranslator;
 
 pub struct ReolinkEventTranslator;
 
 impl ReolinkEventTranslator {
-    pub fn translate_response(xml: &str, quirks: &[String]) -> Result<String> {
-        let mut result = xml.to_string();
+    /// Quirks understood by `pipeline`
+    pub const QUIRKS: &'static [&'static str] = &[
+        "fix_device_info_namespace",
+        "normalize_media_profiles",
+        "translate_smart_events",
+        "add_missing_namespaces",
+        "fix_analytics_xaddr",
+    ];
+
+    /// Build the translation pipeline for a camera; steps run in the order the quirks are listed
+    pub fn pipeline(quirks: &[String]) -> TranslationPipeline {
+        let mut pipeline = TranslationPipeline::new();
 
         for quirk in quirks {
-            result = match quirk.as_str() {
-                "fix_device_info_namespace" => Self::fix_device_info_namespace(&result),
-                "normalize_media_profiles" => Self::normalize_media_profiles(&result),
-                "translate_smart_events" => Self::translate_smart_events(&result),
-                "add_missing_namespaces" => Self::add_missing_namespaces(&result),
+            pipeline = match quirk.as_str() {
+                "fix_device_info_namespace" => pipeline.step("fix_device_info_namespace", Self::fix_device_info_namespace),
+                "normalize_media_profiles" => pipeline.step("normalize_media_profiles", Self::normalize_media_profiles),
+                "translate_smart_events" => pipeline.step("translate_smart_events", Self::translate_smart_events),
+                "add_missing_namespaces" => pipeline.step("add_missing_namespaces", Self::add_missing_namespaces),
+                "fix_analytics_xaddr" => pipeline.step("fix_analytics_xaddr", Self::fix_analytics_xaddr),
                 _ => {
                     tracing::warn!("Unknown quirk: {}", quirk);
-                    result
+                    pipeline
                 }
             };
         }
 
-        Ok(result)
+        pipeline
     }
 
     fn fix_device_info_namespace(xml: &str) -> String {
@@ -39,7 +52,13 @@ impl ReolinkEventTranslator {
     }
 
     fn normalize_media_profiles(xml: &str) -> String {
-        let mut fixed = xml.to_string();
+        // Some firmware returns unprefixed / default-namespace profiles, or drops the
+        // GetProfilesResponse wrapper entirely; clients look for trt:Profiles
+        let mut fixed = Self::prefix_element(xml, "GetProfilesResponse", "trt");
+        fixed = Self::prefix_element(&fixed, "Profiles", "trt");
+        if fixed.contains("<trt:Profiles") && !fixed.contains("GetProfilesResponse") {
+            fixed = Self::wrap_body(&fixed, "trt:GetProfilesResponse");
+        }
 
         // Add xmlns:trt if missing
         if !fixed.contains("xmlns:trt=") && fixed.contains("<trt:") {
@@ -55,24 +74,48 @@ impl ReolinkEventTranslator {
     }
 
     fn translate_smart_events(xml: &str) -> String {
-        let mut fixed = xml.to_string();
+        // Only topics are remapped; the same words elsewhere (profile names, tokens) are data
+        let mut fixed = Self::map_topics(xml, Self::translate_topic);
+
+        // Ensure tns1 namespace is present for topics
+        if !fixed.contains("xmlns:tns1=") && fixed.contains("tns1:") {
+            fixed = Self::add_namespace(&fixed, "tns1", "http://www.onvif.org/ver10/topics");
+        }
+
+        // Add missing event data fields that iSpy expects
+        if fixed.contains("Motion") && !fixed.contains("State") {
+            // Add State SimpleItem if motion event exists but State is missing
+            if fixed.contains("<SimpleItem") && !fixed.contains(r#"Name="State""#) {
+                fixed = fixed.replace(
+                    "</wsnt:Message>",
+                    r#"<tt:SimpleItem Name="State" Value="true"/></tt:Data></tt:Message></wsnt:Message>"#,
+                );
+            }
+        }
+
+        fixed
+    }
+
+    /// A Reolink smart detection topic as the ONVIF motion topic
+    fn translate_topic(topic: &str) -> String {
+        // Full topic paths must come before the bare event names, otherwise the bare
+        // replacement rewrites the path first and the topic mapping never matches
+        let event_mappings = [
+            ("tns1:RuleEngine/MyRuleDetector/PeopleDetect", "tns1:RuleEngine/CellMotionDetector/Motion"),
+            ("tns1:RuleEngine/MyRuleDetector/VehicleDetect", "tns1:RuleEngine/CellMotionDetector/Motion"),
+            ("tns1:RuleEngine/MyRuleDetector/DogCatDetect", "tns1:RuleEngine/CellMotionDetector/Motion"),
 
-        // Reolink smart detection event mappings
-        let event_mappings = vec![
             // Person detection
             ("PeopleDetect", "Motion"),
             ("PersonDetection", "Motion"),
-            ("tns1:RuleEngine/MyRuleDetector/PeopleDetect", "tns1:RuleEngine/CellMotionDetector/Motion"),
 
             // Vehicle detection
             ("VehicleDetect", "Motion"),
             ("VehicleDetection", "Motion"),
-            ("tns1:RuleEngine/MyRuleDetector/VehicleDetect", "tns1:RuleEngine/CellMotionDetector/Motion"),
 
             // Pet/Animal detection
             ("DogCatDetect", "Motion"),
             ("PetDetection", "Motion"),
-            ("tns1:RuleEngine/MyRuleDetector/DogCatDetect", "tns1:RuleEngine/CellMotionDetector/Motion"),
 
             // Face detection
             ("FaceDetect", "Motion"),
@@ -83,27 +126,39 @@ impl ReolinkEventTranslator {
             ("AIDetection", "Motion"),
         ];
 
+        let mut translated = topic.to_string();
         for (reolink_event, onvif_event) in event_mappings {
-            fixed = fixed.replace(reolink_event, onvif_event);
+            translated = translated.replace(reolink_event, onvif_event);
         }
+        translated
+    }
 
-        // Ensure tns1 namespace is present for topics
-        if !fixed.contains("xmlns:tns1=") && fixed.contains("tns1:") {
-            fixed = Self::add_namespace(&fixed, "tns1", "http://www.onvif.org/ver10/topics");
-        }
+    /// Apply `map` to the text of every `Topic`/`TopicExpression` element, whatever its prefix
+    pub(crate) fn map_topics(xml: &str, map: impl Fn(&str) -> String) -> String {
+        let mut result = String::with_capacity(xml.len());
+        let mut rest = xml;
 
-        // Add missing event data fields that iSpy expects
-        if fixed.contains("Motion") && !fixed.contains("State") {
-            // Add State SimpleItem if motion event exists but State is missing
-            if fixed.contains("<SimpleItem") && !fixed.contains(r#"Name="State""#) {
-                fixed = fixed.replace(
-                    "</wsnt:Message>",
-                    r#"<tt:SimpleItem Name="State" Value="true"/></tt:Data></tt:Message></wsnt:Message>"#,
-                );
+        while let Some(open) = rest.find('<') {
+            let Some(tag_end) = rest[open..].find('>').map(|end| open + end) else {
+                break;
+            };
+            let tag = &rest[open + 1..tag_end];
+            let name = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or("");
+            let is_topic = !tag.starts_with('/')
+                && !tag.ends_with('/')
+                && matches!(name.rsplit(':').next(), Some("Topic" | "TopicExpression"));
+
+            result.push_str(&rest[..=tag_end]);
+            rest = &rest[tag_end + 1..];
+            if is_topic {
+                let end = rest.find('<').unwrap_or(rest.len());
+                result.push_str(&map(&rest[..end]));
+                rest = &rest[end..];
             }
         }
 
-        fixed
+        result.push_str(rest);
+        result
     }
 
     fn add_missing_namespaces(xml: &str) -> String {
@@ -130,21 +185,97 @@ impl ReolinkEventTranslator {
         fixed
     }
 
-    fn add_namespace(xml: &str, prefix: &str, uri: &str) -> String {
-        // Add namespace declaration to the SOAP Envelope
-        let namespace_decl = format!(r#" xmlns:{}="{}""#, prefix, uri);
-
-        if let Some(pos) = xml.find("<SOAP-ENV:Envelope") {
-            if let Some(end_pos) = xml[pos..].find('>') {
-                let insert_pos = pos + end_pos;
-                let before = &xml[..insert_pos];
-                let after = &xml[insert_pos..];
-                return format!("{}{}{}", before, namespace_decl, after);
+    /// Drop a GetCapabilities Analytics section whose XAddr the proxy doesn't serve. Reolink
+    /// sometimes reports an analytics path other than `/onvif/analytics_service`, which the URL
+    /// rewriting leaves pointing at the camera, and clients that follow it hang. Sections already
+    /// pointing at the proxy's analytics service are kept
+    fn fix_analytics_xaddr(xml: &str) -> String {
+        let Some(start) = xml.find(":Analytics>").and_then(|pos| xml[..pos].rfind('<')) else {
+            return xml.to_string();
+        };
+        let prefix = &xml[start + 1..xml[start..].find(':').unwrap() + start];
+        if prefix.starts_with('/') || prefix.contains(char::is_whitespace) {
+            return xml.to_string();
+        }
+        let closing = format!("</{}:Analytics>", prefix);
+        let Some(end) = xml[start..].find(&closing).map(|len| start + len + closing.len()) else {
+            return xml.to_string();
+        };
+
+        let section = &xml[start..end];
+        let xaddr = section
+            .find("XAddr>")
+            .map(|pos| pos + "XAddr>".len())
+            .and_then(|from| section[from..].find('<').map(|len| section[from..from + len].trim()));
+        if xaddr.is_some_and(|xaddr| xaddr.ends_with("/analytics_service")) {
+            return xml.to_string();
+        }
+
+        tracing::debug!("Removing unreachable analytics capability {:?}", xaddr);
+        format!("{}{}", &xml[..start], &xml[end..])
+    }
+
+    /// Give every unprefixed start/end/empty tag with this name the given prefix
+    fn prefix_element(xml: &str, name: &str, prefix: &str) -> String {
+        let mut result = String::with_capacity(xml.len());
+        let mut rest = xml;
+
+        while let Some(pos) = rest.find('<') {
+            result.push_str(&rest[..=pos]);
+            rest = &rest[pos + 1..];
+
+            let closing = rest.starts_with('/');
+            let name_start = usize::from(closing);
+            let is_match = rest[name_start..].strip_prefix(name).is_some_and(|after| {
+                after.starts_with(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            });
+
+            if is_match {
+                if closing {
+                    result.push('/');
+                }
+                result.push_str(prefix);
+                result.push(':');
+                rest = &rest[name_start..];
             }
         }
 
-        // Fallback: just return original if we can't find the envelope
-        xml.to_string()
+        result.push_str(rest);
+        result
+    }
+
+    /// Wrap the contents of the SOAP Body in a new element
+    fn wrap_body(xml: &str, element: &str) -> String {
+        let body_start = xml
+            .find(":Body")
+            .and_then(|pos| xml[pos..].find('>').map(|end| pos + end + 1));
+        // The closing tag is the last "</prefix:Body>"
+        let body_end = xml
+            .rfind(":Body>")
+            .and_then(|pos| xml[..pos].rfind("</"));
+
+        match (body_start, body_end) {
+            (Some(start), Some(end)) if start <= end => format!(
+                "{}<{}>{}</{}>{}",
+                &xml[..start],
+                element,
+                &xml[start..end],
+                element,
+                &xml[end..]
+            ),
+            _ => xml.to_string(),
+        }
+    }
+
+    fn add_namespace(xml: &str, prefix: &str, uri: &str) -> String {
+        // Declared on the SOAP Envelope; unchanged if there is none
+        xmlns::ensure_namespace(xml, prefix, uri)
+    }
+}
+
+impl ModelTranslator for ReolinkEventTranslator {
+    fn pipeline(&self, quirks: &[String]) -> TranslationPipeline {
+        ReolinkEventTranslator::pipeline(quirks)
     }
 }
 
@@ -154,9 +285,65 @@ mod tests {
 
     #[test]
     fn test_translate_smart_events() {
-        let xml = r#"<tns1:RuleEngine/MyRuleDetector/PeopleDetect>"#;
+        let xml = r#"<wsnt:Topic Dialect="http://www.onvif.org/ver10/tev/topicExpression/ConcreteSet">tns1:RuleEngine/MyRuleDetector/PeopleDetect</wsnt:Topic>"#;
         let result = ReolinkEventTranslator::translate_smart_events(xml);
-        assert!(result.contains("CellMotionDetector/Motion"));
+        assert!(result.contains(">tns1:RuleEngine/CellMotionDetector/Motion</wsnt:Topic>"), "{}", result);
+    }
+
+    #[test]
+    fn test_smart_events_outside_topics_kept() {
+        let xml = r#"<trt:Profiles token="PeopleDetectCam"><tt:Name>PeopleDetectCam</tt:Name></trt:Profiles><wsnt:NotificationMessage><wsnt:Topic>tns1:RuleEngine/MyRuleDetector/PeopleDetect</wsnt:Topic><wsnt:Message><tt:Message><tt:Source><tt:SimpleItem Name="Rule" Value="VehicleDetect"/></tt:Source></tt:Message></wsnt:Message></wsnt:NotificationMessage>"#;
+        let result = ReolinkEventTranslator::translate_smart_events(xml);
+        assert_eq!(
+            result,
+            xml.replace("MyRuleDetector/PeopleDetect", "CellMotionDetector/Motion")
+        );
+    }
+
+    #[test]
+    fn test_fix_analytics_xaddr() {
+        let capabilities = |analytics: &str| {
+            format!("<tds:GetCapabilitiesResponse><tds:Capabilities>{}<tt:Device><tt:XAddr>http://proxy/onvif/cam1/device_service</tt:XAddr></tt:Device></tds:Capabilities></tds:GetCapabilitiesResponse>", analytics)
+        };
+
+        let unreachable = capabilities("<tt:Analytics><tt:XAddr>http://192.168.1.100:8000/onvif/analytics</tt:XAddr><tt:RuleSupport>true</tt:RuleSupport></tt:Analytics>");
+        assert_eq!(ReolinkEventTranslator::fix_analytics_xaddr(&unreachable), capabilities(""));
+
+        let proxied = capabilities("<tt:Analytics><tt:XAddr>http://proxy/onvif/cam1/analytics_service</tt:XAddr></tt:Analytics>");
+        assert_eq!(ReolinkEventTranslator::fix_analytics_xaddr(&proxied), proxied);
+        assert_eq!(ReolinkEventTranslator::fix_analytics_xaddr(&capabilities("")), capabilities(""));
+    }
+
+    #[test]
+    fn test_pipeline_follows_quirk_order() {
+        let quirks = vec![
+            "translate_smart_events".to_string(),
+            "bogus".to_string(),
+            "fix_device_info_namespace".to_string(),
+        ];
+        let pipeline = ReolinkEventTranslator::pipeline(&quirks);
+        assert_eq!(pipeline.step_names(), vec!["translate_smart_events", "fix_device_info_namespace"]);
+    }
+
+    #[test]
+    fn test_normalize_default_namespace_profiles() {
+        let xml = r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope" xmlns:tt="http://www.onvif.org/ver10/schema"><SOAP-ENV:Body><GetProfilesResponse xmlns="http://www.onvif.org/ver10/media/wsdl"><Profiles token="000" fixed="true"><tt:Name>mainStream</tt:Name></Profiles><Profiles token="001"/></GetProfilesResponse></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+        let result = ReolinkEventTranslator::normalize_media_profiles(xml);
+
+        assert!(result.contains(r#"xmlns:trt="http://www.onvif.org/ver10/media/wsdl""#));
+        assert!(result.contains(r#"<trt:GetProfilesResponse xmlns="http://www.onvif.org/ver10/media/wsdl"><trt:Profiles token="000" fixed="true"><tt:Name>mainStream</tt:Name></trt:Profiles><trt:Profiles token="001"/></trt:GetProfilesResponse>"#));
+    }
+
+    #[test]
+    fn test_normalize_unwrapped_profiles() {
+        let xml = r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope"><SOAP-ENV:Body><Profiles token="000"></Profiles></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+        let result = ReolinkEventTranslator::normalize_media_profiles(xml);
+
+        assert!(result.contains(r#"<SOAP-ENV:Body><trt:GetProfilesResponse><trt:Profiles token="000"></trt:Profiles></trt:GetProfilesResponse></SOAP-ENV:Body>"#));
+
+        // Already well-formed responses are left alone
+        let good = ReolinkEventTranslator::normalize_media_profiles(&result);
+        assert_eq!(good, result);
     }
 
     #[test]
@@ -164,5 +351,9 @@ mod tests {
         let xml = r#"<SOAP-ENV:Envelope><tt:Something/></SOAP-ENV:Envelope>"#;
         let result = ReolinkEventTranslator::fix_device_info_namespace(xml);
         assert!(result.contains(r#"xmlns:tt="http://www.onvif.org/ver10/schema""#));
+
+        let xml = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><tds:GetDeviceInformationResponse/></s:Body></s:Envelope>"#;
+        let result = ReolinkEventTranslator::fix_device_info_namespace(xml);
+        assert!(result.starts_with(r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:tds="http://www.onvif.org/ver10/device/wsdl">"#));
     }
 }
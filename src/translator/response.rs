@@ -1,16 +1,163 @@
 use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+use crate::onvif::xmlns;
+use crate::translator::generic::GenericTranslator;
+use crate::translator::pipeline::{TranslationPipeline, TranslationReport};
 use crate::translator::reolink::ReolinkEventTranslator;
+use crate::translator::rules::RuleEngine;
+
+/// Response fixes for one camera model. Implement `pipeline` (its steps show up in
+/// `/debug/translate`) and register the translator under the model's name
+pub trait ModelTranslator: Send + Sync {
+    /// The steps applied to a response for a camera with `quirks`, in order
+    fn pipeline(&self, quirks: &[String]) -> TranslationPipeline;
+
+    fn translate_response(&self, xml: &str, quirks: &[String]) -> String {
+        self.pipeline(quirks).run(xml).output
+    }
+}
+
+/// Leaves responses as the camera sent them: for the simulator (whose responses are already
+/// spec-valid) and for models without a translator
+pub struct IdentityTranslator;
+
+impl ModelTranslator for IdentityTranslator {
+    fn pipeline(&self, _quirks: &[String]) -> TranslationPipeline {
+        TranslationPipeline::new()
+    }
+}
+
+type Registry = RwLock<HashMap<String, Arc<dyn ModelTranslator>>>;
+
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let mut translators: HashMap<String, Arc<dyn ModelTranslator>> = HashMap::new();
+        translators.insert("reolink".to_string(), Arc::new(ReolinkEventTranslator));
+        translators.insert("simulated".to_string(), Arc::new(IdentityTranslator));
+        translators.insert("generic".to_string(), Arc::new(GenericTranslator));
+        RwLock::new(translators)
+    })
+}
 
 pub struct ResponseTranslator;
 
 impl ResponseTranslator {
-    pub fn translate(xml: &str, camera_model: &str, quirks: &[String]) -> Result<String> {
-        match camera_model {
-            "reolink" => ReolinkEventTranslator::translate_response(xml, quirks),
-            _ => {
-                tracing::warn!("Unknown camera model: {}, no translation applied", camera_model);
-                Ok(xml.to_string())
-            }
+    /// Use `translator` for cameras whose `model` is `model`, replacing any translator it had
+    pub fn register(model: &str, translator: Arc<dyn ModelTranslator>) {
+        registry().write().unwrap().insert(model.to_string(), translator);
+    }
+
+    /// Camera models with a translator, sorted
+    pub fn models() -> Vec<String> {
+        let mut models: Vec<String> = registry().read().unwrap().keys().cloned().collect();
+        models.sort();
+        models
+    }
+
+    pub fn is_known_model(model: &str) -> bool {
+        registry().read().unwrap().contains_key(model)
+    }
+
+    /// Apply the model's quirks, then the camera's `rules`
+    pub fn translate(xml: &str, camera_model: &str, quirks: &[String], rules: &RuleEngine) -> Result<String> {
+        let translated = rules.apply(&Self::translator(camera_model).translate_response(xml, quirks));
+        // Steps that each declared a namespace may have declared it twice
+        Ok(xmlns::remove_duplicate_namespaces(&translated))
+    }
+
+    /// Run the same translation as `translate` and report what each step did, the camera's
+    /// `rules` as one step (see `/debug/translate`)
+    pub fn translate_with_report(xml: &str, camera_model: &str, quirks: &[String], rules: &RuleEngine) -> TranslationReport {
+        Self::translator(camera_model)
+            .pipeline(quirks)
+            .run(xml)
+            .then("rules", |xml| rules.apply(xml))
+            .then("remove_duplicate_namespaces", xmlns::remove_duplicate_namespaces)
+    }
+
+    fn translator(camera_model: &str) -> Arc<dyn ModelTranslator> {
+        if let Some(translator) = registry().read().unwrap().get(camera_model) {
+            return translator.clone();
+        }
+        tracing::warn!("Unknown camera model: {}, no translation applied", camera_model);
+        Arc::new(IdentityTranslator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicate_namespaces_removed() {
+        let xml = r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope" xmlns:tt="http://www.onvif.org/ver10/schema" xmlns:tt="http://www.onvif.org/ver10/schema"><SOAP-ENV:Body><tt:Name>a</tt:Name></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+        for model in ResponseTranslator::models().iter().map(String::as_str).chain(["unknown"]) {
+            let translated = ResponseTranslator::translate(xml, model, &["add_missing_namespaces".to_string()], &RuleEngine::new()).unwrap();
+            assert_eq!(translated.matches("xmlns:tt=").count(), 1, "{}", model);
         }
     }
+
+    struct DummyTranslator;
+
+    impl ModelTranslator for DummyTranslator {
+        fn pipeline(&self, _quirks: &[String]) -> TranslationPipeline {
+            TranslationPipeline::new().step("mark_dummy", |xml| xml.replace("<tt:Name>", "<tt:Name>dummy-"))
+        }
+    }
+
+    #[test]
+    fn test_registered_translator() {
+        let xml = "<tt:Name>a</tt:Name>";
+        assert_eq!(ResponseTranslator::translate(xml, "dummy-model", &[], &RuleEngine::new()).unwrap(), xml);
+        assert!(!ResponseTranslator::is_known_model("dummy-model"));
+
+        ResponseTranslator::register("dummy-model", Arc::new(DummyTranslator));
+        assert!(ResponseTranslator::is_known_model("dummy-model"));
+        assert_eq!(ResponseTranslator::translate(xml, "dummy-model", &[], &RuleEngine::new()).unwrap(), "<tt:Name>dummy-a</tt:Name>");
+        let report = ResponseTranslator::translate_with_report(xml, "dummy-model", &[], &RuleEngine::new());
+        assert_eq!(report.steps.iter().map(|step| step.name).collect::<Vec<_>>(), vec!["mark_dummy", "rules", "remove_duplicate_namespaces"]);
+
+        // Other models are unaffected
+        assert_eq!(ResponseTranslator::translate(xml, "simulated", &[], &RuleEngine::new()).unwrap(), xml);
+    }
+
+    #[test]
+    fn test_rules_from_config() {
+        let camera: crate::config::CameraConfig = serde_yaml::from_str(
+            r#"
+id: cam1
+name: Front
+address: 192.168.1.100
+username: admin
+password: secret
+model: reolink
+quirks: [translate_smart_events]
+rules:
+  - name: rename-manufacturer
+    rule_type: string_replace
+    pattern: "<tds:Manufacturer>Reolink</tds:Manufacturer>"
+    replacement: "<tds:Manufacturer>Acme</tds:Manufacturer>"
+  - name: after-the-first
+    rule_type: string_replace
+    pattern: "Acme"
+    replacement: "Acme Corp"
+"#,
+        )
+        .unwrap();
+        let rules = RuleEngine::from_rules(&camera.rules).unwrap();
+
+        let xml = "<tds:Manufacturer>Reolink</tds:Manufacturer><wsnt:Topic>tns1:RuleEngine/MyRuleDetector/PeopleDetect</wsnt:Topic>";
+        assert_eq!(
+            ResponseTranslator::translate(xml, &camera.model, &camera.quirks, &rules).unwrap(),
+            "<tds:Manufacturer>Acme Corp</tds:Manufacturer><wsnt:Topic>tns1:RuleEngine/CellMotionDetector/Motion</wsnt:Topic>"
+        );
+
+        // The report ends with what clients receive, the rules showing as a step of their own
+        let report = ResponseTranslator::translate_with_report(xml, &camera.model, &camera.quirks, &rules);
+        assert_eq!(report.output, ResponseTranslator::translate(xml, &camera.model, &camera.quirks, &rules).unwrap());
+        let rules_step = report.steps.iter().find(|step| step.name == "rules").unwrap();
+        assert!(rules_step.changed);
+    }
 }
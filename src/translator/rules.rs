@@ -1,2 +1,122 @@
-// Translation rules engine - placeholder for future extensibility
-// This allows for dynamic rule-based translation configurations
+// Translation rules engine: rule-based rewrites of camera responses (a camera's `rules`), for
+// fixes that don't warrant a model translator
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::onvif::xmlns;
+use crate::translator::reolink::ReolinkEventTranslator;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleType {
+    /// Replace every occurrence of `pattern` with `replacement`
+    StringReplace,
+    /// Replace `pattern` with `replacement` inside `Topic`/`TopicExpression` elements only
+    TopicMap,
+    /// Replace matches of the regular expression `pattern`; `replacement` may use `$1`/`${name}`
+    RegexReplace,
+    /// Declare prefix `pattern` as namespace URI `replacement` on the Envelope, unless declared
+    NamespaceAdd,
+}
+
+/// A camera's `rules` entry
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TranslationRule {
+    pub name: String,
+    pub rule_type: RuleType,
+    pub pattern: String,
+    pub replacement: String,
+}
+
+struct CompiledRule {
+    rule: TranslationRule,
+    /// Set for `RegexReplace`
+    regex: Option<Regex>,
+}
+
+/// Rules applied in the order they were added
+#[derive(Default)]
+pub struct RuleEngine {
+    rules: Vec<CompiledRule>,
+}
+
+impl RuleEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An engine running `rules` in order
+    pub fn from_rules(rules: &[TranslationRule]) -> Result<Self> {
+        let mut engine = Self::new();
+        for rule in rules {
+            engine.add_rule(rule.clone())?;
+        }
+        Ok(engine)
+    }
+
+    /// Add a rule; fails when a `RegexReplace` pattern doesn't compile
+    pub fn add_rule(&mut self, rule: TranslationRule) -> Result<()> {
+        let regex = match rule.rule_type {
+            RuleType::RegexReplace => {
+                Some(Regex::new(&rule.pattern).with_context(|| format!("Invalid pattern in rule {}", rule.name))?)
+            }
+            _ => None,
+        };
+        self.rules.push(CompiledRule { rule, regex });
+        Ok(())
+    }
+
+    pub fn apply(&self, xml: &str) -> String {
+        self.rules.iter().fold(xml.to_string(), |xml, rule| Self::apply_rule(rule, &xml))
+    }
+
+    fn apply_rule(compiled: &CompiledRule, xml: &str) -> String {
+        let rule = &compiled.rule;
+        match (rule.rule_type, &compiled.regex) {
+            (RuleType::StringReplace, _) => xml.replace(&rule.pattern, &rule.replacement),
+            (RuleType::TopicMap, _) => {
+                ReolinkEventTranslator::map_topics(xml, |topic| topic.replace(&rule.pattern, &rule.replacement))
+            }
+            (RuleType::RegexReplace, Some(regex)) => regex.replace_all(xml, rule.replacement.as_str()).into_owned(),
+            (RuleType::RegexReplace, None) => xml.to_string(),
+            (RuleType::NamespaceAdd, _) => xmlns::ensure_namespace(xml, &rule.pattern, &rule.replacement),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(rule_type: RuleType, pattern: &str, replacement: &str) -> TranslationRule {
+        TranslationRule { name: "test".to_string(), rule_type, pattern: pattern.to_string(), replacement: replacement.to_string() }
+    }
+
+    #[test]
+    fn test_regex_replace() {
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(rule(RuleType::RegexReplace, r"<tt:Width>(\d+)</tt:Width><tt:Height>(\d+)</tt:Height>", "<tt:Width>$2</tt:Width><tt:Height>$1</tt:Height>"))
+            .unwrap();
+        assert_eq!(
+            engine.apply("<tt:Resolution><tt:Width>1080</tt:Width><tt:Height>1920</tt:Height></tt:Resolution>"),
+            "<tt:Resolution><tt:Width>1920</tt:Width><tt:Height>1080</tt:Height></tt:Resolution>"
+        );
+
+        let error = engine.add_rule(rule(RuleType::RegexReplace, "(unclosed", "")).unwrap_err();
+        assert!(error.to_string().contains("rule test"), "{}", error);
+    }
+
+    #[test]
+    fn test_namespace_add() {
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule(RuleType::NamespaceAdd, "tt", "http://www.onvif.org/ver10/schema")).unwrap();
+
+        let missing = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><tt:Name>a</tt:Name></s:Body></s:Envelope>"#;
+        assert!(engine.apply(missing).starts_with(r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:tt="http://www.onvif.org/ver10/schema">"#));
+
+        let declared = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:tt="urn:camera-schema"><s:Body><tt:Name>a</tt:Name></s:Body></s:Envelope>"#;
+        assert_eq!(engine.apply(declared), declared);
+    }
+}
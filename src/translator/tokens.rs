@@ -0,0 +1,163 @@
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+
+/// Longest token handed to clients with strict token rules
+const MAX_CLIENT_TOKEN_LEN: usize = 32;
+
+/// Rewrites camera tokens (profile, configuration, video source, event source tokens) into
+/// short alphanumeric ones for VMSes such as Milestone XProtect that reject anything else, and
+/// maps them back in requests. Client tokens are derived from a hash of the camera token, so they
+/// stay the same across restarts; the reverse mapping is learned from the responses, and relearned
+/// from the camera's listings (see `client_profile::adapt_tokens`) when a request carries a
+/// client token the map doesn't know, e.g. after a restart or the camera being re-added.
+#[derive(Debug, Default)]
+pub struct TokenMap {
+    to_camera: HashMap<String, String>,
+}
+
+impl TokenMap {
+    /// The token shown to clients; tokens that are already acceptable are kept
+    pub fn client_token(camera_token: &str) -> String {
+        let acceptable = !camera_token.is_empty()
+            && camera_token.len() <= MAX_CLIENT_TOKEN_LEN
+            && camera_token.chars().all(|c| c.is_ascii_alphanumeric());
+        if acceptable {
+            return camera_token.to_string();
+        }
+
+        let digest = Sha1::digest(camera_token.as_bytes());
+        let hex: String = digest.iter().take(6).map(|b| format!("{:02x}", b)).collect();
+        format!("T{}", hex)
+    }
+
+    /// Replace camera tokens in a response with client tokens, remembering the mapping
+    pub fn sanitize_response(&mut self, xml: &str) -> String {
+        map_tokens(xml, |camera_token| {
+            let client_token = Self::client_token(camera_token);
+            if client_token == camera_token {
+                return None;
+            }
+            self.to_camera.insert(client_token.clone(), camera_token.to_string());
+            Some(client_token)
+        })
+    }
+
+    /// Replace client tokens in a request with the camera tokens they stand for
+    pub fn restore_request(&self, xml: &str) -> String {
+        map_tokens(xml, |client_token| self.to_camera.get(client_token).cloned())
+    }
+
+    /// Remember the client tokens for the camera tokens in a response, without rewriting it
+    pub fn learn(&mut self, xml: &str) {
+        self.sanitize_response(xml);
+    }
+
+    /// Whether a request carries a token shaped like a client token that the map can't resolve
+    pub fn has_unknown_tokens(&self, xml: &str) -> bool {
+        let mut unknown = false;
+        map_tokens(xml, |token| {
+            unknown |= is_hashed_token(token) && !self.to_camera.contains_key(token);
+            None
+        });
+        unknown
+    }
+}
+
+/// `T` + 12 hex digits, as `client_token` makes from a hash
+fn is_hashed_token(token: &str) -> bool {
+    token.len() == 13
+        && token.starts_with('T')
+        && token[1..].chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+/// Apply `map` to every token value: `token="..."` attributes, `<...Token>...</...Token>`
+/// elements and `SimpleItem`s named `...Token` (event sources)
+fn map_tokens(xml: &str, mut map: impl FnMut(&str) -> Option<String>) -> String {
+    let xml = map_values(xml, " token=\"", '"', |_, _| true, &mut map);
+    let xml = map_values(&xml, "Token>", '<', is_start_tag, &mut map);
+    map_values(&xml, "Token\" Value=\"", '"', |_, _| true, &mut map)
+}
+
+/// Whether the `Token>` at `at` ends a start tag such as `<tt:ProfileToken>`
+fn is_start_tag(xml: &str, at: usize) -> bool {
+    let Some(open) = xml[..at].rfind('<') else {
+        return false;
+    };
+    let name = &xml[open + 1..at];
+    !name.starts_with('/') && !name.contains([' ', '>', '"', '/'])
+}
+
+fn map_values(
+    xml: &str,
+    marker: &str,
+    terminator: char,
+    valid: impl Fn(&str, usize) -> bool,
+    map: &mut impl FnMut(&str) -> Option<String>,
+) -> String {
+    let mut result = String::with_capacity(xml.len());
+    let mut offset = 0;
+
+    while let Some(found) = xml[offset..].find(marker) {
+        let at = offset + found;
+        let start = at + marker.len();
+        let Some(len) = xml[start..].find(terminator) else {
+            break;
+        };
+        let end = start + len;
+
+        result.push_str(&xml[offset..start]);
+        let value = &xml[start..end];
+        let replacement = if !value.trim().is_empty() && valid(xml, at) { map(value.trim()) } else { None };
+        result.push_str(replacement.as_deref().unwrap_or(value));
+        offset = end;
+    }
+
+    result.push_str(&xml[offset..]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(TokenMap::client_token("000"), "000");
+        assert_eq!(TokenMap::client_token("MainStreamProfile"), "MainStreamProfile");
+        let long = TokenMap::client_token("Profile_1-main.stream/with a very long name");
+        assert!(long.len() <= MAX_CLIENT_TOKEN_LEN && long.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_eq!(long, TokenMap::client_token("Profile_1-main.stream/with a very long name"));
+
+        let mut map = TokenMap::default();
+        let response = r#"<trt:Profiles token="Profile_1" fixed="true"><tt:Name>main</tt:Name><tt:VideoSourceConfiguration token="000"><tt:SourceToken>VideoSource-1</tt:SourceToken></tt:VideoSourceConfiguration></trt:Profiles><tt:SimpleItem Name="VideoSourceConfigurationToken" Value="camera-01"/>"#;
+        let sanitized = map.sanitize_response(response);
+
+        let profile = TokenMap::client_token("Profile_1");
+        let source = TokenMap::client_token("VideoSource-1");
+        assert!(sanitized.contains(&format!(r#"<trt:Profiles token="{}" fixed="true">"#, profile)));
+        assert!(sanitized.contains(r#"token="000""#));
+        assert!(sanitized.contains(&format!("<tt:SourceToken>{}</tt:SourceToken>", source)));
+        assert!(sanitized.contains(&format!(r#"Value="{}""#, TokenMap::client_token("camera-01"))));
+
+        let request = format!("<trt:GetStreamUri><trt:ProfileToken>{}</trt:ProfileToken></trt:GetStreamUri>", profile);
+        assert_eq!(
+            map.restore_request(&request),
+            "<trt:GetStreamUri><trt:ProfileToken>Profile_1</trt:ProfileToken></trt:GetStreamUri>"
+        );
+        // Unknown tokens go to the camera unchanged
+        assert_eq!(map.restore_request("<ProfileToken>Tdeadbeef</ProfileToken>"), "<ProfileToken>Tdeadbeef</ProfileToken>");
+    }
+
+    #[test]
+    fn test_relearn() {
+        // A fresh map, as after a restart, spots the client token it can't resolve
+        let mut map = TokenMap::default();
+        let request = format!("<trt:GetStreamUri><trt:ProfileToken>{}</trt:ProfileToken></trt:GetStreamUri>", TokenMap::client_token("Profile_1"));
+        assert!(map.has_unknown_tokens(&request));
+        assert!(!map.has_unknown_tokens("<trt:GetStreamUri><trt:ProfileToken>000</trt:ProfileToken></trt:GetStreamUri>"));
+
+        map.learn(r#"<trt:GetProfilesResponse><trt:Profiles token="Profile_1" fixed="true"/></trt:GetProfilesResponse>"#);
+        assert!(!map.has_unknown_tokens(&request));
+        assert!(map.restore_request(&request).contains("<trt:ProfileToken>Profile_1</trt:ProfileToken>"));
+    }
+}
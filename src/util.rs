@@ -0,0 +1,141 @@
+/// Query parameters and JSON fields whose values are replaced by `redact_secrets`
+const SECRET_PARAMS: &[&str] = &["password", "token"];
+
+/// WS-Security elements whose content is replaced by `redact_secrets`, matched by local name
+const SECRET_ELEMENTS: &[&str] = &["Password", "Nonce"];
+
+const REDACTED: &str = "***";
+
+/// Text with credentials blanked out, for logging requests to and from cameras: `password=`
+/// and `token=` query values (Reolink CGI URLs), the same JSON fields, and the content of
+/// WS-Security `Password` and `Nonce` elements
+pub fn redact_secrets(text: &str) -> String {
+    let mut redacted = text.to_string();
+    for param in SECRET_PARAMS {
+        redacted = redact_query_param(&redacted, param);
+        redacted = redact_json_field(&redacted, param);
+    }
+    for element in SECRET_ELEMENTS {
+        redacted = redact_element(&redacted, element);
+    }
+    redacted
+}
+
+/// `name=value` after `?` or `&`, up to the next `&`, quote or whitespace
+fn redact_query_param(text: &str, name: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(found) = find_ignore_case(rest, &format!("{}=", name)) {
+        let start = found + name.len() + 1;
+        let preceded = rest[..found].ends_with(['?', '&']);
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if preceded {
+            let end = rest.find(|c: char| c == '&' || c == '"' || c == '\'' || c == '<' || c.is_whitespace()).unwrap_or(rest.len());
+            result.push_str(REDACTED);
+            rest = &rest[end..];
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// `"name": "value"`, as in a CGI `Login` body
+fn redact_json_field(text: &str, name: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    let key = format!("\"{}\"", name);
+
+    while let Some(found) = find_ignore_case(rest, &key) {
+        let after_key = found + key.len();
+        result.push_str(&rest[..after_key]);
+        rest = &rest[after_key..];
+
+        let value_start = rest.len() - rest.trim_start().len();
+        let Some(after_colon) = rest[value_start..].strip_prefix(':') else {
+            continue;
+        };
+        let quote = after_colon.len() - after_colon.trim_start().len();
+        let Some(value) = after_colon[quote..].strip_prefix('"') else {
+            continue;
+        };
+        let Some(end) = value.find('"') else {
+            continue;
+        };
+
+        let prefix_len = rest.len() - value.len();
+        result.push_str(&rest[..prefix_len]);
+        result.push_str(REDACTED);
+        rest = &value[end..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Content of `<Name ...>`/`<prefix:Name ...>` elements
+fn redact_element(text: &str, local_name: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(open) = rest.find('<') {
+        let tag_end = match rest[open..].find('>') {
+            Some(end) => open + end,
+            None => break,
+        };
+        let tag = &rest[open + 1..tag_end];
+        let name = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or("");
+        let matches = !tag.starts_with('/')
+            && !tag.ends_with('/')
+            && name.rsplit(':').next() == Some(local_name);
+
+        result.push_str(&rest[..=tag_end]);
+        rest = &rest[tag_end + 1..];
+        if matches {
+            let end = rest.find('<').unwrap_or(rest.len());
+            result.push_str(REDACTED);
+            rest = &rest[end..];
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack.to_ascii_lowercase().find(&needle.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_cgi() {
+        assert_eq!(
+            redact_secrets("https://192.168.1.10/cgi-bin/api.cgi?cmd=GetMdState&channel=0&user=admin&password=s3cr%26t"),
+            "https://192.168.1.10/cgi-bin/api.cgi?cmd=GetMdState&channel=0&user=admin&password=***"
+        );
+        assert_eq!(redact_secrets("GET /api.cgi?token=abc123&cmd=Snap"), "GET /api.cgi?token=***&cmd=Snap");
+        assert_eq!(
+            redact_secrets(r#"[{"cmd":"Login","param":{"User":{"userName":"admin","password" : "hunter2"}}}]"#),
+            r#"[{"cmd":"Login","param":{"User":{"userName":"admin","password" : "***"}}}]"#
+        );
+        // Not a query parameter
+        assert_eq!(redact_secrets("the password=field is empty"), "the password=field is empty");
+    }
+
+    #[test]
+    fn test_redact_soap() {
+        let header = r#"<wsse:Security><wsse:UsernameToken><wsse:Username>admin</wsse:Username><wsse:Password Type="...#PasswordText">hunter2</wsse:Password><wsse:Nonce EncodingType="...#Base64Binary">bm9uY2U=</wsse:Nonce><wsu:Created>2026-01-01T00:00:00Z</wsu:Created></wsse:UsernameToken></wsse:Security>"#;
+        assert_eq!(
+            redact_secrets(header),
+            r#"<wsse:Security><wsse:UsernameToken><wsse:Username>admin</wsse:Username><wsse:Password Type="...#PasswordText">***</wsse:Password><wsse:Nonce EncodingType="...#Base64Binary">***</wsse:Nonce><wsu:Created>2026-01-01T00:00:00Z</wsu:Created></wsse:UsernameToken></wsse:Security>"#
+        );
+        // Elements that merely contain the name are left alone
+        let other = "<tds:PasswordComplexityOptions/><tt:NonceLength>16</tt:NonceLength><Password/>";
+        assert_eq!(redact_secrets(other), other);
+    }
+}
@@ -20,6 +20,7 @@ async fn main() -> anyhow::Result<()> {
         model: "reolink".to_string(),
         enable_smart_detection: false,
         quirks: vec![],
+        ..Default::default()
     };
 
     // Create camera client
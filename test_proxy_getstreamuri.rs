@@ -21,6 +21,7 @@ async fn main() -> anyhow::Result<()> {
             "normalize_media_profiles".to_string(),
             "translate_smart_events".to_string(),
         ],
+        ..Default::default()
     };
 
     let camera = CameraClient::new(config.clone());
@@ -43,7 +44,7 @@ async fn main() -> anyhow::Result<()> {
 
             // Apply translation with quirks (simulating what the proxy does)
             let quirks = config.quirks.clone();
-            let translated = match ResponseTranslator::translate(&raw_response, &config.model, &quirks) {
+            let translated = match ResponseTranslator::translate(&raw_response, &config.model, &quirks, camera.rules()) {
                 Ok(t) => t,
                 Err(e) => {
                     eprintln!("Translation failed: {}", e);